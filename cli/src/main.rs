@@ -2,19 +2,25 @@ extern crate image;
 extern crate rustico_core;
 extern crate rustico_ui_common;
 
+use rustico_core::benchmark;
 use rustico_core::nes::NesState;
 use rustico_core::palettes::NTSC_PAL;
 use rustico_core::cartridge::mapper_from_file;
 
 use rustico_ui_common::application::RuntimeState as RusticoRuntimeState;
+use rustico_ui_common::drawing::SimpleBuffer;
 use rustico_ui_common::events;
 use rustico_ui_common::panel::Panel;
 use rustico_ui_common::piano_roll_window::PianoRollWindow;
 use rustico_ui_common::event_window::EventWindow;
+use rustico_ui_common::resampler::Resampler;
+use rustico_ui_common::resampler::resampler_from_settings;
 
 use std::env;
+use std::fs;
 use std::fs::File;
 use std::str;
+use std::sync::Arc;
 
 use std::io::Read;
 use std::io::Write;
@@ -28,6 +34,7 @@ pub struct CliRuntimeState {
   pub game_file: Option<File>,
   pub piano_file: Option<File>,
   pub audio_file: Option<File>,
+  pub audio_resampler: Option<Box<dyn Resampler>>,
   pub event_file: Option<File>,
 }
 
@@ -40,6 +47,7 @@ impl CliRuntimeState {
       game_file: None,
       piano_file: None,
       audio_file: None,
+      audio_resampler: None,
       event_file: None,
     }
   }
@@ -117,9 +125,14 @@ fn dump_audio(state: &mut CliRuntimeState) {
   match &mut state.audio_file {
     Some(file) => {
       if state.core.nes.apu.buffer_full {
-        let buffer_size = state.core.nes.apu.output_buffer.len();
-        for i in 0 .. buffer_size {
-          let _ = file.write_all(&state.core.nes.apu.output_buffer[i].to_be_bytes());
+        let samples: Vec<f32> = state.core.nes.apu.output_buffer.iter().map(|&sample| sample as f32 / 32767.0).collect();
+        let resampled = match &mut state.audio_resampler {
+          Some(resampler) => resampler.process(&samples),
+          None => samples
+        };
+        for sample in resampled {
+          let sample_i16 = (sample.max(-1.0).min(1.0) * 32767.0) as i16;
+          let _ = file.write_all(&sample_i16.to_be_bytes());
         }
         state.core.nes.apu.buffer_full = false;
       }
@@ -128,24 +141,11 @@ fn dump_audio(state: &mut CliRuntimeState) {
   }
 }
 
-fn dump_panel(file_handle: &mut Option<File>, panel: & dyn Panel) {
+fn dump_canvas(file_handle: &mut Option<File>, canvas: &SimpleBuffer) {
   match file_handle {
     Some(file) => {
-      let width = panel.active_canvas().width as usize;
-      let height = panel.active_canvas().height as usize;
-      let buffer_size = width * height * 4;
-      let mut buffer = vec!(0u8; buffer_size);
-      for x in 0 .. width {
-        for y in 0 .. height {
-          let pixel_index = (width * y + x) * 4;
-          let color = panel.active_canvas().get_pixel(x as u32, y as u32);
-          buffer[pixel_index + 0] = color.r();
-          buffer[pixel_index + 1] = color.g();
-          buffer[pixel_index + 2] = color.b();
-          buffer[pixel_index + 3] = color.alpha();
-        }
-      }
-      let _ = file.write_all(&buffer);
+      // The canvas is already packed RGBA, which is just what we write out
+      let _ = file.write_all(&canvas.buffer);
     }
     None => {}
   }
@@ -167,8 +167,23 @@ fn run(state: &mut CliRuntimeState, frames: u64) {
     // If there are any outstanding dump configurations, process those
     dump_frame(state);
     dump_audio(state);
-    dump_panel(&mut state.piano_file, &state.piano_roll_panel);
-    dump_panel(&mut state.event_file, &state.event_viewer_panel);
+    if state.piano_file.is_some() {
+      // Scaled and dithered per piano_roll.export_scale and piano_roll.export_dither
+      let piano_canvas = state.piano_roll_panel.export_canvas();
+      dump_canvas(&mut state.piano_file, &piano_canvas);
+    }
+    dump_canvas(&mut state.event_file, state.event_viewer_panel.active_canvas());
+  }
+}
+
+// Runs the core alone for a fixed number of frames, skipping the panels and any dumps, so the
+// result tracks CPU/PPU/APU stepping cost and nothing else. Returns frames per second.
+// Timing zero frames would divide by zero, so that's rejected along with anything unparseable
+fn benchmark_frames(argument: &str) -> Result<u64, String> {
+  match argument.parse::<u64>() {
+    Ok(0) => Err("Benchmark needs at least one frame to time".to_string()),
+    Ok(frames) => Ok(frames),
+    Err(why) => Err(format!("Invalid benchmark frame count {}: {}", argument, why))
   }
 }
 
@@ -282,6 +297,18 @@ fn process_command_list(state: &mut CliRuntimeState, mut command_list: Vec<Strin
         load_cartridge(&mut state.core.nes, cartridge_path.as_ref());
         state.core.running = true;
       },
+      "compare" => {
+        // Plays a second cartridge in lockstep, overlaid on the piano roll for A/B comparison
+        let comparison_path = command_list.remove(0);
+        match fs::read(&comparison_path) {
+          Ok(file_data) => {
+            dispatch_event(state, events::Event::LoadComparisonCartridge(Arc::new(file_data)));
+          },
+          Err(why) => {
+            panic!("Couldn't read from {}: {}", comparison_path, why);
+          }
+        }
+      },
       "config"  => {
         let config_path = command_list.remove(0);
         state.core.settings.load(&config_path.into());
@@ -293,6 +320,17 @@ fn process_command_list(state: &mut CliRuntimeState, mut command_list: Vec<Strin
         let frames: u64 = command_list.remove(0).parse().unwrap();
         run(state, frames);
       },
+      "benchmark" => {
+        let frames = match benchmark_frames(&command_list.remove(0)) {
+          Ok(frames) => frames,
+          Err(why) => panic!("{}", why)
+        };
+        let result = benchmark::run_frames(&mut state.core.nes, frames);
+        println!("Benchmark: {}", result);
+        if !(result.fps > 0.0) {
+          panic!("Benchmark failed to produce a sensible frame rate: {}", result.fps);
+        }
+      },
       "reset" => {
         // TODO: implement this with the standard event instead
         reset(&mut state.core.nes);
@@ -351,7 +389,12 @@ fn process_command_list(state: &mut CliRuntimeState, mut command_list: Vec<Strin
             panic!("Couldn't open {}: {}", output_path, why);
           },
           Ok(file) => {
+            // Exported audio goes through the same resampler as live playback, configured
+            // by audio.resampler_quality, so load any config before this command
+            let apu_sample_rate = state.core.nes.apu.sample_rate();
+            let export_sample_rate = state.core.settings.get_integer("audio.export_sample_rate".to_string()).unwrap_or(apu_sample_rate as i64) as u64;
             state.audio_file = Some(file);
+            state.audio_resampler = Some(resampler_from_settings(&state.core.settings, apu_sample_rate, export_sample_rate));
           }
         }
       }
@@ -382,3 +425,33 @@ fn main() {
 
   process_command_list(&mut state, args);
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn benchmark_runs_a_few_frames_headless() {
+    let mut state = CliRuntimeState::new();
+    // Power on lands partway through a frame, so line up on vblank first
+    state.core.nes.run_until_vblank();
+    let starting_frame = state.core.nes.ppu.current_frame;
+    process_command_list(&mut state, vec!("benchmark".to_string(), "3".to_string()));
+    assert_eq!(state.core.nes.ppu.current_frame, starting_frame + 3);
+  }
+
+  #[test]
+  fn benchmark_frame_counts_must_be_positive() {
+    assert_eq!(benchmark_frames("3600"), Ok(3600));
+    assert!(benchmark_frames("0").is_err());
+    assert!(benchmark_frames("-1").is_err());
+    assert!(benchmark_frames("lots").is_err());
+  }
+
+  #[test]
+  #[should_panic(expected = "at least one frame")]
+  fn benchmarking_zero_frames_is_an_error() {
+    let mut state = CliRuntimeState::new();
+    process_command_list(&mut state, vec!("benchmark".to_string(), "0".to_string()));
+  }
+}
@@ -0,0 +1,31 @@
+// Runs the core headless against a fixed ROM and reports frames per second, to catch
+// performance regressions in CPU/PPU/APU stepping. No audio or video devices are involved.
+//
+// cargo bench
+// RUSTICO_BENCH_FRAMES=600 cargo bench
+
+extern crate rustico_core;
+
+use rustico_core::benchmark;
+use rustico_core::cartridge::mapper_from_file;
+use rustico_core::nes::NesState;
+
+use std::env;
+
+const DEFAULT_FRAMES: u64 = 3600;
+
+fn main() {
+    let frames = match env::var("RUSTICO_BENCH_FRAMES") {
+        Ok(value) => match value.parse::<u64>() {
+            Ok(frames) if frames > 0 => frames,
+            _ => panic!("RUSTICO_BENCH_FRAMES must be a positive number of frames, got {}", value)
+        },
+        Err(_) => DEFAULT_FRAMES
+    };
+
+    let mapper = mapper_from_file(include_bytes!("../../ui-common/src/assets/rustico_no_cart.nes")).unwrap();
+    let mut nes = NesState::new(mapper);
+    nes.power_on();
+
+    println!("emulation: {}", benchmark::run_frames(&mut nes, frames));
+}
@@ -21,6 +21,8 @@ pub enum Timbre {
     DutyIndex { index: usize, max: usize },
     LsfrMode { index: usize, max: usize },
     PatchIndex { index: usize, max: usize },
+    // How far past clean output the channel is being driven, 0 being not at all
+    Distortion { index: usize, max: usize },
 }
 
 pub trait AudioChannelState {
@@ -41,6 +43,12 @@ pub trait AudioChannelState {
     fn rate(&self) -> PlaybackRate { return PlaybackRate::SampleRate{frequency: 0.0}; }
     fn volume(&self) -> Option<Volume> {return None}
     fn timbre(&self) -> Option<Timbre> {return None}
+    // Sample-based channels may be able to guess at the pitch of the sample they are playing,
+    // ie: a short looping DPCM sample. Return None unless this is reasonably reliable.
+    fn estimated_frequency(&self) -> Option<f32> {return None}
+    // Named raw register values, for debug displays. Channels can list whatever internal
+    // state is useful to see; the default is nothing at all.
+    fn debug_registers(&self) -> Vec<(String, u32)> {return Vec::new()}
     fn amplitude(&self) -> f32 {
         /* pre-mixed volume, allows chips using non-linear mixing to tailor this value.
            results should be based on 2A03 pulse, where 1.0 corresponds to 0xF */
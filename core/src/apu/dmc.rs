@@ -1,5 +1,6 @@
 use mmc::mapper::Mapper;
 use super::audio_channel::AudioChannelState;
+use super::audio_channel::PlaybackRate;
 use super::ring_buffer::RingBuffer;
 use super::filters;
 use super::filters::DspFilter;
@@ -72,6 +73,22 @@ impl DmcState {
             self.bytes_remaining, self.bits_remaining);
     }
 
+    pub fn restart_sample(&mut self) {
+        self.current_address = self.starting_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Same as writing $4010, $4012 and $4013 and then enabling the channel through $4015, but
+    // in raw units: the address is the CPU address, and the period is in APU cycles.
+    pub fn play_sample(&mut self, starting_address: u16, sample_length: u16, period_initial: u16, looping: bool) {
+        self.starting_address = starting_address;
+        self.sample_length = sample_length;
+        self.period_initial = period_initial;
+        self.looping = looping;
+        self.interrupt_flag = false;
+        self.restart_sample();
+    }
+
     pub fn read_next_sample(&mut self, mapper: &mut dyn Mapper) {
         match mapper.read_cpu(0x8000 | (self.current_address & 0x7FFF)) {
             Some(byte) => self.sample_buffer = byte,
@@ -81,8 +98,7 @@ impl DmcState {
         self.bytes_remaining -= 1;
         if self.bytes_remaining == 0 {
             if self.looping {
-                self.current_address = self.starting_address;
-                self.bytes_remaining = self.sample_length;
+                self.restart_sample();
                 self.last_edge = true;
             } else {
                 if self.interrupt_enabled {
@@ -149,6 +165,15 @@ impl DmcState {
     pub fn output(&self) -> i16 {
         return self.output_level as i16;
     }
+
+    // Rate at which the output unit consumes bits. Note that period_initial is stored in APU
+    // cycles, which run at half the CPU clock.
+    pub fn bit_rate(&self) -> f32 {
+        if self.period_initial == 0 {
+            return 0.0;
+        }
+        return 1_789_773.0 / (self.period_initial as f32 * 2.0);
+    }
 }
 
 impl AudioChannelState for DmcState {
@@ -199,9 +224,38 @@ impl AudioChannelState for DmcState {
         return self.amplitude() > 0.0;
     }
 
+    fn rate(&self) -> PlaybackRate {
+        return PlaybackRate::SampleRate{frequency: self.bit_rate()};
+    }
+
+    fn estimated_frequency(&self) -> Option<f32> {
+        // A looping sample repeats once per pass through its data, which for short melodic
+        // samples is usually one period of the waveform. One-shot samples have no such
+        // relationship, so don't guess.
+        if !self.looping || self.sample_length == 0 {
+            return None;
+        }
+        let bits_per_loop = self.sample_length as f32 * 8.0;
+        return Some(self.bit_rate() / bits_per_loop);
+    }
+
+    fn debug_registers(&self) -> Vec<(String, u32)> {
+        return vec!(
+            ("looping".to_string(), self.looping as u32),
+            ("irq_enabled".to_string(), self.interrupt_enabled as u32),
+            ("irq_flag".to_string(), self.interrupt_flag as u32),
+            ("period_initial".to_string(), self.period_initial as u32),
+            ("output_level".to_string(), self.output_level as u32),
+            ("starting_address".to_string(), self.starting_address as u32),
+            ("sample_length".to_string(), self.sample_length as u32),
+            ("current_address".to_string(), self.current_address as u32),
+            ("bytes_remaining".to_string(), self.bytes_remaining as u32),
+        );
+    }
+
     fn amplitude(&self) -> f32 {
         let buffer = self.output_buffer.buffer();
-        let mut index = (self.output_buffer.index() - 256) % buffer.len();
+        let mut index = self.output_buffer.index_before(self.output_buffer.index(), 256);
         let mut max = buffer[index];
         let mut min = buffer[index];
         for _i in 0 .. 256 {
@@ -212,4 +266,100 @@ impl AudioChannelState for DmcState {
         }
         return (max - min) as f32 / 256.0;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mmc::mapper::Mirroring;
+
+    // Serves $FF everywhere and remembers which addresses the DMC fetched
+    struct FetchLog {
+        reads: Vec<u16>,
+    }
+
+    impl Mapper for FetchLog {
+        fn read_cpu(&mut self, address: u16) -> Option<u8> {
+            self.reads.push(address);
+            return Some(0xFF);
+        }
+        fn write_cpu(&mut self, _address: u16, _data: u8) {}
+        fn write_ppu(&mut self, _address: u16, _data: u8) {}
+        fn debug_read_cpu(&self, _address: u16) -> Option<u8> {return Some(0xFF);}
+        fn debug_read_ppu(&self, _address: u16) -> Option<u8> {return Some(0);}
+        fn mirroring(&self) -> Mirroring {return Mirroring::Horizontal;}
+    }
+
+    // Long enough for the fastest rate to play through the whole sample several times over
+    fn clock_dmc(dmc: &mut DmcState, mapper: &mut FetchLog) {
+        for _ in 0 .. 54 * 8 * 16 {
+            dmc.clock(mapper);
+        }
+    }
+
+    #[test]
+    fn looping_sample_restarts_from_the_starting_address() {
+        let mut dmc = DmcState::new("DMC", "2A03");
+        let mut mapper = FetchLog{reads: Vec::new()};
+        dmc.interrupt_enabled = true;
+        dmc.play_sample(0xC000, 3, 54, true);
+        clock_dmc(&mut dmc, &mut mapper);
+
+        assert!(mapper.reads.len() > 6);
+        for (i, address) in mapper.reads.iter().enumerate() {
+            assert_eq!(*address, 0xC000 + (i % 3) as u16);
+        }
+        // Looping samples never raise the IRQ
+        assert!(!dmc.interrupt_flag);
+        assert!(dmc.bytes_remaining > 0);
+    }
+
+    #[test]
+    fn one_shot_sample_stops_after_its_last_byte() {
+        let mut dmc = DmcState::new("DMC", "2A03");
+        let mut mapper = FetchLog{reads: Vec::new()};
+        dmc.interrupt_enabled = true;
+        dmc.play_sample(0xC000, 3, 54, false);
+        clock_dmc(&mut dmc, &mut mapper);
+
+        assert_eq!(mapper.reads, vec!(0xC000, 0xC001, 0xC002));
+        assert_eq!(dmc.bytes_remaining, 0);
+        assert!(dmc.interrupt_flag);
+
+        // Same again with the IRQ disabled: stops just the same, but quietly
+        let mut dmc = DmcState::new("DMC", "2A03");
+        let mut mapper = FetchLog{reads: Vec::new()};
+        dmc.interrupt_enabled = false;
+        dmc.play_sample(0xC000, 3, 54, false);
+        clock_dmc(&mut dmc, &mut mapper);
+        assert_eq!(mapper.reads.len(), 3);
+        assert!(!dmc.interrupt_flag);
+    }
+
+    #[test]
+    fn delta_counter_stays_within_seven_bits() {
+        let mut dmc = DmcState::new("DMC", "2A03");
+        let mut mapper = FetchLog{reads: Vec::new()};
+        dmc.output_level = 120;
+        // Every bit of $FF asks for +2
+        dmc.play_sample(0xC000, 3, 54, true);
+        clock_dmc(&mut dmc, &mut mapper);
+        assert!(dmc.output_level <= 127);
+        assert!(dmc.output_level >= 124);
+    }
+
+    #[test]
+    fn debug_registers_expose_the_loop_and_irq_flags() {
+        let mut dmc = DmcState::new("DMC", "2A03");
+        dmc.interrupt_enabled = false;
+        dmc.play_sample(0xC123, 17, 54, true);
+        let registers = dmc.debug_registers();
+        let value = |name: &str| registers.iter().find(|(register, _)| register == name).map(|(_, value)| *value);
+        assert_eq!(value("looping"), Some(1));
+        assert_eq!(value("irq_enabled"), Some(0));
+        assert_eq!(value("irq_flag"), Some(0));
+        assert_eq!(value("starting_address"), Some(0xC123));
+        assert_eq!(value("sample_length"), Some(17));
+        assert_eq!(value("period_initial"), Some(54));
+    }
+}
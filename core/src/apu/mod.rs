@@ -1,5 +1,7 @@
 use mmc::mapper::Mapper;
+use mmc::none::NoneMapper;
 
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 
@@ -32,6 +34,32 @@ pub enum FilterType {
     FamiCom,
 }
 
+// How loud expansion audio should be relative to the 2A03. Each mapper already mixes its
+// chip at roughly the level a real Famicom would, so Famicom leaves that alone. Flat undoes
+// those differences so every chip peaks at about the level of an APU pulse, and Custom uses
+// a user-provided gain per chip.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExpansionLevels {
+    Famicom,
+    Flat,
+    Custom,
+}
+
+// Relative levels of each chip in decibels, from the NSFe defaults here:
+// https://www.nesdev.org/wiki/NSFe#mixe
+// These are approximate, and only used to flatten the mix back out.
+fn expansion_chip_db(chip_name: &str) -> f32 {
+    return match chip_name {
+        "VRC6" => 0.0,
+        "VRC7" => 11.0,
+        "FDS" => 7.0,
+        "MMC5" => 0.0,
+        "N163" => 11.0,
+        "YM2149F" => -1.3,
+        _ => 0.0
+    };
+}
+
 pub struct ApuState {
     pub current_cycle: u64,
 
@@ -69,6 +97,13 @@ pub struct ApuState {
     pub filter_type: FilterType,
     pub filter_chain: FilterChain,
     pub filter_hq: bool,
+
+    pub expansion_levels: ExpansionLevels,
+    // Keyed on chip name, only used with ExpansionLevels::Custom
+    pub expansion_gains: HashMap<String, f32>,
+    // Looking up the mapper's channels allocates, so this is cached. Call refresh_expansion_gain
+    // after changing the levels or the cartridge.
+    pub expansion_gain: f32,
 }
 
 fn generate_pulse_table() -> Vec<f32> {
@@ -183,7 +218,7 @@ fn construct_lq_filter_chain(clock_rate: f32, target_sample_rate: f32, filter_ty
 impl ApuState {
     pub fn new() -> ApuState {
         let default_samplerate = 44100;
-        let output_buffer_size = recommended_buffer_size(44100);
+        let output_buffer_size = recommended_buffer_size(default_samplerate);
 
         return ApuState {
             current_cycle: 0,
@@ -211,8 +246,12 @@ impl ApuState {
             tnd_table: generate_tnd_table(),
 
             filter_type: FilterType::FamiCom,
-            filter_chain: construct_hq_filter_chain(1789773.0, 44100.0, FilterType::FamiCom),
+            filter_chain: construct_hq_filter_chain(1789773.0, default_samplerate as f32, FilterType::FamiCom),
             filter_hq: true,
+
+            expansion_levels: ExpansionLevels::Famicom,
+            expansion_gains: HashMap::new(),
+            expansion_gain: 1.0,
         }
     }
 
@@ -227,6 +266,19 @@ impl ApuState {
         self.update_filter();
         let output_buffer_size = recommended_buffer_size(sample_rate);
         self.set_buffer_size(output_buffer_size);
+
+        // The debug buffers are recorded once per output sample, so their filters need to
+        // track the output rate as well
+        self.pulse_1.debug_filter = filters::HighPassIIR::new(sample_rate as f32, 300.0);
+        self.pulse_2.debug_filter = filters::HighPassIIR::new(sample_rate as f32, 300.0);
+        self.triangle.debug_filter = filters::HighPassIIR::new(sample_rate as f32, 300.0);
+        self.noise.debug_filter = filters::HighPassIIR::new(sample_rate as f32, 300.0);
+        self.dmc.debug_filter = filters::HighPassIIR::new(sample_rate as f32, 300.0);
+    }
+
+    // The rate, in Hz, at which output samples are generated
+    pub fn sample_rate(&self) -> u64 {
+        return self.sample_rate;
     }
 
     pub fn set_filter(&mut self, filter_type: FilterType, hq: bool) {
@@ -481,8 +533,7 @@ impl ApuState {
                     self.dmc.bytes_remaining = 0;
                 }
                 if dmc_enable && self.dmc.bytes_remaining == 0 {
-                    self.dmc.current_address = self.dmc.starting_address;
-                    self.dmc.bytes_remaining = self.dmc.sample_length;
+                    self.dmc.restart_sample();
                 }
                 self.dmc.interrupt_flag = false;
             }
@@ -508,6 +559,10 @@ impl ApuState {
 
     // Note: this uses CPU clocks, NOT APU clocks! It's simpler to represent the half-clock
     // updates this way. Documentation: https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+    //
+    // Both modes clock 4 quarter frames and 2 half frames per sequence, but the 5-step sequence
+    // is longer (37282 cycles vs. 29830) and never raises the frame IRQ. Writing $4017 with the
+    // 5-step bit set also clocks a quarter and half frame right away.
 
     pub fn clock_frame_sequencer(&mut self) {
         if self.frame_reset_delay > 0 {
@@ -592,6 +647,64 @@ impl ApuState {
         self.half_frame_counter += 1;
     }
 
+    // At the NTSC CPU clock: ~240Hz in 4-step mode, ~192Hz in 5-step mode
+    pub fn quarter_frame_rate_hz(&self) -> f64 {
+        let sequence_length = if self.frame_sequencer_mode == 0 {29830.0} else {37282.0};
+        return 1789773.0 * 4.0 / sequence_length;
+    }
+
+    // ~120Hz in 4-step mode, ~96Hz in 5-step mode
+    pub fn half_frame_rate_hz(&self) -> f64 {
+        return self.quarter_frame_rate_hz() / 2.0;
+    }
+
+    // Mappers mix all of their expansion audio into a single sample, so when several chips
+    // are present (NSF files, mostly) they have to share one gain. Use the largest of them:
+    // that way the quietest chip is always brought up to level, at the cost of the others
+    // running a little hot.
+    pub fn compute_expansion_gain(&self, mapper: &dyn Mapper) -> f32 {
+        if self.expansion_levels == ExpansionLevels::Famicom {
+            return 1.0;
+        }
+
+        let mut chip_names: Vec<String> = Vec::new();
+        let mut n163_channels = 0;
+        for channel in mapper.channels() {
+            let chip_name = channel.chip();
+            if chip_name == "N163" {
+                n163_channels += 1;
+            }
+            if !chip_names.contains(&chip_name) {
+                chip_names.push(chip_name);
+            }
+        }
+        if chip_names.len() == 0 {
+            return 1.0;
+        }
+
+        let mut largest_gain: f32 = 0.0;
+        for chip_name in chip_names.iter() {
+            let gain = match self.expansion_levels {
+                ExpansionLevels::Flat => {
+                    let mut gain = f32::powf(10.0, -expansion_chip_db(chip_name) / 20.0);
+                    if chip_name == "N163" {
+                        // The N163 time-multiplexes its channels, so each one gets quieter as
+                        // more are enabled. Undo that, so a single channel is as loud as a pulse.
+                        gain *= n163_channels.max(1) as f32;
+                    }
+                    gain
+                },
+                _ => *self.expansion_gains.get(chip_name).unwrap_or(&1.0)
+            };
+            largest_gain = largest_gain.max(gain);
+        }
+        return largest_gain;
+    }
+
+    pub fn refresh_expansion_gain(&mut self, mapper: &dyn Mapper) {
+        self.expansion_gain = self.compute_expansion_gain(mapper);
+    }
+
     pub fn clock_apu(&mut self, mapper: &mut dyn Mapper) {
         self.clock_frame_sequencer();
 
@@ -631,7 +744,11 @@ impl ApuState {
         let tnd_output = self.tnd_table[full_tnd_index(tri_output as usize, noise_output as usize, dmc_output as usize)];
 
         let current_2a03_sample = (pulse_output - 0.5) + (tnd_output - 0.5);
-        let current_dac_sample = mapper.mix_expansion_audio(current_2a03_sample) as f32;
+        let mut current_dac_sample = mapper.mix_expansion_audio(current_2a03_sample) as f32;
+        if self.expansion_gain != 1.0 {
+            let expansion_sample = current_dac_sample - current_2a03_sample;
+            current_dac_sample = current_2a03_sample + expansion_sample * self.expansion_gain;
+        }
 
         // apply filters NEW
         self.filter_chain.consume(current_dac_sample, 1.0 / (self.cpu_clock_rate as f32));
@@ -663,6 +780,21 @@ impl ApuState {
         self.current_cycle += 1;
     }
 
+    // Advances the APU by a fixed number of CPU cycles without a cartridge attached. DMC
+    // fetches read from an empty mapper, so this is mostly useful for driving the other
+    // channels from a known register state, ie: for tooling and reproducible debugging.
+    pub fn clock_cycles(&mut self, cycles: u64) {
+        let mut mapper = NoneMapper::new();
+        for _ in 0 .. cycles {
+            self.clock_apu(&mut mapper);
+        }
+    }
+
+    // Advances the APU by one NTSC frame's worth of CPU cycles (roughly 29780)
+    pub fn clock_frame(&mut self) {
+        self.clock_cycles(29780);
+    }
+
     pub fn samples_queued(&self) -> usize {
         let mut sample_count = self.staging_buffer.index();
         if self.buffer_full {
@@ -691,6 +823,7 @@ impl ApuState {
         let _ = file.write_all(&buffer);
     }
 
+    // Returns all pending output samples, mono, at sample_rate() Hz
     pub fn consume_samples(&mut self) -> Vec<i16> {
         let mut output_buffer = vec!(0i16; 0);
         if self.buffer_full {
@@ -703,6 +836,24 @@ impl ApuState {
         return output_buffer;
     }
 
+    // Same as consume_samples, but scaled to -1.0 - 1.0 and appended to an existing buffer,
+    // which skips the intermediate i16 Vec on the audio path
+    pub fn consume_samples_f32_into(&mut self, output: &mut Vec<f32>) {
+        if self.buffer_full {
+            output.extend(self.output_buffer.iter().map(|&sample| sample as f32 / 32767.0));
+            self.buffer_full = false;
+        }
+        let staging_index = self.staging_buffer.index();
+        output.extend(self.staging_buffer.buffer()[0 .. staging_index].iter().map(|&sample| sample as f32 / 32767.0));
+        self.staging_buffer.reset();
+    }
+
+    pub fn consume_samples_f32(&mut self) -> Vec<f32> {
+        let mut output_buffer: Vec<f32> = Vec::new();
+        self.consume_samples_f32_into(&mut output_buffer);
+        return output_buffer;
+    }
+
     pub fn irq_signal(&self) -> bool {
         return self.frame_interrupt || self.dmc.interrupt_flag;
     }
@@ -767,3 +918,298 @@ impl AudioChannelState for ApuState {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_shift_register_long_mode() {
+        let mut apu = ApuState::new();
+        // Mode 0, period index 0: the register shifts once every 4 CPU cycles
+        apu.write_register(0x400E, 0x00);
+        apu.clock_cycles(4 * 20);
+        assert_eq!(apu.noise.shift_register, 0x0600);
+    }
+
+    #[test]
+    fn noise_shift_register_short_mode() {
+        let mut apu = ApuState::new();
+        apu.write_register(0x400E, 0x80);
+        apu.clock_cycles(4 * 20);
+        assert_eq!(apu.noise.shift_register, 0x2410);
+        // The short sequence repeats after 93 steps, so we should land right back here
+        apu.clock_cycles(4 * 93);
+        assert_eq!(apu.noise.shift_register, 0x2410);
+    }
+
+    #[test]
+    fn noise_shift_register_full_period() {
+        let mut apu = ApuState::new();
+        apu.write_register(0x400E, 0x00);
+        apu.clock_cycles(4 * 32767);
+        assert_eq!(apu.noise.shift_register, 1);
+    }
+
+    #[test]
+    fn sample_rate_defaults_to_44100() {
+        let apu = ApuState::new();
+        assert_eq!(apu.sample_rate(), 44100);
+    }
+
+    #[test]
+    fn changing_sample_rate_changes_output_rate() {
+        let mut apu = ApuState::new();
+        apu.set_sample_rate(48000);
+        assert_eq!(apu.sample_rate(), 48000);
+        // A tenth of a second of CPU time should produce a tenth of a second of samples
+        apu.clock_cycles(apu.cpu_clock_rate / 10);
+        assert!((apu.generated_samples as i64 - 4800).abs() <= 1);
+        // And the buffer still has room for a whole frame of them
+        assert!(apu.output_buffer.len() >= 48000 / 60);
+    }
+
+    #[test]
+    fn clock_frame_advances_one_ntsc_frame() {
+        let mut apu = ApuState::new();
+        apu.clock_frame();
+        apu.clock_frame();
+        assert_eq!(apu.current_cycle, 2 * 29780);
+    }
+
+    #[test]
+    fn dmc_rate_index_maps_to_bit_rate() {
+        let mut apu = ApuState::new();
+        // Rate index 15 is the fastest: 54 CPU cycles per bit
+        apu.write_register(0x4010, 0x0F);
+        assert!((apu.dmc.bit_rate() - 1_789_773.0 / 54.0).abs() < 0.5);
+        // Rate index 0 is the slowest: 428 CPU cycles per bit
+        apu.write_register(0x4010, 0x00);
+        assert!((apu.dmc.bit_rate() - 1_789_773.0 / 428.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn looping_dmc_sample_estimates_its_pitch() {
+        let mut apu = ApuState::new();
+        // Looping, rate index 15, and a 17 byte (136 bit) sample
+        apu.write_register(0x4010, 0x4F);
+        apu.write_register(0x4013, 0x01);
+        let expected = 1_789_773.0 / 54.0 / 136.0;
+        let estimate = apu.dmc.estimated_frequency().unwrap();
+        assert!((estimate - expected).abs() < 0.01, "{} vs {}", estimate, expected);
+    }
+
+    #[test]
+    fn one_shot_dmc_sample_has_no_pitch() {
+        let mut apu = ApuState::new();
+        apu.write_register(0x4010, 0x0F);
+        apu.write_register(0x4013, 0x01);
+        assert!(apu.dmc.estimated_frequency().is_none());
+    }
+
+    // An expansion chip with no real registers: it reports `channel_count` channels from
+    // `chip`, and adds a square wave of the given height to the 2A03's output
+    struct FakeChannel {
+        chip: String,
+        buffer: RingBuffer,
+    }
+
+    impl AudioChannelState for FakeChannel {
+        fn name(&self) -> String {return "Fake".to_string();}
+        fn chip(&self) -> String {return self.chip.clone();}
+        fn sample_buffer(&self) -> &RingBuffer {return &self.buffer;}
+        fn edge_buffer(&self) -> &RingBuffer {return &self.buffer;}
+        fn record_current_output(&mut self) {}
+        fn muted(&self) -> bool {return false;}
+        fn mute(&mut self) {}
+        fn unmute(&mut self) {}
+    }
+
+    struct FakeExpansion {
+        channels: Vec<FakeChannel>,
+        level: f32,
+        cycle: u32,
+    }
+
+    impl FakeExpansion {
+        fn new(chips: &[(&str, usize)], level: f32) -> FakeExpansion {
+            let mut channels = Vec::new();
+            for &(chip, channel_count) in chips.iter() {
+                for _ in 0 .. channel_count {
+                    channels.push(FakeChannel{chip: chip.to_string(), buffer: RingBuffer::new(16)});
+                }
+            }
+            return FakeExpansion{channels: channels, level: level, cycle: 0};
+        }
+    }
+
+    impl Mapper for FakeExpansion {
+        fn mirroring(&self) -> ::mmc::mapper::Mirroring {return ::mmc::mapper::Mirroring::Horizontal;}
+        fn debug_read_cpu(&self, _: u16) -> Option<u8> {return None;}
+        fn debug_read_ppu(&self, _: u16) -> Option<u8> {return None;}
+        fn write_cpu(&mut self, _: u16, _: u8) {}
+        fn write_ppu(&mut self, _: u16, _: u8) {}
+        fn clock_cpu(&mut self) {self.cycle += 1;}
+        // Roughly 900 Hz, well above the high pass filters
+        fn mix_expansion_audio(&self, nes_sample: f32) -> f32 {
+            let high = (self.cycle / 1000) % 2 == 0;
+            return nes_sample + if high {self.level} else {0.0};
+        }
+        fn channels(&self) -> Vec<&dyn AudioChannelState> {
+            return self.channels.iter().map(|channel| channel as &dyn AudioChannelState).collect();
+        }
+    }
+
+    fn expansion_rms(levels: ExpansionLevels, mapper: &mut FakeExpansion) -> f32 {
+        let mut apu = ApuState::new();
+        apu.expansion_levels = levels;
+        apu.refresh_expansion_gain(mapper);
+        // A few frames, so the filters settle after the 2A03's initial step
+        for _ in 0 .. 29780 * 10 {
+            mapper.clock_cpu();
+            apu.clock_apu(mapper);
+        }
+        // The 2A03 idles well below zero, so measure around the mean rather than around silence
+        let samples = apu.staging_buffer.last_n(512);
+        let mean = samples.iter().map(|&sample| sample as f64).sum::<f64>() / samples.len() as f64;
+        let sum_of_squares: f64 = samples.iter().map(|&sample| (sample as f64 - mean) * (sample as f64 - mean)).sum();
+        return (sum_of_squares / samples.len() as f64).sqrt() as f32;
+    }
+
+    #[test]
+    fn flat_levels_bring_chips_at_full_output_to_comparable_levels() {
+        // The N163 is documented as 11 dB hotter than the VRC6
+        let mut vrc6 = FakeExpansion::new(&[("VRC6", 3)], 0.02);
+        let mut n163 = FakeExpansion::new(&[("N163", 1)], 0.02 * f32::powf(10.0, 11.0 / 20.0));
+        let vrc6_rms = expansion_rms(ExpansionLevels::Flat, &mut vrc6);
+        let n163_rms = expansion_rms(ExpansionLevels::Flat, &mut n163);
+        assert!(vrc6_rms > 0.0);
+        assert!((n163_rms / vrc6_rms - 1.0).abs() < 0.05, "{} vs {}", n163_rms, vrc6_rms);
+
+        // Left alone, they stay as far apart as the hardware would have them
+        let vrc6_rms = expansion_rms(ExpansionLevels::Famicom, &mut vrc6);
+        let n163_rms = expansion_rms(ExpansionLevels::Famicom, &mut n163);
+        assert!(n163_rms / vrc6_rms > 3.0, "{} vs {}", n163_rms, vrc6_rms);
+    }
+
+    #[test]
+    fn flat_n163_gain_scales_with_its_channel_count() {
+        let apu = ApuState {expansion_levels: ExpansionLevels::Flat, .. ApuState::new()};
+        let one_channel = apu.compute_expansion_gain(&FakeExpansion::new(&[("N163", 1)], 0.0));
+        let four_channels = apu.compute_expansion_gain(&FakeExpansion::new(&[("N163", 4)], 0.0));
+        assert!((four_channels / one_channel - 4.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn several_chips_share_the_largest_gain() {
+        let mut apu = ApuState::new();
+        apu.expansion_levels = ExpansionLevels::Custom;
+        apu.expansion_gains.insert("VRC6".to_string(), 0.5);
+        apu.expansion_gains.insert("FDS".to_string(), 2.0);
+        let mapper = FakeExpansion::new(&[("VRC6", 3), ("FDS", 1)], 0.0);
+        assert_eq!(apu.compute_expansion_gain(&mapper), 2.0);
+    }
+
+    #[test]
+    fn expansion_gain_is_cached_until_refreshed() {
+        let mut apu = ApuState::new();
+        let mut mapper = FakeExpansion::new(&[("VRC6", 3)], 0.0);
+        apu.expansion_levels = ExpansionLevels::Custom;
+        apu.expansion_gains.insert("VRC6".to_string(), 2.0);
+        for _ in 0 .. 1000 {
+            apu.clock_apu(&mut mapper);
+        }
+        assert_eq!(apu.expansion_gain, 1.0);
+        apu.refresh_expansion_gain(&mapper);
+        assert_eq!(apu.expansion_gain, 2.0);
+    }
+
+    fn playing_pulse() -> ApuState {
+        let mut apu = ApuState::new();
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0x00);
+        return apu;
+    }
+
+    #[test]
+    fn f32_samples_match_the_i16_samples() {
+        let mut i16_apu = playing_pulse();
+        let mut f32_apu = playing_pulse();
+        // Enough to fill the output buffer once and leave some in staging
+        i16_apu.clock_cycles(29780 * 2);
+        f32_apu.clock_cycles(29780 * 2);
+        assert!(i16_apu.buffer_full);
+
+        let i16_samples = i16_apu.consume_samples();
+        let f32_samples = f32_apu.consume_samples_f32();
+        assert!(i16_samples.len() > i16_apu.output_buffer.len());
+        assert_eq!(i16_samples.len(), f32_samples.len());
+        assert!(i16_samples.iter().any(|&sample| sample != i16_samples[0]));
+        for (&a, &b) in i16_samples.iter().zip(f32_samples.iter()) {
+            assert_eq!(a as f32 / 32767.0, b);
+        }
+        assert_eq!(f32_apu.samples_queued(), 0);
+    }
+
+    #[test]
+    fn f32_samples_append_to_the_given_buffer() {
+        let mut apu = playing_pulse();
+        apu.clock_cycles(10000);
+        let queued = apu.samples_queued();
+        let mut output = vec!(2.0f32; 3);
+        apu.consume_samples_f32_into(&mut output);
+        assert_eq!(output.len(), 3 + queued);
+        assert_eq!(output[0 .. 3], [2.0, 2.0, 2.0]);
+    }
+
+    // CPU cycles at which the frame counter clocked a quarter frame, after writing $4017
+    fn quarter_frame_cycles(frame_counter: u8, cycles: u64) -> Vec<u64> {
+        let mut apu = ApuState::new();
+        apu.write_register(0x4017, frame_counter);
+        let mut last_count = apu.quarter_frame_counter;
+        let mut clocked_at = Vec::new();
+        for cycle in 0 .. cycles {
+            apu.clock_cycles(1);
+            if apu.quarter_frame_counter != last_count {
+                clocked_at.push(cycle);
+                last_count = apu.quarter_frame_counter;
+            }
+        }
+        return clocked_at;
+    }
+
+    #[test]
+    fn five_step_mode_stretches_the_quarter_frame_interval() {
+        // Skip the extra quarter frame the 5-step write clocks right away
+        let four_step = quarter_frame_cycles(0x40, 80000);
+        let five_step: Vec<u64> = quarter_frame_cycles(0xC0, 80000).into_iter().skip(1).collect();
+
+        // Four quarter frames per sequence either way, but the 5-step sequence is longer
+        assert_eq!(four_step[4] - four_step[0], 29830);
+        assert_eq!(five_step[4] - five_step[0], 37282);
+        // The extra 7452 cycles all go in the gap before the last quarter frame
+        assert_eq!(five_step[3] - five_step[2], (four_step[3] - four_step[2]) + 7452);
+
+        let mut apu = ApuState::new();
+        let four_step_rate = apu.quarter_frame_rate_hz();
+        apu.write_register(0x4017, 0x80);
+        assert!((four_step_rate - 1789773.0 * 4.0 / 29830.0).abs() < 0.001);
+        assert!((apu.quarter_frame_rate_hz() - 1789773.0 * 4.0 / 37282.0).abs() < 0.001);
+        assert!((apu.half_frame_rate_hz() - apu.quarter_frame_rate_hz() / 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn five_step_mode_never_raises_the_frame_irq() {
+        let mut apu = ApuState::new();
+        apu.write_register(0x4017, 0x00);
+        apu.clock_cycles(30000);
+        assert!(apu.frame_interrupt);
+
+        let mut apu = ApuState::new();
+        apu.write_register(0x4017, 0x80);
+        apu.clock_cycles(80000);
+        assert!(!apu.frame_interrupt);
+    }
+}
@@ -46,7 +46,8 @@ impl NoiseChannelState {
             envelope: VolumeEnvelopeState::new(),
             length_counter: LengthCounterState::new(),
             mode: 0,
-            period_initial: 0,
+            // Power on selects period index 0, and clocking with a period of 0 would underflow
+            period_initial: 4,
             period_current: 0,
 
             // Actually a 15-bit register
@@ -163,4 +164,41 @@ impl AudioChannelState for NoiseChannelState {
     fn timbre(&self) -> Option<Timbre> {
         return Some(Timbre::LsfrMode{index: self.mode as usize, max: 1});
     }
-}
\ No newline at end of file
+
+    fn debug_registers(&self) -> Vec<(String, u32)> {
+        return vec!(
+            ("mode".to_string(), self.mode as u32),
+            ("period_initial".to_string(), self.period_initial as u32),
+            ("period_current".to_string(), self.period_current as u32),
+            ("shift_register".to_string(), self.shift_register as u32),
+            ("envelope_volume".to_string(), self.envelope.current_volume() as u32),
+            ("length".to_string(), self.length_counter.length as u32),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_registers_report_the_live_noise_state() {
+        let mut noise = NoiseChannelState::new("Noise", "2A03");
+        noise.mode = 1;
+        noise.period_initial = 202;
+        noise.period_current = 17;
+        noise.shift_register = 0x4321;
+        noise.envelope.volume_register = 9;
+        noise.length_counter.length = 30;
+
+        let registers = noise.debug_registers();
+        let names: Vec<&str> = registers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!("mode", "period_initial", "period_current", "shift_register", "envelope_volume", "length"));
+        let values: Vec<u32> = registers.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, vec!(1, 202, 17, 0x4321, 9, 30));
+
+        // Values are read live, not cached
+        noise.clock();
+        assert_eq!(noise.debug_registers()[2], ("period_current".to_string(), 16));
+    }
+}
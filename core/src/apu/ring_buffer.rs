@@ -33,4 +33,80 @@ impl RingBuffer {
     pub fn reset(&mut self) {
         self.index = 0;
     }
-}
\ No newline at end of file
+
+    // Steps backwards from the given index by some distance, wrapping around the start
+    // of the buffer. Use this instead of subtracting indices directly, which will underflow.
+    pub fn index_before(&self, index: usize, distance: usize) -> usize {
+        let length = self.buffer.len();
+        return (index % length + length - (distance % length)) % length;
+    }
+
+    // Iterates over every sample in the buffer in chronological order, beginning at the
+    // given index and wrapping around the end of the buffer back to just before it.
+    pub fn iter_from(&self, start: usize) -> impl Iterator<Item = &i16> {
+        let start = start % self.buffer.len();
+        return self.buffer[start ..].iter().chain(self.buffer[.. start].iter());
+    }
+
+    // Returns the most recently pushed n samples, oldest first
+    pub fn last_n(&self, n: usize) -> Vec<i16> {
+        let count = n.min(self.buffer.len());
+        let start = self.index_before(self.index, count);
+        return self.iter_from(start).take(count).cloned().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pushes 1, 2, 3, ... so the newest sample is always the largest
+    fn filled(length: usize, pushes: usize) -> RingBuffer {
+        let mut ring = RingBuffer::new(length);
+        for i in 1 ..= pushes {
+            ring.push(i as i16);
+        }
+        return ring;
+    }
+
+    #[test]
+    fn index_before_wraps_around_the_start() {
+        let ring = filled(8, 3);
+        assert_eq!(ring.index_before(2, 5), 5);
+        assert_eq!(ring.index_before(0, 1), 7);
+        assert_eq!(ring.index_before(3, 19), 0);
+    }
+
+    #[test]
+    fn iter_from_crosses_the_wrap_boundary_in_order() {
+        // 10 pushes into 8 slots: the oldest surviving sample (3) sits at the write index
+        let ring = filled(8, 10);
+        assert_eq!(ring.index(), 2);
+        let samples: Vec<i16> = ring.iter_from(ring.index()).cloned().collect();
+        assert_eq!(samples, vec!(3, 4, 5, 6, 7, 8, 9, 10));
+    }
+
+    #[test]
+    fn iter_from_accepts_out_of_range_starts() {
+        let ring = filled(4, 4);
+        let samples: Vec<i16> = ring.iter_from(6).cloned().collect();
+        assert_eq!(samples, vec!(3, 4, 1, 2));
+    }
+
+    #[test]
+    fn last_n_returns_newest_samples_oldest_first() {
+        let ring = filled(8, 10);
+        assert_eq!(ring.last_n(4), vec!(7, 8, 9, 10));
+        // Spans the wrap: indices 7, 0 and 1
+        assert_eq!(ring.last_n(3), vec!(8, 9, 10));
+    }
+
+    #[test]
+    fn last_n_is_clamped_to_the_buffer_length() {
+        let ring = filled(8, 10);
+        let samples = ring.last_n(100);
+        assert_eq!(samples.len(), 8);
+        assert_eq!(samples, vec!(3, 4, 5, 6, 7, 8, 9, 10));
+        assert_eq!(ring.last_n(0).len(), 0);
+    }
+}
@@ -0,0 +1,55 @@
+// Times the core running headless, with no audio or video devices involved. Shared by the
+// emulation bench and the CLI's benchmark command, so both report the same numbers.
+
+use nes::NesState;
+
+use std::fmt;
+use std::time::Instant;
+
+pub struct BenchmarkResult {
+    pub frames: u64,
+    pub seconds: f64,
+    pub fps: f64,
+}
+
+impl fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "ran {} frames in {:.3}s, {:.2} FPS ({:.2}x realtime)", self.frames, self.seconds, self.fps, self.fps / 60.0988);
+    }
+}
+
+pub fn run_frames(nes: &mut NesState, frames: u64) -> BenchmarkResult {
+    let start = Instant::now();
+    for _ in 0 .. frames {
+        nes.run_until_vblank();
+        // Nobody is listening, but keep the APU from sitting on a full buffer
+        nes.apu.buffer_full = false;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    return BenchmarkResult {
+        frames: frames,
+        seconds: seconds,
+        fps: frames as f64 / seconds,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::mapper_from_file;
+
+    #[test]
+    fn runs_exactly_the_requested_frames() {
+        let mut rom = vec!(0x4Eu8, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        rom.extend(vec!(0u8; 0x4000 + 0x2000));
+        let mut nes = NesState::new(mapper_from_file(&rom).ok().unwrap());
+        nes.power_on();
+        // Power on lands partway through a frame, so line up on vblank first
+        nes.run_until_vblank();
+        let starting_frame = nes.ppu.current_frame;
+        let result = run_frames(&mut nes, 5);
+        assert_eq!(nes.ppu.current_frame, starting_frame + 5);
+        assert_eq!(result.frames, 5);
+        assert!(result.fps > 0.0 && result.fps.is_finite());
+    }
+}
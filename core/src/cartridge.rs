@@ -20,35 +20,130 @@ use mmc::vrc6::Vrc6;
 use mmc::vrc7::Vrc7;
 
 use ines::INesCartridge;
+use ines::INesError;
 use nsf::NsfFile;
 use fds::FdsFile;
 
+use std::fmt;
 use std::io::Read;
 
-fn mapper_from_ines(ines: INesCartridge) -> Result<Box<dyn Mapper>, String> {
+// Names for the mappers people are most likely to run into, supported or not, so an
+// unsupported cartridge can say what it actually needs
+const MAPPER_NAMES: &[(u16, &str)] = &[
+    (0, "NROM"),
+    (1, "MMC1"),
+    (2, "UxROM"),
+    (3, "CNROM"),
+    (4, "MMC3"),
+    (5, "MMC5"),
+    (7, "AxROM"),
+    (9, "MMC2 (PxROM)"),
+    (10, "MMC4 (FxROM)"),
+    (11, "Color Dreams"),
+    (13, "CPROM"),
+    (16, "Bandai FCG"),
+    (18, "Jaleco SS 88006"),
+    (19, "Namco 163"),
+    (21, "Konami VRC4a/c"),
+    (22, "Konami VRC2a"),
+    (23, "Konami VRC2b/VRC4e"),
+    (24, "Konami VRC6a"),
+    (25, "Konami VRC4b/d"),
+    (26, "Konami VRC6b"),
+    (28, "Action 53"),
+    (30, "UNROM 512"),
+    (31, "NSF subset (iNES 31)"),
+    (32, "Irem G-101"),
+    (33, "Taito TC0190"),
+    (34, "BNROM / NINA-001"),
+    (48, "Taito TC0690"),
+    (64, "Tengen RAMBO-1"),
+    (65, "Irem H3001"),
+    (66, "GxROM"),
+    (67, "Sunsoft-3"),
+    (68, "Sunsoft-4"),
+    (69, "Sunsoft FME-7"),
+    (71, "Camerica / Codemasters"),
+    (73, "Konami VRC3"),
+    (75, "Konami VRC1"),
+    (79, "NINA-003 / NINA-006"),
+    (85, "Konami VRC7"),
+    (87, "Jaleco J87"),
+    (118, "TxSROM"),
+    (119, "TQROM"),
+    (206, "Namco 118 / DxROM"),
+    (210, "Namco 175 / 340"),
+    (682, "Rainbow"),
+];
+
+pub fn mapper_name(mapper_number: u16) -> Option<&'static str> {
+    return MAPPER_NAMES.iter().find(|(number, _)| *number == mapper_number).map(|(_, name)| *name);
+}
+
+type MapperConstructor = fn(INesCartridge) -> Result<Box<dyn Mapper>, String>;
+
+// Every iNES mapper number mapper_from_ines knows how to load
+const SUPPORTED_MAPPERS: &[(u16, MapperConstructor)] = &[
+    (0, |ines| Ok(Box::new(Nrom::from_ines(ines)?))),
+    (1, |ines| Ok(Box::new(Mmc1::from_ines(ines)?))),
+    (2, |ines| Ok(Box::new(UxRom::from_ines(ines)?))),
+    (3, |ines| Ok(Box::new(CnRom::from_ines(ines)?))),
+    (4, |ines| Ok(Box::new(Mmc3::from_ines(ines)?))),
+    (5, |ines| Ok(Box::new(Mmc5::from_ines(ines)?))),
+    (7, |ines| Ok(Box::new(AxRom::from_ines(ines)?))),
+    (9, |ines| Ok(Box::new(PxRom::from_ines(ines)?))),
+    (19, |ines| Ok(Box::new(Namco163::from_ines(ines)?))),
+    (24, |ines| Ok(Box::new(Vrc6::from_ines(ines)?))),
+    (26, |ines| Ok(Box::new(Vrc6::from_ines(ines)?))),
+    (28, |ines| Ok(Box::new(Action53::from_ines(ines)?))),
+    (31, |ines| Ok(Box::new(INes31::from_ines(ines)?))),
+    (34, |ines| Ok(Box::new(BnRom::from_ines(ines)?))),
+    (66, |ines| Ok(Box::new(GxRom::from_ines(ines)?))),
+    (69, |ines| Ok(Box::new(Fme7::from_ines(ines)?))),
+    (85, |ines| Ok(Box::new(Vrc7::from_ines(ines)?))),
+    (682, |ines| Ok(Box::new(Rainbow::from_ines(ines)?))),
+];
+
+// Every supported mapper number, with its name
+pub fn supported_mappers() -> Vec<(u16, &'static str)> {
+    return SUPPORTED_MAPPERS.iter().map(|(number, _)| (*number, mapper_name(*number).unwrap_or("Unknown"))).collect();
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    // The file is a valid iNES cartridge, we just can't run it (yet)
+    UnsupportedMapper{mapper_number: u16, mapper_name: Option<&'static str>},
+    // The header declares more data than the file holds, in bytes past the header
+    TruncatedRom{expected: usize, found: usize},
+    // Anything else: unreadable, not a format we know, or a mapper rejected it
+    InvalidFile(String),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::UnsupportedMapper{mapper_number, mapper_name: Some(name)} => write!(f, "Mapper {} ({}) isn't supported yet", mapper_number, name),
+            CartridgeError::UnsupportedMapper{mapper_number, mapper_name: None} => write!(f, "Mapper {} isn't supported yet", mapper_number),
+            CartridgeError::TruncatedRom{expected, found} => write!(f, "This ROM is truncated: its header declares {} bytes of data, but the file only has {}", expected, found),
+            CartridgeError::InvalidFile(why) => write!(f, "{}", why),
+        }
+    }
+}
+
+// So the mappers' own String errors still work with ?
+impl From<String> for CartridgeError {
+    fn from(why: String) -> CartridgeError {
+        return CartridgeError::InvalidFile(why);
+    }
+}
+
+fn mapper_from_ines(ines: INesCartridge) -> Result<Box<dyn Mapper>, CartridgeError> {
     let mapper_number = ines.header.mapper_number();
 
-    let mapper: Box<dyn Mapper> = match mapper_number {
-        0 => Box::new(Nrom::from_ines(ines)?),
-        1 => Box::new(Mmc1::from_ines(ines)?),
-        2 => Box::new(UxRom::from_ines(ines)?),
-        3 => Box::new(CnRom::from_ines(ines)?),
-        4 => Box::new(Mmc3::from_ines(ines)?),
-        5 => Box::new(Mmc5::from_ines(ines)?),
-        7 => Box::new(AxRom::from_ines(ines)?),
-        9 => Box::new(PxRom::from_ines(ines)?),
-        19 => Box::new(Namco163::from_ines(ines)?),
-        24 => Box::new(Vrc6::from_ines(ines)?),
-        26 => Box::new(Vrc6::from_ines(ines)?),
-        28 => Box::new(Action53::from_ines(ines)?),
-        31 => Box::new(INes31::from_ines(ines)?),
-        34 => Box::new(BnRom::from_ines(ines)?),
-        66 => Box::new(GxRom::from_ines(ines)?),
-        69 => Box::new(Fme7::from_ines(ines)?),
-        85 => Box::new(Vrc7::from_ines(ines)?),
-        682 => Box::new(Rainbow::from_ines(ines)?),
-        _ => {
-            return Err(format!("Unsupported iNES mapper: {}", ines.header.mapper_number()));
+    let mapper = match SUPPORTED_MAPPERS.iter().find(|(number, _)| *number == mapper_number) {
+        Some((_, constructor)) => constructor(ines)?,
+        None => {
+            return Err(CartridgeError::UnsupportedMapper{mapper_number: mapper_number, mapper_name: mapper_name(mapper_number)});
         }
     };
 
@@ -57,18 +152,20 @@ fn mapper_from_ines(ines: INesCartridge) -> Result<Box<dyn Mapper>, String> {
     return Ok(mapper);
 }
 
-pub fn mapper_from_reader(file_reader: &mut dyn Read) -> Result<Box<dyn Mapper>, String> {
+pub fn mapper_from_reader(file_reader: &mut dyn Read) -> Result<Box<dyn Mapper>, CartridgeError> {
     let mut entire_file = Vec::new();
     match file_reader.read_to_end(&mut entire_file) {
         Ok(_) => {/* proceed normally */},
         Err(e) => {
-            return Err(format!("Failed to read any data at all, giving up.{}\n", e));
+            return Err(CartridgeError::InvalidFile(format!("Failed to read any data at all, giving up.{}\n", e)));
         }
     }
 
     let mut errors = String::new();
     match INesCartridge::from_reader(&mut entire_file.as_slice()) {
         Ok(ines) => {return mapper_from_ines(ines);},
+        // The header was valid, so there's no point trying the other formats
+        Err(INesError::Truncated{expected, found}) => {return Err(CartridgeError::TruncatedRom{expected: expected, found: found});},
         Err(e) => {errors += format!("ines: {}\n", e).as_str()}
     }
 
@@ -82,10 +179,72 @@ pub fn mapper_from_reader(file_reader: &mut dyn Read) -> Result<Box<dyn Mapper>,
         Err(e) => {errors += format!("fds: {}\n", e).as_str()}
     }
 
-    return Err(format!("Unable to open file as any known type, giving up.\n{}", errors));
+    return Err(CartridgeError::InvalidFile(format!("Unable to open file as any known type, giving up.\n{}", errors)));
 }
 
-pub fn mapper_from_file(file_data: &[u8]) -> Result<Box<dyn Mapper>, String> {
+pub fn mapper_from_file(file_data: &[u8]) -> Result<Box<dyn Mapper>, CartridgeError> {
     let mut file_reader = file_data;
     return mapper_from_reader(&mut file_reader);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 16k PRG / 8k CHR iNES 1.0 image for any 8-bit mapper number
+    fn ines_image(mapper_number: u8) -> Vec<u8> {
+        let mut image = vec!(0x4E, 0x45, 0x53, 0x1A, 1, 1, (mapper_number & 0x0F) << 4, mapper_number & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0);
+        image.extend(vec!(0u8; 0x4000 + 0x2000));
+        return image;
+    }
+
+    #[test]
+    fn unsupported_mappers_report_their_number_and_name() {
+        match mapper_from_file(&ines_image(16)) {
+            Err(CartridgeError::UnsupportedMapper{mapper_number, mapper_name}) => {
+                assert_eq!(mapper_number, 16);
+                assert_eq!(mapper_name, Some("Bandai FCG"));
+            },
+            Err(other) => panic!("Expected an unsupported mapper, got {}", other),
+            Ok(_) => panic!("Mapper 16 shouldn't load"),
+        }
+        let message = mapper_from_file(&ines_image(16)).err().unwrap().to_string();
+        assert_eq!(message, "Mapper 16 (Bandai FCG) isn't supported yet");
+        match mapper_from_file(&ines_image(200)) {
+            Err(CartridgeError::UnsupportedMapper{mapper_number: 200, mapper_name: None}) => {},
+            _ => panic!("Mapper 200 should be unsupported and unnamed"),
+        }
+    }
+
+    #[test]
+    fn every_supported_mapper_loads() {
+        let supported = supported_mappers();
+        assert!(supported.contains(&(0, "NROM")));
+        assert!(supported.contains(&(69, "Sunsoft FME-7")));
+        for (mapper_number, name) in supported {
+            assert_ne!(name, "Unknown");
+            // Mappers past 255 need an NES 2.0 header, which this image doesn't have
+            if mapper_number < 256 {
+                assert!(mapper_from_file(&ines_image(mapper_number as u8)).is_ok(), "Mapper {} ({}) didn't load", mapper_number, name);
+            }
+        }
+    }
+
+    #[test]
+    fn fme7_and_rainbow_expose_chr_in_4k_banks() {
+        let mut fme7_image = ines_image(69);
+        // Mark the start of the second 4k bank of CHR
+        fme7_image[16 + 0x4000 + 0x1000] = 0xAB;
+        // Rainbow is mapper 682, so it needs an NES 2.0 header
+        let mut rainbow_image = fme7_image.clone();
+        rainbow_image[6] = 0xA0;
+        rainbow_image[7] = 0xA8;
+        rainbow_image[8] = 0x02;
+        for image in [fme7_image, rainbow_image].iter() {
+            let mapper = mapper_from_file(image).ok().unwrap();
+            assert_eq!(mapper.chr_bank_count(), 2);
+            assert_eq!(mapper.read_chr_bank(1, 0), 0xAB);
+            assert_eq!(mapper.read_chr_bank(0, 0), 0);
+        }
+    }
+}
@@ -7,6 +7,7 @@ use std::io::Read;
 use std::error::Error;
 use std::fmt;
 
+use logging;
 use mmc::mapper::Mirroring;
 use memoryblock::MemoryBlock;
 use memoryblock::MemoryType;
@@ -15,7 +16,9 @@ use memoryblock::MemoryType;
 pub enum INesError {
     InvalidHeader,
     Unimplemented,
-    ReadError{reason: String}
+    ReadError{reason: String},
+    // The header is fine, but the file ends before the trainer/PRG/CHR it declares
+    Truncated{expected: usize, found: usize},
 }
 
 impl Error for INesError {}
@@ -25,7 +28,8 @@ impl fmt::Display for INesError  {
         match self {
             INesError::InvalidHeader => {write!(f, "Invalid iNES Header")},
             INesError::Unimplemented => {write!(f, "Unimplemented (Lazy programmers!!1)")},
-            INesError::ReadError{reason} => {write!(f, "Error reading cartridge: {}", reason)}
+            INesError::ReadError{reason} => {write!(f, "Error reading cartridge: {}", reason)},
+            INesError::Truncated{expected, found} => {write!(f, "Truncated ROM: header declares {} bytes of data, but only {} are present", expected, found)}
         }
     }
 }
@@ -62,7 +66,7 @@ const INES2_PRG_RAM: usize = 10;
 const INES2_CHR_RAM: usize = 11;
 //const INES2_CPU_PPU_TIMING: usize = 12;
 //const INES2_SYSTEM_TYPE: usize = 13;
-//const INES2_MISC_ROM_COUNT: usize = 14;
+const INES2_MISC_ROM_COUNT: usize = 14;
 //const INES2_DEFAULT_EXPANSION: usize = 15;
 
 impl INesHeader {
@@ -122,11 +126,11 @@ impl INesHeader {
             let exponent = ((lsb & 0b1111_1100) >> 2) as u32;
             let base: usize = 2;
             let size = base.pow(exponent) * multiplier;
-            println!("base: {}, multiplier: {}, exponent: {}, size: {}", base, multiplier, exponent, size);
+            logging::debug(&format!("base: {}, multiplier: {}, exponent: {}, size: {}", base, multiplier, exponent, size));
             return size;
         } else {
             // simple mode
-            println!("used simple mode!");
+            logging::debug("used simple mode!");
             return (((msb as usize) << 8) + (lsb as usize)) * 16 * 1024;
         }
     }
@@ -323,6 +327,13 @@ impl INesHeader {
         }
     }
 
+    pub fn misc_rom_count(&self) -> u8 {
+        match self.version() {
+            2 => self.raw_bytes[INES2_MISC_ROM_COUNT] & 0b0000_0011,
+            _ => 0
+        }
+    }
+
     pub fn submapper_number(&self) -> u8 {
         match self.version() {
             1 => 0,
@@ -353,32 +364,41 @@ impl INesCartridge {
         if !header.magic_header_valid() {
             return Err(INesError::InvalidHeader);
         }
-        println!("iNes version: {}", header.version());
+        logging::debug(&format!("iNes version: {}", header.version()));
+
+        // Read everything up front, so a short file can be reported as such rather than
+        // as whatever read_exact happened to trip over
+        let mut data: Vec<u8> = Vec::new();
+        file_reader.read_to_end(&mut data)?;
 
         let trainer_size = if header.has_trainer() {512} else {0};
-        let mut trainer: Vec<u8> = Vec::new();
-        trainer.resize(trainer_size, 0);
-        file_reader.read_exact(&mut trainer)?;
-        println!("trainer size: {}", trainer.len());
-
-        let mut prg: Vec<u8> = Vec::new();
-        prg.resize(header.prg_size(), 0);
-        file_reader.read_exact(&mut prg)?;
+        let prg_size = header.prg_size();
+        let chr_size = header.chr_rom_size();
+        let expected_size = trainer_size + prg_size + chr_size;
+        if data.len() < expected_size {
+            return Err(INesError::Truncated{expected: expected_size, found: data.len()});
+        }
+
+        let trainer = data[0 .. trainer_size].to_vec();
+        logging::debug(&format!("trainer size: {}", trainer.len()));
+
+        let prg = data[trainer_size .. trainer_size + prg_size].to_vec();
         if prg.len() == 0 {
             return Err(INesError::ReadError{reason: format!("PRG ROM size is {}. This file is invalid, or at the very least quite unusual. Aborting.", prg.len())});
         }
-        println!("prg rom size: {}", prg.len());
+        logging::debug(&format!("prg rom size: {}", prg.len()));
 
-        let mut chr: Vec<u8> = Vec::new();
-        chr.resize(header.chr_rom_size(), 0);
-        file_reader.read_exact(&mut chr)?;
-        println!("chr rom size: {}", chr.len());
+        let chr = data[trainer_size + prg_size .. expected_size].to_vec();
+        logging::debug(&format!("chr rom size: {}", chr.len()));
 
         // If there is any remaining data at this point, it becomes misc_rom and,
-        // currently, has no other special handling
-        let mut misc: Vec<u8> = Vec::new();
-        file_reader.read_to_end(&mut misc)?;
-        println!("misc_size: {}", misc.len());
+        // currently, has no other special handling. Only NES 2.0 headers can declare
+        // misc ROMs though; anywhere else it's most likely junk from a bad dump.
+        let misc = data[expected_size ..].to_vec();
+        logging::debug(&format!("misc_size: {}", misc.len()));
+        if misc.len() > 0 && header.misc_rom_count() == 0 {
+            logging::warn(&format!("{} bytes of unexpected data after the end of CHR ROM, ignoring", misc.len()));
+        }
 
         return Ok(INesCartridge {
             header: header,
@@ -393,18 +413,40 @@ impl INesCartridge {
         return MemoryBlock::new(&self.prg, MemoryType::Rom);
     }
 
+    // The trainer lives at $7000-$71FF, which is 0x1000 into the usual $6000 PRG RAM window.
+    // Grows the RAM to cover it if the header didn't ask for enough.
+    fn load_trainer(&self, prg_ram: &mut Vec<u8>) {
+        if self.trainer.len() == 0 {
+            return;
+        }
+        if prg_ram.len() < 0x1000 + self.trainer.len() {
+            prg_ram.resize(0x2000, 0);
+        }
+        prg_ram[0x1000 .. 0x1000 + self.trainer.len()].copy_from_slice(&self.trainer);
+    }
+
     pub fn prg_ram_blocks(&self) -> Vec<MemoryBlock> {
         let mut blocks: Vec<MemoryBlock> = Vec::new();
         if self.header.prg_ram_size() > 0 {
             let mut prg_ram: Vec<u8> = Vec::new();
             prg_ram.resize(self.header.prg_ram_size(), 0);
+            self.load_trainer(&mut prg_ram);
             blocks.push(MemoryBlock::new(&prg_ram, MemoryType::Ram));
         }
         if self.header.prg_sram_size() > 0 {
             let mut prg_sram: Vec<u8> = Vec::new();
             prg_sram.resize(self.header.prg_sram_size(), 0);
+            if blocks.len() == 0 {
+                self.load_trainer(&mut prg_sram);
+            }
             blocks.push(MemoryBlock::new(&prg_sram, MemoryType::NvRam));
         }
+        if blocks.len() == 0 && self.trainer.len() > 0 {
+            // No RAM declared, but the trainer still needs somewhere to go
+            let mut prg_ram: Vec<u8> = Vec::new();
+            self.load_trainer(&mut prg_ram);
+            blocks.push(MemoryBlock::new(&prg_ram, MemoryType::Ram));
+        }
         if blocks.len() == 0 {
             // Always include at least one entry in this list; in this case, a
             // single empty block.
@@ -452,3 +494,98 @@ impl INesCartridge {
         return Ok(blocks[0].clone());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge;
+    use memory;
+    use nes::NesState;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    // NROM, one 16K PRG bank and one 8K CHR bank. Each chunk is filled with its own byte so a
+    // misplaced offset is obvious.
+    fn nrom_image(flags_6: u8) -> Vec<u8> {
+        let mut rom = vec!(0x4Eu8, 0x45, 0x53, 0x1A, 1, 1, flags_6, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        if flags_6 & 0b0000_0100 != 0 {
+            let trainer: Vec<u8> = (0 .. 512).map(|i| (i % 251) as u8).collect();
+            rom.extend(trainer);
+        }
+        rom.extend(vec!(0xAAu8; 0x4000));
+        rom.extend(vec!(0xCCu8; 0x2000));
+        return rom;
+    }
+
+    #[test]
+    fn trainer_is_kept_and_prg_starts_after_it() {
+        let rom = nrom_image(0b0000_0100);
+        let cartridge = INesCartridge::from_reader(&mut &rom[..]).unwrap();
+        assert!(cartridge.header.has_trainer());
+        assert_eq!(cartridge.trainer, rom[16 .. 16 + 512].to_vec());
+        assert_eq!(cartridge.prg, vec!(0xAAu8; 0x4000));
+        assert_eq!(cartridge.chr, vec!(0xCCu8; 0x2000));
+        assert_eq!(cartridge.misc_rom.len(), 0);
+
+        // And it lands in PRG RAM at $7000, 0x1000 into the $6000 window
+        let prg_ram = &cartridge.prg_ram_blocks()[0];
+        for i in 0 .. 512 {
+            assert_eq!(prg_ram.bounded_read(0x1000 + i), Some((i % 251) as u8));
+        }
+        assert_eq!(prg_ram.bounded_read(0x0FFF), Some(0));
+
+        // Which is where the CPU sees it, too
+        let nes = NesState::new(cartridge::mapper_from_file(&rom).unwrap());
+        assert_eq!(memory::debug_read_byte(&nes, 0x7000), 0);
+        assert_eq!(memory::debug_read_byte(&nes, 0x7001), 1);
+        assert_eq!(memory::debug_read_byte(&nes, 0x71FF), (511 % 251) as u8);
+    }
+
+    #[test]
+    fn no_trainer_means_prg_starts_right_after_the_header() {
+        let rom = nrom_image(0);
+        let cartridge = INesCartridge::from_reader(&mut &rom[..]).unwrap();
+        assert_eq!(cartridge.trainer.len(), 0);
+        assert_eq!(cartridge.prg, vec!(0xAAu8; 0x4000));
+        assert_eq!(cartridge.chr, vec!(0xCCu8; 0x2000));
+    }
+
+    #[test]
+    fn truncated_files_report_how_much_data_is_missing() {
+        let mut rom = nrom_image(0);
+        rom.truncate(16 + 0x4000 + 0x1000);
+        match INesCartridge::from_reader(&mut &rom[..]) {
+            Err(INesError::Truncated{expected, found}) => {assert_eq!((expected, found), (0x6000, 0x5000));},
+            Err(other) => panic!("Expected a truncated ROM, got {}", other),
+            Ok(_) => panic!("A truncated ROM shouldn't load")
+        }
+        // Even with the trainer missing entirely, it's an error rather than a panic
+        match cartridge::mapper_from_file(&nrom_image(0b0000_0100)[.. 16 + 100]) {
+            Err(cartridge::CartridgeError::TruncatedRom{expected, found}) => {assert_eq!((expected, found), (512 + 0x6000, 100));},
+            Err(other) => panic!("Expected a truncated ROM, got {}", other),
+            Ok(_) => panic!("A truncated ROM shouldn't load")
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_load_with_a_warning() {
+        let mut rom = nrom_image(0);
+        rom.extend(vec!(0xEEu8; 7));
+        let captured: Arc<Mutex<Vec<(logging::LogLevel, String)>>> = Arc::new(Mutex::new(Vec::new()));
+        let sink_messages = captured.clone();
+        logging::set_log_sink(Some(Box::new(move |level, message| {
+            sink_messages.lock().unwrap().push((level, message.to_string()));
+        })));
+        let cartridge = INesCartridge::from_reader(&mut &rom[..]);
+        logging::set_log_sink(None);
+
+        let cartridge = cartridge.unwrap();
+        assert_eq!(cartridge.prg, vec!(0xAAu8; 0x4000));
+        assert_eq!(cartridge.chr, vec!(0xCCu8; 0x2000));
+        assert_eq!(cartridge.misc_rom, vec!(0xEEu8; 7));
+        // Other tests may log while the sink is installed, so look for ours among them
+        let messages = captured.lock().unwrap();
+        assert!(messages.iter().any(|(level, message)| *level == logging::LogLevel::Warning && message == "7 bytes of unexpected data after the end of CHR ROM, ignoring"), "{:?}", *messages);
+        assert!(cartridge::mapper_from_file(&rom).is_ok());
+    }
+}
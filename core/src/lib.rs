@@ -1,9 +1,11 @@
 pub mod addressing;
 pub mod apu;
 pub mod asm;
+pub mod benchmark;
 pub mod cartridge;
 pub mod cycle_cpu;
 pub mod fds;
+pub mod logging;
 pub mod tracked_events;
 pub mod ines;
 pub mod memory;
@@ -15,6 +17,7 @@ pub mod opcodes;
 pub mod opcode_info;
 pub mod palettes;
 pub mod ppu;
+pub mod snapshot;
 pub mod unofficial_opcodes;
 
 pub fn version() -> &'static str {
@@ -0,0 +1,87 @@
+// A tiny logging facade, so the host application can decide where messages end up and
+// how chatty they are. Until a sink is installed, everything goes to stderr.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "Error",
+            LogLevel::Warning => "Warning",
+            LogLevel::Info => "Info",
+            LogLevel::Debug => "Debug",
+        }
+    }
+
+    pub fn from_string(name: &str) -> Option<LogLevel> {
+        match name {
+            "error" => Some(LogLevel::Error),
+            "warning" => Some(LogLevel::Warning),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None
+        }
+    }
+}
+
+pub type LogSink = Box<dyn Fn(LogLevel, &str) + Send>;
+
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Info as usize);
+static SINK: Mutex<Option<LogSink>> = Mutex::new(None);
+
+// Messages less severe than this are dropped before they reach the sink
+pub fn set_log_level(level: LogLevel) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+// None restores the default stderr output
+pub fn set_log_sink(sink: Option<LogSink>) {
+    match SINK.lock() {
+        Ok(mut current_sink) => {*current_sink = sink;},
+        Err(poisoned) => {*poisoned.into_inner() = sink;}
+    }
+}
+
+pub fn log(level: LogLevel, message: &str) {
+    if level as usize > MAX_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let sink = match SINK.lock() {
+        Ok(sink) => sink,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    match *sink {
+        Some(ref sink) => {sink(level, message);},
+        None => {
+            match level {
+                LogLevel::Info => {eprintln!("{}", message);},
+                _ => {eprintln!("{}: {}", level.name(), message);}
+            }
+        }
+    }
+}
+
+pub fn error(message: &str) {
+    log(LogLevel::Error, message);
+}
+
+pub fn warn(message: &str) {
+    log(LogLevel::Warning, message);
+}
+
+pub fn info(message: &str) {
+    log(LogLevel::Info, message);
+}
+
+pub fn debug(message: &str) {
+    log(LogLevel::Debug, message);
+}
@@ -17,6 +17,12 @@ impl CpuMemory {
             open_bus: 0,
         }
     }
+
+    // The 2 KB of internal work RAM, for debuggers that want the whole thing at once.
+    // Reading this way has no side effects.
+    pub fn work_ram(&self) -> &[u8] {
+        return &self.iram_raw;
+    }
 }
 
 pub fn debug_read_byte(nes: &NesState, address: u16) -> u8 {
@@ -318,3 +324,36 @@ pub fn write_byte(nes: &mut NesState, address: u16, data: u8) {
         _ => () // Do nothing!
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge;
+
+    fn nrom_nes() -> NesState {
+        let mut rom = vec!(0x4Eu8, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        rom.extend(vec!(0u8; 0x4000 + 0x2000));
+        return NesState::new(cartridge::mapper_from_file(&rom).unwrap());
+    }
+
+    #[test]
+    fn bus_writes_show_up_in_the_work_ram_slice() {
+        let mut nes = nrom_nes();
+        assert_eq!(nes.memory.work_ram().len(), 0x800);
+
+        write_byte(&mut nes, 0x0123, 0x45);
+        // $0800-$1FFF mirrors the same 2 KB
+        write_byte(&mut nes, 0x1FFF, 0x67);
+        assert_eq!(nes.memory.work_ram()[0x123], 0x45);
+        assert_eq!(nes.memory.work_ram()[0x7FF], 0x67);
+    }
+
+    #[test]
+    fn bus_writes_show_up_in_the_prg_ram_slice() {
+        let mut nes = nrom_nes();
+        assert_eq!(nes.mapper.prg_ram().len(), 0x2000);
+        write_byte(&mut nes, 0x6010, 0xAB);
+        assert_eq!(nes.mapper.prg_ram()[0x10], 0xAB);
+        assert_eq!(debug_read_byte(&nes, 0x6010), 0xAB);
+    }
+}
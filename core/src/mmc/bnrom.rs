@@ -57,6 +57,14 @@ impl Mapper for BnRom {
         }
     }
 
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr.len() / 0x1000;
+    }
+
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         match address {
             0x0000 ..= 0x1FFF => self.chr.wrapping_read(address as usize),
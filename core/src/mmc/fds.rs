@@ -372,6 +372,10 @@ impl Mapper for FdsMapper {
         return data;
     }
     
+    fn prg_ram(&self) -> &[u8] {
+        return &self.prg_ram;
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x4033 => {
@@ -384,6 +388,11 @@ impl Mapper for FdsMapper {
         }
     }
 
+    // Everything below the BIOS is RAM, disk contents included
+    fn cpu_address_is_rom(&self, address: u16) -> bool {
+        return address >= 0xE000;
+    }
+
     fn write_cpu(&mut self, address: u16, data: u8) {
         match address {
             0x6000 ..= 0xDFFF => {self.prg_ram[address as usize - 0x6000] = data;},
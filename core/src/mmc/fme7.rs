@@ -75,6 +75,18 @@ impl Mapper for Fme7 {
         return Mirroring::Horizontal;
     }
     
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr_rom.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr_rom.len() / 0x1000;
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x6000 ..= 0x7FFF => {
@@ -22,6 +22,8 @@ pub fn mirroring_mode_name(mode: Mirroring) -> &'static str {
 pub trait Mapper: Send {
     fn read_cpu(&mut self, address: u16) -> Option<u8> {return self.debug_read_cpu(address);}
     fn write_cpu(&mut self, address: u16, data: u8);
+    // Called when the PPU drives an address without reading it; actual reads go through
+    // read_ppu. MMC3 watches A12 in both for its IRQ counter.
     fn access_ppu(&mut self, _address: u16) {}
     fn read_ppu(&mut self, address: u16) -> Option<u8> {return self.debug_read_ppu(address);}
     fn write_ppu(&mut self, address: u16, data: u8);
@@ -33,6 +35,7 @@ pub trait Mapper: Send {
     fn get_sram(&self) -> Vec<u8> {return vec![0u8; 0];}
     fn load_sram(&mut self, _: Vec<u8>) {}
     fn irq_flag(&self) -> bool {return false;}
+    // Called once per CPU cycle, for cycle counting IRQs (FME-7, VRC) and expansion audio
     fn clock_cpu(&mut self) {}
     fn mix_expansion_audio(&self, nes_sample: f32) -> f32 {return nes_sample;}
     fn channels(&self) ->  Vec<& dyn AudioChannelState> {return Vec::new();}
@@ -45,4 +48,12 @@ pub trait Mapper: Send {
     fn load_bios(&mut self, _: Vec<u8>) {}
     fn switch_disk(&mut self, _: usize) {}
     fn vrc7_set_patches(&mut self, _patches: &[u8]) {}
+    // Debugger access to CHR in 4 KB banks, regardless of what is currently mapped in
+    fn read_chr_bank(&self, _bank: usize, _offset: usize) -> u8 {return 0;}
+    fn chr_bank_count(&self) -> usize {return 0;}
+    // Debugger access to all of PRG RAM at once, without side effects. Empty if there is none.
+    fn prg_ram(&self) -> &[u8] {return &[];}
+    // Debugger hint: whether the CPU sees ROM at this address, where a write would land on
+    // mapper registers instead of changing what's there
+    fn cpu_address_is_rom(&self, address: u16) -> bool {return address >= 0x8000;}
 }
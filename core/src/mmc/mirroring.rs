@@ -67,3 +67,28 @@ pub fn four_banks(read_address: u16) -> u16 {
         _ => return 0, // wat
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_screen_lower_maps_every_quadrant_to_the_first_page() {
+        for quadrant in [0x2000u16, 0x2400, 0x2800, 0x2C00].iter() {
+            assert_eq!(one_screen_lower(*quadrant), 0x000);
+            assert_eq!(one_screen_lower(*quadrant + 0x123), 0x123);
+            assert_eq!(one_screen_lower(*quadrant + 0x3FF), 0x3FF);
+        }
+    }
+
+    #[test]
+    fn one_screen_upper_maps_every_quadrant_to_the_second_page() {
+        for quadrant in [0x2000u16, 0x2400, 0x2800, 0x2C00].iter() {
+            assert_eq!(one_screen_upper(*quadrant), 0x400);
+            assert_eq!(one_screen_upper(*quadrant + 0x123), 0x523);
+            assert_eq!(one_screen_upper(*quadrant + 0x3FF), 0x7FF);
+        }
+        // $3000-$3EFF mirrors the nametables, and lands in the same place
+        assert_eq!(one_screen_upper(0x3523), 0x523);
+    }
+}
@@ -186,6 +186,10 @@ impl Mapper for Mmc3 {
         self.snoop_cpu_m2();
     }
 
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             // PRG RAM
@@ -298,6 +302,14 @@ impl Mapper for Mmc3 {
         self.snoop_ppu_a12(address);
     }    
 
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr.len() / 0x1000;
+    }
+
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         return self._read_ppu(address);
     }
@@ -99,7 +99,7 @@ impl AudioChannelState for Mmc5PcmChannel {
 
     fn amplitude(&self) -> f32 {
         let buffer = self.output_buffer.buffer();
-        let mut index = (self.output_buffer.index() - 256) % buffer.len();
+        let mut index = self.output_buffer.index_before(self.output_buffer.index(), 256);
         let mut max = buffer[index];
         let mut min = buffer[index];
         for _i in 0 .. 256 {
@@ -680,6 +680,10 @@ impl Mapper for Mmc5 {
         return data;
     }
 
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         return self._read_cpu(address);
     }
@@ -826,6 +830,14 @@ impl Mapper for Mmc5 {
         }
     }
 
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr.len() / 0x1000;
+    }
+
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         return self._read_ppu(address);
     }
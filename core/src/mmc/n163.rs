@@ -456,6 +456,10 @@ impl Mapper for Namco163 {
         return Mirroring::Horizontal;
     }
     
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x4800 ..= 0x4FFF => {
@@ -492,6 +496,14 @@ impl Mapper for Namco163 {
         return data;
     }
 
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr.len() / 0x1000;
+    }
+
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         let masked_address = address & 0xFC00;
         match masked_address {
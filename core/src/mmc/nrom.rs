@@ -2,6 +2,7 @@
 // Reference capabilities: https://wiki.nesdev.com/w/index.php/NROM
 
 use ines::INesCartridge;
+use logging;
 use memoryblock::MemoryBlock;
 
 use mmc::mapper::*;
@@ -22,7 +23,7 @@ impl Nrom {
         let prg_ram_block = ines.prg_ram_block()?;
         let chr_block = ines.chr_block()?;
 
-        println!("NROM Mirroring Mode: {}", mirroring_mode_name(ines.header.mirroring()));
+        logging::info(&format!("NROM Mirroring Mode: {}", mirroring_mode_name(ines.header.mirroring())));
 
         return Ok(Nrom {
             prg_rom: prg_rom_block.clone(),
@@ -45,6 +46,10 @@ impl Mapper for Nrom {
         return self.mirroring;
     }
     
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x6000 ..= 0x7FFF => {self.prg_ram.wrapping_read((address - 0x6000) as usize)},
@@ -60,6 +65,14 @@ impl Mapper for Nrom {
         }
     }
 
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr.len() / 0x1000;
+    }
+
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         match address {
             0x0000 ..= 0x1FFF => return self.chr.wrapping_read(address as usize),
@@ -70,7 +83,8 @@ impl Mapper for Nrom {
                 // to build a board that does. Since iNes allows this, some homebrew requires it, and
                 // so we support it in the interest of compatibility.
                 Mirroring::FourScreen => Some(self.vram[mirroring::four_banks(address) as usize]),
-                _ => None
+                Mirroring::OneScreenLower => Some(self.vram[mirroring::one_screen_lower(address) as usize]),
+                Mirroring::OneScreenUpper => Some(self.vram[mirroring::one_screen_upper(address) as usize]),
             },
             _ => return None
         }
@@ -83,7 +97,8 @@ impl Mapper for Nrom {
                 Mirroring::Horizontal => self.vram[mirroring::horizontal_mirroring(address) as usize] = data,
                 Mirroring::Vertical   => self.vram[mirroring::vertical_mirroring(address) as usize] = data,
                 Mirroring::FourScreen => self.vram[mirroring::four_banks(address) as usize] = data,
-                _ => {}
+                Mirroring::OneScreenLower => self.vram[mirroring::one_screen_lower(address) as usize] = data,
+                Mirroring::OneScreenUpper => self.vram[mirroring::one_screen_upper(address) as usize] = data,
             },
             _ => {}
         }
@@ -1300,6 +1300,10 @@ impl Mapper for NsfMapper {
         return data;
     }
 
+    fn prg_ram(&self) -> &[u8] {
+        return &self.prg_ram;
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match self.read_mmc5(address) {
             Some(data) => return Some(data),
@@ -59,6 +59,10 @@ impl Mapper for PxRom {
         return self.mirroring;
     }
   
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x6000 ..= 0x7FFF => self.prg_ram.wrapping_read((address - 0x6000) as usize),
@@ -100,6 +104,14 @@ impl Mapper for PxRom {
         return self.debug_read_ppu(address);
     }
 
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr.len() / 0x1000;
+    }
+
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         match address {
             0x0000 ..= 0x0FFF => {
@@ -816,6 +816,24 @@ impl Mapper for Rainbow {
         return self.mirroring;
     }
 
+    // Follows the chip select, same as the PPU does
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return match self.chr_chip {
+            ChrChipSelect::ChrRom => self.chr_rom.banked_read(0x1000, bank, offset),
+            ChrChipSelect::ChrRam => self.chr_ram.banked_read(0x1000, bank, offset),
+            ChrChipSelect::FpgaRam => self.fpga_ram.banked_read(0x1000, 0, offset),
+        }.unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return match self.chr_chip {
+            ChrChipSelect::ChrRom => self.chr_rom.len() / 0x1000,
+            ChrChipSelect::ChrRam => self.chr_ram.len() / 0x1000,
+            // Only the first 4k of FPGA RAM is visible to the PPU
+            ChrChipSelect::FpgaRam => 1,
+        };
+    }
+
     fn clock_cpu(&mut self) {
         self.vrc6_pulse1.clock();
         self.vrc6_pulse2.clock();
@@ -856,6 +874,10 @@ impl Mapper for Rainbow {
         return data;
     }
     
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             // ZPCM
@@ -364,12 +364,9 @@ impl AudioChannelState for Vrc6SawtoothChannel {
     }
 
     fn timbre(&self) -> Option<Timbre> {
-        if self.accumulator_rate <= 42 {
-            return Some(Timbre::DutyIndex{ index: 0 as usize, max: 1 });
-        } else {
-            return Some(Timbre::DutyIndex{ index: 1 as usize, max: 1 });
-        }
-        
+        // Rates above 42 overflow the accumulator, which distorts the saw more the higher they go
+        let distortion = self.accumulator_rate.saturating_sub(42);
+        return Some(Timbre::Distortion{ index: distortion as usize, max: 63 - 42 });
     }
 }
 
@@ -950,6 +947,10 @@ impl Mapper for Vrc6 {
         return self.irq_pending;
     }
 
+    fn prg_ram(&self) -> &[u8] {
+        return self.prg_ram.as_vec();
+    }
+
     fn debug_read_cpu(&self, address: u16) -> Option<u8> {
         match address {
             0x6000 ..= 0x7FFF => self.prg_ram.wrapping_read(address as usize - 0x6000),
@@ -1039,6 +1040,14 @@ impl Mapper for Vrc6 {
         }
     }
 
+    fn read_chr_bank(&self, bank: usize, offset: usize) -> u8 {
+        return self.chr.banked_read(0x1000, bank, offset).unwrap_or(0);
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        return self.chr.len() / 0x1000;
+    }
+
     fn debug_read_ppu(&self, address: u16) -> Option<u8> {
         match address {
             0x0000 ..= 0x1FFF => {
@@ -1116,3 +1125,29 @@ impl Mapper for Vrc6 {
         self.sawtooth.record_current_output();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distortion(channel: &dyn AudioChannelState) -> Option<(usize, usize)> {
+        match channel.timbre() {
+            Some(Timbre::Distortion{index, max}) => {return Some((index, max));},
+            _ => {return None;}
+        }
+    }
+
+    #[test]
+    fn sawtooth_timbre_tracks_distortion() {
+        let mut sawtooth = Vrc6SawtoothChannel::new();
+        // Up to 42 the accumulator never overflows
+        for &rate in [0u8, 20, 42].iter() {
+            sawtooth.accumulator_rate = rate;
+            assert_eq!(distortion(&sawtooth), Some((0, 21)));
+        }
+        sawtooth.accumulator_rate = 50;
+        assert_eq!(distortion(&sawtooth), Some((8, 21)));
+        sawtooth.accumulator_rate = 63;
+        assert_eq!(distortion(&sawtooth), Some((21, 21)));
+    }
+}
@@ -56,7 +56,7 @@ impl NesState {
                 return Ok(nes);
             },
             Err(why) => {
-                return Err(why);
+                return Err(why.to_string());
             }
         }
     }
@@ -99,6 +99,10 @@ impl NesState {
         self.registers.pc = pc_low as u16 + ((pc_high as u16) << 8);
     }
 
+    // Everything else is built on this: the CPU and PPU always advance together at the 1:3 dot
+    // ratio, one CPU clock at a time. Running "by scanline" or "by frame" only changes how
+    // often control returns to the caller, so mid-scanline writes land on the correct dot
+    // either way.
     pub fn cycle(&mut self) {
         cycle_cpu::run_one_clock(self);
         self.master_clock = self.master_clock + 12;
@@ -163,3 +167,154 @@ impl NesState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mmc::mapper::Mirroring;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // NROM with blank CHR, running a single JMP in place forever
+    fn idle_loop_nes() -> NesState {
+        let mut rom = vec!(0x4Eu8, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        let mut prg = vec!(0u8; 0x4000);
+        prg[0..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFA..0x4000].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        rom.extend(prg);
+        rom.extend(vec!(0u8; 0x2000));
+        let mapper = cartridge::mapper_from_file(&rom).unwrap();
+        let mut nes = NesState::new(mapper);
+        nes.power_on();
+        // Background on (left column included), so every pixel is the backdrop color
+        memory::write_byte(&mut nes, 0x2001, 0x0A);
+        nes.run_until_vblank();
+        return nes;
+    }
+
+    fn screen_color(nes: &NesState, x: usize, y: usize) -> u16 {
+        return nes.ppu.screen[y * 256 + x];
+    }
+
+    #[test]
+    fn mid_scanline_palette_write_splits_the_line() {
+        let mut nes = idle_loop_nes();
+        let old_color = nes.ppu.palette[0] as u16;
+        let new_color = 0x16;
+        assert!(old_color != new_color);
+
+        while !(nes.ppu.current_scanline == 100 && nes.ppu.current_scanline_cycle >= 128) {
+            nes.cycle();
+        }
+        nes.ppu.write_byte(&mut *nes.mapper, 0x3F00, new_color as u8);
+        let split_dot = nes.ppu.current_scanline_cycle as usize;
+        nes.run_until_vblank();
+
+        // The dot counter has already moved on to the next dot, and dot N draws pixel N - 1,
+        // so everything before the write keeps the old color
+        for x in 0 .. 256 {
+            let expected = if x + 2 <= split_dot {old_color} else {new_color};
+            assert_eq!(screen_color(&nes, x, 100), expected, "pixel {} of the split line", x);
+        }
+        assert_eq!(screen_color(&nes, 255, 99), old_color);
+        assert_eq!(screen_color(&nes, 0, 101), new_color);
+    }
+
+    #[test]
+    fn palette_write_during_hblank_changes_whole_lines() {
+        let mut nes = idle_loop_nes();
+        let old_color = nes.ppu.palette[0] as u16;
+        let new_color = 0x16;
+
+        // Past the last visible dot, the same write lands cleanly between two lines
+        while !(nes.ppu.current_scanline == 99 && nes.ppu.current_scanline_cycle > 257) {
+            nes.cycle();
+        }
+        nes.ppu.write_byte(&mut *nes.mapper, 0x3F00, new_color as u8);
+        nes.run_until_vblank();
+
+        for x in 0 .. 256 {
+            assert_eq!(screen_color(&nes, x, 99), old_color);
+            assert_eq!(screen_color(&nes, x, 100), new_color);
+        }
+    }
+
+    // Runs the same JMP loop as idle_loop_nes, counting the mapper hooks as they arrive
+    struct CountingMapper {
+        cpu_cycles: Arc<AtomicUsize>,
+        a12_rises: Arc<AtomicUsize>,
+        last_a12: bool,
+    }
+
+    impl Mapper for CountingMapper {
+        fn write_cpu(&mut self, _address: u16, _data: u8) {}
+        fn write_ppu(&mut self, _address: u16, _data: u8) {}
+        fn debug_read_cpu(&self, address: u16) -> Option<u8> {
+            match address {
+                0x8000 => Some(0x4C),
+                0x8001 => Some(0x00),
+                0x8002 => Some(0x80),
+                0xFFFA ..= 0xFFFF => Some(if address & 1 == 0 {0x00} else {0x80}),
+                _ => None
+            }
+        }
+        fn debug_read_ppu(&self, _address: u16) -> Option<u8> {return Some(0);}
+        fn mirroring(&self) -> Mirroring {return Mirroring::Vertical;}
+        fn clock_cpu(&mut self) {
+            self.cpu_cycles.fetch_add(1, Ordering::Relaxed);
+        }
+        // Like MMC3, watch A12 on reads and on bare address changes alike
+        fn read_ppu(&mut self, address: u16) -> Option<u8> {
+            self.access_ppu(address);
+            return self.debug_read_ppu(address);
+        }
+        fn access_ppu(&mut self, address: u16) {
+            let a12 = address & 0x1000 != 0;
+            if a12 && !self.last_a12 {
+                self.a12_rises.fetch_add(1, Ordering::Relaxed);
+            }
+            self.last_a12 = a12;
+        }
+    }
+
+    #[test]
+    fn mapper_hooks_see_every_cpu_cycle_and_a12_rise_in_a_frame() {
+        let cpu_cycles = Arc::new(AtomicUsize::new(0));
+        let a12_rises = Arc::new(AtomicUsize::new(0));
+        let mapper = CountingMapper{cpu_cycles: cpu_cycles.clone(), a12_rises: a12_rises.clone(), last_a12: false};
+        let mut nes = NesState::new(Box::new(mapper));
+        nes.power_on();
+        // Background from $0000 and sprites from $1000, like most MMC3 games, so A12 only
+        // rises during sprite fetches
+        memory::write_byte(&mut nes, 0x2000, 0x08);
+        memory::write_byte(&mut nes, 0x2001, 0x18);
+        nes.run_until_vblank();
+        nes.run_until_vblank();
+
+        cpu_cycles.store(0, Ordering::Relaxed);
+        a12_rises.store(0, Ordering::Relaxed);
+        nes.run_until_vblank();
+        // 262 lines of 341 dots, at 3 dots per CPU cycle, with one dot skipped on odd frames
+        let cycles = cpu_cycles.load(Ordering::Relaxed);
+        assert!(cycles == 29780 || cycles == 29781);
+        // Each of the 8 sprite fetches on a line follows a garbage nametable read, so without
+        // MMC3's filtering A12 rises 8 times per line, on 240 visible lines plus the pre-render line
+        assert_eq!(a12_rises.load(Ordering::Relaxed), 241 * 8);
+    }
+
+    #[test]
+    fn greyscale_bit_renders_colors_as_their_grey_column() {
+        let mut nes = idle_loop_nes();
+        nes.ppu.write_byte(&mut *nes.mapper, 0x3F00, 0x16);
+        memory::write_byte(&mut nes, 0x2001, 0x0B);
+        nes.run_until_vblank();
+        nes.run_until_vblank();
+
+        // $16 is a red; with greyscale on it becomes the grey from the same row, $10
+        assert_eq!(screen_color(&nes, 128, 120), 0x10);
+        // The debugger's palette view reads through the same mask
+        assert_eq!(nes.ppu.debug_read_byte(&*nes.mapper, 0x3F00), 0x10);
+        // The stored palette keeps the real color, ready for greyscale to be switched off again
+        assert_eq!(nes.ppu.palette[0], 0x16);
+    }
+}
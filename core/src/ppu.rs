@@ -134,6 +134,13 @@ pub struct PpuState {
 
     pub sprite_zero_on_scanline: bool,
 
+    // (scanline, dot) at which $2002's sprite zero hit and sprite overflow bits were set,
+    // for the frame in progress and for the last complete frame. None if they never were.
+    pub sprite_zero_hit_position: Option<(u16, u16)>,
+    pub sprite_overflow_position: Option<(u16, u16)>,
+    pub last_sprite_zero_hit_position: Option<(u16, u16)>,
+    pub last_sprite_overflow_position: Option<(u16, u16)>,
+
     // Debug Viewer
     pub recent_reads: Vec<u16>,
     pub recent_writes: Vec<u16>,
@@ -205,6 +212,11 @@ impl PpuState {
             attribute_byte: 0,
             sprite_zero_on_scanline: false,
 
+            sprite_zero_hit_position: None,
+            sprite_overflow_position: None,
+            last_sprite_zero_hit_position: None,
+            last_sprite_overflow_position: None,
+
             // Debug
             recent_reads: Vec::new(),
             recent_writes: Vec::new(),
@@ -333,6 +345,9 @@ impl PpuState {
                         self.sprite_zero_on_scanline = true;
                     }
                 } else {
+                    if self.status & 0x20 == 0 {
+                        self.sprite_overflow_position = Some((self.current_scanline, self.current_scanline_cycle));
+                    }
                     self.status = self.status | 0x20; // bit 5 = sprite overflow this frame
                 }
             }
@@ -409,6 +424,9 @@ impl PpuState {
                 if self.secondary_oam[sprite_index].active && self.secondary_oam[sprite_index].palette_index() != 0 {
                     if self.sprite_zero_on_scanline && sprite_index == 0 && bg_palette_index != 0 {
                         // Sprite zero hit!
+                        if self.status & 0x40 == 0 {
+                            self.sprite_zero_hit_position = Some((self.current_scanline, self.current_scanline_cycle));
+                        }
                         self.status = self.status | 0x40;
                     }
                     if bg_palette_index == 0 || !self.secondary_oam[sprite_index].bg_priority() {
@@ -589,6 +607,8 @@ impl PpuState {
             1 => {
                 // Clear vblank, sprite overflow and sprite zero hit
                 self.status = self.status & 0x1F;
+                self.last_sprite_zero_hit_position = self.sprite_zero_hit_position.take();
+                self.last_sprite_overflow_position = self.sprite_overflow_position.take();
                 if self.rendering_enabled() {
                     self.fetch_bg_tile(mapper, 0);
                 }
@@ -934,4 +954,61 @@ pub fn yiq_to_argb(y: f32, i: f32, q: f32) -> u32 {
     + 0x00100 * clamp(255.95 * gammafix(y + (-0.274788*i) + -(0.635691*q)))
     + 0x00001 * clamp(255.95 * gammafix(y + (-1.108545*i) +  (1.709007*q)));
     return 0xFF000000 + rgb; // set alpha exlicitly to full
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use cartridge;
+    use memory;
+    use nes::NesState;
+
+    // NROM idling in place, with every background tile and every sprite a solid block of color 1
+    fn solid_tile_nes() -> NesState {
+        let mut rom = vec!(0x4Eu8, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        let mut prg = vec!(0u8; 0x4000);
+        prg[0..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFA..0x4000].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        rom.extend(prg);
+        let mut chr = vec!(0u8; 0x2000);
+        for i in 0 .. 8 {
+            chr[i] = 0xFF;
+        }
+        rom.extend(chr);
+        let mapper = cartridge::mapper_from_file(&rom).unwrap();
+        let mut nes = NesState::new(mapper);
+        nes.power_on();
+        nes.run_until_vblank();
+        return nes;
+    }
+
+    fn place_sprite_zero(nes: &mut NesState, x: u8, y: u8) {
+        nes.ppu.oam = vec!(0xFFu8; 256);
+        nes.ppu.oam[0..4].copy_from_slice(&[y, 0x00, 0x00, x]);
+        // Backgrounds and sprites on, including the leftmost column
+        memory::write_byte(nes, 0x2001, 0x1E);
+    }
+
+    #[test]
+    fn sprite_zero_hit_records_its_scanline_and_dot() {
+        let mut nes = solid_tile_nes();
+        // OAM Y is one less than the first scanline the sprite appears on
+        place_sprite_zero(&mut nes, 100, 49);
+        nes.run_until_vblank();
+        assert_eq!(nes.ppu.sprite_zero_hit_position, Some((50, 101)));
+
+        // The snapshot for display moves over once the next frame begins
+        nes.run_until_vblank();
+        assert_eq!(nes.ppu.last_sprite_zero_hit_position, Some((50, 101)));
+        assert_eq!(nes.ppu.last_sprite_overflow_position, None);
+    }
+
+    #[test]
+    fn offscreen_sprite_zero_reports_no_hit() {
+        let mut nes = solid_tile_nes();
+        place_sprite_zero(&mut nes, 100, 0xFF);
+        nes.run_until_vblank();
+        nes.run_until_vblank();
+        assert_eq!(nes.ppu.last_sprite_zero_hit_position, None);
+        assert_eq!(nes.ppu.status & 0x40, 0);
+    }
+}
@@ -0,0 +1,111 @@
+// A debugging snapshot of the console: registers and RAM, enough to see exactly where two
+// runs have drifted apart. It isn't a save state, and can't be loaded back in.
+
+use nes::NesState;
+
+use std::fmt;
+
+#[derive(Clone)]
+pub struct NesSnapshot {
+    // Register names and their values, already formatted, in a fixed order
+    pub registers: Vec<(&'static str, String)>,
+    pub work_ram: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+}
+
+impl NesSnapshot {
+    pub fn capture(nes: &NesState) -> NesSnapshot {
+        let byte = |value: u8| format!("0x{:02X}", value);
+        let word = |value: u16| format!("0x{:04X}", value);
+        let registers = vec!(
+            ("CPU.A", byte(nes.registers.a)),
+            ("CPU.X", byte(nes.registers.x)),
+            ("CPU.Y", byte(nes.registers.y)),
+            ("CPU.S", byte(nes.registers.s)),
+            ("CPU.P", byte(nes.registers.status_as_byte(false))),
+            ("CPU.PC", word(nes.registers.pc)),
+            ("PPU.CTRL", byte(nes.ppu.control)),
+            ("PPU.MASK", byte(nes.ppu.mask)),
+            ("PPU.STATUS", byte(nes.ppu.status)),
+            ("PPU.OAMADDR", byte(nes.ppu.oam_addr)),
+            ("PPU.V", word(nes.ppu.current_vram_address)),
+            ("PPU.T", word(nes.ppu.temporary_vram_address)),
+            ("PPU.X", byte(nes.ppu.fine_x)),
+            ("PPU.W", nes.ppu.write_toggle.to_string()),
+            ("APU.STATUS", byte(nes.apu.debug_read_register(0x4015))),
+            ("APU.FRAME_MODE", nes.apu.frame_sequencer_mode.to_string()),
+        );
+        return NesSnapshot {
+            registers: registers,
+            work_ram: nes.memory.work_ram().to_vec(),
+            prg_ram: nes.mapper.prg_ram().to_vec(),
+        };
+    }
+}
+
+pub enum StateDiff {
+    Register(&'static str, String, String),
+    WorkRam(usize, u8, u8),
+    PrgRam(usize, u8, u8),
+    // Snapshots of two different cartridges can disagree on how much PRG RAM there is
+    PrgRamSize(usize, usize),
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateDiff::Register(name, a, b) => write!(f, "{}: {} vs {}", name, a, b),
+            StateDiff::WorkRam(address, a, b) => write!(f, "WRAM[0x{:04X}]: {} vs {}", address, a, b),
+            StateDiff::PrgRam(address, a, b) => write!(f, "PRGRAM[0x{:04X}]: {} vs {}", address, a, b),
+            StateDiff::PrgRamSize(a, b) => write!(f, "PRGRAM size: {} vs {}", a, b),
+        }
+    }
+}
+
+// Every register and RAM byte that differs between the two, registers first
+pub fn diff_states(a: &NesSnapshot, b: &NesSnapshot) -> Vec<StateDiff> {
+    let mut diffs: Vec<StateDiff> = Vec::new();
+    for (&(name, ref a_value), &(_, ref b_value)) in a.registers.iter().zip(b.registers.iter()) {
+        if a_value != b_value {
+            diffs.push(StateDiff::Register(name, a_value.clone(), b_value.clone()));
+        }
+    }
+    for (address, (&a_byte, &b_byte)) in a.work_ram.iter().zip(b.work_ram.iter()).enumerate() {
+        if a_byte != b_byte {
+            diffs.push(StateDiff::WorkRam(address, a_byte, b_byte));
+        }
+    }
+    if a.prg_ram.len() != b.prg_ram.len() {
+        diffs.push(StateDiff::PrgRamSize(a.prg_ram.len(), b.prg_ram.len()));
+    }
+    for (address, (&a_byte, &b_byte)) in a.prg_ram.iter().zip(b.prg_ram.iter()).enumerate() {
+        if a_byte != b_byte {
+            diffs.push(StateDiff::PrgRam(address, a_byte, b_byte));
+        }
+    }
+    return diffs;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::mapper_from_file;
+
+    #[test]
+    fn one_ram_byte_and_one_register_make_two_diffs() {
+        let mut rom = vec!(0x4Eu8, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        rom.extend(vec!(0u8; 0x4000 + 0x2000));
+        let mut nes = NesState::new(mapper_from_file(&rom).ok().unwrap());
+        nes.power_on();
+        nes.registers.a = 0x12;
+        nes.memory.iram_raw[0x0300] = 5;
+        let before = NesSnapshot::capture(&nes);
+        assert_eq!(diff_states(&before, &before).len(), 0);
+
+        nes.registers.a = 0x34;
+        nes.memory.iram_raw[0x0300] = 7;
+        let after = NesSnapshot::capture(&nes);
+        let diffs: Vec<String> = diff_states(&before, &after).iter().map(|diff| diff.to_string()).collect();
+        assert_eq!(diffs, vec!("CPU.A: 0x12 vs 0x34".to_string(), "WRAM[0x0300]: 5 vs 7".to_string()));
+    }
+}
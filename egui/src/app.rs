@@ -11,6 +11,7 @@ use std::sync::mpsc::{Sender, Receiver, TryRecvError};
 pub enum ShellEvent {
     ImageRendered(String, Arc<worker::RenderedImage>),
     HasSram(bool),
+    EmulatorStalled(u32),
     SettingsUpdated(Arc<rustico_ui_common::settings::SettingsState>)
 }
 
@@ -28,6 +29,8 @@ pub struct RusticoApp {
     pub settings_cache: rustico_ui_common::settings::SettingsState,
 
     pub game_window: game_window::GameWindow,
+    pub vu_meter_texture: egui::TextureHandle,
+    pub vu_meter_scale: usize,
 }
 
 impl RusticoApp {
@@ -46,6 +49,8 @@ impl RusticoApp {
             settings_cache: rustico_ui_common::settings::SettingsState::new(),
 
             game_window: game_window::GameWindow::new(cc),
+            vu_meter_texture: cc.egui_ctx.load_texture("vu_meter_canvas", egui::ColorImage::new([256, 36], egui::Color32::BLACK), egui::TextureOptions::default()),
+            vu_meter_scale: 2,
         }
     }
 
@@ -79,6 +84,18 @@ impl RusticoApp {
             ShellEvent::SettingsUpdated(settings_object) => {
                 self.settings_cache = Arc::unwrap_or_clone(settings_object);
             },
+            ShellEvent::ImageRendered(id, canvas) => {
+                if id == "vu_meter" {
+                    let image = egui::ColorImage::from_rgba_unmultiplied([canvas.width, canvas.height], &canvas.rgba_buffer);
+                    let texture_options = egui::TextureOptions{
+                        magnification: egui::TextureFilter::Nearest,
+                        minification: egui::TextureFilter::Nearest,
+                        ..egui::TextureOptions::default()
+                    };
+                    self.vu_meter_texture.set(image, texture_options);
+                    self.vu_meter_scale = canvas.scale;
+                }
+            },
             _ => {}
         }
     }
@@ -256,6 +273,33 @@ impl eframe::App for RusticoApp {
                 }
             );
         }
+
+        if self.game_window.show_vu_meter {
+            let vu_meter_width = (self.vu_meter_texture.size()[0] * self.vu_meter_scale) as f32;
+            let vu_meter_height = (self.vu_meter_texture.size()[1] * self.vu_meter_scale) as f32;
+            ctx.show_viewport_immediate(
+                egui::ViewportId::from_hash_of("vu_meter_viewport"),
+                egui::ViewportBuilder::default()
+                    .with_title("VU Meter")
+                    .with_inner_size([vu_meter_width, vu_meter_height]),
+                |ctx, class| {
+                    assert!(
+                        class == egui::ViewportClass::Immediate,
+                        "This egui backend doesn't support multiple viewports!"
+                    );
+                    egui::CentralPanel::default().frame(egui::Frame::none()).show(ctx, |ui| {
+                        ui.add(
+                            egui::Image::new(egui::load::SizedTexture::from_handle(&self.vu_meter_texture))
+                                .fit_to_exact_size([vu_meter_width, vu_meter_height].into())
+                        );
+                    });
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        self.game_window.show_vu_meter = false;
+                        let _ = self.runtime_tx.send(events::Event::HideVuMeterWindow);
+                    }
+                }
+            );
+        }
     }
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
@@ -20,6 +20,10 @@ pub struct GameWindow {
     pub game_window_scale: usize,
     pub sram_path: PathBuf,
     pub has_sram: bool,
+    // Shown at the end of the menu bar until dismissed, for problems the user should know about
+    pub status_message: Option<String>,
+    // The VU meter lives in its own viewport, which RusticoApp shows while this is set
+    pub show_vu_meter: bool,
 }
 
 impl GameWindow {
@@ -34,6 +38,8 @@ impl GameWindow {
             game_window_scale: 2,
             sram_path: PathBuf::new(),
             has_sram: false,
+            status_message: None,
+            show_vu_meter: false,
         };
     }
 
@@ -42,6 +48,9 @@ impl GameWindow {
             ShellEvent::HasSram(has_sram) => {
                 self.has_sram = has_sram;
             },
+            ShellEvent::EmulatorStalled(scanlines_run) => {
+                self.status_message = Some(format!("Emulator stalled ({} scanlines without progress)", scanlines_run));
+            },
             ShellEvent::ImageRendered(id, canvas) => {
                 if id == "game_window" {
                     self.last_rendered_frames.push_back(canvas);
@@ -193,7 +202,19 @@ impl GameWindow {
                         //self.show_piano_roll = !self.show_piano_roll;
                         ui.close_menu();
                     }
+                    if ui.button("VU Meter").clicked() {
+                        self.show_vu_meter = true;
+                        let _ = runtime_tx.send(events::Event::ShowVuMeterWindow);
+                        ui.close_menu();
+                    }
                 });
+                if let Some(message) = self.status_message.clone() {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::from_rgb(0xFF, 0x80, 0x40), message);
+                    if ui.small_button("x").clicked() {
+                        self.status_message = None;
+                    }
+                }
             });
         });
 
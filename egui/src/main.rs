@@ -1,7 +1,5 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-#[macro_use]
-extern crate lazy_static;
 extern crate rustico_core;
 extern crate rustico_ui_common;
 
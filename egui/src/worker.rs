@@ -1,22 +1,189 @@
 use crate::app;
 
 use rustico_ui_common::application::RuntimeState as RusticoRuntimeState;
+use rustico_ui_common::application::run_bounded_step;
 use rustico_ui_common::events;
 use rustico_ui_common::game_window::GameWindow;
 use rustico_ui_common::panel::Panel;
+use rustico_ui_common::resampler::Resampler;
+use rustico_ui_common::resampler::resampler_from_quality;
+use rustico_ui_common::resampler::resampler_from_settings;
+use rustico_ui_common::vu_meter_window::VuMeterWindow;
+
+use rustico_core::logging;
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io::Write;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::thread;
 use std::time::Duration;
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 
-lazy_static! {
-    pub static ref AUDIO_OUTPUT_BUFFER: Mutex<VecDeque<f32>> = Mutex::new(VecDeque::new());
+// The worker produces samples and the audio callback consumes them, through a lock-free
+// single producer / single consumer ring, so neither one can stall the other. This is
+// far more than we ever buffer (step_emulator stops at 512), so in practice it never fills.
+const AUDIO_RING_CAPACITY: usize = 16384;
+
+// If the audio buffer drops below this when a frame completes, the renderer is falling
+// behind, and that frame is a candidate for skipping (see render.max_frameskip)
+const FRAMESKIP_AUDIO_THRESHOLD: usize = 256;
+
+const EVENT_LOG_CAPACITY: usize = 4096;
+const EVENT_LOG_MAX_DESCRIPTION: usize = 160;
+
+// Every dispatched event, oldest first, with how many dispatches deep it was produced
+pub struct EventLog {
+    pub entries: VecDeque<(usize, String)>,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        return EventLog{entries: VecDeque::new()};
+    }
+
+    pub fn record(&mut self, event: &events::Event, depth: usize) {
+        // Some events carry entire ROM images, so keep the descriptions short
+        let mut description = format!("{:?}", event);
+        if description.len() > EVENT_LOG_MAX_DESCRIPTION {
+            let mut cutoff = EVENT_LOG_MAX_DESCRIPTION;
+            while !description.is_char_boundary(cutoff) {
+                cutoff -= 1;
+            }
+            description.truncate(cutoff);
+            description.push_str("...");
+        }
+        if self.entries.len() >= EVENT_LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((depth, description));
+    }
+
+    // One event per line, indented by depth so responses sit under their cause
+    pub fn to_text(&self) -> String {
+        let mut log_text = String::new();
+        for (depth, description) in self.entries.iter() {
+            log_text.push_str(&"  ".repeat(*depth));
+            log_text.push_str(description);
+            log_text.push('\n');
+        }
+        return log_text;
+    }
+}
+
+// What the audio callback plays when the worker hasn't kept up
+#[derive(Clone, Copy, PartialEq)]
+pub enum UnderrunFill {
+    Silence,
+    // Repeat the last sample, so the waveform doesn't jump to zero and click
+    HoldLast,
+    // Ramp from the last sample down to zero over the block
+    Fade,
+}
+
+impl UnderrunFill {
+    pub fn from_setting(value: &str) -> Option<UnderrunFill> {
+        return match value {
+            "silence" => Some(UnderrunFill::Silence),
+            "hold_last" => Some(UnderrunFill::HoldLast),
+            "fade" => Some(UnderrunFill::Fade),
+            _ => None
+        };
+    }
+
+    fn from_u8(value: u8) -> UnderrunFill {
+        return match value {
+            1 => UnderrunFill::HoldLast,
+            2 => UnderrunFill::Fade,
+            _ => UnderrunFill::Silence
+        };
+    }
+
+    fn to_u8(self) -> u8 {
+        return match self {
+            UnderrunFill::Silence => 0,
+            UnderrunFill::HoldLast => 1,
+            UnderrunFill::Fade => 2,
+        };
+    }
+}
+
+// Fills an output block during an underrun, and returns the new "last sample"
+pub fn fill_underrun(data: &mut [f32], strategy: UnderrunFill, last_sample: f32) -> f32 {
+    match strategy {
+        UnderrunFill::Silence => {
+            for sample in data.iter_mut() {
+                *sample = cpal::Sample::EQUILIBRIUM;
+            }
+            return 0.0;
+        },
+        UnderrunFill::HoldLast => {
+            for sample in data.iter_mut() {
+                *sample = last_sample;
+            }
+            return last_sample;
+        },
+        UnderrunFill::Fade => {
+            let length = data.len() as f32;
+            for (i, sample) in data.iter_mut().enumerate() {
+                *sample = last_sample * (1.0 - (i + 1) as f32 / length);
+            }
+            return 0.0;
+        }
+    }
+}
+
+// The body of the audio callback: plays a whole block from the ring if there's enough queued
+// up, otherwise leaves the ring alone and fills in. Returns the last sample played.
+fn fill_from_ring(data: &mut [f32], audio_consumer: &mut HeapConsumer<f32>, strategy: UnderrunFill, last_sample: f32) -> f32 {
+    if audio_consumer.len() > data.len() {
+        audio_consumer.pop_slice(data);
+        return *data.last().unwrap_or(&0.0);
+    }
+    return fill_underrun(data, strategy, last_sample);
+}
+
+// Applies one scanline's worth of samples to the audio buffer (if the ring is somehow full, the
+// excess is dropped). Master mute still pushes the same amount, so the audio clock keeps pacing
+// emulation.
+fn push_output_samples(audio_producer: &mut HeapProducer<f32>, samples: &mut Vec<f32>, master_mute: bool) -> usize {
+    if master_mute {
+        for sample in samples.iter_mut() {
+            *sample = 0.0;
+        }
+    }
+    return audio_producer.push_slice(samples);
+}
+
+// Decides which completed frames get drawn. Never skips more than max_frameskip frames in a
+// row, so the game window still updates (slowly) under sustained load.
+pub struct FrameSkipper {
+    pub max_frameskip: u32,
+    frames_skipped: u32,
+}
+
+impl FrameSkipper {
+    pub fn new() -> FrameSkipper {
+        return FrameSkipper{max_frameskip: 0, frames_skipped: 0};
+    }
+
+    pub fn set_max_frameskip(&mut self, max_frameskip: u32) {
+        self.max_frameskip = max_frameskip;
+        self.frames_skipped = 0;
+    }
+
+    // Call once per completed frame; returns true if that frame should be drawn
+    pub fn frame_completed(&mut self, behind: bool) -> bool {
+        if behind && self.frames_skipped < self.max_frameskip {
+            self.frames_skipped += 1;
+            return false;
+        }
+        self.frames_skipped = 0;
+        return true;
+    }
 }
 
 pub struct RenderedImage {
@@ -26,102 +193,178 @@ pub struct RenderedImage {
     pub rgba_buffer: Vec<u8>,
 }
 
-struct Worker {
-    runtime_rx: Receiver<events::Event>,
-    shell_tx: Sender<app::ShellEvent>,
-
-    // We need to keep the audio stream around so that it continues to run, but
-    // we never need to read it directly. Rust complains about this. :)
-    _audio_stream: Box<dyn StreamTrait>,
-    runtime_state: RusticoRuntimeState,
-    game_window: GameWindow,
-
-    exit_requested: bool,
+// Everything a tick of emulation writes to besides the runtime: the audio on its way to the
+// ring, and how completed frames get paced
+pub struct Ticker {
+    deterministic: bool,
+    output_sample_rate: u64,
+    resampler: Box<dyn Resampler>,
+    // Scratch space for each scanline's worth of audio. step_emulator runs a few hundred
+    // scanlines per frame, and used to allocate two fresh Vecs for every one of them;
+    // reusing these brings that down to zero once they've grown to size.
+    apu_samples: Vec<f32>,
+    resampled_samples: Vec<f32>,
+    audio_producer: HeapProducer<f32>,
+    frame_skipper: FrameSkipper,
 }
 
-impl Worker {
-    pub fn new(runtime_rx: Receiver<events::Event>, shell_tx: Sender<app::ShellEvent>) -> Worker {
-        let audio_stream = setup_audio_stream();
-        let runtime_state = RusticoRuntimeState::new();
-        let game_window = GameWindow::new();
-
-        return Worker{
-            runtime_rx: runtime_rx,
-            shell_tx: shell_tx,
-            _audio_stream: audio_stream,
-            runtime_state: runtime_state,
-            game_window: game_window,
-            exit_requested: false
+impl Ticker {
+    pub fn new(runtime_state: &RusticoRuntimeState, output_sample_rate: u64, audio_producer: HeapProducer<f32>) -> Ticker {
+        return Ticker{
+            deterministic: false,
+            output_sample_rate: output_sample_rate,
+            resampler: resampler_from_settings(&runtime_state.settings, runtime_state.nes.apu.sample_rate(), output_sample_rate),
+            apu_samples: Vec::new(),
+            resampled_samples: Vec::new(),
+            audio_producer: audio_producer,
+            frame_skipper: FrameSkipper::new(),
         };
     }
 
-    pub fn process_incoming_events(&mut self) {
-        loop {
-            match self.runtime_rx.try_recv() {
-                Ok(event) => {
-                    self.dispatch_event(event);
-                },
-                Err(error) => {
-                    match error {
-                        TryRecvError::Empty => {
-                            // all done!
-                            return
-                        },
-                        TryRecvError::Disconnected => {
-                            // PANIC AT THE DISCO, ALL HOPE IS LOST!
-                            // (We're just shutting down, it's fine)
-                            return
-                        }
-                    }
+    pub fn handle_event(&mut self, runtime_state: &RusticoRuntimeState, event: events::Event) -> Vec<events::Event> {
+        match event {
+            rustico_ui_common::Event::ApplyBooleanSetting(path, value) => {
+                if path == "emulation.deterministic" {
+                    self.deterministic = value;
                 }
-            }
+            },
+            rustico_ui_common::Event::ApplyIntegerSetting(path, value) => {
+                if path == "render.max_frameskip" {
+                    self.frame_skipper.set_max_frameskip(value as u32);
+                }
+            },
+            rustico_ui_common::Event::ApplyStringSetting(path, value) => {
+                if path == "audio.resampler_quality" {
+                    self.resampler = resampler_from_quality(&value, runtime_state.nes.apu.sample_rate(), self.output_sample_rate);
+                }
+            },
+            _ => {}
         }
+        return Vec::new();
     }
+}
 
-    pub fn dispatch_event(&mut self, event: events::Event) {
-        let mut responses: Vec<events::Event> = Vec::new();
-        responses.extend(self.runtime_state.handle_event(event.clone()));
-        responses.extend(self.game_window.handle_event(&self.runtime_state, event.clone()));
-        responses.extend(self.handle_event(event.clone()));
-        for response in responses {
-            self.dispatch_event(response);
+// Runs one scanline and queues up its audio. Returns true if that completed a frame.
+fn run_scanline(runtime_state: &mut RusticoRuntimeState, ticker: &mut Ticker, dispatch: &mut dyn FnMut(&mut RusticoRuntimeState, &mut Ticker, events::Event)) -> bool {
+    dispatch(runtime_state, ticker, events::Event::NesRunScanline);
+    let frame_completed = runtime_state.nes.ppu.current_scanline == 242;
+    ticker.apu_samples.clear();
+    ticker.resampled_samples.clear();
+    runtime_state.nes.apu.consume_samples_f32_into(&mut ticker.apu_samples);
+    ticker.resampler.process_into(&ticker.apu_samples, &mut ticker.resampled_samples);
+    push_output_samples(&mut ticker.audio_producer, &mut ticker.resampled_samples, runtime_state.master_mute);
+
+    if frame_completed {
+        // The meter measures every frame, even ones we end up not drawing
+        dispatch(runtime_state, ticker, events::Event::Update);
+        // we just finished a game frame, so have the game window repaint itself, unless
+        // we're running behind. Audio above is never skipped, only the drawing.
+        let behind = !ticker.deterministic && ticker.audio_producer.len() < FRAMESKIP_AUDIO_THRESHOLD;
+        if ticker.frame_skipper.frame_completed(behind) {
+            dispatch(runtime_state, ticker, events::Event::RequestFrame);
         }
     }
+    return frame_completed;
+}
+
+// The body of step_emulator: as long as we are under the audio threshold, run one scanline,
+// queueing its audio in the ring. Every event goes through `dispatch`, which should hand it
+// to everything listening, and is lent the ticker back so settings can reach it mid-tick.
+// (512 is arbitrary, make this configurable later!)
+fn run_tick(runtime_state: &mut RusticoRuntimeState, ticker: &mut Ticker, dispatch: &mut dyn FnMut(&mut RusticoRuntimeState, &mut Ticker, events::Event)) -> Result<usize, usize> {
+    if ticker.deterministic {
+        // Ignore audio pressure entirely, and run exactly one frame per tick. The core itself
+        // has no random state (RAM and mapper registers power on to fixed values), so with
+        // the same inputs this produces the same frames and audio every time.
+        return run_bounded_step(|| run_scanline(runtime_state, ticker, dispatch));
+    }
+    if ticker.audio_producer.len() < 512 {
+        return run_bounded_step(|| {
+            run_scanline(runtime_state, ticker, dispatch);
+            return ticker.audio_producer.len() >= 512;
+        });
+    }
+    return Ok(0);
+}
 
-    pub fn handle_event(&mut self, event: events::Event) -> Vec<events::Event> {
-        // For now, the WORKER doesn't need to do anything with runtime events. Later it might
+// The worker's own side of event handling: talking to the shell, and the audio stream
+pub struct ShellLink {
+    shell_tx: Sender<app::ShellEvent>,
+    // We need to keep the audio stream around so that it continues to run. Other than
+    // pausing and resuming it, we never touch it directly.
+    audio_stream: Box<dyn StreamTrait>,
+    audio_paused: bool,
+    underrun_fill: Arc<AtomicU8>,
+    // Every dispatched event along with its recursion depth, when enabled
+    event_log: Option<EventLog>,
+    // Set when the game window has drawn a frame that hasn't been sent to the shell yet
+    frame_pending: bool,
+    exit_requested: bool,
+}
+
+impl ShellLink {
+    pub fn handle_event(&mut self, runtime_state: &RusticoRuntimeState, event: events::Event) -> Vec<events::Event> {
+        // For now, the WORKER doesn't need to respond to runtime events. Later it might
         // and this is where those would get handled. Setting this up now for consistency.
         let events: Vec<events::Event> = Vec::new();
         match event {
             rustico_ui_common::Event::CartridgeLoaded(_id) => {
-                let has_sram = self.runtime_state.nes.mapper.has_sram();
+                let has_sram = runtime_state.nes.mapper.has_sram();
                 let _ = self.shell_tx.send(app::ShellEvent::HasSram(has_sram));
             }
             rustico_ui_common::Event::SaveSram(sram_id, sram_data) => {
                 self.save_sram(sram_id, &sram_data);
             },
+            rustico_ui_common::Event::EmulatorStalled(scanlines_run) => {
+                let _ = self.shell_tx.send(app::ShellEvent::EmulatorStalled(scanlines_run));
+            },
+            rustico_ui_common::Event::RequestFrame => {
+                self.frame_pending = true;
+            },
+            rustico_ui_common::Event::NesPauseEmulation => {
+                self.sync_audio_stream(runtime_state);
+            },
+            rustico_ui_common::Event::NesResumeEmulation => {
+                self.sync_audio_stream(runtime_state);
+            },
+            rustico_ui_common::Event::NesToggleEmulation => {
+                self.sync_audio_stream(runtime_state);
+            },
+            rustico_ui_common::Event::DumpEventLog(filename) => {
+                self.dump_event_log(&filename);
+            },
             rustico_ui_common::Event::CloseApplication => {
-                println!("WORKER: application close requested, will exit after processing remaining events...");
+                logging::debug("WORKER: application close requested, will exit after processing remaining events...");
                 self.exit_requested = true;
             },
-            rustico_ui_common::Event::ApplyBooleanSetting(_,_) => {
+            rustico_ui_common::Event::ApplyBooleanSetting(path, value) => {
+                // Settings are replayed on every cartridge load, which shouldn't wipe the log
+                if path == "debug.event_log" && value != self.event_log.is_some() {
+                    self.event_log = if value {Some(EventLog::new())} else {None};
+                }
                 let _ = self.shell_tx.send(app::ShellEvent::SettingsUpdated(
-                    Arc::new(self.runtime_state.settings.clone())
+                    Arc::new(runtime_state.settings.clone())
                 ));
             },
             rustico_ui_common::Event::ApplyIntegerSetting(_,_) => {
                 let _ = self.shell_tx.send(app::ShellEvent::SettingsUpdated(
-                    Arc::new(self.runtime_state.settings.clone())
+                    Arc::new(runtime_state.settings.clone())
                 ));
             },
             rustico_ui_common::Event::ApplyFloatSetting(_,_) => {
                 let _ = self.shell_tx.send(app::ShellEvent::SettingsUpdated(
-                    Arc::new(self.runtime_state.settings.clone())
+                    Arc::new(runtime_state.settings.clone())
                 ));
             },
-            rustico_ui_common::Event::ApplyStringSetting(_,_) => {
+            rustico_ui_common::Event::ApplyStringSetting(path, value) => {
+                if path == "audio.underrun_fill" {
+                    match UnderrunFill::from_setting(&value) {
+                        Some(strategy) => {self.underrun_fill.store(strategy.to_u8(), Ordering::Relaxed)},
+                        None => {logging::warn(&format!("Unknown underrun fill {}, ignoring.", value))}
+                    }
+                }
                 let _ = self.shell_tx.send(app::ShellEvent::SettingsUpdated(
-                    Arc::new(self.runtime_state.settings.clone())
+                    Arc::new(runtime_state.settings.clone())
                 ));
             },
             _ => {}
@@ -129,48 +372,140 @@ impl Worker {
         return events;
     }
 
+    // The runtime has already processed the pause / resume by the time we see it, so just
+    // bring the stream in line with whatever state it ended up in
+    pub fn sync_audio_stream(&mut self, runtime_state: &RusticoRuntimeState) {
+        sync_stream_to_emulation(&*self.audio_stream, &mut self.audio_paused, runtime_state.running);
+    }
+
     pub fn save_sram(&self, filename: String, sram_data: &[u8]) {
         let file = File::create(filename.clone());
         match file {
             Err(why) => {
-                println!("Couldn't open {}: {}", filename, why.to_string());
+                logging::error(&format!("Couldn't open {}: {}", filename, why.to_string()));
             },
             Ok(mut file) => {
                 let _ = file.write_all(sram_data);
-                println!("Wrote sram data to: {}", filename);
+                logging::info(&format!("Wrote sram data to: {}", filename));
             },
         };
     }
 
-    pub fn step_emulator(&mut self) {
-        // Quickly poll the length of the audio buffer
-        let audio_output_buffer = AUDIO_OUTPUT_BUFFER.lock().expect("wat");
-        let mut output_buffer_len = audio_output_buffer.len();
-        drop(audio_output_buffer); // immediately free the mutex, so running the emulator doesn't starve the audio thread
-
-        // Now we do fun stuff: as long as we are under the audio threshold, run one scanline. If we happen
-        // to complete a frame while doing this, update the game window texture (and later, call "draw" on all
-        // active subwindows so they know to repaint)
-        // (2048 is arbitrary, make this configurable later!)
-        let mut repaint_needed = false;
-        while output_buffer_len < 512 {
-            self.dispatch_event(events::Event::NesRunScanline);
-            if self.runtime_state.nes.ppu.current_scanline == 242 {
-                // we just finished a game frame, so have the game window repaint itself
-                self.dispatch_event(events::Event::RequestFrame);
-                repaint_needed = true;
+    fn dump_event_log(&self, filename: &str) {
+        let event_log = match self.event_log {
+            Some(ref event_log) => event_log,
+            None => {
+                logging::info("Event log is disabled, set debug.event_log to enable it.");
+                return;
             }
-            let samples_i16 = self.runtime_state.nes.apu.consume_samples();
-            let samples_float: Vec<f32> = samples_i16.into_iter().map(|x| <i16 as Into<f32>>::into(x) / 32767.0).collect();
-            // Apply those samples to the audio buffer AND recheck our count
-            // (keep going until we rise above the threshold)
-            let mut audio_output_buffer = AUDIO_OUTPUT_BUFFER.lock().expect("wat");
-            audio_output_buffer.extend(samples_float);
-            output_buffer_len = audio_output_buffer.len();
-            drop(audio_output_buffer);
+        };
+        match File::create(filename).and_then(|mut file| file.write_all(event_log.to_text().as_bytes())) {
+            Ok(_) => {logging::info(&format!("Wrote {} events to: {}", event_log.entries.len(), filename));},
+            Err(why) => {logging::error(&format!("Couldn't write event log to {}: {}", filename, why));}
         }
+    }
+}
 
-        if repaint_needed {
+// Hands an event to the runtime, then each panel, then the ticker and the worker's own
+// handling, logging it first if the log is enabled, and does the same for whatever they
+// respond with. Everything the worker dispatches goes through here.
+fn dispatch_event_at_depth(runtime_state: &mut RusticoRuntimeState, panels: &mut [&mut dyn Panel], ticker: &mut Ticker, shell: &mut ShellLink, event: events::Event, depth: usize) {
+    if let Some(ref mut event_log) = shell.event_log {
+        event_log.record(&event, depth);
+    }
+    let mut responses: Vec<events::Event> = Vec::new();
+    responses.extend(runtime_state.handle_event(event.clone()));
+    for panel in panels.iter_mut() {
+        responses.extend(panel.handle_event(runtime_state, event.clone()));
+    }
+    responses.extend(ticker.handle_event(runtime_state, event.clone()));
+    responses.extend(shell.handle_event(runtime_state, event.clone()));
+    for response in responses {
+        dispatch_event_at_depth(runtime_state, panels, ticker, shell, response, depth + 1);
+    }
+}
+
+struct Worker {
+    runtime_rx: Receiver<events::Event>,
+    runtime_state: RusticoRuntimeState,
+    game_window: GameWindow,
+    vu_meter_window: VuMeterWindow,
+    ticker: Ticker,
+    shell: ShellLink,
+}
+
+impl Worker {
+    pub fn new(runtime_rx: Receiver<events::Event>, shell_tx: Sender<app::ShellEvent>) -> Worker {
+        let underrun_fill = Arc::new(AtomicU8::new(UnderrunFill::Silence.to_u8()));
+        let (audio_stream, output_sample_rate, audio_producer) = setup_audio_stream(underrun_fill.clone());
+        let runtime_state = RusticoRuntimeState::new();
+        let ticker = Ticker::new(&runtime_state, output_sample_rate, audio_producer);
+
+        return Worker{
+            runtime_rx: runtime_rx,
+            runtime_state: runtime_state,
+            game_window: GameWindow::new(),
+            vu_meter_window: VuMeterWindow::new(),
+            ticker: ticker,
+            shell: ShellLink{
+                shell_tx: shell_tx,
+                audio_stream: audio_stream,
+                audio_paused: false,
+                underrun_fill: underrun_fill,
+                event_log: None,
+                frame_pending: false,
+                exit_requested: false,
+            },
+        };
+    }
+
+    pub fn process_incoming_events(&mut self) {
+        loop {
+            match self.runtime_rx.try_recv() {
+                Ok(event) => {
+                    self.dispatch_event(event);
+                },
+                Err(error) => {
+                    match error {
+                        TryRecvError::Empty => {
+                            // all done!
+                            return
+                        },
+                        TryRecvError::Disconnected => {
+                            // PANIC AT THE DISCO, ALL HOPE IS LOST!
+                            // (We're just shutting down, it's fine)
+                            return
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn dispatch_event(&mut self, event: events::Event) {
+        let mut panels: [&mut dyn Panel; 2] = [&mut self.game_window, &mut self.vu_meter_window];
+        dispatch_event_at_depth(&mut self.runtime_state, &mut panels, &mut self.ticker, &mut self.shell, event, 0);
+    }
+
+    pub fn step_emulator(&mut self) {
+        if !self.runtime_state.running {
+            return;
+        }
+
+        let step_result = {
+            let mut panels: [&mut dyn Panel; 2] = [&mut self.game_window, &mut self.vu_meter_window];
+            let shell = &mut self.shell;
+            run_tick(&mut self.runtime_state, &mut self.ticker, &mut |runtime_state, ticker, event| {
+                dispatch_event_at_depth(runtime_state, &mut panels, ticker, shell, event, 0);
+            })
+        };
+        if let Err(scanlines_run) = step_result {
+            logging::warn(&format!("Emulator ran {} scanlines without finishing a step, bailing out.", scanlines_run));
+            self.dispatch_event(events::Event::EmulatorStalled(scanlines_run as u32));
+        }
+
+        if self.shell.frame_pending {
+            self.shell.frame_pending = false;
             let repaint_event = app::ShellEvent::ImageRendered(
                 "game_window".to_string(),
                 Arc::new(RenderedImage{
@@ -180,12 +515,23 @@ impl Worker {
                     rgba_buffer: Vec::from(self.game_window.canvas.buffer.clone())
                 })
             );
-            let _ = self.shell_tx.send(repaint_event);
+            let _ = self.shell.shell_tx.send(repaint_event);
+            if self.vu_meter_window.shown() {
+                let _ = self.shell.shell_tx.send(app::ShellEvent::ImageRendered(
+                    "vu_meter".to_string(),
+                    Arc::new(RenderedImage{
+                        width: self.vu_meter_window.canvas.width as usize,
+                        height: self.vu_meter_window.canvas.height as usize,
+                        scale: self.vu_meter_window.scale_factor() as usize,
+                        rgba_buffer: Vec::from(self.vu_meter_window.canvas.buffer.clone())
+                    })
+                ));
+            }
         }
     }
 }
 
-pub fn setup_audio_stream() -> Box<dyn StreamTrait> {
+pub fn setup_audio_stream(underrun_fill: Arc<AtomicU8>) -> (Box<dyn StreamTrait>, u64, HeapProducer<f32>) {
     // Setup the audio callback, which will ultimately be in charge of trying to step emulation
     let host = cpal::default_host();
     let device = host.default_output_device().expect("no output device available");
@@ -193,45 +539,64 @@ pub fn setup_audio_stream() -> Box<dyn StreamTrait> {
     // TODO: eventually we want to present the supported configs to the end user, and let
     // them pick
     let default_output_config = device.default_output_config().unwrap();
-    println!("default config would be: {:?}", default_output_config);
+    logging::debug(&format!("default config would be: {:?}", default_output_config));
 
     let mut stream_config: cpal::StreamConfig = default_output_config.into();
     stream_config.buffer_size = cpal::BufferSize::Fixed(256);
     stream_config.channels = 1;
-    println!("stream config will be: {:?}", stream_config);
+    logging::debug(&format!("stream config will be: {:?}", stream_config));
+    let output_sample_rate = stream_config.sample_rate.0 as u64;
 
+    let audio_ring = HeapRb::<f32>::new(AUDIO_RING_CAPACITY);
+    let (audio_producer, mut audio_consumer): (HeapProducer<f32>, HeapConsumer<f32>) = audio_ring.split();
+
+    let mut last_sample: f32 = 0.0;
     let stream = device.build_output_stream(
         &stream_config.into(),
         move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            let mut audio_output_buffer = AUDIO_OUTPUT_BUFFER.lock().expect("wat");
-            if audio_output_buffer.len() > data.len() {
-                let output_samples = audio_output_buffer.drain(0..data.len()).collect::<VecDeque<f32>>();
-                for i in 0 .. data.len() {
-                    data[i] = output_samples[i];
-                }
-            } else {
-                for sample in data.iter_mut() {
-                    *sample = cpal::Sample::EQUILIBRIUM;
-                }
-            }
+            let strategy = UnderrunFill::from_u8(underrun_fill.load(Ordering::Relaxed));
+            last_sample = fill_from_ring(data, &mut audio_consumer, strategy, last_sample);
         },
         move |err| {
-            println!("Audio error occurred: {}", err)
+            logging::error(&format!("Audio error occurred: {}", err))
         },
         None // None=blocking, Some(Duration)=timeout
     ).unwrap();
 
     stream.play().unwrap();
 
-    return Box::new(stream);
+    return (Box::new(stream), output_sample_rate, audio_producer);
+}
+
+// Pauses the stream when emulation stops and plays it again when it starts back up. Only
+// touches the stream when the state actually changes.
+fn sync_stream_to_emulation(stream: &dyn StreamTrait, audio_paused: &mut bool, running: bool) {
+    if !running && !*audio_paused {
+        match stream.pause() {
+            Ok(_) => {},
+            Err(why) => {
+                // Some backends can't pause. That's fine: step_emulator stops feeding the
+                // buffer while we're paused, and the callback plays silence once it runs dry
+                logging::warn(&format!("Couldn't pause the audio stream ({}), will output silence instead.", why));
+            }
+        }
+        *audio_paused = true;
+    }
+    if running && *audio_paused {
+        match stream.play() {
+            Ok(_) => {},
+            Err(why) => {
+                logging::warn(&format!("Couldn't resume the audio stream: {}", why));
+            }
+        }
+        *audio_paused = false;
+    }
 }
 
 pub fn worker_main(runtime_rx: Receiver<events::Event>, shell_tx: Sender<app::ShellEvent>) {
-    // We don't need to DO anything with the stream, but we do need to keep it around
-    // or it will stop playing.
     let mut worker = Worker::new(runtime_rx, shell_tx);
 
-    while worker.exit_requested == false {
+    while worker.shell.exit_requested == false {
         worker.process_incoming_events();
         worker.step_emulator();
         thread::sleep(Duration::from_millis(1));
@@ -240,5 +605,354 @@ pub fn worker_main(runtime_rx: Receiver<events::Event>, shell_tx: Sender<app::Sh
     // one more time, just in case things arrive out of order
     thread::sleep(Duration::from_millis(1));
     worker.process_incoming_events();
-    println!("WORKER: finished! proceeding to exit.")
-}
\ No newline at end of file
+    logging::debug("WORKER: finished! proceeding to exit.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustico_ui_common::input_movie::InputMovie;
+    use std::cell::Cell;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hash;
+    use std::hash::Hasher;
+
+    // Counts calls instead of touching a real device
+    struct MockStream {
+        plays: Cell<u32>,
+        pauses: Cell<u32>,
+        can_pause: bool,
+    }
+
+    impl MockStream {
+        fn new(can_pause: bool) -> MockStream {
+            return MockStream{plays: Cell::new(0), pauses: Cell::new(0), can_pause: can_pause};
+        }
+    }
+
+    impl StreamTrait for MockStream {
+        fn play(&self) -> Result<(), cpal::PlayStreamError> {
+            self.plays.set(self.plays.get() + 1);
+            return Ok(());
+        }
+
+        fn pause(&self) -> Result<(), cpal::PauseStreamError> {
+            self.pauses.set(self.pauses.get() + 1);
+            if !self.can_pause {
+                return Err(cpal::PauseStreamError::DeviceNotAvailable);
+            }
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn ring_handoff_loses_no_samples_between_threads() {
+        // Every sample is a distinct, exactly representable, nonzero value, so anything dropped,
+        // duplicated or reordered shows up, and silence from an underrun is easy to skip over
+        const SAMPLE_COUNT: usize = 200_000;
+        let audio_ring = HeapRb::<f32>::new(AUDIO_RING_CAPACITY);
+        let (mut audio_producer, mut audio_consumer) = audio_ring.split();
+
+        let producer = thread::spawn(move || {
+            let mut next = 1;
+            while next <= SAMPLE_COUNT {
+                // Roughly a scanline's worth at a time, like step_emulator
+                let chunk: Vec<f32> = (next .. (next + 29).min(SAMPLE_COUNT + 1)).map(|i| i as f32).collect();
+                let mut pushed = 0;
+                while pushed < chunk.len() {
+                    pushed += audio_producer.push_slice(&chunk[pushed ..]);
+                    if pushed < chunk.len() {
+                        thread::yield_now();
+                    }
+                }
+                next += chunk.len();
+            }
+        });
+
+        let consumer = thread::spawn(move || {
+            let mut received: Vec<f32> = Vec::new();
+            let mut data = [0.0f32; 256];
+            let mut last_sample = 0.0;
+            // The callback never drains the last partial block, so stop once only that's left
+            while received.len() + data.len() < SAMPLE_COUNT {
+                last_sample = fill_from_ring(&mut data, &mut audio_consumer, UnderrunFill::Silence, last_sample);
+                received.extend(data.iter().filter(|&&sample| sample != 0.0));
+            }
+            return (received, audio_consumer.len());
+        });
+
+        producer.join().unwrap();
+        let (received, left_in_ring) = consumer.join().unwrap();
+        assert_eq!(received.len() + left_in_ring, SAMPLE_COUNT);
+        for (i, &sample) in received.iter().enumerate() {
+            assert_eq!(sample, (i + 1) as f32);
+        }
+    }
+
+    #[test]
+    fn underruns_leave_the_ring_alone() {
+        let audio_ring = HeapRb::<f32>::new(16);
+        let (mut audio_producer, mut audio_consumer) = audio_ring.split();
+        audio_producer.push_slice(&[0.5, 0.5, 0.5]);
+        let mut data = [1.0f32; 8];
+        let last_sample = fill_from_ring(&mut data, &mut audio_consumer, UnderrunFill::HoldLast, 0.25);
+        assert_eq!(last_sample, 0.25);
+        assert_eq!(data, [0.25; 8]);
+        assert_eq!(audio_consumer.len(), 3);
+    }
+
+    // What the callback plays from an empty ring, under a given strategy
+    fn underrun_block(strategy: UnderrunFill, last_sample: f32) -> ([f32; 4], f32) {
+        let audio_ring = HeapRb::<f32>::new(16);
+        let (_audio_producer, mut audio_consumer) = audio_ring.split();
+        let mut data = [0.75f32; 4];
+        let last_sample = fill_from_ring(&mut data, &mut audio_consumer, strategy, last_sample);
+        return (data, last_sample);
+    }
+
+    #[test]
+    fn silence_fills_underruns_with_zeroes() {
+        assert_eq!(underrun_block(UnderrunFill::Silence, 0.5), ([0.0; 4], 0.0));
+    }
+
+    #[test]
+    fn hold_last_repeats_the_last_sample() {
+        assert_eq!(underrun_block(UnderrunFill::HoldLast, 0.5), ([0.5; 4], 0.5));
+        // And keeps holding it, block after block
+        assert_eq!(underrun_block(UnderrunFill::HoldLast, 0.5).1, 0.5);
+    }
+
+    #[test]
+    fn fade_ramps_the_last_sample_to_silence() {
+        assert_eq!(underrun_block(UnderrunFill::Fade, 0.5), ([0.375, 0.25, 0.125, 0.0], 0.0));
+    }
+
+    #[test]
+    fn underrun_fill_settings_round_trip() {
+        for &name in ["silence", "hold_last", "fade"].iter() {
+            let strategy = UnderrunFill::from_setting(name).unwrap();
+            assert!(UnderrunFill::from_u8(strategy.to_u8()) == strategy);
+        }
+        assert!(UnderrunFill::from_setting("loud").is_none());
+    }
+
+    #[test]
+    fn pausing_emulation_pauses_the_stream() {
+        let stream = MockStream::new(true);
+        let mut audio_paused = false;
+        sync_stream_to_emulation(&stream, &mut audio_paused, false);
+        assert_eq!(stream.pauses.get(), 1);
+        assert!(audio_paused);
+
+        // A second pause event while already paused leaves the stream alone
+        sync_stream_to_emulation(&stream, &mut audio_paused, false);
+        assert_eq!(stream.pauses.get(), 1);
+
+        sync_stream_to_emulation(&stream, &mut audio_paused, true);
+        assert_eq!(stream.plays.get(), 1);
+        assert!(!audio_paused);
+    }
+
+    #[test]
+    fn running_emulation_never_touches_the_stream() {
+        let stream = MockStream::new(true);
+        let mut audio_paused = false;
+        sync_stream_to_emulation(&stream, &mut audio_paused, true);
+        assert_eq!(stream.pauses.get(), 0);
+        assert_eq!(stream.plays.get(), 0);
+    }
+
+    #[test]
+    fn backends_that_cant_pause_still_resume_cleanly() {
+        let stream = MockStream::new(false);
+        let mut audio_paused = false;
+        sync_stream_to_emulation(&stream, &mut audio_paused, false);
+        assert!(audio_paused);
+        sync_stream_to_emulation(&stream, &mut audio_paused, true);
+        assert_eq!(stream.plays.get(), 1);
+        assert!(!audio_paused);
+    }
+
+    fn test_ticker(runtime_state: &RusticoRuntimeState) -> (Ticker, HeapConsumer<f32>) {
+        let (audio_producer, audio_consumer) = HeapRb::<f32>::new(AUDIO_RING_CAPACITY).split();
+        return (Ticker::new(runtime_state, 48000, audio_producer), audio_consumer);
+    }
+
+    fn test_shell() -> (ShellLink, Receiver<app::ShellEvent>) {
+        let (shell_tx, shell_rx) = std::sync::mpsc::channel();
+        let shell = ShellLink{
+            shell_tx: shell_tx,
+            audio_stream: Box::new(MockStream::new(true)),
+            audio_paused: false,
+            underrun_fill: Arc::new(AtomicU8::new(UnderrunFill::Silence.to_u8())),
+            event_log: None,
+            frame_pending: false,
+            exit_requested: false,
+        };
+        return (shell, shell_rx);
+    }
+
+    #[test]
+    fn cartridge_load_records_its_events_in_order() {
+        let mut runtime_state = RusticoRuntimeState::new();
+        let mut game_window = GameWindow::new();
+        let mut vu_meter_window = VuMeterWindow::new();
+        let (mut ticker, _audio_consumer) = test_ticker(&runtime_state);
+        let (mut shell, shell_rx) = test_shell();
+
+        let mut rom = vec!(0x4Eu8, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        rom.extend(vec!(0u8; 0x4000 + 0x2000));
+        let load = events::Event::LoadCartridge("test".to_string(), Arc::new(rom), Arc::new(Vec::new()));
+        let mut panels: [&mut dyn Panel; 2] = [&mut game_window, &mut vu_meter_window];
+        // Loading replays every setting, so the log has to be turned on through them to stay on
+        dispatch_event_at_depth(&mut runtime_state, &mut panels, &mut ticker, &mut shell, events::Event::StoreBooleanSetting("debug.event_log".to_string(), true), 0);
+        shell.event_log.as_mut().unwrap().entries.clear();
+        dispatch_event_at_depth(&mut runtime_state, &mut panels, &mut ticker, &mut shell, load, 0);
+
+        // The worker's own handling saw the load too
+        assert!(shell_rx.try_iter().any(|shell_event| matches!(shell_event, app::ShellEvent::HasSram(false))));
+
+        let event_log = shell.event_log.as_ref().unwrap();
+        let entries: Vec<&(usize, String)> = event_log.entries.iter().collect();
+        assert_eq!(entries[0].0, 0);
+        assert!(entries[0].1.starts_with("LoadCartridge(\"test\""));
+        // The ROM image is far too long to keep, so its description is cut short
+        assert!(entries[0].1.ends_with("..."));
+        assert!(entries[0].1.len() <= EVENT_LOG_MAX_DESCRIPTION + 3);
+        assert_eq!(*entries[1], (1, "CartridgeLoaded(\"test\")".to_string()));
+
+        // Then the runtime replays every setting for the new cartridge, one level down, in path order
+        let replayed: Vec<&(usize, String)> = entries[2..].iter().cloned().collect();
+        assert!(replayed.len() > 0);
+        assert!(replayed.iter().all(|(depth, description)| *depth == 1 && description.starts_with("Apply")));
+        assert!(replayed[0].1.starts_with("ApplyFloatSetting(\"audio.expansion_gain.FDS\""));
+
+        let text = event_log.to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("LoadCartridge("));
+        assert_eq!(lines[1], "  CartridgeLoaded(\"test\")");
+    }
+
+    #[test]
+    fn event_log_drops_the_oldest_entries_when_full() {
+        let mut event_log = EventLog::new();
+        for _ in 0 .. EVENT_LOG_CAPACITY {
+            event_log.record(&events::Event::NesRunFrame, 0);
+        }
+        event_log.record(&events::Event::NesReset, 1);
+        assert_eq!(event_log.entries.len(), EVENT_LOG_CAPACITY);
+        assert_eq!(*event_log.entries.back().unwrap(), (1, "NesReset".to_string()));
+    }
+
+    #[test]
+    fn slow_renderer_skips_frames_up_to_the_cap() {
+        let mut frame_skipper = FrameSkipper::new();
+        frame_skipper.set_max_frameskip(3);
+        // Behind on every frame: three skipped, then one drawn anyway, over and over
+        let drawn: Vec<bool> = (0 .. 8).map(|_| frame_skipper.frame_completed(true)).collect();
+        assert_eq!(drawn, vec!(false, false, false, true, false, false, false, true));
+
+        // Catching up draws right away, and starts the count over
+        frame_skipper.frame_completed(true);
+        assert!(frame_skipper.frame_completed(false));
+        assert!(!frame_skipper.frame_completed(true));
+    }
+
+    #[test]
+    fn frameskip_of_zero_draws_everything() {
+        let mut frame_skipper = FrameSkipper::new();
+        assert!((0 .. 10).all(|_| frame_skipper.frame_completed(true)));
+    }
+
+    #[test]
+    fn master_mute_sends_silence_and_leaves_the_channels_alone() {
+        let mut runtime_state = RusticoRuntimeState::new();
+        // Pulse 1, constant volume 15, held on
+        runtime_state.nes.apu.write_register(0x4015, 0x01);
+        runtime_state.nes.apu.write_register(0x4000, 0xBF);
+        runtime_state.nes.apu.write_register(0x4002, 0xFD);
+        runtime_state.nes.apu.write_register(0x4003, 0x08);
+
+        let responses = runtime_state.handle_event(events::Event::MuteMasterAudio(true));
+        assert!(runtime_state.master_mute);
+        assert!(responses.iter().any(|event| matches!(event, events::Event::StoreBooleanSetting(path, true) if path == "audio.master_mute")));
+
+        runtime_state.nes.run_until_vblank();
+        let mut samples: Vec<f32> = Vec::new();
+        runtime_state.nes.apu.consume_samples_f32_into(&mut samples);
+        // The emulation still hears the pulse, and so does everything visualizing it
+        assert!(samples.iter().any(|&sample| sample != 0.0));
+        assert!(runtime_state.nes.apu.channels().iter().all(|channel| !channel.muted()));
+
+        let audio_ring = HeapRb::<f32>::new(AUDIO_RING_CAPACITY);
+        let (mut audio_producer, mut audio_consumer) = audio_ring.split();
+        let sample_count = samples.len();
+        assert_eq!(push_output_samples(&mut audio_producer, &mut samples, runtime_state.master_mute), sample_count);
+        let output: Vec<f32> = audio_consumer.pop_iter().collect();
+        assert_eq!(output.len(), sample_count);
+        assert!(output.iter().all(|&sample| sample == 0.0));
+    }
+
+    // Plays a movie through the worker's deterministic mode, a tick at a time. Returns the final
+    // RAM and a hash of every sample pushed into the ring along the way.
+    fn deterministic_run(movie_path: &str, ticks: usize) -> (Vec<u8>, u64) {
+        let mut runtime_state = RusticoRuntimeState::new();
+        runtime_state.handle_event(events::Event::PlayInputMovie(movie_path.to_string()));
+        let (mut ticker, mut audio_consumer) = test_ticker(&runtime_state);
+        ticker.handle_event(&runtime_state, events::Event::ApplyBooleanSetting("emulation.deterministic".to_string(), true));
+        let mut audio_hasher = DefaultHasher::new();
+        let mut samples_pushed = 0;
+        for _ in 0 .. ticks {
+            let step_result = run_tick(&mut runtime_state, &mut ticker, &mut |runtime_state, _ticker, event| {
+                runtime_state.handle_event(event);
+            });
+            assert!(step_result.is_ok());
+            // Each tick is exactly one frame, however much audio is already queued
+            assert_eq!(runtime_state.nes.ppu.current_scanline, 242);
+            for sample in audio_consumer.pop_iter() {
+                sample.to_bits().hash(&mut audio_hasher);
+                samples_pushed += 1;
+            }
+        }
+        assert!(samples_pushed > 0);
+        return (runtime_state.nes.memory.iram_raw.clone(), audio_hasher.finish());
+    }
+
+    #[test]
+    fn deterministic_ticks_match_exactly() {
+        let path = std::env::temp_dir().join(format!("rustico_deterministic_movie_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut movie = InputMovie::new();
+        for frame in 0 .. 60 {
+            movie.frames.push(((frame * 37 % 256) as u8, (frame * 11 % 256) as u8));
+        }
+        movie.save(&path).unwrap();
+
+        let (first_ram, first_audio) = deterministic_run(&path, 60);
+        let (second_ram, second_audio) = deterministic_run(&path, 60);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(first_ram, second_ram);
+        assert_eq!(first_audio, second_audio);
+    }
+
+    #[test]
+    fn dispatch_reaches_the_worker_handlers() {
+        let mut runtime_state = RusticoRuntimeState::new();
+        let mut game_window = GameWindow::new();
+        let mut vu_meter_window = VuMeterWindow::new();
+        let (mut ticker, _audio_consumer) = test_ticker(&runtime_state);
+        let (mut shell, _shell_rx) = test_shell();
+        let mut panels: [&mut dyn Panel; 2] = [&mut game_window, &mut vu_meter_window];
+
+        dispatch_event_at_depth(&mut runtime_state, &mut panels, &mut ticker, &mut shell, events::Event::RequestFrame, 0);
+        assert!(shell.frame_pending);
+
+        dispatch_event_at_depth(&mut runtime_state, &mut panels, &mut ticker, &mut shell, events::Event::ApplyBooleanSetting("debug.event_log".to_string(), true), 0);
+        assert!(shell.event_log.is_some());
+        dispatch_event_at_depth(&mut runtime_state, &mut panels, &mut ticker, &mut shell, events::Event::ApplyBooleanSetting("debug.event_log".to_string(), false), 0);
+        assert!(shell.event_log.is_none());
+        dispatch_event_at_depth(&mut runtime_state, &mut panels, &mut ticker, &mut shell, events::Event::ApplyBooleanSetting("debug.event_log".to_string(), true), 0);
+        dispatch_event_at_depth(&mut runtime_state, &mut panels, &mut ticker, &mut shell, events::Event::NesReset, 0);
+        assert_eq!(*shell.event_log.unwrap().entries.back().unwrap(), (0, "NesReset".to_string()));
+    }
+}
@@ -93,10 +93,21 @@ impl CartridgeManager {
         self.game_path = cart_id.to_string();
         self.sram_path = PathBuf::from(cart_id).with_extension("sav").to_str().unwrap().to_string();
         println!("Cartridge loading success! Storing save path as: {}", self.sram_path);
+        // Pick up debug symbols sitting next to the game, if there are any
+        for extension in ["nl", "dbg", "sym"].iter() {
+          let symbol_path = PathBuf::from(&self.game_path).with_extension(*extension);
+          if symbol_path.exists() {
+            responses.push(rustico_ui_common::Event::LoadSymbols(symbol_path.to_str().unwrap().to_string()));
+            break;
+          }
+        }
       },
       rustico_ui_common::Event::LoadFailed(reason) => {
         println!("Loading failed: {}", reason);
       },
+      rustico_ui_common::Event::EmulatorStalled(scanlines_run) => {
+        println!("Warning: Emulator ran {} scanlines without finishing a frame, bailing out.", scanlines_run);
+      },
       rustico_ui_common::Event::CartridgeRejected(cart_id, reason) => {
         println!("Cartridge {} could not be played: {}", cart_id, reason);
       },
@@ -33,6 +33,7 @@ use std::time;
 use std::ffi::OsString;
 
 use rustico_ui_common::application::RuntimeState as RusticoRuntimeState;
+use rustico_ui_common::application::run_bounded_step;
 use rustico_ui_common::events;
 use rustico_ui_common::events::StandardControllerButton;
 use rustico_ui_common::apu_window::ApuWindow;
@@ -42,6 +43,7 @@ use rustico_ui_common::event_window::EventWindow;
 use rustico_ui_common::memory_window::MemoryWindow;
 use rustico_ui_common::piano_roll_window::PianoRollWindow;
 use rustico_ui_common::ppu_window::PpuWindow;
+use rustico_ui_common::vu_meter_window::VuMeterWindow;
 
 use cartridge_manager::CartridgeManager;
 use platform_window::PlatformWindow;
@@ -96,6 +98,7 @@ pub fn main() {
   windows.push(PlatformWindow::from_panel(&video_subsystem, Box::new(MemoryWindow::new())));
   windows.push(PlatformWindow::from_panel(&video_subsystem, Box::new(PianoRollWindow::new())));
   windows.push(PlatformWindow::from_panel(&video_subsystem, Box::new(PpuWindow::new())));
+  windows.push(PlatformWindow::from_panel(&video_subsystem, Box::new(VuMeterWindow::new())));
 
   let mut texture_creators: Vec<TextureCreator<WindowContext>> = Vec::new();
   for i in 0 .. windows.len() {
@@ -160,6 +163,27 @@ pub fn main() {
                 }
               }
             },
+            Event::MouseButtonUp{ window_id: id, mouse_btn: MouseButton::Left, .. } => {
+              for i in 0 .. windows.len() {
+                if id == windows[i].canvas.window().id() {
+                  application_events.extend(windows[i].panel.handle_event(&runtime_state, events::Event::MouseRelease));
+                }
+              }
+            },
+            Event::MouseWheel{ window_id: id, y: delta, .. } => {
+              for i in 0 .. windows.len() {
+                if id == windows[i].canvas.window().id() {
+                  application_events.extend(windows[i].panel.handle_event(&runtime_state, events::Event::MouseWheel(delta)));
+                }
+              }
+            },
+            Event::TextInput{ window_id: id, text, .. } => {
+              for i in 0 .. windows.len() {
+                if id == windows[i].canvas.window().id() {
+                  application_events.extend(windows[i].panel.handle_event(&runtime_state, events::Event::TextInput(text.clone())));
+                }
+              }
+            },
             Event::MouseMotion{ window_id: id, x: omx, y: omy, .. } => {
               for i in 0 .. windows.len() {
                 if id == windows[i].canvas.window().id() {
@@ -182,9 +206,11 @@ pub fn main() {
           if sdl_context.keyboard().focused_window_id().is_some() {
             let focused_window_id = sdl_context.keyboard().focused_window_id().unwrap();
             let mut application_focused = false;
+            let mut panel_has_keyboard_focus = false;
             for i in 0 .. windows.len() {
               if windows[i].canvas.window().id() == focused_window_id {
                 application_focused = true;
+                panel_has_keyboard_focus = windows[i].panel.has_keyboard_focus();
               }
             }
 
@@ -198,6 +224,8 @@ pub fn main() {
                   }
 
                   match key {
+                    // A panel taking typed text gets it through TextInput instead
+                    _ if panel_has_keyboard_focus => (),
                     Keycode::X =>      {application_events.push(events::Event::StandardControllerPress(0, StandardControllerButton::A))},
                     Keycode::Z =>      {application_events.push(events::Event::StandardControllerPress(0, StandardControllerButton::B))},
                     Keycode::RShift => {application_events.push(events::Event::StandardControllerPress(0, StandardControllerButton::Select))},
@@ -251,6 +279,8 @@ pub fn main() {
                           }
                         }
                       },
+                      // The rest are single key shortcuts, and would steal text typed into a panel
+                      _ if panel_has_keyboard_focus => (),
 
                       Keycode::F1 => {application_events.push(events::Event::ShowPpuWindow);},
                       Keycode::F2 => {application_events.push(events::Event::ShowApuWindow);},
@@ -258,12 +288,19 @@ pub fn main() {
                       Keycode::F4 => {application_events.push(events::Event::ShowCpuWindow);},
                       Keycode::F5 => {application_events.push(events::Event::ShowPianoRollWindow);},
                       Keycode::F6 => {application_events.push(events::Event::ShowEventWindow);},
+                      Keycode::F7 => {application_events.push(events::Event::ShowVuMeterWindow);},
 
                       Keycode::F9 => {application_events.push(events::Event::NesNudgeAlignment);},
 
                       Keycode::Period => {application_events.push(events::Event::MemoryViewerNextPage);},
                       Keycode::Comma => {application_events.push(events::Event::MemoryViewerPreviousPage);},
                       Keycode::Slash => {application_events.push(events::Event::MemoryViewerNextBus);},
+                      Keycode::Semicolon => {application_events.push(events::Event::MemoryViewerToggleAscii);},
+
+                      Keycode::RightBracket => {application_events.push(events::Event::PpuViewerNextChrBank);},
+                      Keycode::LeftBracket => {application_events.push(events::Event::PpuViewerPreviousChrBank);},
+                      Keycode::Backslash => {application_events.push(events::Event::PpuViewerNextPalette);},
+                      Keycode::Quote => {application_events.push(events::Event::PpuViewerToggleAttributeOverlay);},
 
                       Keycode::N => {application_events.push(events::Event::ToggleBooleanSetting("video.ntsc_filter".to_string()));},
                       Keycode::F => {application_events.push(events::Event::ToggleBooleanSetting("video.display_fps".to_string()));},
@@ -314,8 +351,10 @@ pub fn main() {
         if runtime_state.nes.apu.buffer_full {
           let buffer_size = runtime_state.nes.apu.output_buffer.len();
           let mut buffer = vec!(0i16; buffer_size);
-          for i in 0 .. buffer_size {
-            buffer[i] = runtime_state.nes.apu.output_buffer[i] as i16;
+          if !runtime_state.master_mute {
+            for i in 0 .. buffer_size {
+              buffer[i] = runtime_state.nes.apu.output_buffer[i] as i16;
+            }
           }
           _ = device.queue_audio(&buffer);
           runtime_state.nes.apu.buffer_full = false;
@@ -333,13 +372,17 @@ pub fn main() {
             application_events.extend(dispatch_event(&mut windows, &mut runtime_state, &mut cartridge_state, event));
           }
         }
-        while runtime_state.nes.ppu.current_scanline != 242 {
+        let step_result = run_bounded_step(|| {
           application_events.push(events::Event::NesRunScanline);
           let events_to_process = application_events.clone();
           application_events.clear();
           for event in events_to_process {
             application_events.extend(dispatch_event(&mut windows, &mut runtime_state, &mut cartridge_state, event));
           }
+          return runtime_state.nes.ppu.current_scanline == 242;
+        });
+        if let Err(scanlines_run) = step_result {
+          application_events.push(events::Event::EmulatorStalled(scanlines_run as u32));
         }
       } else {
         // we have to queue up *something*, so let's target around 60 Hz ish of silence
@@ -3,24 +3,53 @@ use std::sync::Arc;
 use events::Event;
 use events::StandardControllerButton;
 
+use input_movie::InputMovie;
 use settings::SettingsState;
+use symbols::SymbolTable;
 
 use rustico_core::nes::NesState;
 use rustico_core::cartridge::mapper_from_file;
+use rustico_core::memory;
+use rustico_core::logging;
+use rustico_core::snapshot::NesSnapshot;
+use rustico_core::snapshot::diff_states;
 
 use rustico_core::apu::AudioChannelState;
+use rustico_core::apu::ExpansionLevels;
 
 
 
 pub struct RuntimeState {
     pub nes: NesState,
     pub running: bool,
+    // Frontends write silence instead of the APU's output while this is set
+    pub master_mute: bool,
     pub file_loaded: bool,
     pub last_frame: u32,
     pub last_scanline: u16,
     pub last_apu_quarter_frame_count: u32,
     pub last_apu_half_frame_count: u32,
     pub settings: SettingsState,
+
+    // Input movies are indexed from the frame they started on
+    pub movie_playback: Option<InputMovie>,
+    pub movie_recording: Option<(String, InputMovie)>,
+    pub movie_start_frame: u32,
+    // While recording, live input waits here for the next frame boundary
+    pub movie_live_input: (u8, u8),
+
+    // Labels for the debugger windows, if the user has loaded any
+    pub symbols: SymbolTable,
+
+    // Lets external tools look at the audio channels once per frame, see set_channel_observer
+    pub channel_observer: Option<Box<dyn FnMut(&[&dyn AudioChannelState]) + Send>>,
+
+    // The B side of an A/B comparison, if one is running. It's stepped alongside nes and
+    // given the same input, but nothing else: no audio output, no movies, no settings.
+    pub comparison: Option<NesState>,
+
+    // Captured with Event::CaptureSnapshot, for tracking down where a run goes wrong
+    pub snapshot: Option<NesSnapshot>,
 }
 
 impl RuntimeState {
@@ -30,11 +59,20 @@ impl RuntimeState {
             nes: NesState::new(initial_cartridge),
             file_loaded: true,
             running: true,
+            master_mute: false,
             last_frame: 0,
             last_scanline: 0,
             last_apu_quarter_frame_count: 0,
             last_apu_half_frame_count: 0,
             settings: SettingsState::new(),
+            movie_playback: None,
+            movie_recording: None,
+            movie_start_frame: 0,
+            movie_live_input: (0, 0),
+            symbols: SymbolTable::new(),
+            channel_observer: None,
+            comparison: None,
+            snapshot: None,
         };
         state.nes.power_on();
         return state;
@@ -45,14 +83,23 @@ impl RuntimeState {
         let maybe_mapper = mapper_from_file(file_data);
         match maybe_mapper {
             Ok(mapper) => {
-
+                // The new console starts with a fresh APU, so bring the expansion levels along
+                let expansion_levels = self.nes.apu.expansion_levels;
+                let expansion_gains = self.nes.apu.expansion_gains.clone();
                 self.nes = NesState::new(mapper);
+                // The comparison was lined up against the old cartridge, and there's no
+                // power cycling it in step with this one, so it ends here
+                self.comparison = None;
+                self.nes.apu.expansion_levels = expansion_levels;
+                self.nes.apu.expansion_gains = expansion_gains;
+                self.refresh_expansion_gain();
+                self.symbols = SymbolTable::new();
                 self.file_loaded = true;
                 responses.push(Event::CartridgeLoaded(cart_id));
                 if self.nes.mapper.needs_bios() {
                     responses.push(Event::RequestBios);
                     self.running = false;
-                    println!("FDS game needs bios, pausing emulation to request it from the shell...");
+                    logging::info("FDS game needs bios, pausing emulation to request it from the shell...");
                 } else {
                     self.nes.power_on();
                     self.running = true;
@@ -60,12 +107,50 @@ impl RuntimeState {
                 return responses
             },
             Err(why) => {
-                responses.push(Event::CartridgeRejected(cart_id, why));
+                responses.push(Event::CartridgeRejected(cart_id, why.to_string()));
                 return responses
             }
         }
     }
 
+    pub fn load_comparison(&mut self, file_data: &[u8]) -> Result<(), String> {
+        let mapper = match mapper_from_file(file_data) {
+            Ok(mapper) => mapper,
+            Err(why) => {return Err(why.to_string());}
+        };
+        let mut comparison = NesState::new(mapper);
+        comparison.power_on();
+        self.comparison = Some(comparison);
+        return Ok(());
+    }
+
+    // Keeps the comparison console in step with the main one, pressing the same buttons
+    fn run_comparison(&mut self, run: fn(&mut NesState)) {
+        match self.comparison {
+            Some(ref mut comparison) => {
+                comparison.p1_input = self.nes.p1_input;
+                comparison.p2_input = self.nes.p2_input;
+                run(comparison);
+            },
+            None => {}
+        }
+    }
+
+    pub fn diff_against_snapshot(&mut self) {
+        let snapshot = match self.snapshot {
+            Some(ref snapshot) => snapshot,
+            None => {
+                logging::warn("No snapshot to compare against, capture one first.");
+                return;
+            }
+        };
+        let diffs = diff_states(snapshot, &NesSnapshot::capture(&self.nes));
+        logging::info(&format!("{} differences from the snapshot:", diffs.len()));
+        for diff in diffs {
+            logging::info(&diff.to_string());
+        }
+    }
+
     pub fn load_sram(&mut self, file_data: &[u8]) {
         if self.nes.mapper.has_sram() {
             if file_data.len() > 0 {
@@ -83,11 +168,17 @@ impl RuntimeState {
         }
     }
 
+    // Recorded input only changes at frame boundaries, so a replay sees exactly what the
+    // game saw. Outside of a recording, input goes straight to the console.
+    fn live_controllers(&mut self) -> [&mut u8; 2] {
+        if self.movie_recording.is_some() {
+            return [&mut self.movie_live_input.0, &mut self.movie_live_input.1];
+        }
+        return [&mut self.nes.p1_input, &mut self.nes.p2_input];
+    }
+
     pub fn button_press(&mut self, player_index: usize, button: StandardControllerButton) {
-        let controllers = [
-            &mut self.nes.p1_input,
-            &mut self.nes.p2_input
-        ];
+        let controllers = self.live_controllers();
 
         if player_index > controllers.len() {
             return;
@@ -101,10 +192,7 @@ impl RuntimeState {
     }
 
     pub fn button_release(&mut self, player_index: usize, button: StandardControllerButton) {
-        let controllers = [
-            &mut self.nes.p1_input,
-            &mut self.nes.p2_input
-        ];
+        let controllers = self.live_controllers();
 
         if player_index > controllers.len() {
             return;
@@ -122,6 +210,10 @@ impl RuntimeState {
         if self.nes.ppu.current_frame != self.last_frame {
             responses.push(Event::NesNewFrame);
             self.last_frame = self.nes.ppu.current_frame;
+            self.update_input_movie();
+            self.notify_channel_observer();
+            // The N163 can change how many channels it has enabled at any time
+            self.refresh_expansion_gain();
         }
         if self.nes.ppu.current_scanline != self.last_scanline {
             responses.push(Event::NesNewScanline);
@@ -138,6 +230,104 @@ impl RuntimeState {
         return responses;
     }
 
+    // Registers a function to be called once per emulated frame with every audio channel, the
+    // 2A03's first and then any expansion audio. It runs as the frame finishes, before NesNewFrame
+    // reaches any panel, so it sees the same state the piano roll and friends are about to draw.
+    // The channels are borrowed from the running NesState and only live for the duration of the
+    // call: copy out whatever you need (amplitude, rate, timbre) rather than holding on to them.
+    pub fn set_channel_observer(&mut self, observer: Box<dyn FnMut(&[&dyn AudioChannelState]) + Send>) {
+        self.channel_observer = Some(observer);
+    }
+
+    pub fn clear_channel_observer(&mut self) {
+        self.channel_observer = None;
+    }
+
+    fn notify_channel_observer(&mut self) {
+        match self.channel_observer {
+            Some(ref mut observer) => {
+                let mut channels: Vec<&dyn AudioChannelState> = Vec::new();
+                channels.extend(self.nes.apu.channels());
+                channels.extend(self.nes.mapper.channels());
+                observer(&channels);
+            },
+            None => {}
+        }
+    }
+
+    pub fn play_input_movie(&mut self, path: &str) {
+        match InputMovie::load(path) {
+            Ok(movie) => {
+                logging::info(&format!("Playing input movie {} ({} frames)", path, movie.frames.len()));
+                self.movie_playback = Some(movie);
+                self.movie_start_frame = self.nes.ppu.current_frame;
+                self.apply_movie_frame();
+            },
+            Err(why) => {
+                logging::warn(&why.to_string());
+            }
+        }
+    }
+
+    pub fn record_input_movie(&mut self, path: &str) {
+        self.stop_input_movie();
+        logging::info(&format!("Recording input movie to {}", path));
+        self.movie_recording = Some((path.to_string(), InputMovie::new()));
+        self.movie_start_frame = self.nes.ppu.current_frame;
+        self.movie_live_input = (self.nes.p1_input, self.nes.p2_input);
+    }
+
+    pub fn stop_input_movie(&mut self) {
+        if self.movie_playback.is_some() {
+            self.movie_playback = None;
+            self.nes.p1_input = 0;
+            self.nes.p2_input = 0;
+        }
+        if let Some((path, movie)) = self.movie_recording.take() {
+            // Anything pressed since the last frame boundary shouldn't be lost
+            self.nes.p1_input = self.movie_live_input.0;
+            self.nes.p2_input = self.movie_live_input.1;
+            match movie.save(&path) {
+                Ok(_) => {logging::info(&format!("Wrote {} frames of input to {}", movie.frames.len(), path));},
+                Err(why) => {logging::warn(&why.to_string());}
+            }
+        }
+    }
+
+    // Called once at the start of every emulated frame
+    fn update_input_movie(&mut self) {
+        if let Some((_, ref mut movie)) = self.movie_recording {
+            // Record the input that was held for the frame that just finished
+            movie.frames.push((self.nes.p1_input, self.nes.p2_input));
+            self.nes.p1_input = self.movie_live_input.0;
+            self.nes.p2_input = self.movie_live_input.1;
+        }
+        self.apply_movie_frame();
+    }
+
+    fn apply_movie_frame(&mut self) {
+        let frame_index = self.nes.ppu.current_frame.wrapping_sub(self.movie_start_frame) as usize;
+        let next_input = match self.movie_playback {
+            Some(ref movie) => movie.frames.get(frame_index).cloned(),
+            None => {return;}
+        };
+        match next_input {
+            Some((p1, p2)) => {
+                self.nes.p1_input = p1;
+                self.nes.p2_input = p2;
+            },
+            None => {
+                // Out of frames; let go of everything so the game isn't left holding a button
+                logging::info("Input movie finished.");
+                self.stop_input_movie();
+            }
+        }
+    }
+
+    pub fn refresh_expansion_gain(&mut self) {
+        self.nes.apu.refresh_expansion_gain(&*self.nes.mapper);
+    }
+
     pub fn handle_event(&mut self, event: Event) -> Vec<Event> {
         let mut responses: Vec<Event> = Vec::new();
         responses.extend(self.settings.handle_event(event.clone()));
@@ -145,9 +335,37 @@ impl RuntimeState {
             Event::ApplyBooleanSetting(path, value) => {
                 match path.as_str() {
                     "audio.multiplexing" => {self.nes.mapper.audio_multiplexing(value)},
+                    "audio.master_mute" => {self.master_mute = value},
                     _ => {}
                 }
             },
+            Event::ApplyFloatSetting(path, value) => {
+                let components = path.split(".").collect::<Vec<&str>>();
+                if components.len() == 3 && components[0] == "audio" && components[1] == "expansion_gain" {
+                    self.nes.apu.expansion_gains.insert(components[2].to_string(), value as f32);
+                    self.refresh_expansion_gain();
+                }
+            },
+            Event::ApplyStringSetting(path, value) => {
+                match path.as_str() {
+                    "audio.expansion_levels" => {
+                        match value.as_str() {
+                            "famicom" => {self.nes.apu.expansion_levels = ExpansionLevels::Famicom},
+                            "flat" => {self.nes.apu.expansion_levels = ExpansionLevels::Flat},
+                            "custom" => {self.nes.apu.expansion_levels = ExpansionLevels::Custom},
+                            _ => {
+                                logging::warn(&format!("Invalid expansion audio levels {}, ignoring.", value));
+                            }
+                        }
+                        self.refresh_expansion_gain();
+                    },
+                    _ => {}
+                }
+            },
+            Event::MuteMasterAudio(muted) => {
+                self.master_mute = muted;
+                responses.push(Event::StoreBooleanSetting("audio.master_mute".to_string(), muted));
+            },
             Event::MuteChannel(chip_name, channel_name) => {
                 let mut channels: Vec<&mut dyn AudioChannelState> = Vec::new();
                 channels.extend(self.nes.apu.channels_mut());
@@ -187,12 +405,23 @@ impl RuntimeState {
             Event::LoadSram(sram_data) => {
                 self.load_sram(&sram_data);
             },
+            Event::LoadComparisonCartridge(file_data) => {
+                match self.load_comparison(&file_data) {
+                    Ok(_) => {logging::info("Started an A/B comparison")},
+                    Err(why) => {responses.push(Event::LoadFailed(format!("Couldn't load the comparison cartridge: {}", why)));}
+                }
+            },
+            Event::StopComparison => {
+                self.comparison = None;
+            },
             Event::NesRunCycle => {
                 self.nes.cycle();
+                self.run_comparison(NesState::cycle);
                 responses.extend(self.collect_timing_events());
             },
             Event::NesRunFrame => {
                 self.nes.run_until_vblank();
+                self.run_comparison(NesState::run_until_vblank);
                 responses.extend(self.collect_timing_events());
             },
             Event::NesRenderNTSC(width) => {
@@ -200,13 +429,49 @@ impl RuntimeState {
             },
             Event::NesRunOpcode => {
                 self.nes.step();
+                self.run_comparison(NesState::step);
             },
             Event::NesRunScanline => {
                 self.nes.run_until_hblank();
+                self.run_comparison(NesState::run_until_hblank);
                 responses.extend(self.collect_timing_events());
             },
             Event::NesReset => {
                 self.nes.reset();
+                self.run_comparison(NesState::reset);
+            },
+            Event::LoadSymbols(path) => {
+                match SymbolTable::load(&path) {
+                    Ok(symbols) => {
+                        logging::info(&format!("Loaded {} symbols from {}", symbols.labels.len(), path));
+                        self.symbols = symbols;
+                    },
+                    Err(why) => {logging::warn(&why.to_string());}
+                }
+            },
+            Event::CaptureSnapshot => {
+                self.snapshot = Some(NesSnapshot::capture(&self.nes));
+            },
+            Event::DiffAgainstSnapshot => {
+                self.diff_against_snapshot();
+            },
+            Event::MemoryViewerWriteByte(address, data) => {
+                // Goes through the bus like any other write, so mapper registers respond
+                memory::write_byte(&mut self.nes, address, data);
+            },
+
+            Event::PlayInputMovie(path) => {
+                self.play_input_movie(&path);
+            },
+            Event::RecordInputMovie(path) => {
+                self.record_input_movie(&path);
+            },
+            Event::StopInputMovie => {
+                self.stop_input_movie();
+            },
+            Event::CloseApplication => {
+                // Don't lose a recording in progress
+                self.stop_input_movie();
             },
             
             // These three events should ideally move to some sort of FrameTiming manager
@@ -232,11 +497,16 @@ impl RuntimeState {
 
             // Input is due for an overhaul. Ideally the IoBus should handle its own
             // events, rather than doing this here.
+            // A movie being played back owns the controllers until it ends
             Event::StandardControllerPress(controller_index, button) => {
-                self.button_press(controller_index, button);
+                if self.movie_playback.is_none() {
+                    self.button_press(controller_index, button);
+                }
             },
             Event::StandardControllerRelease(controller_index, button) => {
-                self.button_release(controller_index, button);
+                if self.movie_playback.is_none() {
+                    self.button_release(controller_index, button);
+                }
             },
             _ => {}
         }
@@ -255,4 +525,221 @@ pub fn fix_dpad(controller_byte: u8, last_button_pressed: StandardControllerButt
     }
 
     return fixed_byte;
-}
\ No newline at end of file
+}
+
+// A normal step runs a frame or so worth of scanlines. If a shell is still going after this many,
+// the core has wedged (usually no audio coming out), so it should give up and tell the user.
+pub const MAX_SCANLINES_PER_STEP: usize = 262 * 10;
+
+// Calls run_scanline until it reports the step is finished, up to MAX_SCANLINES_PER_STEP times.
+// Returns the number of scanlines run, or Err with that number if we had to bail out; shells
+// should follow an Err with Event::EmulatorStalled.
+pub fn run_bounded_step<F: FnMut() -> bool>(mut run_scanline: F) -> Result<usize, usize> {
+    let mut scanlines_run = 0;
+    while scanlines_run < MAX_SCANLINES_PER_STEP {
+        scanlines_run += 1;
+        if run_scanline() {
+            return Ok(scanlines_run);
+        }
+    }
+    return Err(scanlines_run);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rustico_{}_{}", name, std::process::id()));
+        return path.to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn recorded_input_replays_frame_for_frame() {
+        let path = temp_path("movie.txt");
+        let inputs: Vec<(u8, u8)> = vec!((0x00, 0x00), (0x08, 0x00), (0x08, 0x01), (0x81, 0x00), (0x00, 0x40), (0x10, 0x10));
+
+        // NesRunFrame stops at vblank, partway through a frame, so run one first to line the
+        // frame counter up with the loop below
+        let mut recorder = RuntimeState::new();
+        recorder.handle_event(Event::NesRunFrame);
+        recorder.handle_event(Event::RecordInputMovie(path.clone()));
+        for &(p1, p2) in inputs.iter() {
+            recorder.nes.p1_input = p1;
+            recorder.nes.p2_input = p2;
+            recorder.handle_event(Event::NesRunFrame);
+        }
+        recorder.handle_event(Event::StopInputMovie);
+
+        let mut player = RuntimeState::new();
+        player.handle_event(Event::NesRunFrame);
+        player.handle_event(Event::PlayInputMovie(path.clone()));
+        let mut replayed: Vec<(u8, u8)> = Vec::new();
+        for _ in 0 .. inputs.len() {
+            replayed.push((player.nes.p1_input, player.nes.p2_input));
+            player.handle_event(Event::NesRunFrame);
+        }
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(replayed, inputs);
+
+        // Out of frames: everything is released, and the movie is done
+        assert!(player.movie_playback.is_none());
+        assert_eq!((player.nes.p1_input, player.nes.p2_input), (0, 0));
+    }
+
+    #[test]
+    fn live_input_is_latched_at_frame_boundaries_while_recording() {
+        let path = temp_path("latched_movie.txt");
+        let mut recorder = RuntimeState::new();
+        recorder.handle_event(Event::NesRunFrame);
+        recorder.handle_event(Event::RecordInputMovie(path.clone()));
+        let changes = vec!(
+            Event::StandardControllerPress(0, StandardControllerButton::A),
+            Event::StandardControllerPress(1, StandardControllerButton::Start),
+            Event::StandardControllerRelease(0, StandardControllerButton::A),
+        );
+        let mut recorded: Vec<(u8, u8)> = Vec::new();
+        for change in changes {
+            let held = (recorder.nes.p1_input, recorder.nes.p2_input);
+            recorded.push(held);
+            recorder.handle_event(change);
+            // Still mid-frame, so the game hasn't seen the change yet
+            recorder.handle_event(Event::NesRunScanline);
+            assert_eq!((recorder.nes.p1_input, recorder.nes.p2_input), held);
+            recorder.handle_event(Event::NesRunFrame);
+        }
+        assert_eq!(recorded, vec!((0x00, 0x00), (0x01, 0x00), (0x01, 0x08)));
+        assert_eq!((recorder.nes.p1_input, recorder.nes.p2_input), (0x00, 0x08));
+        recorder.handle_event(Event::StopInputMovie);
+
+        let mut player = RuntimeState::new();
+        player.handle_event(Event::NesRunFrame);
+        player.handle_event(Event::PlayInputMovie(path.clone()));
+        let mut replayed: Vec<(u8, u8)> = Vec::new();
+        for _ in 0 .. recorded.len() {
+            // The movie owns the controllers, so live input is ignored
+            player.handle_event(Event::StandardControllerPress(0, StandardControllerButton::B));
+            replayed.push((player.nes.p1_input, player.nes.p2_input));
+            player.handle_event(Event::NesRunFrame);
+        }
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn channel_observer_sees_every_channel_once_per_frame() {
+        let mut runtime = RuntimeState::new();
+        runtime.handle_event(Event::NesRunFrame);
+        let expected_channels = runtime.nes.apu.channels().len() + runtime.nes.mapper.channels().len();
+
+        let calls: Arc<Mutex<Vec<usize>>> = Arc::new(Mutex::new(Vec::new()));
+        let observer_calls = calls.clone();
+        runtime.set_channel_observer(Box::new(move |channels: &[&dyn AudioChannelState]| {
+            observer_calls.lock().unwrap().push(channels.len());
+        }));
+        for _ in 0 .. 5 {
+            runtime.handle_event(Event::NesRunFrame);
+        }
+        assert_eq!(*calls.lock().unwrap(), vec!(expected_channels; 5));
+
+        runtime.clear_channel_observer();
+        runtime.handle_event(Event::NesRunFrame);
+        assert_eq!(calls.lock().unwrap().len(), 5);
+    }
+
+    // Stands in for the shell's scanline runner; a wedged APU never adds any samples
+    struct MockApu {
+        samples_per_scanline: usize,
+        samples_queued: usize,
+        scanlines_run: usize,
+    }
+
+    impl MockApu {
+        fn run_scanline(&mut self) -> bool {
+            self.scanlines_run += 1;
+            self.samples_queued += self.samples_per_scanline;
+            return self.samples_queued >= 512;
+        }
+    }
+
+    #[test]
+    fn step_bails_out_when_the_apu_never_produces_samples() {
+        let mut apu = MockApu{samples_per_scanline: 0, samples_queued: 0, scanlines_run: 0};
+        assert_eq!(run_bounded_step(|| apu.run_scanline()), Err(MAX_SCANLINES_PER_STEP));
+        assert_eq!(apu.scanlines_run, MAX_SCANLINES_PER_STEP);
+
+        // A healthy APU finishes well before the bound
+        let mut apu = MockApu{samples_per_scanline: 3, samples_queued: 0, scanlines_run: 0};
+        assert_eq!(run_bounded_step(|| apu.run_scanline()), Ok(171));
+        assert_eq!(apu.scanlines_run, 171);
+    }
+
+    #[test]
+    fn running_a_frame_fits_comfortably_in_one_step() {
+        let mut runtime = RuntimeState::new();
+        let result = run_bounded_step(|| {
+            runtime.handle_event(Event::NesRunScanline);
+            return runtime.nes.ppu.current_scanline == 242;
+        });
+        match result {
+            Ok(scanlines_run) => {assert!(scanlines_run <= 262);},
+            Err(scanlines_run) => {panic!("bailed out after {} scanlines", scanlines_run);}
+        }
+    }
+
+    #[test]
+    fn diffing_against_a_snapshot_logs_what_changed() {
+        let mut runtime = RuntimeState::new();
+        runtime.handle_event(Event::NesRunFrame);
+        runtime.handle_event(Event::CaptureSnapshot);
+        runtime.nes.registers.a = runtime.nes.registers.a.wrapping_add(1);
+        runtime.nes.memory.iram_raw[0x0300] = runtime.nes.memory.iram_raw[0x0300].wrapping_add(2);
+        let expected = diff_states(runtime.snapshot.as_ref().unwrap(), &NesSnapshot::capture(&runtime.nes));
+        assert_eq!(expected.len(), 2);
+
+        let captured: Arc<Mutex<Vec<(logging::LogLevel, String)>>> = Arc::new(Mutex::new(Vec::new()));
+        let sink_messages = captured.clone();
+        logging::set_log_sink(Some(Box::new(move |level, message| {
+            sink_messages.lock().unwrap().push((level, message.to_string()));
+        })));
+        runtime.handle_event(Event::DiffAgainstSnapshot);
+        logging::set_log_sink(None);
+
+        // Other tests may log while the sink is installed, so look for ours among them
+        let messages = captured.lock().unwrap();
+        assert!(messages.iter().any(|(_, message)| message == "2 differences from the snapshot:"), "{:?}", *messages);
+        for diff in expected {
+            assert!(messages.iter().any(|(_, message)| *message == diff.to_string()), "{:?}", *messages);
+        }
+    }
+
+    #[test]
+    fn comparison_console_follows_opcodes_and_resets() {
+        let mut runtime = RuntimeState::new();
+        runtime.load_comparison(include_bytes!("assets/rustico_no_cart.nes")).unwrap();
+        let in_step = |runtime: &RuntimeState| {
+            let comparison = runtime.comparison.as_ref().unwrap();
+            return diff_states(&NesSnapshot::capture(&runtime.nes), &NesSnapshot::capture(comparison)).len() == 0
+                && runtime.nes.master_clock == comparison.master_clock;
+        };
+        assert!(in_step(&runtime));
+        for _ in 0 .. 100 {
+            runtime.handle_event(Event::NesRunOpcode);
+        }
+        assert!(runtime.nes.master_clock > 0);
+        assert!(in_step(&runtime));
+        runtime.handle_event(Event::NesReset);
+        assert!(in_step(&runtime));
+        runtime.handle_event(Event::NesRunFrame);
+        assert!(in_step(&runtime));
+    }
+
+    #[test]
+    fn loading_a_cartridge_ends_the_comparison() {
+        let mut runtime = RuntimeState::new();
+        runtime.load_comparison(include_bytes!("assets/rustico_no_cart.nes")).unwrap();
+        runtime.handle_event(Event::LoadCartridge("again".to_string(), Arc::new(include_bytes!("assets/rustico_no_cart.nes").to_vec()), Arc::new(Vec::new())));
+        assert!(runtime.comparison.is_none());
+    }
+}
@@ -21,20 +21,30 @@ pub struct ApuWindow {
     pub old_channels: usize,
 }
 
-pub fn find_edge(edge_buffer: &RingBuffer, window_size: usize) -> usize {
-    let start_index = (edge_buffer.index() - window_size) % edge_buffer.buffer().len();
+// Searches backwards for the most recent point where the buffer rises from at-or-below the
+// threshold to above it, and returns an index which centers the window on that point. Both
+// this window and the piano roll's surfboard trigger their scopes with this.
+pub fn find_edge(buffer: &RingBuffer, window_size: usize, threshold: i16) -> usize {
+    let start_index = buffer.index_before(buffer.index(), window_size);
     let mut current_index = start_index;
     for _i in 0 .. (window_size * 4) {
-        if edge_buffer.buffer()[current_index] != 0 {
+        let previous_index = buffer.index_before(current_index, 1);
+        if buffer.buffer()[current_index] > threshold && buffer.buffer()[previous_index] <= threshold {
             // center the window on this sample
-            return (current_index - (window_size / 2)) % edge_buffer.buffer().len();
+            return buffer.index_before(current_index, window_size / 2);
         }
-        current_index = (current_index - 1) % edge_buffer.buffer().len();
+        current_index = previous_index;
     }
     // couldn't find an edge, so return the most recent slice
     return start_index;
 }
 
+// A channel which idles at some level other than zero would otherwise trigger constantly,
+// so aim for the middle of its range instead
+pub fn default_edge_threshold(channel: &dyn AudioChannelState) -> i16 {
+    return ((channel.min_sample() as i32 + channel.max_sample() as i32) / 2) as i16;
+}
+
 impl ApuWindow {
     pub fn new() -> ApuWindow {
         let font = Font::from_raw(include_bytes!("assets/8x8_font.png"), 8);
@@ -59,12 +69,12 @@ impl ApuWindow {
         let mut start_index = channel.sample_buffer().index() - ((width as usize) * 2) - 1000;
         start_index = start_index % audiobuffer.len();
         if align {
-            start_index = find_edge(channel.edge_buffer(), (width * 3) as usize);
+            start_index = find_edge(channel.sample_buffer(), (width * 3) as usize, default_edge_threshold(channel));
         }
         
         let sample_min = channel.min_sample();
         let sample_max = channel.max_sample() + 1;
-        let range = (sample_max as u32) - (sample_min as u32);
+        let range = (sample_max as i32 - sample_min as i32) as u32;
         let mut last_y = (((audiobuffer[start_index] - sample_min) as u64 * height as u64) / range as u64) as u32;
         if last_y >= height {
             last_y = height - 1;
@@ -254,4 +264,51 @@ impl Panel for ApuWindow {
     fn scale_factor(&self) -> u32 {
         return 2;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A square wave idling well above zero: never zero, but only occasionally rising
+    fn dc_offset_square(ring: &mut RingBuffer, samples: usize, period: usize) {
+        for i in 0 .. samples {
+            let sample = if i % period < period / 2 {100} else {60};
+            ring.push(sample);
+        }
+    }
+
+    #[test]
+    fn find_edge_ignores_dc_offset() {
+        let mut ring = RingBuffer::new(256);
+        dc_offset_square(&mut ring, 256, 32);
+        // Every sample is nonzero, so the old trigger would have fired at the very first one
+        // it looked at. The rise from 60 to 100 happens at multiples of 32.
+        let window_size = 40;
+        let edge = find_edge(&ring, window_size, 80);
+        let rising_sample = (edge + window_size / 2) % 256;
+        assert_eq!(rising_sample % 32, 0);
+        assert_eq!(ring.buffer()[rising_sample], 100);
+        assert_eq!(ring.buffer()[ring.index_before(rising_sample, 1)], 60);
+    }
+
+    #[test]
+    fn find_edge_fires_at_the_threshold_not_below_it() {
+        let mut ring = RingBuffer::new(64);
+        // A slow ramp from 0 to 63: with a threshold of 40, the crossing is the sample valued 41
+        for i in 0 .. 64 {
+            ring.push(i as i16);
+        }
+        let edge = find_edge(&ring, 8, 40);
+        assert_eq!(ring.buffer()[(edge + 4) % 64], 41);
+    }
+
+    #[test]
+    fn find_edge_without_a_crossing_returns_the_latest_window() {
+        let mut ring = RingBuffer::new(64);
+        for _ in 0 .. 64 {
+            ring.push(50);
+        }
+        assert_eq!(find_edge(&ring, 16, 80), ring.index_before(ring.index(), 16));
+    }
+}
@@ -0,0 +1,226 @@
+// Keeps a running summary of what every audio channel has been up to: how often it played,
+// how many distinct notes it started, the range it covered, and which timbres it favored.
+// Handy for comparing arrangements; the piano roll feeds this once per poll.
+
+use rustico_core::apu::AudioChannelState;
+use rustico_core::apu::PlaybackRate;
+use rustico_core::apu::Timbre;
+
+use std::collections::HashMap;
+use std::fs;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+// Standard MIDI numbering, so A4 = 69 = 440 Hz
+pub fn frequency_to_midi(frequency: f32) -> f32 {
+    return 69.0 + 12.0 * (frequency / 440.0).log2();
+}
+
+pub fn note_name(frequency: f32) -> String {
+    let midi_index = frequency_to_midi(frequency).round() as i32;
+    let octave = midi_index.div_euclid(12) - 1;
+    return format!("{}{}", NOTE_NAMES[midi_index.rem_euclid(12) as usize], octave);
+}
+
+pub fn timbre_name(timbre: Option<Timbre>) -> String {
+    match timbre {
+        Some(Timbre::DutyIndex{index, max}) => {return format!("duty {}/{}", index, max);},
+        Some(Timbre::LsfrMode{index, max}) => {return format!("mode {}/{}", index, max);},
+        Some(Timbre::PatchIndex{index, max}) => {return format!("patch {}/{}", index, max);},
+        Some(Timbre::Distortion{index, max}) => {return format!("distortion {}/{}", index, max);},
+        None => {return "none".to_string();}
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum NoteKey {
+    Pitch(i32),
+    Noise(usize),
+    Sample,
+}
+
+pub struct ChannelStats {
+    pub chip: String,
+    pub name: String,
+    pub polls: u64,
+    pub active_polls: u64,
+    pub note_count: u64,
+    pub lowest_frequency: Option<f32>,
+    pub highest_frequency: Option<f32>,
+    // Polls spent on each timbre, while the channel was playing
+    pub timbre_usage: HashMap<String, u64>,
+    last_note: Option<NoteKey>,
+}
+
+impl ChannelStats {
+    pub fn new(chip: String, name: String) -> ChannelStats {
+        return ChannelStats {
+            chip: chip,
+            name: name,
+            polls: 0,
+            active_polls: 0,
+            note_count: 0,
+            lowest_frequency: None,
+            highest_frequency: None,
+            timbre_usage: HashMap::new(),
+            last_note: None,
+        };
+    }
+
+    pub fn record(&mut self, channel: &dyn AudioChannelState) {
+        self.polls += 1;
+        if !channel.playing() {
+            self.last_note = None;
+            return;
+        }
+        self.active_polls += 1;
+
+        let note = match channel.rate() {
+            PlaybackRate::FundamentalFrequency{frequency} => {
+                self.lowest_frequency = Some(self.lowest_frequency.map_or(frequency, |lowest| lowest.min(frequency)));
+                self.highest_frequency = Some(self.highest_frequency.map_or(frequency, |highest| highest.max(frequency)));
+                NoteKey::Pitch(frequency_to_midi(frequency).round() as i32)
+            },
+            PlaybackRate::LfsrRate{index, max: _} => NoteKey::Noise(index),
+            PlaybackRate::SampleRate{frequency: _} => NoteKey::Sample,
+        };
+        if self.last_note != Some(note) {
+            self.note_count += 1;
+        }
+        self.last_note = Some(note);
+
+        *self.timbre_usage.entry(timbre_name(channel.timbre())).or_insert(0) += 1;
+    }
+
+    pub fn most_used_timbre(&self) -> Option<String> {
+        let mut most_used: Option<(&String, &u64)> = None;
+        for (timbre, count) in self.timbre_usage.iter() {
+            match most_used {
+                // Break ties by name, so the report doesn't depend on hash order
+                Some((best_timbre, best_count)) if count < best_count || (count == best_count && timbre > best_timbre) => {},
+                _ => {most_used = Some((timbre, count));}
+            }
+        }
+        return most_used.map(|(timbre, _)| timbre.clone());
+    }
+}
+
+pub struct SessionStats {
+    // In the order the channels were first seen, which matches the piano roll
+    pub channels: Vec<ChannelStats>,
+}
+
+impl SessionStats {
+    pub fn new() -> SessionStats {
+        return SessionStats {
+            channels: Vec::new(),
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.channels.clear();
+    }
+
+    // Starts one channel over, keeping its place in the report
+    pub fn reset_channel(&mut self, chip: &str, name: &str) {
+        for stats in self.channels.iter_mut() {
+            if stats.chip == chip && stats.name == name {
+                *stats = ChannelStats::new(chip.to_string(), name.to_string());
+            }
+        }
+    }
+
+    pub fn record(&mut self, channels: &[&dyn AudioChannelState]) {
+        for channel in channels {
+            let chip = channel.chip();
+            let name = channel.name();
+            let existing = self.channels.iter().position(|stats| stats.chip == chip && stats.name == name);
+            match existing {
+                Some(index) => {self.channels[index].record(*channel);},
+                None => {
+                    let mut stats = ChannelStats::new(chip, name);
+                    stats.record(*channel);
+                    self.channels.push(stats);
+                }
+            }
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("chip,channel,polls,active_polls,note_count,lowest_note,highest_note,lowest_hz,highest_hz,most_used_timbre\n");
+        for stats in self.channels.iter() {
+            let (lowest_note, lowest_hz) = match stats.lowest_frequency {
+                Some(frequency) => (note_name(frequency), format!("{:.2}", frequency)),
+                None => (String::new(), String::new())
+            };
+            let (highest_note, highest_hz) = match stats.highest_frequency {
+                Some(frequency) => (note_name(frequency), format!("{:.2}", frequency)),
+                None => (String::new(), String::new())
+            };
+            csv.push_str(&format!("{},{},{},{},{},{},{},{},{},{}\n",
+                stats.chip, stats.name, stats.polls, stats.active_polls, stats.note_count,
+                lowest_note, highest_note, lowest_hz, highest_hz,
+                stats.most_used_timbre().unwrap_or(String::new())));
+        }
+        return csv;
+    }
+
+    pub fn export(&self, path: &str) -> Result<(), String> {
+        match fs::write(path, self.to_csv()) {
+            Ok(_) => {return Ok(());},
+            Err(why) => {return Err(format!("Couldn't write channel stats to {}: {}", path, why));}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustico_core::apu::RingBuffer;
+
+    // A pulse channel that plays whatever the test sets
+    struct ScriptedChannel {
+        frequency: Option<f32>,
+        duty: usize,
+        buffer: RingBuffer,
+    }
+
+    impl AudioChannelState for ScriptedChannel {
+        fn name(&self) -> String {return "Pulse 1".to_string();}
+        fn chip(&self) -> String {return "2A03".to_string();}
+        fn sample_buffer(&self) -> &RingBuffer {return &self.buffer;}
+        fn edge_buffer(&self) -> &RingBuffer {return &self.buffer;}
+        fn record_current_output(&mut self) {}
+        fn muted(&self) -> bool {return false;}
+        fn mute(&mut self) {}
+        fn unmute(&mut self) {}
+        fn playing(&self) -> bool {return self.frequency.is_some();}
+        fn rate(&self) -> PlaybackRate {return PlaybackRate::FundamentalFrequency{frequency: self.frequency.unwrap_or(0.0)};}
+        fn timbre(&self) -> Option<Timbre> {return Some(Timbre::DutyIndex{index: self.duty, max: 3});}
+    }
+
+    #[test]
+    fn note_count_and_range_follow_the_played_sequence() {
+        let mut stats = SessionStats::new();
+        let mut channel = ScriptedChannel{frequency: None, duty: 2, buffer: RingBuffer::new(16)};
+        // A4 held, a slight vibrato that stays on A4, C5, a rest, then A4 again, then E4
+        let sequence = [Some(440.0), Some(440.0), Some(442.0), Some(523.25), None, Some(440.0), Some(329.63)];
+        for (i, frequency) in sequence.iter().enumerate() {
+            channel.frequency = *frequency;
+            channel.duty = if i == 6 {1} else {2};
+            stats.record(&[&channel as &dyn AudioChannelState]);
+        }
+
+        assert_eq!(stats.channels.len(), 1);
+        let pulse = &stats.channels[0];
+        assert_eq!(pulse.polls, 7);
+        assert_eq!(pulse.active_polls, 6);
+        assert_eq!(pulse.note_count, 4);
+        assert_eq!(pulse.lowest_frequency, Some(329.63));
+        assert_eq!(pulse.highest_frequency, Some(523.25));
+        assert_eq!(pulse.most_used_timbre(), Some("duty 2/3".to_string()));
+
+        let csv = stats.to_csv();
+        assert_eq!(csv.lines().nth(1), Some("2A03,Pulse 1,7,6,4,E4,C5,329.63,523.25,duty 2/3"));
+    }
+}
@@ -5,6 +5,7 @@ use drawing::Font;
 use drawing::SimpleBuffer;
 use events::Event;
 use panel::Panel;
+use symbols::SymbolTable;
 
 use rustico_core::nes::NesState;
 use rustico_core::opcode_info::disassemble_instruction;
@@ -57,7 +58,29 @@ impl CpuWindow {
             Color::rgb(128, 192, 128));
     }
 
-    pub fn draw_disassembly(&mut self, nes: &NesState, x: u32, y: u32) {
+    // Swaps the operand placeholder for a label, when the operand points somewhere we know
+    pub fn label_operand(symbols: &SymbolTable, instruction: &str, data_bytes: u8, data1: u8, data2: u8) -> String {
+        let (operand_address, placeholder) = match data_bytes {
+            1 => (data1 as u16, 'd'),
+            2 => ((data2 as u16) << 8 | data1 as u16, 'a'),
+            _ => {return instruction.to_string();}
+        };
+        match symbols.label(operand_address) {
+            Some(label) if instruction.len() > 3 => {
+                let (opcode_name, addressing_mode) = instruction.split_at(3);
+                return format!("{}{}", opcode_name, addressing_mode.replacen(placeholder, label, 1));
+            },
+            _ => {return instruction.to_string();}
+        }
+    }
+
+    // One row of the disassembly, with labels substituted for the address and operand
+    pub fn disassembly_line(symbols: &SymbolTable, pc: u16, instruction: &str, opcode: u8, data_bytes: u8, data1: u8, data2: u8) -> String {
+        let instruction = CpuWindow::label_operand(symbols, instruction, data_bytes, data1, data2);
+        return format!("{} - 0x{:02X}:  {}", symbols.format_address_column(pc, 6), opcode, instruction);
+    }
+
+    pub fn draw_disassembly(&mut self, nes: &NesState, symbols: &SymbolTable, x: u32, y: u32) {
         drawing::text(&mut self.canvas, &self.font, x, y, 
         "===== Disassembly =====", Color::rgb(255, 255, 255));
 
@@ -78,18 +101,18 @@ impl CpuWindow {
             }
 
             drawing::text(&mut self.canvas, &self.font, x, y + 16 + (i as u32 * 8),
-                &format!("0x{:04X} - 0x{:02X}:  {}", pc, opcode, instruction),
+                &CpuWindow::disassembly_line(symbols, pc, &instruction, opcode, data_bytes, data1, data2),
                 text_color);
         }
     }
 
-    fn draw(&mut self, nes: &NesState) {
+    fn draw(&mut self, nes: &NesState, symbols: &SymbolTable) {
         // Clear!
         let width = self.canvas.width;
         let height = self.canvas.height;
         drawing::rect(&mut self.canvas, 0, 0, width, height, Color::rgb(0,0,0));
         self.draw_registers(nes, 0, 0);
-        self.draw_disassembly(nes, 0, 40);    
+        self.draw_disassembly(nes, symbols, 0, 40);    
     }
 }
 
@@ -104,7 +127,7 @@ impl Panel for CpuWindow {
 
     fn handle_event(&mut self, runtime: &RuntimeState, event: Event) -> Vec<Event> {
         match event {
-            Event::RequestFrame => {self.draw(&runtime.nes)},
+            Event::RequestFrame => {self.draw(&runtime.nes, &runtime.symbols)},
             Event::ShowCpuWindow => {self.shown = true},
             Event::CloseWindow => {self.shown = false},
             _ => {}
@@ -119,4 +142,30 @@ impl Panel for CpuWindow {
     fn scale_factor(&self) -> u32 {
         return 2;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn disassembly_shows_labels_from_a_symbol_file() {
+        let path = env::temp_dir().join("rustico_disassembly_labels.nl");
+        fs::write(&path, "$C000#reset#\n$C123#play_note#called every frame\n").unwrap();
+        let symbols = SymbolTable::load(path.to_str().unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+
+        // JMP $C123, sitting at the reset vector
+        let (instruction, data_bytes) = disassemble_instruction(0x4C, 0x23, 0xC1);
+        let line = CpuWindow::disassembly_line(&symbols, 0xC000, &instruction, 0x4C, data_bytes, 0x23, 0xC1);
+        assert!(line.starts_with("reset "));
+        assert!(line.ends_with("play_note"));
+
+        // Nothing known about either address, so both stay hex
+        let line = CpuWindow::disassembly_line(&symbols, 0xC003, &instruction, 0x4C, data_bytes, 0x00, 0x80);
+        assert!(line.starts_with("0xC003"));
+        assert!(line.ends_with("JMP a"));
+    }
+}
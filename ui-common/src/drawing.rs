@@ -48,8 +48,19 @@ impl Color {
         }
     }
 
+    // Hex colors are handled here, so malformed ones get a useful error. Everything else
+    // (rgb(), named colors, etc) goes to csscolorparser.
     pub fn from_string(color_string: &str) -> Result<Color, String> {
-        match csscolorparser::parse(color_string) {
+        let trimmed = color_string.trim();
+        let bare_hex = trimmed.len() > 0 && trimmed.chars().all(|c| c.is_ascii_hexdigit());
+        if trimmed.starts_with("#") || bare_hex {
+            let digits = trimmed.trim_start_matches("#");
+            if trimmed.len() - digits.len() > 1 {
+                return Err(format!("Invalid color {}: more than one '#'", color_string));
+            }
+            return Color::from_hex_digits(digits).map_err(|why| format!("Invalid color {}: {}", color_string, why));
+        }
+        match csscolorparser::parse(trimmed) {
             Ok(css_color) => {
                 let rgba8_color = css_color.to_rgba8();
                 return Ok(Color {
@@ -67,6 +78,26 @@ impl Color {
         }
     }
 
+    // RGB, RGBA, RRGGBB or RRGGBBAA, in either case, without the '#'
+    pub fn from_hex_digits(digits: &str) -> Result<Color, String> {
+        match digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+            Some(c) => {return Err(format!("'{}' is not a hex digit", c));},
+            None => {}
+        }
+        let expanded: String = match digits.len() {
+            3 | 4 => digits.chars().flat_map(|c| vec!(c, c)).collect(),
+            6 | 8 => digits.to_string(),
+            length => {return Err(format!("expected 3, 4, 6 or 8 hex digits, got {}", length));}
+        };
+        let mut data = [255u8; 4];
+        for i in 0 .. expanded.len() / 2 {
+            data[i] = u8::from_str_radix(&expanded[i * 2 .. i * 2 + 2], 16).unwrap();
+        }
+        return Ok(Color {
+            data: data
+        });
+    }
+
     pub fn r(&self) -> u8 {
         return self.data[0];
     }
@@ -110,6 +141,13 @@ pub fn apply_gradient(colors: Vec<Color>, index: f32) -> Color {
 
 }
 
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [ 0,  8,  2, 10],
+    [12,  4, 14,  6],
+    [ 3, 11,  1,  9],
+    [15,  7, 13,  5],
+];
+
 #[derive(Clone)]
 pub struct SimpleBuffer {
     pub buffer: Vec<u8>,
@@ -146,6 +184,42 @@ impl SimpleBuffer {
         return SimpleBuffer::from_image(img);
     }
 
+    // Box filters the buffer down by an integer factor. With dither set, the fraction left over
+    // from averaging is spread out with a 4x4 ordered (Bayer) pattern instead of being rounded
+    // off, which hides banding in smooth gradients. The pattern is fixed, so the same input
+    // always produces the same output.
+    pub fn downsample(&self, factor: u32, dither: bool) -> SimpleBuffer {
+        let factor = factor.max(1);
+        let mut output = SimpleBuffer::new(self.width / factor, self.height / factor);
+        let pixel_count = (factor * factor) as f32;
+        for y in 0 .. output.height {
+            for x in 0 .. output.width {
+                let mut totals = [0u32; 4];
+                for sy in y * factor .. (y + 1) * factor {
+                    for sx in x * factor .. (x + 1) * factor {
+                        let index = ((sy * self.width + sx) * 4) as usize;
+                        for c in 0 .. 4 {
+                            totals[c] += self.buffer[index + c] as u32;
+                        }
+                    }
+                }
+                let threshold = if dither {
+                    (BAYER_4X4[(y % 4) as usize][(x % 4) as usize] as f32 + 0.5) / 16.0
+                } else {
+                    0.5
+                };
+                let mut data = [0u8; 4];
+                for c in 0 .. 4 {
+                    let average = totals[c] as f32 / pixel_count;
+                    let rounded = if average.fract() >= threshold {average.ceil()} else {average.floor()};
+                    data[c] = rounded.min(255.0) as u8;
+                }
+                output.put_pixel(x, y, Color{data: data});
+            }
+        }
+        return output;
+    }
+
     pub fn put_pixel(&mut self, x: u32, y: u32, color: Color) {
         let index = ((y * self.width + x) * 4) as usize;
         self.buffer[index .. (index + 4)].copy_from_slice(&color.data);
@@ -248,6 +322,18 @@ pub fn blit(destination: &mut SimpleBuffer, source: &SimpleBuffer, dx: u32, dy:
     }
 }
 
+// A straight copy with no tinting or blending, for when the source is already final
+pub fn copy(destination: &mut SimpleBuffer, source: &SimpleBuffer, dx: u32, dy: u32) {
+    let row_width = source.width.min(destination.width.saturating_sub(dx));
+    for y in 0 .. source.height.min(destination.height.saturating_sub(dy)) {
+        let source_index = (y * source.width * 4) as usize;
+        let destination_index = (((dy + y) * destination.width + dx) * 4) as usize;
+        let row_bytes = (row_width * 4) as usize;
+        destination.buffer[destination_index .. destination_index + row_bytes]
+            .copy_from_slice(&source.buffer[source_index .. source_index + row_bytes]);
+    }
+}
+
 pub fn char(destination: &mut SimpleBuffer, font: &Font, x: u32, y: u32, c: char, color: Color) {
     if c.is_ascii() {
         let ascii_code_point = c as u32;
@@ -285,4 +371,88 @@ pub fn blend_rect(destination: &mut SimpleBuffer, x: u32, y: u32, width: u32, he
             destination.blend_pixel(dx, dy, color);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two levels spread over the whole width. The source is finely speckled between neighboring
+    // levels, the way antialiased edges are, so each 4x4 average climbs smoothly through the
+    // fractions in between.
+    fn shallow_gradient() -> SimpleBuffer {
+        let mut gradient = SimpleBuffer::new(512, 16);
+        for y in 0 .. 16 {
+            for x in 0 .. 512 {
+                let speckle = ((x * 7 + y * 13) % 16) as f32 / 16.0;
+                let level = (x as f32 / 256.0 + speckle) as u8;
+                gradient.put_pixel(x, y, Color::rgb(level, level, level));
+            }
+        }
+        return gradient;
+    }
+
+    // The longest run of identical values along any row
+    fn longest_flat_run(buffer: &SimpleBuffer) -> u32 {
+        let mut longest = 0;
+        for y in 0 .. buffer.height {
+            let mut run = 1;
+            for x in 1 .. buffer.width {
+                if buffer.get_pixel(x, y).r() == buffer.get_pixel(x - 1, y).r() {
+                    run += 1;
+                } else {
+                    run = 1;
+                }
+                longest = longest.max(run);
+            }
+        }
+        return longest;
+    }
+
+    #[test]
+    fn dithered_downsample_breaks_up_gradient_bands() {
+        let gradient = shallow_gradient();
+        let plain = gradient.downsample(4, false);
+        let dithered = gradient.downsample(4, true);
+        assert_eq!((dithered.width, dithered.height), (128, 4));
+
+        // Rounding alone leaves long flat bands; dithering mixes the neighboring levels into them
+        assert_eq!(longest_flat_run(&plain), 64);
+        assert!(longest_flat_run(&dithered) <= 32);
+
+        // While keeping the overall brightness of the source
+        let total = |buffer: &SimpleBuffer, scale: u32| -> u32 {
+            (0 .. buffer.height).map(|y| (0 .. buffer.width).map(|x| buffer.get_pixel(x, y).r() as u32).sum::<u32>()).sum::<u32>() / scale
+        };
+        let source_total = total(&gradient, 16);
+        assert!((total(&dithered, 1) as i32 - source_total as i32).abs() <= 8);
+
+        // And the same input always gives the same pixels
+        assert_eq!(gradient.downsample(4, true).buffer, dithered.buffer);
+    }
+
+    #[test]
+    fn hex_colors_parse_in_every_length_and_case() {
+        assert_eq!(Color::from_string("#1a2").unwrap().data, [0x11, 0xAA, 0x22, 0xFF]);
+        assert_eq!(Color::from_string("#1a2b").unwrap().data, [0x11, 0xAA, 0x22, 0xBB]);
+        assert_eq!(Color::from_string("#12aB3c").unwrap().data, [0x12, 0xAB, 0x3C, 0xFF]);
+        assert_eq!(Color::from_string("#12AB3C80").unwrap().data, [0x12, 0xAB, 0x3C, 0x80]);
+        // The '#' is optional, and surrounding whitespace is ignored
+        assert_eq!(Color::from_string("12ab3c").unwrap().data, [0x12, 0xAB, 0x3C, 0xFF]);
+        assert_eq!(Color::from_string("  #FFF ").unwrap().data, [0xFF, 0xFF, 0xFF, 0xFF]);
+        // Anything else still goes through the CSS parser
+        assert_eq!(Color::from_string("rgb(1, 2, 3)").unwrap().data, [1, 2, 3, 0xFF]);
+    }
+
+    #[test]
+    fn malformed_hex_colors_are_rejected_with_a_reason() {
+        let error = |color_string: &str| Color::from_string(color_string).err().unwrap();
+        assert_eq!(error("#GGG"), "Invalid color #GGG: 'G' is not a hex digit");
+        assert_eq!(error("#12345"), "Invalid color #12345: expected 3, 4, 6 or 8 hex digits, got 5");
+        assert_eq!(error("#"), "Invalid color #: expected 3, 4, 6 or 8 hex digits, got 0");
+        assert_eq!(error("##123456"), "Invalid color ##123456: more than one '#'");
+        assert_eq!(error("#12#456"), "Invalid color #12#456: '#' is not a hex digit");
+        assert!(Color::from_string("").is_err());
+        assert!(Color::from_string("not a color").is_err());
+    }
+}
@@ -22,7 +22,12 @@ pub enum Event {
     CloseWindow,
     CartridgeLoaded(String),
     CartridgeRejected(String, String),
+    // Keeps a snapshot of the console, for DiffAgainstSnapshot to compare against later
+    CaptureSnapshot,
     ChangeDisk(usize, usize),
+    // Logs every register and RAM byte that has changed since CaptureSnapshot
+    DiffAgainstSnapshot,
+    DumpEventLog(String),
     GameToggleOverscan,
     GameIncreaseScale,
     GameDecreaseScale,
@@ -30,13 +35,26 @@ pub enum Event {
     LoadSram(Arc<Vec<u8>>),
     LoadBios(Arc<Vec<u8>>),
     LoadFailed(String),
+    // The shell gave up on a step after running this many scanlines without the expected
+    // audio or frame; the core is probably stuck
+    EmulatorStalled(u32),
+    LoadSymbols(String),
+    // Starts (or replaces) the B side of an A/B comparison: a second console running this file
+    // in lockstep with the first, which the piano roll plots alongside it
+    LoadComparisonCartridge(Arc<Vec<u8>>),
+    StopComparison,
     MouseMove(i32, i32),
     MouseClick(i32, i32),
     MouseRelease,
+    MouseWheel(i32),
     MemoryViewerNextPage,
     MemoryViewerPreviousPage,
     MemoryViewerNextBus,
+    MemoryViewerToggleAscii,
+    MemoryViewerWriteByte(u16, u8),
     MuteChannel(String, String),
+    // Silences what we send to the audio device; channels keep running and stay visible
+    MuteMasterAudio(bool),
     UnmuteChannel(String, String),
     NesNudgeAlignment,
     NesNewApuHalfFrame,
@@ -52,11 +70,30 @@ pub enum Event {
     NesRunOpcode,
     NesRunScanline,
     NesToggleEmulation,
+    PianoRollExportCsv(String),
+    PianoRollExportStats(String),
+    // path to a preset file, see PianoRollWindow::export_config
+    LoadPianoRollPreset(String),
+    SavePianoRollPreset(String),
+    PlayInputMovie(String),
+    // channel index, MIDI note number, velocity (0.0 releases the note)
+    PlayTestNote(usize, u8, f32),
+    RecordInputMovie(String),
+    StopInputMovie,
+    PpuExportChr(String),
+    PpuViewerNextChrBank,
+    PpuViewerPreviousChrBank,
+    PpuViewerNextPalette,
+    PpuViewerToggleAttributeOverlay,
     RequestFrame,
     RequestCartridgeDialog,
     RequestSramSave(String),
+    // Clears what the visualizers track for one channel (chip, channel), leaving the others alone
+    ResetChannelVisualization(String, String),
+    CenterOnActiveNotes,
     RequestBios,
     SaveSram(String, Arc<Vec<u8>>),
+    SetNtscFilter(bool),
     ShowApuWindow,
     ShowCpuWindow,
     ShowGameWindow,
@@ -65,12 +102,16 @@ pub enum Event {
     ShowPianoRollWindow,
     ShowPpuWindow,
     ShowTestWindow,
+    ShowVuMeterWindow,
+    // For shells that close panels themselves rather than through CloseWindow
+    HideVuMeterWindow,
     StandardControllerPress(usize, StandardControllerButton),
     StandardControllerRelease(usize, StandardControllerButton),
     StoreBooleanSetting(String, bool),
     StoreFloatSetting(String, f64),
     StoreIntegerSetting(String, i64),
     StoreStringSetting(String, String),
+    TextInput(String),
     ToggleBooleanSetting(String),
     Update,
 }
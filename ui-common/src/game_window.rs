@@ -17,8 +17,18 @@ pub struct GameWindow {
     pub shown: bool,
     pub scale: u32,
     pub simulate_overscan: bool,
+    pub overscan_top: u32,
+    pub overscan_bottom: u32,
+    pub overscan_left: u32,
+    pub overscan_right: u32,
     pub ntsc_filter: bool,
     pub display_fps: bool,
+    pub aspect_correction: bool,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+    // Lookup table for the three above, None when they leave every color alone
+    color_curve: Option<Vec<u8>>,
 
     pub frame_duration: Instant,
     pub durations: [f32; 60],
@@ -36,8 +46,17 @@ impl GameWindow {
             shown: true,
             scale: 2,
             simulate_overscan: false,
+            overscan_top: 0,
+            overscan_bottom: 0,
+            overscan_left: 0,
+            overscan_right: 0,
             ntsc_filter: false,
             display_fps: false,
+            aspect_correction: false,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            color_curve: None,
 
             frame_duration: Instant::now(),
             durations: [0f32; 60],
@@ -57,33 +76,73 @@ impl GameWindow {
         }
     }
 
+    // Returns the number of pixels to crop from the (top, bottom, left, right) edges of the screen.
+    // Simulated overscan is a preset which hides 8 pixels on every side.
+    fn overscan_crop(&self) -> (u32, u32, u32, u32) {
+        if self.simulate_overscan {
+            return (8, 8, 8, 8);
+        }
+        return (self.overscan_top, self.overscan_bottom, self.overscan_left, self.overscan_right);
+    }
+
+    // NES pixels are slightly wider than they are tall, roughly 8:7 on an NTSC television
+    pub fn corrected_width(&self, width: u32) -> u32 {
+        if self.aspect_correction {
+            return width * 8 / 7;
+        }
+        return width;
+    }
+
+    // Brightness and contrast work on 0.0 - 1.0, around mid grey, and gamma is applied to the result
+    pub fn adjust_component(value: u8, brightness: f32, contrast: f32, gamma: f32) -> u8 {
+        let adjusted = ((value as f32 / 255.0 - 0.5) * contrast + 0.5 + brightness).max(0.0).min(1.0);
+        let corrected = if gamma != 1.0 {adjusted.powf(1.0 / gamma)} else {adjusted};
+        return (corrected * 255.0).round() as u8;
+    }
+
+    fn update_color_curve(&mut self) {
+        if self.brightness == 0.0 && self.contrast == 1.0 && self.gamma == 1.0 {
+            self.color_curve = None;
+            return;
+        }
+        self.color_curve = Some((0 .. 256).map(|value| GameWindow::adjust_component(value as u8, self.brightness, self.contrast, self.gamma)).collect());
+    }
+
+    fn adjust_color(&self, color: Color) -> Color {
+        match self.color_curve {
+            Some(ref curve) => {
+                return Color::rgba(curve[color.r() as usize], curve[color.g() as usize], curve[color.b() as usize], color.alpha());
+            },
+            None => {return color;}
+        }
+    }
+
     fn draw(&mut self, nes: &NesState) {
-        let overscan: u32 = if self.simulate_overscan {8} else {0};
+        let (top, _bottom, left, right) = self.overscan_crop();
 
-        // Update the game screen
-        for x in overscan .. 256 - overscan {
-            for y in overscan .. 240 - overscan {
+        let pixel_scale = if self.ntsc_filter {self.scale} else {1};
+        let source_width = (256 - left - right) * pixel_scale;
+        let output_width = self.canvas.width;
+        let output_height = self.canvas.height;
+
+        // Update the game screen. When aspect correction is on the canvas is wider than the
+        // source, so pick the nearest source column for every output column.
+        for ox in 0 .. output_width {
+            let source_column = left * pixel_scale + (ox * source_width) / output_width;
+            for oy in 0 .. output_height {
+                let y = top + oy / pixel_scale;
                 if self.ntsc_filter {
-                    let scale = self.scale;
-                    let base_x = x * scale;
-                    let base_y = y * 256 * scale;
-
-                    for sx in 0 .. self.scale {
-                        let column_color = Color::from_raw(nes.ppu.filtered_screen[(base_y + base_x + sx) as usize]);
-                        for sy in 0 .. self.scale {
-                            self.canvas.put_pixel((x - overscan) * scale + sx, (y - overscan) * scale + sy, column_color);        
-                        }
-                    }
+                    let row_start = y * 256 * pixel_scale;
+                    let color = Color::from_raw(nes.ppu.filtered_screen[(row_start + source_column) as usize]);
+                    let color = self.adjust_color(color);
+                    self.canvas.put_pixel(ox, oy, color);
                 } else {
-                    let palette_index = ((nes.ppu.screen[(y * 256 + x) as usize]) as usize) * 3;
-                    self.canvas.put_pixel(
-                        x - overscan,
-                        y - overscan,
-                        Color::rgb(
-                            NTSC_PAL[palette_index + 0],
-                            NTSC_PAL[palette_index + 1],
-                            NTSC_PAL[palette_index + 2])
-                    );
+                    let palette_index = ((nes.ppu.screen[(y * 256 + source_column) as usize]) as usize) * 3;
+                    let color = self.adjust_color(Color::rgb(
+                        NTSC_PAL[palette_index + 0],
+                        NTSC_PAL[palette_index + 1],
+                        NTSC_PAL[palette_index + 2]));
+                    self.canvas.put_pixel(ox, oy, color);
                 }
             }
         }
@@ -109,8 +168,9 @@ impl GameWindow {
     }
 
     fn update_canvas_size(&mut self) {
-        let base_width = if self.simulate_overscan {240} else {256};
-        let base_height = if self.simulate_overscan {224} else {240};
+        let (top, bottom, left, right) = self.overscan_crop();
+        let base_width = self.corrected_width(256 - left - right);
+        let base_height = 240 - top - bottom;
         let scaled_width = if self.ntsc_filter {base_width * self.scale} else {base_width};
         let scaled_height = if self.ntsc_filter {base_height * self.scale} else {base_height};
         self.canvas = SimpleBuffer::new(scaled_width, scaled_height);
@@ -149,10 +209,18 @@ impl Panel for GameWindow {
                 self.decrease_scale();
                 responses.push(Event::StoreIntegerSetting("video.scale_factor".to_string(), self.scale as i64));
             },
+            Event::SetNtscFilter(enabled) => {
+                // The filtered output is rendered at the full scale factor, so the canvas
+                // needs to be resized whenever this changes
+                self.ntsc_filter = enabled;
+                self.update_canvas_size();
+                responses.push(Event::StoreBooleanSetting("video.ntsc_filter".to_string(), enabled));
+            },
 
             Event::ApplyBooleanSetting(path, value) => {
                 match path.as_str() {
                     "video.display_fps" => {self.display_fps = value},
+                    "video.aspect_correction" => {self.aspect_correction = value; self.update_canvas_size()},
                     "video.ntsc_filter" => {self.ntsc_filter = value; self.update_canvas_size()},
                     "video.simulate_overscan" => {self.simulate_overscan = value; self.update_canvas_size()},
                     _ => {}
@@ -166,6 +234,19 @@ impl Panel for GameWindow {
                             self.update_canvas_size();
                         }
                     },
+                    // Cap each edge well short of half the screen, so there's always something left to draw
+                    "video.overscan.top" => {self.overscan_top = value.max(0).min(64) as u32; self.update_canvas_size()},
+                    "video.overscan.bottom" => {self.overscan_bottom = value.max(0).min(64) as u32; self.update_canvas_size()},
+                    "video.overscan.left" => {self.overscan_left = value.max(0).min(64) as u32; self.update_canvas_size()},
+                    "video.overscan.right" => {self.overscan_right = value.max(0).min(64) as u32; self.update_canvas_size()},
+                    _ => {}
+                }
+            },
+            Event::ApplyFloatSetting(path, value) => {
+                match path.as_str() {
+                    "video.brightness" => {self.brightness = value as f32; self.update_color_curve()},
+                    "video.contrast" => {self.contrast = value as f32; self.update_color_curve()},
+                    "video.gamma" => {self.gamma = value as f32; self.update_color_curve()},
                     _ => {}
                 }
             },
@@ -185,4 +266,140 @@ impl Panel for GameWindow {
             return self.scale;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ntsc_filter_resizes_the_canvas() {
+        let runtime = RuntimeState::new();
+        let mut game_window = GameWindow::new();
+        assert_eq!((game_window.canvas.width, game_window.canvas.height), (256, 240));
+
+        // The shells report the canvas dimensions as-is, so this is what they'll see
+        let responses = game_window.handle_event(&runtime, Event::SetNtscFilter(true));
+        assert_eq!((game_window.canvas.width, game_window.canvas.height), (512, 480));
+        assert!(responses.iter().any(|event| match event {
+            Event::StoreBooleanSetting(path, true) => path == "video.ntsc_filter",
+            _ => false
+        }));
+        game_window.handle_event(&runtime, Event::RequestFrame);
+        assert_eq!(game_window.canvas.buffer.len(), 512 * 480 * 4);
+
+        game_window.handle_event(&runtime, Event::SetNtscFilter(false));
+        assert_eq!((game_window.canvas.width, game_window.canvas.height), (256, 240));
+    }
+
+    #[test]
+    fn overscan_crop_drops_rows_from_the_output() {
+        let mut runtime = RuntimeState::new();
+        let mut game_window = GameWindow::new();
+        // Paint the first and last visible rows a distinct color, and the cropped ones another
+        for x in 0 .. 256 {
+            for y in 0 .. 240 {
+                let palette_index = match y {
+                    0 ..= 7 | 232 ..= 239 => 0x30,
+                    8 | 231 => 0x16,
+                    _ => 0x0F
+                };
+                runtime.nes.ppu.screen[y * 256 + x] = palette_index;
+            }
+        }
+        game_window.handle_event(&runtime, Event::ApplyIntegerSetting("video.overscan.top".to_string(), 8));
+        game_window.handle_event(&runtime, Event::ApplyIntegerSetting("video.overscan.bottom".to_string(), 8));
+        assert_eq!(game_window.canvas.height, 240 - 16);
+        assert_eq!(game_window.canvas.width, 256);
+
+        game_window.handle_event(&runtime, Event::RequestFrame);
+        let red = 0x16 * 3;
+        for &y in [0, 223].iter() {
+            let pixel = game_window.canvas.get_pixel(128, y);
+            assert_eq!((pixel.r(), pixel.g(), pixel.b()), (NTSC_PAL[red], NTSC_PAL[red + 1], NTSC_PAL[red + 2]));
+        }
+    }
+
+    #[test]
+    fn aspect_correction_stretches_to_eight_sevenths() {
+        let runtime = RuntimeState::new();
+        let mut game_window = GameWindow::new();
+        game_window.handle_event(&runtime, Event::ApplyBooleanSetting("video.aspect_correction".to_string(), true));
+        // 256 * 8 / 7 = 292.57, rounded down
+        assert_eq!((game_window.canvas.width, game_window.canvas.height), (292, 240));
+
+        // And the stretched image is what gets drawn, so a capture sees it too
+        game_window.handle_event(&runtime, Event::RequestFrame);
+        assert_eq!(game_window.canvas.buffer.len(), 292 * 240 * 4);
+
+        game_window.handle_event(&runtime, Event::ApplyBooleanSetting("video.aspect_correction".to_string(), false));
+        assert_eq!(game_window.canvas.width, 256);
+    }
+
+    #[test]
+    fn aspect_correction_picks_the_nearest_source_column() {
+        let mut runtime = RuntimeState::new();
+        let mut game_window = GameWindow::new();
+        // Stripes one pixel wide: every output column should still land on one or the other
+        for x in 0 .. 256 {
+            for y in 0 .. 240 {
+                runtime.nes.ppu.screen[y * 256 + x] = if x % 2 == 0 {0x16} else {0x2A};
+            }
+        }
+        game_window.handle_event(&runtime, Event::ApplyBooleanSetting("video.aspect_correction".to_string(), true));
+        game_window.handle_event(&runtime, Event::RequestFrame);
+        let last_column = game_window.canvas.get_pixel(291, 100);
+        let odd = 0x2A * 3;
+        assert_eq!((last_column.r(), last_column.g(), last_column.b()), (NTSC_PAL[odd], NTSC_PAL[odd + 1], NTSC_PAL[odd + 2]));
+    }
+
+    #[test]
+    fn emphasis_bits_attenuate_the_other_channels() {
+        let mut runtime = RuntimeState::new();
+        let mut game_window = GameWindow::new();
+        // Solid white ($30), with each emphasis bit in turn: red, green, blue
+        let mut rendered: Vec<(u8, u8, u8)> = Vec::new();
+        for emphasis in [0u16, 0b001, 0b010, 0b100].iter() {
+            for pixel in runtime.nes.ppu.screen.iter_mut() {
+                *pixel = (emphasis << 6) | 0x30;
+            }
+            game_window.handle_event(&runtime, Event::RequestFrame);
+            let pixel = game_window.canvas.get_pixel(128, 120);
+            rendered.push((pixel.r(), pixel.g(), pixel.b()));
+        }
+        let (plain, red, green, blue) = (rendered[0], rendered[1], rendered[2], rendered[3]);
+        // Each bit keeps its own channel (near enough) and dims the other two
+        assert!(red.1 < plain.1 && red.2 < plain.2 && red.0 > red.1 && red.0 > red.2);
+        assert!(green.0 < plain.0 && green.2 < plain.2 && green.1 > green.0 && green.1 > green.2);
+        assert!(blue.0 < plain.0 && blue.1 < plain.1 && blue.2 > blue.0 && blue.2 > blue.1);
+    }
+
+    #[test]
+    fn brightness_and_contrast_shift_mid_grey() {
+        // (128 / 255 - 0.5) * 2.0 + 0.5 + 0.1 = 0.6039, or 154 out of 255
+        assert_eq!(GameWindow::adjust_component(128, 0.1, 2.0, 1.0), 154);
+        assert_eq!(GameWindow::adjust_component(128, -0.1, 1.0, 1.0), 103);
+        // Contrast pivots around the middle, so black and white get pushed off the ends
+        assert_eq!(GameWindow::adjust_component(0, 0.0, 2.0, 1.0), 0);
+        assert_eq!(GameWindow::adjust_component(255, 0.0, 2.0, 1.0), 255);
+        // Gamma above 1.0 lifts the midtones but leaves the ends alone
+        assert!(GameWindow::adjust_component(128, 0.0, 1.0, 2.2) > 128);
+        assert_eq!(GameWindow::adjust_component(255, 0.0, 1.0, 2.2), 255);
+
+        // And the settings reach the rendered frame
+        let mut runtime = RuntimeState::new();
+        let mut game_window = GameWindow::new();
+        for pixel in runtime.nes.ppu.screen.iter_mut() {
+            *pixel = 0x10;
+        }
+        game_window.handle_event(&runtime, Event::RequestFrame);
+        let plain = game_window.canvas.get_pixel(128, 120);
+        game_window.handle_event(&runtime, Event::ApplyFloatSetting("video.brightness".to_string(), 0.1));
+        game_window.handle_event(&runtime, Event::ApplyFloatSetting("video.contrast".to_string(), 2.0));
+        game_window.handle_event(&runtime, Event::RequestFrame);
+        let adjusted = game_window.canvas.get_pixel(128, 120);
+        assert_eq!(adjusted.r(), GameWindow::adjust_component(plain.r(), 0.1, 2.0, 1.0));
+        assert_eq!(adjusted.g(), GameWindow::adjust_component(plain.g(), 0.1, 2.0, 1.0));
+        assert_eq!(adjusted.b(), GameWindow::adjust_component(plain.b(), 0.1, 2.0, 1.0));
+    }
+}
@@ -0,0 +1,87 @@
+// A very simple input movie format: one line per emulated frame, holding the controller
+// bytes for port 1 and port 2 in hex, ie:
+//   00 00
+//   08 00
+//   08 00
+// Blank lines and anything after a # are ignored, so movies can be annotated by hand.
+
+use std::fs;
+
+pub struct InputMovie {
+    pub frames: Vec<(u8, u8)>,
+}
+
+impl InputMovie {
+    pub fn new() -> InputMovie {
+        return InputMovie {
+            frames: Vec::new(),
+        };
+    }
+
+    pub fn from_string(movie_text: &str) -> Result<InputMovie, String> {
+        let mut movie = InputMovie::new();
+        for (line_number, line) in movie_text.lines().enumerate() {
+            let contents = line.split('#').next().unwrap_or("").trim();
+            if contents.len() == 0 {
+                continue;
+            }
+            let fields = contents.split_whitespace().collect::<Vec<&str>>();
+            if fields.len() != 2 {
+                return Err(format!("Line {}: expected two controller bytes, found {}", line_number + 1, fields.len()));
+            }
+            match (u8::from_str_radix(fields[0], 16), u8::from_str_radix(fields[1], 16)) {
+                (Ok(p1), Ok(p2)) => {movie.frames.push((p1, p2));},
+                _ => {
+                    return Err(format!("Line {}: invalid controller bytes {}", line_number + 1, contents));
+                }
+            }
+        }
+        return Ok(movie);
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut movie_text = String::new();
+        for &(p1, p2) in self.frames.iter() {
+            movie_text.push_str(&format!("{:02X} {:02X}\n", p1, p2));
+        }
+        return movie_text;
+    }
+
+    pub fn load(path: &str) -> Result<InputMovie, String> {
+        match fs::read_to_string(path) {
+            Ok(movie_text) => {return InputMovie::from_string(&movie_text);},
+            Err(why) => {return Err(format!("Couldn't read input movie {}: {}", path, why));}
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        match fs::write(path, self.to_string()) {
+            Ok(_) => {return Ok(());},
+            Err(why) => {return Err(format!("Couldn't write input movie {}: {}", path, why));}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let movie = InputMovie::from_string("# title screen\n00 00\n\n08 00  # start\n  81 4f\n").unwrap();
+        assert_eq!(movie.frames, vec!((0x00, 0x00), (0x08, 0x00), (0x81, 0x4F)));
+        assert_eq!(movie.to_string(), "00 00\n08 00\n81 4F\n");
+    }
+
+    #[test]
+    fn malformed_lines_report_their_line_number() {
+        match InputMovie::from_string("00 00\n08\n") {
+            Err(why) => assert!(why.starts_with("Line 2:"), "{}", why),
+            Ok(_) => panic!("a line with one byte should be rejected")
+        }
+        match InputMovie::from_string("00 zz\n") {
+            Err(why) => assert!(why.starts_with("Line 1:"), "{}", why),
+            Ok(_) => panic!("a line with a bad byte should be rejected")
+        }
+    }
+}
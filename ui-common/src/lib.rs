@@ -5,9 +5,14 @@ extern crate rustico_core;
 extern crate toml;
 
 pub mod application;
+pub mod channel_stats;
 pub mod events;
 pub mod panel;
 pub mod drawing;
+pub mod input_movie;
+pub mod pitch_detection;
+pub mod resampler;
+pub mod symbols;
 
 pub use events::Event;
 
@@ -19,4 +24,5 @@ pub mod memory_window;
 pub mod test_window;
 pub mod piano_roll_window;
 pub mod ppu_window;
+pub mod vu_meter_window;
 pub mod settings;
\ No newline at end of file
@@ -5,7 +5,9 @@ use drawing::Font;
 use drawing::SimpleBuffer;
 use events::Event;
 use panel::Panel;
+use symbols::SymbolTable;
 
+use rustico_core::logging;
 use rustico_core::nes::NesState;
 use rustico_core::memory;
 
@@ -16,6 +18,16 @@ pub struct MemoryWindow {
     pub shown: bool,
     pub view_ppu: bool,
     pub memory_page: u16,
+    pub ascii_mode: bool,
+    // The byte being edited, and the high nybble typed so far, if any
+    pub edit_address: Option<u16>,
+    pub pending_nybble: Option<u8>,
+}
+
+// Cartridge ROM; writes here would land on mapper registers instead of changing what's shown.
+// Where that is depends on the mapper: the FDS has RAM all the way up to its BIOS.
+pub fn is_read_only(nes: &NesState, address: u16) -> bool {
+    return nes.mapper.cpu_address_is_rom(address);
 }
 
 impl MemoryWindow {
@@ -29,10 +41,66 @@ impl MemoryWindow {
             shown: false,
             view_ppu: false,
             memory_page: 0x0000,
+            ascii_mode: false,
+            edit_address: None,
+            pending_nybble: None,
         };
     }
 
+    pub fn select_byte(&mut self, address: u16) {
+        if self.view_ppu {
+            logging::warn("Editing PPU memory is not supported.");
+            return;
+        }
+        self.edit_address = Some(address);
+        self.pending_nybble = None;
+    }
+
+    // Commits a new value for the selected byte, and moves on to the next one
+    pub fn write_selected_byte(&mut self, nes: &NesState, data: u8) -> Vec<Event> {
+        let mut responses: Vec<Event> = Vec::new();
+        match self.edit_address {
+            Some(address) => {
+                if is_read_only(nes, address) {
+                    logging::warn(&format!("0x{:04X} is in ROM, ignoring write of 0x{:02X}.", address, data));
+                } else {
+                    responses.push(Event::MemoryViewerWriteByte(address, data));
+                }
+                self.edit_address = Some(address.wrapping_add(1));
+                self.pending_nybble = None;
+            },
+            None => {}
+        }
+        return responses;
+    }
+
+    pub fn handle_text_input(&mut self, nes: &NesState, text: &str) -> Vec<Event> {
+        let mut responses: Vec<Event> = Vec::new();
+        if self.edit_address.is_none() {
+            return responses;
+        }
+        for c in text.chars() {
+            if self.ascii_mode {
+                if c.is_ascii() && !c.is_ascii_control() {
+                    responses.extend(self.write_selected_byte(nes, c as u8));
+                }
+            } else {
+                match c.to_digit(16) {
+                    Some(nybble) => {
+                        match self.pending_nybble {
+                            Some(high) => {responses.extend(self.write_selected_byte(nes, (high << 4) | nybble as u8));},
+                            None => {self.pending_nybble = Some(nybble as u8);}
+                        }
+                    },
+                    None => {}
+                }
+            }
+        }
+        return responses;
+    }
+
     pub fn draw_memory_page(&mut self, nes: &NesState, sx: u32, sy: u32) {
+        let work_ram = nes.memory.work_ram();
         for y in 0 .. 16 {
             for x in 0 .. 16 {
                 let address = self.memory_page + (x as u16) + (y as u16 * 16);
@@ -64,7 +132,12 @@ impl MemoryWindow {
                         }
                     }
                 } else {
-                    byte = memory::debug_read_byte(nes, address);
+                    if address < 0x2000 {
+                        // Internal RAM and its mirrors, no need to go through the bus
+                        byte = work_ram[(address & 0x7FF) as usize];
+                    } else {
+                        byte = memory::debug_read_byte(nes, address);
+                    }
                     if address == nes.registers.pc {
                         bg_color = Color::rgb(128, 32, 32);
                     } else if address == (nes.registers.s as u16 + 0x100) {
@@ -91,18 +164,36 @@ impl MemoryWindow {
                 if byte == 0 {
                     text_color = Color::rgba(255, 255, 255, 64);
                 }
+                let selected = !self.view_ppu && self.edit_address == Some(address);
+                if selected {
+                    bg_color = Color::rgb(32, 96, 160);
+                    text_color = Color::rgba(255, 255, 255, 255);
+                }
                 let cell_x = sx + x * 19;
                 let cell_y = sy + y * 11;
                 drawing::rect(&mut self.canvas, cell_x, cell_y, 19, 11, bg_color);
-                drawing::hex(&mut self.canvas, &self.font, 
-                    cell_x + 2, cell_y + 2,
-                    byte as u32, 2, 
-                    text_color);
+                match self.pending_nybble {
+                    Some(high) if selected => {
+                        drawing::hex(&mut self.canvas, &self.font, cell_x + 2, cell_y + 2, high as u32, 1, text_color);
+                        drawing::char(&mut self.canvas, &self.font, cell_x + 2 + 8, cell_y + 2, '_', text_color);
+                    },
+                    _ => {
+                        if self.ascii_mode {
+                            let c = if byte >= 0x20 && byte < 0x7F {byte as char} else {'.'};
+                            drawing::char(&mut self.canvas, &self.font, cell_x + 6, cell_y + 2, c, text_color);
+                        } else {
+                            drawing::hex(&mut self.canvas, &self.font, 
+                                cell_x + 2, cell_y + 2,
+                                byte as u32, 2, 
+                                text_color);
+                        }
+                    }
+                }
             }
         }
     }
 
-    pub fn draw(&mut self, nes: &NesState) {
+    pub fn draw(&mut self, nes: &NesState, symbols: &SymbolTable) {
         let width = self.canvas.width;
         let height = self.canvas.height;
         
@@ -111,6 +202,9 @@ impl MemoryWindow {
         drawing::text(&mut self.canvas, &self.font, 0, 0, &format!("{} Page: 0x{:04X}",
             if self.view_ppu {"PPU"} else {"CPU"}, self.memory_page), 
             Color::rgb(255, 255, 255));
+        drawing::text(&mut self.canvas, &self.font, width - 24, 0, 
+            if self.ascii_mode {"ASC"} else {"HEX"}, 
+            Color::rgb(255, 255, 255));
 
         // Draw memory region selector
         for i in 0x0 .. 0x10 {
@@ -138,16 +232,40 @@ impl MemoryWindow {
 
         // Draw row labels
         for i in 0 .. 0x10 {
-            drawing::text(&mut self.canvas, &self.font, 0, 44 + 2 + (i as u32 * 11), &format!("0x{:04X}",
-                self.memory_page + (i as u16 * 0x10)), 
+            let row_address = self.memory_page + (i as u16 * 0x10);
+            let row_label = if self.view_ppu {format!("0x{:04X}", row_address)} else {symbols.format_address_column(row_address, 6)};
+            drawing::text(&mut self.canvas, &self.font, 0, 44 + 2 + (i as u32 * 11), &row_label, 
                 Color::rgba(255, 255, 255, 64));
         }
+
+        // Name the selected byte, if we know what it is
+        match self.edit_address {
+            Some(address) if !self.view_ppu => {
+                match symbols.label(address) {
+                    Some(label) => {
+                        let label_text = label.chars().take(22).collect::<String>();
+                        drawing::text(&mut self.canvas, &self.font, 136, 0, &label_text, Color::rgb(128, 192, 255));
+                    },
+                    None => {}
+                }
+            },
+            _ => {}
+        }
         self.draw_memory_page(nes, 56, 44);
     }
 
     pub fn handle_click(&mut self, mx: i32, my: i32) {
         if my < 11 && mx < 32 {
             self.view_ppu = !self.view_ppu;
+            self.edit_address = None;
+        }
+        if my < 11 && mx >= (self.canvas.width as i32 - 24) {
+            self.ascii_mode = !self.ascii_mode;
+        }
+        if my >= 44 && my < 44 + 16 * 11 && mx >= 56 && mx < 56 + 16 * 19 {
+            let column = ((mx - 56) / 19) as u16;
+            let row = ((my - 44) / 11) as u16;
+            self.select_byte(self.memory_page + row * 16 + column);
         }
         if my >= 11 && my < 22 && mx > 56 && mx < 360 {
             let high_nybble = ((mx - 56) / 19) as u16;
@@ -172,9 +290,13 @@ impl Panel for MemoryWindow {
 
     fn handle_event(&mut self, runtime: &RuntimeState, event: Event) -> Vec<Event> {
         match event {
-            Event::RequestFrame => {self.draw(&runtime.nes)},
+            Event::RequestFrame => {self.draw(&runtime.nes, &runtime.symbols)},
             Event::ShowMemoryWindow => {self.shown = true},
-            Event::CloseWindow => {self.shown = false},
+            Event::CloseWindow => {
+                self.shown = false;
+                self.edit_address = None;
+                self.pending_nybble = None;
+            },
             Event::MemoryViewerNextPage => {
                 self.memory_page = self.memory_page.wrapping_add(0x100);
             },
@@ -183,8 +305,14 @@ impl Panel for MemoryWindow {
             },
             Event::MemoryViewerNextBus => {
                 self.view_ppu = !self.view_ppu;
+                self.edit_address = None;
+            },
+            Event::MemoryViewerToggleAscii => {
+                self.ascii_mode = !self.ascii_mode;
+                self.pending_nybble = None;
             },
             Event::MouseClick(x, y) => {self.handle_click(x, y);},
+            Event::TextInput(text) => {return self.handle_text_input(&runtime.nes, &text);},
             _ => {}
         }
         return Vec::<Event>::new();
@@ -197,4 +325,75 @@ impl Panel for MemoryWindow {
     fn scale_factor(&self) -> u32 {
         return 2;
     }
-}
\ No newline at end of file
+
+    // Hex digits and ASCII both overlap the single key shortcuts, so hold those off mid-edit
+    fn has_keyboard_focus(&self) -> bool {
+        return self.edit_address.is_some();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustico_core::cartridge::mapper_from_file;
+
+    #[test]
+    fn typed_hex_writes_ram_through_the_bus() {
+        let mut runtime = RuntimeState::new();
+        let mut memory_window = MemoryWindow::new();
+        memory_window.select_byte(0x0010);
+
+        let responses = memory_window.handle_event(&runtime, Event::TextInput("a".to_string()));
+        assert_eq!(responses.len(), 0);
+        let responses = memory_window.handle_event(&runtime, Event::TextInput("5".to_string()));
+        assert_eq!(responses.len(), 1);
+        match responses[0] {
+            Event::MemoryViewerWriteByte(address, data) => {assert_eq!((address, data), (0x0010, 0xA5));},
+            _ => {panic!("expected a write, got {:?}", responses[0]);}
+        }
+        // Selection moves along, ready for the next byte
+        assert_eq!(memory_window.edit_address, Some(0x0011));
+
+        for response in responses {
+            runtime.handle_event(response);
+        }
+        assert_eq!(runtime.nes.memory.work_ram()[0x10], 0xA5);
+    }
+
+    #[test]
+    fn rom_edits_are_rejected() {
+        let runtime = RuntimeState::new();
+        let mut memory_window = MemoryWindow::new();
+        memory_window.select_byte(0x8000);
+        let responses = memory_window.handle_event(&runtime, Event::TextInput("ff".to_string()));
+        assert_eq!(responses.len(), 0);
+        assert_eq!(memory_window.edit_address, Some(0x8001));
+    }
+
+    #[test]
+    fn editing_holds_the_keyboard_until_closed() {
+        let runtime = RuntimeState::new();
+        let mut memory_window = MemoryWindow::new();
+        assert!(!memory_window.has_keyboard_focus());
+        memory_window.select_byte(0x0010);
+        assert!(memory_window.has_keyboard_focus());
+        memory_window.handle_event(&runtime, Event::CloseWindow);
+        assert!(!memory_window.has_keyboard_focus());
+    }
+
+    #[test]
+    fn fds_ram_above_0x8000_is_editable() {
+        let mut fds_image = vec!(0x46u8, 0x44, 0x53, 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        fds_image.extend(vec!(0u8; 65500));
+        let mut runtime = RuntimeState::new();
+        runtime.nes = NesState::new(mapper_from_file(&fds_image).ok().unwrap());
+        let mut memory_window = MemoryWindow::new();
+        memory_window.select_byte(0x8000);
+        let responses = memory_window.handle_event(&runtime, Event::TextInput("ff".to_string()));
+        assert!(matches!(responses[..], [Event::MemoryViewerWriteByte(0x8000, 0xFF)]));
+        // The BIOS is still ROM
+        memory_window.select_byte(0xE000);
+        let responses = memory_window.handle_event(&runtime, Event::TextInput("ff".to_string()));
+        assert_eq!(responses.len(), 0);
+    }
+}
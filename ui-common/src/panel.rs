@@ -8,4 +8,6 @@ pub trait Panel {
     fn active_canvas(&self) -> &SimpleBuffer;
     fn scale_factor(&self) -> u32 {return 1;}
     fn shown(&self) -> bool;
+    // True while the panel wants typed keys for itself, so global shortcuts should stay quiet
+    fn has_keyboard_focus(&self) -> bool {return false;}
 }
\ No newline at end of file
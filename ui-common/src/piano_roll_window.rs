@@ -1,22 +1,46 @@
 use application::RuntimeState;
+use apu_window::default_edge_threshold;
+use apu_window::find_edge;
+use channel_stats::SessionStats;
+use channel_stats::frequency_to_midi;
+use channel_stats::note_name;
+use channel_stats::timbre_name;
 use drawing;
 use drawing::Color;
 use drawing::Font;
 use drawing::SimpleBuffer;
 use events::Event;
 use panel::Panel;
+use pitch_detection::PitchDetector;
+use settings::SettingsState;
+#[cfg(test)]
+use settings::describe_setting;
+#[cfg(test)]
+use settings::known_settings;
+#[cfg(test)]
+use settings::SettingType;
 
 use regex::Regex;
+use toml;
+use toml::Value;
+use toml::map::Map;
 
 use rustico_core::apu::ApuState;
 use rustico_core::apu::AudioChannelState;
 use rustico_core::apu::PlaybackRate;
 use rustico_core::apu::RingBuffer;
 use rustico_core::apu::Timbre;
+use rustico_core::apu::Volume;
+use rustico_core::logging;
 use rustico_core::mmc::mapper::Mapper;
 
+use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::collections::hash_map::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::sync::Once;
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum NoteType {
@@ -31,7 +55,9 @@ pub enum ScrollDirection {
     LeftToRight,
     TopToBottom,
     BottomToTop,
-    PlayerPiano
+    PlayerPiano,
+    // The keyboard across the middle, with the roll scrolling away from it both up and down
+    MirroredVertical
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -41,21 +67,298 @@ pub enum KeySize {
     Large
 }
 
+impl KeySize {
+    // (key_thickness, key_length)
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            KeySize::Small => (8, 32),
+            KeySize::Medium => (16, 64),
+            KeySize::Large => (24, 96),
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum PollingType {
     PpuFrame,
     PpuScanline,
     ApuQuarterFrame,
     ApuHalfFrame,
+    // Every N audio samples, which locks scrolling to the audio rate for captures
+    AudioSamples(u32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VelocityStyle {
+    Alpha,
+    Thickness,
+    Saturation,
+    Brightness,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NoiseStyle {
+    Strings,
+    Band,
+}
+
+// How each surfboard cell picks the window of samples it shows
+#[derive(Clone, Copy, PartialEq)]
+pub enum TriggerMode {
+    // Just the most recent samples; steadier for noise and samples, which have no real period
+    None,
+    // Centered on the latest rising edge, so tonal channels hold still
+    RisingEdge,
+}
+
+impl TriggerMode {
+    pub fn from_setting(value: &str) -> Option<TriggerMode> {
+        return match value {
+            "none" => Some(TriggerMode::None),
+            "rising_edge" => Some(TriggerMode::RisingEdge),
+            _ => None
+        };
+    }
+}
+
+// How the surfboard scope joins up the samples it shows. Purely visual, the audio isn't touched.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScopeInterpolation {
+    // Hold each value until the next one, which shows DMC's staircase as-is
+    Step,
+    // Straight lines between the middles of each held value
+    Linear,
+    // Catmull-Rom curves through the same points
+    Cubic,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScaleKind {
+    Major,
+    Minor,
+    Pentatonic,
+    Chromatic,
+}
+
+impl ScaleKind {
+    // Semitones above the root; minor is natural minor, pentatonic is major pentatonic
+    pub fn intervals(&self) -> &'static [u32] {
+        match self {
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::Pentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+}
+
+// Channels from the second source in an A/B comparison are reported under this prefix, ie
+// "B:2A03", so they get their own slices and colors while sharing the first source's settings.
+pub const COMPARISON_PREFIX: &str = "B:";
+
+// Far more voices than any real expansion chip has. A mapper reporting more than this is
+// confused, and anything past the limit is ignored rather than drawn every single poll.
+pub const MAX_MAPPER_CHANNELS: usize = 64;
+static MAPPER_CHANNEL_WARNING: Once = Once::new();
+
+fn capped_mapper_channels<'a>(mapper: &'a dyn Mapper) -> Vec<&'a dyn AudioChannelState> {
+    let mut channels = mapper.channels();
+    if channels.len() > MAX_MAPPER_CHANNELS {
+        let reported = channels.len();
+        MAPPER_CHANNEL_WARNING.call_once(|| {
+            logging::warn(&format!("Mapper reported {} audio channels, only the first {} will be shown.", reported, MAX_MAPPER_CHANNELS));
+        });
+        channels.truncate(MAX_MAPPER_CHANNELS);
+    }
+    return channels;
+}
+
+// Wraps a channel from the comparison source. It's read only: the roll never mutes or records
+// through it, that's up to whoever runs the second emulator.
+pub struct ComparisonChannel<'a> {
+    pub channel: &'a dyn AudioChannelState,
+}
+
+impl<'a> AudioChannelState for ComparisonChannel<'a> {
+    fn name(&self) -> String {return self.channel.name();}
+    fn chip(&self) -> String {return format!("{}{}", COMPARISON_PREFIX, self.channel.chip());}
+    fn sample_buffer(&self) -> &RingBuffer {return self.channel.sample_buffer();}
+    fn edge_buffer(&self) -> &RingBuffer {return self.channel.edge_buffer();}
+    fn min_sample(&self) -> i16 {return self.channel.min_sample();}
+    fn max_sample(&self) -> i16 {return self.channel.max_sample();}
+    fn record_current_output(&mut self) {}
+    fn muted(&self) -> bool {return self.channel.muted();}
+    fn mute(&mut self) {}
+    fn unmute(&mut self) {}
+    fn playing(&self) -> bool {return self.channel.playing();}
+    fn rate(&self) -> PlaybackRate {return self.channel.rate();}
+    fn volume(&self) -> Option<Volume> {return self.channel.volume();}
+    fn timbre(&self) -> Option<Timbre> {return self.channel.timbre();}
+    fn estimated_frequency(&self) -> Option<f32> {return self.channel.estimated_frequency();}
+    fn debug_registers(&self) -> Vec<(String, u32)> {return self.channel.debug_registers();}
+    fn amplitude(&self) -> f32 {return self.channel.amplitude();}
+}
+
+// The chip name to look settings up under, and whether this is a comparison channel
+fn settings_chip_name(chip: &str) -> (String, bool) {
+    if chip.starts_with(COMPARISON_PREFIX) {
+        return (chip[COMPARISON_PREFIX.len() ..].to_string(), true);
+    }
+    return (chip.to_string(), false);
+}
+
+// How note edges are antialiased. Fast blends the single pixel each edge lands in; High
+// spreads each edge over a couple of pixels by coverage, which holds up better in small exports.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AaQuality {
+    Fast,
+    High,
+}
+
+// How far High quality smears each edge, in pixels
+const HIGH_QUALITY_EDGE_WIDTH: f32 = 2.0;
+
+// How much of the pixel centered at `center` a span from low to high covers, with each edge
+// ramped over edge_width pixels. An edge_width of 1 matches the plain per-pixel blend.
+pub fn span_coverage(center: f32, low: f32, high: f32, edge_width: f32) -> f32 {
+    let from_low = ((center - low) / edge_width + 0.5).max(0.0).min(1.0);
+    let from_high = ((high - center) / edge_width + 0.5).max(0.0).min(1.0);
+    return from_low * from_high;
+}
+
+// The pixels (and their colors) along a note's cross section for High quality edges. Anything
+// only partly covered is an edge, and takes the outline color if there is one; so do the
+// outermost fully covered pixels, same as the fast path.
+fn smooth_span(slice: &ChannelSlice, low: f32, high: f32, outline: Option<Color>, limit: u32) -> Vec<(u32, Color)> {
+    let mut pixels = Vec::new();
+    if limit == 0 {
+        return pixels;
+    }
+    let edge_color = match outline {
+        Some(outline) if high - low >= 4.0 => Some(PianoRollWindow::outline_color_for(slice, outline)),
+        _ => None
+    };
+    let first = (low - HIGH_QUALITY_EDGE_WIDTH / 2.0).floor().max(0.0) as u32;
+    let last = ((high + HIGH_QUALITY_EDGE_WIDTH / 2.0).ceil() as u32).min(limit - 1);
+    let mut solid: Vec<u32> = Vec::new();
+    for p in first ..= last {
+        let coverage = span_coverage(p as f32 + 0.5, low, high, HIGH_QUALITY_EDGE_WIDTH);
+        if coverage <= 0.0 {
+            continue;
+        }
+        if coverage >= 1.0 {
+            solid.push(p);
+            pixels.push((p, slice.color));
+        } else {
+            let mut color = edge_color.unwrap_or(slice.color);
+            color.set_alpha((coverage * color.alpha() as f32) as u8);
+            pixels.push((p, color));
+        }
+    }
+    match (edge_color, solid.first(), solid.last()) {
+        (Some(edge_color), Some(&first_solid), Some(&last_solid)) => {
+            for pixel in pixels.iter_mut() {
+                if pixel.0 == first_solid || pixel.0 == last_solid {
+                    pixel.1 = edge_color;
+                }
+            }
+        },
+        _ => {}
+    }
+    return pixels;
+}
+
+// Whether a MIDI note belongs to the given scale
+pub fn note_in_scale(midi_index: u32, root: u32, kind: ScaleKind) -> bool {
+    let degree = (midi_index + 12 - (root % 12)) % 12;
+    return kind.intervals().contains(&degree);
+}
+
+// A color for channels we have no settings for, picked from a hash of the names so each
+// one gets its own hue. FNV-1a rather than std's hasher, which is free to change between
+// releases. Since it only depends on the names it's the same every frame, no cache needed.
+pub fn unknown_channel_color(chip_name: &str, channel_name: &str) -> Color {
+    let mut hash: u32 = 0x811C9DC5;
+    for byte in chip_name.bytes().chain(Some(0u8)).chain(channel_name.bytes()) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    // Full-ish saturation and brightness, so it stays readable against the dark background
+    let hue = (hash % 360) as f32 / 60.0;
+    let saturation = 0.55;
+    let value = 0.9;
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - ((hue % 2.0) - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x)
+    };
+    let m = value - chroma;
+    return Color::rgb(((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8);
+}
+
+// Resamples a run of scope samples according to the interpolation mode. For Linear and Cubic,
+// each run of identical values becomes one point at its middle, and the curve is drawn between
+// those; before the first and after the last point, the value is held.
+pub fn interpolate_scope(samples: &[i16], mode: ScopeInterpolation) -> Vec<f32> {
+    if mode == ScopeInterpolation::Step || samples.len() < 2 {
+        return samples.iter().map(|sample| *sample as f32).collect();
+    }
+    let mut points: Vec<(f32, f32)> = Vec::new();
+    let mut run_start = 0;
+    for i in 1 ..= samples.len() {
+        if i == samples.len() || samples[i] != samples[run_start] {
+            points.push(((run_start + i - 1) as f32 / 2.0, samples[run_start] as f32));
+            run_start = i;
+        }
+    }
+
+    let mut output = Vec::with_capacity(samples.len());
+    let mut segment = 0;
+    for i in 0 .. samples.len() {
+        let position = i as f32;
+        while segment + 1 < points.len() && points[segment + 1].0 <= position {
+            segment += 1;
+        }
+        if position <= points[0].0 || segment + 1 >= points.len() {
+            output.push(points[segment].1);
+            continue;
+        }
+        let (x1, y1) = points[segment];
+        let (x2, y2) = points[segment + 1];
+        let t = (position - x1) / (x2 - x1);
+        match mode {
+            ScopeInterpolation::Cubic => {
+                let y0 = points[segment.saturating_sub(1)].1;
+                let y3 = points[(segment + 2).min(points.len() - 1)].1;
+                let t2 = t * t;
+                let t3 = t2 * t;
+                output.push(0.5 * (
+                    (2.0 * y1) +
+                    (-y0 + y2) * t +
+                    (2.0 * y0 - 5.0 * y1 + 4.0 * y2 - y3) * t2 +
+                    (-y0 + 3.0 * y1 - 3.0 * y2 + y3) * t3));
+            },
+            _ => {output.push(y1 + (y2 - y1) * t);}
+        }
+    }
+    return output;
 }
 
+#[derive(Clone)]
 pub struct ChannelSlice {
     pub visible: bool,
     pub y: f32,
     pub thickness: f32,
     pub color: Color,
     pub note_type: NoteType,
-
+    // 0.0 - 1.0, used to fade the key spots
+    pub alpha: f32,
+    pub muted: bool,
 }
 
 impl ChannelSlice {
@@ -66,6 +369,8 @@ impl ChannelSlice {
             thickness: 0.0,
             color: Color::rgb(0,0,0),
             note_type: NoteType::Frequency,
+            alpha: 0.0,
+            muted: false,
         };
     }
 }
@@ -99,14 +404,37 @@ fn draw_black_key_horiz(canvas: &mut SimpleBuffer, x: u32, y: u32, color: Color)
     drawing::blend_rect(canvas, x + 1, y + 1, 7, 1, color);
 }
 
-fn draw_speaker_key_horiz(canvas: &mut SimpleBuffer, color: Color, x: u32, y: u32) {
-    drawing::blend_rect(canvas, x +  2, y + 6 - 8, 3, 5, color);
-    drawing::blend_rect(canvas, x +  5, y + 5 - 8, 1, 7, color);
-    drawing::blend_rect(canvas, x +  6, y + 4 - 8, 1, 9, color);
-    drawing::blend_rect(canvas, x +  7, y + 3 - 8, 1, 11, color);
-    drawing::blend_rect(canvas, x +  8, y + 2 - 8, 1, 13, color);
-    drawing::blend_rect(canvas, x + 10, y + 6 - 8, 1, 5, color);
-    drawing::blend_rect(canvas, x + 12, y + 4 - 8, 1, 9, color);
+// The speaker icon, as (x, y, width, height) rects on a 16x16 grid. The cone is centered
+// vertically on row 8.
+const SPEAKER_GLYPH: [(u32, u32, u32, u32); 7] = [
+    ( 2, 6, 3,  5),
+    ( 5, 5, 1,  7),
+    ( 6, 4, 1,  9),
+    ( 7, 3, 1, 11),
+    ( 8, 2, 1, 13),
+    (10, 6, 1,  5),
+    (12, 4, 1,  9),
+];
+
+// Draws the speaker icon scaled to fill a size x size box. Edges are rounded to whole pixels,
+// but every rect stays at least one pixel wide so the icon doesn't fall apart when small.
+fn draw_speaker_glyph(canvas: &mut SimpleBuffer, color: Color, x: u32, y: u32, size: u32) {
+    for &(gx, gy, gw, gh) in SPEAKER_GLYPH.iter() {
+        let left = x + (gx * size) / 16;
+        let top = y + (gy * size) / 16;
+        let width = (((gx + gw) * size) / 16 - (gx * size) / 16).max(1);
+        let height = (((gy + gh) * size) / 16 - (gy * size) / 16).max(1);
+        drawing::blend_rect(canvas, left, top, width, height, color);
+    }
+}
+
+fn draw_speaker_key_horiz(canvas: &mut SimpleBuffer, color: Color, x: u32, y: u32, key_thickness: u32) {
+    // The horizontal keyboard is always 16 pixels deep, so only the key thickness can shrink the icon
+    let size = key_thickness.min(16);
+    if y < size / 2 {
+        return;
+    }
+    draw_speaker_glyph(canvas, color, x + (16 - size) / 2, y - size / 2, size);
 }
 
 // various utility functions for key drawing. The 1px offsets generally account for the
@@ -131,6 +459,33 @@ fn lower_key_lpos(l: u32, base_key_length: u32) -> u32 {
     return l + 1 + upper_key_length(base_key_length);
 }
 
+fn mix_colors(a: Color, b: Color) -> Color {
+    return Color::rgb(
+        ((a.r() as u16 + b.r() as u16) / 2) as u8,
+        ((a.g() as u16 + b.g() as u16) / 2) as u8,
+        ((a.b() as u16 + b.b() as u16) / 2) as u8
+    );
+}
+
+// Weighted version of the above: weight 0.0 is all a, 1.0 is all b
+fn lerp_colors(a: Color, b: Color, weight: f32) -> Color {
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * weight).round() as u8;
+    return Color::rgba(lerp(a.r(), b.r()), lerp(a.g(), b.g()), lerp(a.b(), b.b()), lerp(a.alpha(), b.alpha()));
+}
+
+// Blends a black key's outermost pixels halfway into the white key around it
+fn antialias_black_key_vert(canvas: &mut SimpleBuffer, x: u32, y: u32, white_key: Color, key_thickness: u32, base_key_length: u32) {
+    let mut edge_color = white_key;
+    edge_color.set_alpha(128);
+    let left = x - (key_thickness / 2);
+    let right = left + key_thickness;
+    let top = upper_key_lpos(y);
+    let length = upper_key_length(base_key_length);
+    drawing::blend_rect(canvas, left, top, 1, length, edge_color);
+    drawing::blend_rect(canvas, right, top, 1, length, edge_color);
+    drawing::blend_rect(canvas, left + 1, top + length - 1, key_thickness - 1, 1, edge_color);
+}
+
 fn draw_left_white_key_vert(canvas: &mut SimpleBuffer, x: u32, y: u32, color: Color, key_thickness: u32, base_key_length: u32) {
     drawing::blend_rect(
         canvas, 
@@ -204,6 +559,39 @@ fn draw_speaker_key_vert(canvas: &mut SimpleBuffer, color: Color, x: u32, y: u32
         color);
 }
 
+// Splits a length into count pieces, handing out any leftover pixels to the
+// first few pieces. Returns the (offset, length) of the requested piece.
+fn split_evenly(total: u32, count: u32, index: u32) -> (u32, u32) {
+    let base_length = total / count;
+    let leftover_pixels = total - (base_length * count);
+    let offset = index * base_length + index.min(leftover_pixels);
+    let length = base_length + if index < leftover_pixels {1} else {0};
+    return (offset, length);
+}
+
+// Lays out the surfboard as a grid with the given number of rows, filling each
+// row left to right before moving to the next. Returns (x, y, width, height).
+pub fn surfboard_cell(index: usize, channel_count: usize, rows: u32, x: u32, y: u32, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let rows = rows.max(1).min(channel_count.max(1) as u32);
+    let columns = ((channel_count as u32) + rows - 1) / rows;
+    let row = (index as u32) / columns;
+    let column = (index as u32) % columns;
+    let (cx, cell_width) = split_evenly(width, columns.max(1), column);
+    let (cy, cell_height) = split_evenly(height, rows, row);
+    return (x + cx, y + cy, cell_width, cell_height);
+}
+
+// The inverse of surfboard_cell: which channel's cell, if any, contains this point
+pub fn surfboard_cell_at(mx: u32, my: u32, channel_count: usize, rows: u32, x: u32, y: u32, width: u32, height: u32) -> Option<usize> {
+    for i in 0 .. channel_count {
+        let (cx, cy, cell_width, cell_height) = surfboard_cell(i, channel_count, rows, x, y, width, height);
+        if mx >= cx && mx < cx + cell_width && my >= cy && my < cy + cell_height {
+            return Some(i);
+        }
+    }
+    return None;
+}
+
 fn midi_frequency(midi_index: u32) -> f32 {
     return 440.0 * (2.0_f32).powf(((midi_index as f32) - 69.0) / 12.0);
 }
@@ -280,7 +668,9 @@ pub fn default_channel_settings() -> HashMap<String, HashMap<String, ChannelSett
     });
     apu_settings.insert("DMC".to_string(), ChannelSettings{
         hidden: false,
-        colors: vec!(Color::rgb(96,  32, 192))
+        colors: vec!(
+            Color::rgb(96,  32, 192),   // quiet
+            Color::rgb(192, 128, 255))  // loud
     });
 
     let mut vrc6_settings: HashMap<String, ChannelSettings> = HashMap::new();
@@ -434,27 +824,176 @@ pub struct PianoRollWindow {
     // user-configurable options
     pub key_thickness: u32,
     pub key_length: u32,
+    // A preset for the two above; None picks them based on the canvas size instead
+    pub key_size: Option<KeySize>,
+    // Set once the user picks a value, so resizing the canvas doesn't replace it
+    pub key_thickness_override: Option<u32>,
+    pub key_length_override: Option<u32>,
+    // key_thickness, but fractional, so the keyboard can zoom smoothly. Notes, strings and
+    // the reference line are positioned with this, while the keys themselves still snap to
+    // whole pixels.
+    pub key_thickness_f: f32,
+    pub key_thickness_f_override: Option<f32>,
     pub surfboard_height: u32,
+    pub surfboard_rows: u32,
     pub scroll_direction: ScrollDirection,
     pub polling_type: PollingType,
     pub speed_multiplier: u32,
+    pub smooth_scroll: bool,
+    // pixels per poll when smooth_scroll is on, may be fractional
+    pub scroll_speed: f32,
+    // the portion of a pixel the roll has advanced beyond the newest slice
+    pub scroll_remainder: f32,
+    // When set, speed_multiplier and scroll_speed are derived from this and the poll rate
+    pub scroll_speed_pps: Option<f32>,
     pub surfboard_line_thickness: f32,
     pub surfboard_glow_thickness: f32,
     pub draw_piano_strings: bool,
+    // When off, each frame draws over the last one instead of starting from the background.
+    // The canvas is still cleared whenever the layout changes or the history shrinks, so
+    // nothing is left stranded past the trailing edge of the roll.
+    pub clear_each_frame: bool,
+    last_drawn_layout: Option<(u32, u32, ScrollDirection, usize)>,
     pub background_color: Color,
     pub outline_color: Color,
     pub outline_thickness: u32,
     pub draw_text_labels: bool,
+    pub surfboard_labels: bool,
+    // Marks muted channels with a crossed out dot, on their key spot and surfboard cell
+    pub show_mute_icons: bool,
+    // Labels every octave boundary with its frequency, on the far side of the roll from the keys
+    pub show_hz_axis: bool,
+    // The last value of every piano_roll setting, for export_config
+    applied_settings: BTreeMap<String, Value>,
+    pub dmc_as_pitch: bool,
+    pub use_detected_pitch: bool,
     pub divider_color: Color,
+    // How muted channels are drawn: muted_color if there is one, otherwise the channel's own
+    // colors with desaturate_muted, otherwise a dark grey
+    pub muted_color: Option<Color>,
+    pub desaturate_muted: bool,
+    // A 1px border drawn just inside each note, to tell apart overlapping notes of similar color
+    pub note_outline: Option<Color>,
+    pub aa_quality: AaQuality,
     pub divider_width: u32,
+    pub white_key_color: Color,
+    pub black_key_color: Color,
+    pub white_key_border_color: Color,
+    pub top_edge_color: Color,
+    pub velocity_style: VelocityStyle,
+    pub noise_render_style: NoiseStyle,
+    // Beat and bar lines; a tempo of 0 turns the grid off
+    pub tempo_bpm: f32,
+    pub grid_subdivision: u32,
+    pub quantize_to_grid: bool,
+    // Number of slices over which notes fade in and out, 0 for hard edges
+    pub note_end_fade: u32,
+    // Draw test notes instead of what the emulator is playing
+    pub synthetic_input: bool,
+    // Number keys 1-9 toggle mute on that channel, in collect_channels order. The change is
+    // announced in the corner of the roll for a moment (mute_feedback is the text and the
+    // frames it has left).
+    pub digit_key_muting: bool,
+    pub mute_feedback: Option<(String, u32)>,
+    // Horizontal modes: stretch each column towards its neighbors so slow pitch bends don't
+    // stair-step. Pitch jumps of at least pitch_smoothing_threshold keys are left alone.
+    pub smooth_pitch: bool,
+    pub pitch_smoothing_threshold: f32,
+    // Draws a line across the roll at this pitch, for tuning against by ear
+    pub reference_frequency: Option<f32>,
+    // Integer factor export_canvas scales the roll down by, 1 for full size
+    pub export_scale: u32,
+    // Dither when export_canvas scales the roll down, so gradients don't band
+    pub export_dither: bool,
+    // Per channel surfboard triggering, keyed on (chip, channel). Anything not listed here
+    // uses surfboard_trigger_default.
+    pub surfboard_trigger: HashMap<(String, String), TriggerMode>,
+    pub surfboard_trigger_default: TriggerMode,
+    // Channels, as (chip, channel), that are left off the surfboard. They still appear on the
+    // roll; the remaining surfboard cells widen to fill the space.
+    pub surfboard_excluded: HashSet<(String, String)>,
+    pub scope_interpolation: ScopeInterpolation,
+    // Soften the edges where black keys meet white ones
+    pub antialias_keyboard: bool,
+    // For PollingType::AudioSamples: the sample count we last polled at (None until the
+    // first count arrives), and the rate those samples are produced at
+    pub audio_samples_per_poll: u32,
+    pub last_polled_sample: Option<u64>,
+    pub audio_sample_rate: f64,
+    // Quarter frames per second, which depends on the APU's frame counter mode
+    pub apu_quarter_frame_rate: f64,
+    // Phosphor style trails on the surfboard: each frame keeps the brighter of the new trace and
+    // the last frame dimmed by this much. 0.0 clears every frame like before.
+    pub surfboard_persistence: f32,
+    // The last surfboard we drew, and where it was, so it can be faded into the next one
+    surfboard_trails: Option<(u32, u32, SimpleBuffer)>,
+    // Tints the strings of notes in this scale, as (root pitch class with 0 = C, kind)
+    pub highlight_scale: Option<(u32, ScaleKind)>,
+    scale_root: u32,
 
     // Keyed on: chip name, then channel name within that chip
     pub channel_settings: HashMap<String, HashMap<String, ChannelSettings>>,
+
+    pub pitch_detector: PitchDetector,
+    // Detected pitch for each channel, refreshed once per update. Keyed on "chip/channel"
+    pub detected_pitches: HashMap<String, Option<f32>>,
+
+    // Every slice ever pushed, so the grid stays put relative to the notes as they scroll
+    pub total_slices: u64,
+    // Per channel: a note start is being held back until this slice, to land it on the grid
+    pub grid_holds: Vec<Option<u64>>,
+
+    // Per-channel activity since the cartridge was loaded, see export_stats
+    pub stats: SessionStats,
+
+    // Keyed on the index of the displayed channel
+    pub test_notes: HashMap<usize, TestNote>,
+
+    // What each channel was doing, alongside time_slices (same order, same length) for
+    // export_csv. Slices pushed at the same poll share one entry.
+    pub note_history: VecDeque<Arc<Vec<NoteRecord>>>,
+}
+
+pub struct TestNote {
+    pub midi_index: u8,
+    pub velocity: f32,
+    pub remaining_polls: Option<u32>,
+}
+
+pub struct NoteRecord {
+    pub chip: String,
+    pub name: String,
+    pub rate: PlaybackRate,
+    pub amplitude: f32,
+    pub timbre: Option<Timbre>,
+}
+
+impl NoteRecord {
+    pub fn from_channel(channel: &dyn AudioChannelState) -> NoteRecord {
+        return NoteRecord {
+            chip: channel.chip(),
+            name: channel.name(),
+            rate: channel.rate(),
+            amplitude: channel.amplitude(),
+            timbre: channel.timbre(),
+        };
+    }
+}
+
+// Key geometry that looks right for a given canvas size, as (key_thickness, key_length). This
+// is scaled from what looks good on a 1280x720 canvas, using whichever axis is more cramped.
+pub fn default_key_geometry(canvas_width: u32, canvas_height: u32) -> (u32, u32) {
+    let scale = (canvas_width as f32 / 1280.0).min(canvas_height as f32 / 720.0);
+    // The key drawing functions misbehave on very thin keys, so don't go below this
+    let key_thickness = ((11.0 * scale).round() as u32).max(3);
+    let key_length = ((32.0 * scale).round() as u32).max(8);
+    return (key_thickness, key_length);
 }
 
 impl PianoRollWindow {
     pub fn new() -> PianoRollWindow {
         let font = Font::from_raw(include_bytes!("assets/8x8_font.png"), 8);
+        let (key_thickness, key_length) = default_key_geometry(1920, 1080);
 
         return PianoRollWindow {
             canvas: SimpleBuffer::new(1920, 1080), // actually 1080p
@@ -462,9 +1001,15 @@ impl PianoRollWindow {
             shown: false,
             scale: 1,
             keys: 109,
-            key_thickness: 16,
-            key_length: 64,
+            key_thickness: key_thickness,
+            key_length: key_length,
+            key_size: None,
+            key_thickness_override: None,
+            key_length_override: None,
+            key_thickness_f: key_thickness as f32,
+            key_thickness_f_override: None,
             surfboard_height: 128,
+            surfboard_rows: 1,
             lowest_frequency: midi_frequency(midi_index("C0").unwrap()), // ~C0
             lowest_index: midi_index("C0").unwrap(),
             highest_frequency: midi_frequency(midi_index("Cs9").unwrap()), // ~C#8
@@ -474,35 +1019,103 @@ impl PianoRollWindow {
             scroll_direction: ScrollDirection::TopToBottom,
             polling_type: PollingType::ApuQuarterFrame,
             speed_multiplier: 6,
+            smooth_scroll: false,
+            scroll_speed: 6.0,
+            scroll_speed_pps: None,
+            scroll_remainder: 0.0,
             channel_settings: default_channel_settings(),
             surfboard_line_thickness: 0.5,
             surfboard_glow_thickness: 2.5,
             draw_piano_strings: true,
+            clear_each_frame: true,
+            last_drawn_layout: None,
             background_color: Color::rgba(0, 0, 0, 255),
             outline_color: Color::rgba(0, 0, 0, 255),
             outline_thickness: 2,
             draw_text_labels: true,
+            surfboard_labels: false,
+            show_mute_icons: false,
+            show_hz_axis: false,
+            applied_settings: BTreeMap::new(),
+            dmc_as_pitch: false,
+            use_detected_pitch: false,
             divider_color: Color::rgba(0, 0, 0, 255),
+            muted_color: None,
+            desaturate_muted: false,
+            note_outline: None,
+            aa_quality: AaQuality::Fast,
             divider_width: 5,
+            white_key_color: Color::rgb(0x20, 0x20, 0x20),
+            black_key_color: Color::rgb(0x00, 0x00, 0x00),
+            white_key_border_color: Color::rgb(0x18, 0x18, 0x18),
+            top_edge_color: Color::rgb(0x04, 0x04, 0x04),
+            velocity_style: VelocityStyle::Thickness,
+            noise_render_style: NoiseStyle::Strings,
+            tempo_bpm: 0.0,
+            grid_subdivision: 4,
+            quantize_to_grid: false,
+            note_end_fade: 0,
+            synthetic_input: false,
+            digit_key_muting: true,
+            mute_feedback: None,
+            smooth_pitch: false,
+            pitch_smoothing_threshold: 0.5,
+            reference_frequency: None,
+            export_scale: 1,
+            export_dither: false,
+            surfboard_trigger: HashMap::new(),
+            surfboard_trigger_default: TriggerMode::RisingEdge,
+            surfboard_excluded: HashSet::new(),
+            scope_interpolation: ScopeInterpolation::Step,
+            antialias_keyboard: false,
+            audio_samples_per_poll: 735,
+            last_polled_sample: None,
+            audio_sample_rate: 44100.0,
+            apu_quarter_frame_rate: 1789773.0 * 4.0 / 29830.0,
+            surfboard_persistence: 0.0,
+            surfboard_trails: None,
+            highlight_scale: None,
+            scale_root: 0,
+            pitch_detector: PitchDetector::new(2048),
+            detected_pitches: HashMap::new(),
+            total_slices: 0,
+            grid_holds: Vec::new(),
+            stats: SessionStats::new(),
+            test_notes: HashMap::new(),
+            note_history: VecDeque::new(),
         };
     }
 
-    fn collect_channels<'a>(&self, apu: &'a ApuState, mapper: &'a dyn Mapper) -> Vec<&'a dyn AudioChannelState> {
-        let mut channels: Vec<& dyn AudioChannelState> = Vec::new();
-        channels.extend(apu.channels());
-        channels.extend(mapper.channels());
-        channels.push(apu);
-
+    fn filter_hidden<'a>(&self, channels: Vec<&'a dyn AudioChannelState>) -> Vec<&'a dyn AudioChannelState> {
         let mut displayed_channels: Vec<& dyn AudioChannelState> = Vec::new();
         for channel in channels {
             if !self.channel_is_hidden(channel) {
                 displayed_channels.push(channel);
             }
         }
-
         return displayed_channels;
     }
 
+    // The channels plotted on the roll: every voice from the 2A03 and the expansion chip.
+    fn collect_channels<'a>(&self, apu: &'a ApuState, mapper: &'a dyn Mapper) -> Vec<&'a dyn AudioChannelState> {
+        let mut channels: Vec<& dyn AudioChannelState> = Vec::new();
+        channels.extend(apu.channels());
+        channels.extend(capped_mapper_channels(mapper));
+        return self.filter_hidden(channels);
+    }
+
+    // The channels shown on the surfboard: the same voices, plus the final mix at the end.
+    // The mix is only a waveform with no pitch to plot, so it never appears on the roll itself,
+    // but its scope is a useful reference next to the individual voices.
+    fn collect_surfboard_channels<'a>(&self, apu: &'a ApuState, mapper: &'a dyn Mapper) -> Vec<&'a dyn AudioChannelState> {
+        let mut channels: Vec<& dyn AudioChannelState> = Vec::new();
+        channels.extend(apu.channels());
+        channels.extend(capped_mapper_channels(mapper));
+        channels.push(apu);
+        let channels = self.filter_hidden(channels);
+        return channels.into_iter().filter(|channel| !self.surfboard_excluded.contains(&(channel.chip(), channel.name()))).collect();
+    }
+
     fn roll_width(&self) -> u32 {
         return self.canvas.height - self.key_length - self.surfboard_height;
     }
@@ -526,13 +1139,14 @@ impl PianoRollWindow {
             white_string, //B
         ];
 
+        // Strings line up with the centers of notes, see draw_slice_horiz
         let mut key_counter = 0;
-        let mut y = starting_y;
-        let safety_margin = 0 + self.key_thickness * 2;
+        let mut y = starting_y as f32 + 0.5;
+        let safety_margin = self.key_thickness_f * 2.0;
         while key_counter < self.keys && y > safety_margin {
-            let string_color = string_colors[(key_counter % 12) as usize];
-            drawing::rect(&mut self.canvas, x, y, width, 1, string_color);
-            y -= self.key_thickness;
+            let string_color = self.scale_string_color(string_colors[(key_counter % 12) as usize], key_counter);
+            drawing::rect(&mut self.canvas, x, y as u32, width, 1, string_color);
+            y -= self.key_thickness_f;
             key_counter += 1;
         }
     }
@@ -557,16 +1171,30 @@ impl PianoRollWindow {
         ];
 
         let mut key_counter = 0;
-        let mut x = starting_x;
-        let safety_margin = self.canvas.width - self.key_thickness * 2;
+        let mut x = starting_x as f32 + 0.5;
+        let safety_margin = self.canvas.width as f32 - self.key_thickness_f * 2.0;
         while key_counter < self.keys && x < safety_margin {
-            let string_color = string_colors[(key_counter % 12) as usize];
-            drawing::rect(&mut self.canvas, x, y, 1, height, string_color);
-            x += self.key_thickness; // TODO: it's not "height" anymore, more like key_size?
+            let string_color = self.scale_string_color(string_colors[(key_counter % 12) as usize], key_counter);
+            drawing::rect(&mut self.canvas, x as u32, y, 1, height, string_color);
+            x += self.key_thickness_f;
             key_counter += 1;
         }
     }
 
+    // Strings for notes in highlight_scale get a warm tint; everything else is left as-is, so
+    // out of scale notes stand out against the dark strings
+    fn scale_string_color(&self, string_color: Color, key_counter: u32) -> Color {
+        match self.highlight_scale {
+            Some((root, kind)) if note_in_scale(self.lowest_index + key_counter, root, kind) => {
+                return Color::rgb(
+                    string_color.r().saturating_add(0x14),
+                    string_color.g().saturating_add(0x10),
+                    string_color.b().saturating_add(0x04));
+            },
+            _ => {return string_color;}
+        }
+    }
+
     fn draw_waveform_string_horiz(&mut self, x: u32, y: u32, width: u32) {
         let waveform_string = Color::rgb(0x06, 0x06, 0x06);
         // Draw one extra string for the waveform display
@@ -582,10 +1210,10 @@ impl PianoRollWindow {
     // TOTO: this is hard-coded and isn't especially flexible. Shouldn't we use the key spot routines
     // instead of this?
     fn draw_piano_keys_horiz(&mut self, x: u32, base_y: u32) {
-        let white_key_border = Color::rgb(0x1C, 0x1C, 0x1C);
-        let white_key = Color::rgb(0x20, 0x20, 0x20);
-        let black_key = Color::rgb(0x00, 0x00, 0x00);
-        let top_edge = Color::rgb(0x0A, 0x0A, 0x0A);
+        let white_key_border = self.white_key_border_color;
+        let white_key = self.white_key_color;
+        let black_key = self.black_key_color;
+        let top_edge = self.top_edge_color;
 
         let upper_key_pixels = [
           white_key, // C
@@ -624,9 +1252,21 @@ impl PianoRollWindow {
 
         let canvas_height = self.canvas.height;
         drawing::rect(&mut self.canvas, x, 0, 16, canvas_height, top_edge);
-        for y in 0 .. self.keys * self.key_thickness - 1 {
-            let pixel_index = y % upper_key_pixels.len() as u32;
-            drawing::rect(&mut self.canvas, x+0, base_y - y, 8, 1, upper_key_pixels[pixel_index as usize]);
+        let pattern_length = upper_key_pixels.len();
+        for y in 0 .. self.visible_keys() * self.key_thickness - 1 {
+            let pixel_index = y % pattern_length as u32;
+            let mut upper_color = upper_key_pixels[pixel_index as usize];
+            if self.antialias_keyboard && upper_color.data == black_key.data {
+                // Black rows next to a white one get mixed halfway, to soften that edge
+                let above = upper_key_pixels[(pixel_index as usize + 1) % pattern_length];
+                let below = upper_key_pixels[(pixel_index as usize + pattern_length - 1) % pattern_length];
+                if above.data != black_key.data {
+                    upper_color = mix_colors(black_key, above);
+                } else if below.data != black_key.data {
+                    upper_color = mix_colors(black_key, below);
+                }
+            }
+            drawing::rect(&mut self.canvas, x+0, base_y - y, 8, 1, upper_color);
             drawing::rect(&mut self.canvas, x+8, base_y - y, 8, 1, lower_key_pixels[pixel_index as usize]);
         }
         drawing::rect(&mut self.canvas, x, 0, 1, canvas_height, top_edge);
@@ -635,10 +1275,10 @@ impl PianoRollWindow {
     // TOTO: this is hard-coded and isn't especially flexible. Shouldn't we use the key spot routines
     // instead of this?
     fn draw_piano_keys_vert(&mut self, base_x: u32, y: u32) {
-        let white_key_border = Color::rgb(0x18, 0x18, 0x18);
-        let white_key = Color::rgb(0x20, 0x20, 0x20);
-        let black_key = Color::rgb(0x00, 0x00, 0x00);
-        let top_edge = Color::rgb(0x04, 0x04, 0x04);
+        let white_key_border = self.white_key_border_color;
+        let white_key = self.white_key_color;
+        let black_key = self.black_key_color;
+        let top_edge = self.top_edge_color;
 
         let key_colors = [
           white_key, // C
@@ -672,13 +1312,22 @@ impl PianoRollWindow {
 
         let canvas_width = self.canvas.width;
         drawing::rect(&mut self.canvas, 0, y, canvas_width, self.key_length + 1, top_edge);
-        drawing::rect(&mut self.canvas, base_x, y, self.keys * self.key_thickness, self.key_length, white_key_border);
-        for key_index in 0 .. self.keys - 1 {
+        let keys = self.visible_keys();
+        drawing::rect(&mut self.canvas, base_x, y, keys * self.key_thickness, self.key_length, white_key_border);
+        for key_index in 0 .. keys - 1 {
             let x = base_x + key_index * self.key_thickness;
             key_drawing_functions[key_index as usize % 12](&mut self.canvas, x, y, key_colors[key_index as usize % 12], self.key_thickness, self.key_length);
         }
-        let topmost_x = base_x + (self.keys - 1) * self.key_thickness;
+        let topmost_x = base_x + (keys - 1) * self.key_thickness;
         draw_topmost_white_key_vert(&mut self.canvas, topmost_x, y, white_key, self.key_thickness, self.key_length);
+        if self.antialias_keyboard {
+            for key_index in 0 .. keys - 1 {
+                if key_colors[key_index as usize % 12].data == black_key.data {
+                    let x = base_x + key_index * self.key_thickness;
+                    antialias_black_key_vert(&mut self.canvas, x, y, white_key, self.key_thickness, self.key_length);
+                }
+            }
+        }
         drawing::rect(&mut self.canvas, 0, y, canvas_width, 1, top_edge);
     }
 
@@ -688,9 +1337,8 @@ impl PianoRollWindow {
         match slice.note_type {
             NoteType::Waveform => {
                 let mut base_color = slice.color;
-                let volume_percent = slice.thickness / 6.0;
-                base_color.set_alpha((volume_percent * 255.0) as u8);
-                draw_speaker_key_horiz(canvas, base_color, x, ((starting_y as f32) - slice.y * (key_height as f32)) as u32);
+                base_color.set_alpha((slice.alpha * 255.0) as u8);
+                draw_speaker_key_horiz(canvas, base_color, x, ((starting_y as f32) - slice.y * (key_height as f32)) as u32, key_height);
             },
             _ => {
                 let key_drawing_functions = [
@@ -714,8 +1362,7 @@ impl PianoRollWindow {
                 let base_key = note_key.floor();
                 let adjacent_key = note_key.ceil();
 
-                let base_volume_percent = slice.thickness / 6.0;
-                let adjusted_volume_percent = 0.05 + base_volume_percent * 0.95;
+                let adjusted_volume_percent = 0.05 + slice.alpha * 0.95;
                 let base_percent = (1.0 - (note_key % 1.0)) * adjusted_volume_percent;
                 let adjacent_percent = (note_key % 1.0) * adjusted_volume_percent;
 
@@ -740,8 +1387,7 @@ impl PianoRollWindow {
         match slice.note_type {
             NoteType::Waveform => {
                 let mut base_color = slice.color;
-                let volume_percent = slice.thickness / 6.0;
-                base_color.set_alpha((volume_percent * 255.0) as u8);
+                base_color.set_alpha((slice.alpha * 255.0) as u8);
                 //draw_speaker_key_horiz(canvas, base_color, ((starting_x as f32) - slice.y * (key_width as f32)) as u32, y);
             },
             _ => {
@@ -766,8 +1412,7 @@ impl PianoRollWindow {
                 let base_key = note_key.floor();
                 let adjacent_key = note_key.ceil();
 
-                let base_volume_percent = slice.thickness / 6.0;
-                let adjusted_volume_percent = 0.05 + base_volume_percent * 0.95;
+                let adjusted_volume_percent = 0.05 + slice.alpha * 0.95;
                 let base_percent = (1.0 - (note_key % 1.0)) * adjusted_volume_percent;
                 let adjacent_percent = (note_key % 1.0) * adjusted_volume_percent;
 
@@ -787,17 +1432,33 @@ impl PianoRollWindow {
     }
 
     fn frequency_to_coordinate(&self, note_frequency: f32) -> f32 {
-        let highest_log = self.highest_frequency.ln();
+        // Only the keys that made it onto the canvas, see visible_keys
+        let visible_keys = self.visible_keys();
+        let highest_log = midi_frequency(self.lowest_index + visible_keys).ln();
         let lowest_log = self.lowest_frequency.ln();
         let range = highest_log - lowest_log;
         let note_log = note_frequency.ln();
-        let piano_roll_height = (self.keys) as f32;
+        let piano_roll_height = visible_keys as f32;
         let coordinate = (note_log - lowest_log) * piano_roll_height / range;
         return coordinate;
     }
 
+    // The nearest note and how far off it the channel is, ie "A#4 +12¢". Only pitched
+    // channels have one; noise and samples get None.
+    pub fn channel_note_name(&self, channel: &dyn AudioChannelState) -> Option<String> {
+        match channel.rate() {
+            PlaybackRate::FundamentalFrequency{frequency} if frequency.is_finite() && frequency > 0.0 => {
+                let midi_note = frequency_to_midi(frequency);
+                let cents = ((midi_note - midi_note.round()) * 100.0).round() as i32;
+                return Some(format!("{} {:+}¢", note_name(frequency), cents));
+            },
+            _ => {return None;}
+        }
+    }
+
     pub fn channel_is_hidden(&self, channel: &dyn AudioChannelState) -> bool {
-        match self.channel_settings.get(&channel.chip()) {
+        let (chip_name, _) = settings_chip_name(&channel.chip());
+        match self.channel_settings.get(&chip_name) {
             Some(chip_settings) => {
                 match chip_settings.get(&channel.name()) {
                     Some(channel_settings) => {
@@ -820,25 +1481,50 @@ impl PianoRollWindow {
 
     pub fn channel_colors(&self, channel: &dyn AudioChannelState) -> Vec<Color> {
         if channel.muted() {
-            return vec!(Color::rgb(32, 32, 32));
+            match self.muted_color {
+                Some(color) => {return vec!(color);},
+                None if self.desaturate_muted => {
+                    return self.configured_channel_colors(channel).into_iter().map(|color| PianoRollWindow::desaturate_color(color, 0.0)).collect();
+                },
+                None => {return vec!(Color::rgb(32, 32, 32));}
+            }
+        }
+        return self.configured_channel_colors(channel);
+    }
+
+    // The user's colors for this channel, ignoring whether it is currently muted. Comparison
+    // channels use the complement of the same colors, so the two sources are easy to tell apart.
+    fn configured_channel_colors(&self, channel: &dyn AudioChannelState) -> Vec<Color> {
+        let colors = self.base_channel_colors(channel);
+        if settings_chip_name(&channel.chip()).1 {
+            return colors.into_iter().map(|color| Color::rgba(255 - color.r(), 255 - color.g(), 255 - color.b(), color.alpha())).collect();
         }
+        return colors;
+    }
 
-        match self.channel_settings.get(&channel.chip()) {
+    fn base_channel_colors(&self, channel: &dyn AudioChannelState) -> Vec<Color> {
+        let (chip_name, _) = settings_chip_name(&channel.chip());
+        match self.channel_settings.get(&chip_name) {
             Some(chip_settings) => {
                 match chip_settings.get(&channel.name()) {
                     Some(channel_settings) => {
+                        // An empty gradient (say, from a theme that left it out) would leave the
+                        // callers with nothing to index, so fall back to the unknown-channel color
+                        if channel_settings.colors.len() == 0 {
+                            return vec!(unknown_channel_color(&chip_name, &channel.name()));
+                        }
                         return channel_settings.colors.clone();
                     },
                     None => {
                         // Known chip, but unknown channel within this chip. Weird!
-                        // Default to a different grey
-                        return vec!(Color::rgb(192,  192, 192));
+                        return vec!(unknown_channel_color(&chip_name, &channel.name()));
                     }
                 }
             },
             None => {
-                // No color is defined for this whole chip. Is it new? Use a default color.
-                return vec!(Color::rgb(224, 224, 224));
+                // No color is defined for this whole chip. Is it new? Give each of its
+                // channels a color of their own, so they can at least be told apart.
+                return vec!(unknown_channel_color(&chip_name, &channel.name()));
             }
         }
     }
@@ -859,25 +1545,68 @@ impl PianoRollWindow {
                 let weight = index as f32 / (max + 1) as f32;
                 color = drawing::apply_gradient(colors, weight);  
             }
+            Some(Timbre::Distortion{index, max}) => {
+                let weight = index as f32 / (max + 1) as f32;
+                color = drawing::apply_gradient(colors, weight);
+            }
             None => {},
         }
         return color;
     }
 
+    fn detected_pitch(&self, channel: &dyn AudioChannelState) -> Option<f32> {
+        if !self.use_detected_pitch {
+            return None;
+        }
+        match self.detected_pitches.get(&format!("{}/{}", channel.chip(), channel.name())) {
+            Some(&Some(frequency)) if frequency >= self.lowest_frequency && frequency <= self.highest_frequency => {
+                return Some(frequency);
+            },
+            _ => {return None;}
+        }
+    }
+
+    // Runs detection for every pitched channel. The sample buffers only change once per
+    // update, so this result is shared by all of the slices generated during it.
+    fn update_detected_pitches(&mut self, channels: &Vec<&dyn AudioChannelState>, sample_rate: f32) {
+        self.detected_pitches.clear();
+        if !self.use_detected_pitch {
+            return;
+        }
+        for channel in channels {
+            match channel.rate() {
+                PlaybackRate::FundamentalFrequency{frequency: _} if channel.playing() => {
+                    let detected_frequency = self.pitch_detector.detect(channel.sample_buffer(), sample_rate);
+                    self.detected_pitches.insert(format!("{}/{}", channel.chip(), channel.name()), detected_frequency);
+                },
+                _ => {}
+            }
+        }
+    }
+
     fn slice_from_channel(&self, channel: &dyn AudioChannelState) -> ChannelSlice {
         if !channel.playing() {
             return ChannelSlice::none();
         }
 
         let y: f32;
-        let thickness: f32 = channel.amplitude() * 6.0;
+        let velocity = channel.amplitude();
         let colors = self.channel_colors(channel);
         let mut color = colors[0]; // default to the first color
         let note_type: NoteType;
 
         match channel.rate() {
             PlaybackRate::FundamentalFrequency{frequency} => {
-                y = self.frequency_to_coordinate(frequency);
+                let placement_frequency = match self.detected_pitch(channel) {
+                    Some(detected_frequency) => detected_frequency,
+                    None => frequency
+                };
+                // A period of 0 or a confused expansion channel can report nonsense here, and
+                // ln() of that would put a NaN into the slice. Better to just not draw it.
+                if !placement_frequency.is_finite() || placement_frequency <= 0.0 {
+                    return ChannelSlice::none();
+                }
+                y = self.frequency_to_coordinate(placement_frequency);
                 note_type = NoteType::Frequency;
             },
             PlaybackRate::LfsrRate{index, max} => {
@@ -892,8 +1621,16 @@ impl PianoRollWindow {
 
             },
             PlaybackRate::SampleRate{frequency: _} => {
-                y = 0.0;
-                note_type = NoteType::Waveform;
+                match channel.estimated_frequency() {
+                    Some(frequency) if self.dmc_as_pitch && frequency >= self.lowest_frequency && frequency <= self.highest_frequency => {
+                        y = self.frequency_to_coordinate(frequency);
+                        note_type = NoteType::Frequency;
+                    },
+                    _ => {
+                        y = 0.0;
+                        note_type = NoteType::Waveform;
+                    }
+                }
             }
         }
         
@@ -910,7 +1647,27 @@ impl PianoRollWindow {
                 let weight = index as f32 / (max + 1) as f32;
                 color = drawing::apply_gradient(colors, weight);  
             }
-            None => {},
+            Some(Timbre::Distortion{index, max}) => {
+                let weight = index as f32 / (max + 1) as f32;
+                color = drawing::apply_gradient(colors, weight);
+            }
+            None => {
+                if note_type == NoteType::Waveform {
+                    // Samples have no timbre to speak of, so shade them by how loud they are instead
+                    color = drawing::apply_gradient(colors, velocity.min(1.0));
+                }
+            },
+        }
+
+        let (mut color, thickness, alpha) = self.apply_velocity_style(color, velocity);
+
+        let mut thickness = thickness;
+        if note_type == NoteType::Noise && self.noise_render_style == NoiseStyle::Band {
+            // Spread the noise out over the strings around it, and let whatever is underneath
+            // show through, so it reads as a wash of energy rather than a pitch
+            thickness *= PianoRollWindow::noise_band_spread(channel.timbre());
+            let band_alpha = color.alpha() as f32 * 0.5;
+            color.set_alpha(band_alpha as u8);
         }
 
         return ChannelSlice{
@@ -918,13 +1675,240 @@ impl PianoRollWindow {
             y: y,
             thickness: thickness,
             color: color,
-            note_type: note_type
+            note_type: note_type,
+            alpha: alpha,
+            muted: channel.muted(),
+        };
+    }
+
+    // Returns the color, thickness and alpha of a note played at this velocity
+    fn apply_velocity_style(&self, color: Color, velocity: f32) -> (Color, f32, f32) {
+        let mut color = color;
+        let thickness: f32;
+        let alpha: f32;
+        match self.velocity_style {
+            VelocityStyle::Thickness => {
+                thickness = velocity * 6.0;
+                alpha = velocity;
+            },
+            VelocityStyle::Alpha => {
+                thickness = 6.0;
+                alpha = velocity;
+                color.set_alpha((velocity * 255.0) as u8);
+            },
+            VelocityStyle::Saturation => {
+                thickness = 6.0;
+                alpha = 1.0;
+                color = PianoRollWindow::desaturate_color(color, velocity);
+            },
+            VelocityStyle::Brightness => {
+                thickness = 6.0;
+                alpha = 1.0;
+                color = PianoRollWindow::scale_color(color, velocity);
+            }
+        }
+        return (color, thickness, alpha);
+    }
+
+    // A made up note on one of the displayed channels, for testing the roll without a game.
+    // midi_index uses standard MIDI numbering, so 60 is middle C.
+    pub fn test_note_slice(&self, channel: Option<&dyn AudioChannelState>, midi_index: u8, velocity: f32) -> ChannelSlice {
+        let base_color = match channel {
+            Some(channel) => self.channel_colors(channel)[0],
+            None => Color::rgb(192, 192, 192)
+        };
+        let (color, thickness, alpha) = self.apply_velocity_style(base_color, velocity.max(0.0).min(1.0));
+        return ChannelSlice{
+            visible: true,
+            y: self.frequency_to_coordinate(midi_frequency(midi_index as u32)),
+            thickness: thickness,
+            color: color,
+            note_type: NoteType::Frequency,
+            alpha: alpha,
+            muted: false,
+        };
+    }
+
+    fn synthetic_slices(&self, channels: &Vec<&dyn AudioChannelState>) -> Vec<ChannelSlice> {
+        let mut frame_notes: Vec<ChannelSlice> = Vec::new();
+        let channel_count = channels.len().max(self.test_notes.keys().map(|index| index + 1).max().unwrap_or(0));
+        for i in 0 .. channel_count {
+            match self.test_notes.get(&i) {
+                Some(test_note) => {frame_notes.push(self.test_note_slice(channels.get(i).cloned(), test_note.midi_index, test_note.velocity));},
+                None => {frame_notes.push(ChannelSlice::none());}
+            }
+        }
+        return frame_notes;
+    }
+
+    // Shifts the keyboard by whole octaves so the loudest pitched note in the newest slice
+    // lands as close to the middle as it can. Does nothing if nothing pitched is sounding.
+    pub fn center_on_active_notes(&mut self, channels: &Vec<&dyn AudioChannelState>) {
+        let newest_slices = match self.time_slices.front() {
+            Some(slices) => slices,
+            None => {return;}
+        };
+        let mut loudest: Option<(f32, f32)> = None;
+        for (channel_index, slice) in newest_slices.iter().enumerate() {
+            if !slice.visible || slice.note_type != NoteType::Frequency {
+                continue;
+            }
+            let amplitude = match channels.get(channel_index) {
+                Some(channel) => channel.amplitude(),
+                None => slice.alpha
+            };
+            match loudest {
+                Some((loudest_amplitude, _)) if loudest_amplitude >= amplitude => {},
+                _ => {loudest = Some((amplitude, slice.y));}
+            }
+        }
+        let coordinate = match loudest {
+            Some((_, coordinate)) => coordinate,
+            None => {return;}
+        };
+        let note_index = self.lowest_index as f32 + coordinate;
+        let lowest_octave_index = midi_index("C0").unwrap() as f32;
+        let ideal_lowest_index = note_index - (self.visible_keys() as f32 / 2.0);
+        let octave = ((ideal_lowest_index - lowest_octave_index) / 12.0).round().max(0.0).min(9.0);
+        let old_lowest_index = self.lowest_index;
+        self.set_starting_octave(octave as u32);
+
+        // Slices store coordinates relative to the lowest key, so move the ones already on the
+        // roll along with the keyboard, otherwise the history jumps by whole octaves
+        let shift = old_lowest_index as f32 - self.lowest_index as f32;
+        if shift != 0.0 {
+            for slices in self.time_slices.iter_mut() {
+                for slice in slices.iter_mut() {
+                    if slice.note_type == NoteType::Frequency {
+                        slice.y += shift;
+                    }
+                }
+            }
+        }
+    }
+
+    // Mutes are handled elsewhere, by chip and channel name, so this only asks for one
+    pub fn toggle_channel_mute(&mut self, channels: &Vec<&dyn AudioChannelState>, channel_index: usize) -> Vec<Event> {
+        let channel = match channels.get(channel_index) {
+            Some(channel) => channel,
+            None => {return Vec::new();}
+        };
+        let (verb, event) = if channel.muted() {
+            ("Unmuted", Event::UnmuteChannel(channel.chip(), channel.name()))
+        } else {
+            ("Muted", Event::MuteChannel(channel.chip(), channel.name()))
+        };
+        self.mute_feedback = Some((format!("{} {}: {} {}", verb, channel_index + 1, channel.chip(), channel.name()), 90));
+        return vec!(event);
+    }
+
+    fn draw_mute_feedback(&mut self) {
+        let (message, frames_left) = match self.mute_feedback.take() {
+            Some(feedback) => feedback,
+            None => {return;}
         };
+        let width = message.len() as u32 * self.font.glyph_width;
+        if width + 8 <= self.canvas.width && self.canvas.height > 12 {
+            let x = self.canvas.width - width - 4;
+            drawing::blend_rect(&mut self.canvas, x - 1, 3, width + 2, 10, Color::rgba(0, 0, 0, 0xA0));
+            drawing::text(&mut self.canvas, &self.font, x, 4, &message, Color::rgb(224, 224, 224));
+        }
+        if frames_left > 1 {
+            self.mute_feedback = Some((message, frames_left - 1));
+        }
+    }
+
+    // Forgets what we've been tracking for one channel: its detected pitch, a note held back for
+    // the grid, a test note and its stats. Slices already on the roll are left where they are.
+    pub fn reset_channel_visualization(&mut self, channels: &Vec<&dyn AudioChannelState>, chip_name: &str, channel_name: &str) {
+        self.detected_pitches.remove(&format!("{}/{}", chip_name, channel_name));
+        self.stats.reset_channel(chip_name, channel_name);
+        match channels.iter().position(|channel| channel.chip() == chip_name && channel.name() == channel_name) {
+            Some(channel_index) => {
+                if channel_index < self.grid_holds.len() {
+                    self.grid_holds[channel_index] = None;
+                }
+                self.test_notes.remove(&channel_index);
+            },
+            None => {}
+        }
+    }
+
+    // Same, for every channel at once, plus the surfboard trails
+    pub fn reset_all_channel_visualization(&mut self) {
+        self.detected_pitches.clear();
+        self.grid_holds.clear();
+        self.test_notes.clear();
+        self.stats.reset();
+        self.surfboard_trails = None;
+        self.last_drawn_layout = None;
+    }
+
+    // Velocity 0 releases the note. Notes typed on the keyboard have no release, so they
+    // are given a fixed length instead.
+    pub fn play_test_note(&mut self, channels: &Vec<&dyn AudioChannelState>, channel_index: usize, midi_index: u8, velocity: f32, length: Option<u32>) {
+        if velocity <= 0.0 {
+            self.test_notes.remove(&channel_index);
+        } else {
+            self.test_notes.insert(channel_index, TestNote{
+                midi_index: midi_index,
+                velocity: velocity,
+                remaining_polls: length,
+            });
+        }
+        // Show it right away, even if emulation is paused
+        let frame_notes = self.synthetic_slices(channels);
+        self.push_slice(frame_notes, Arc::new(Vec::new()));
+    }
+
+    fn expire_test_notes(&mut self) {
+        for test_note in self.test_notes.values_mut() {
+            match test_note.remaining_polls {
+                Some(remaining) => {test_note.remaining_polls = Some(remaining.saturating_sub(1));},
+                None => {}
+            }
+        }
+        self.test_notes.retain(|_, test_note| test_note.remaining_polls != Some(0));
+    }
+
+    // Tracker style layout: the bottom two letter rows play C4 through E5
+    fn typed_test_note(c: char) -> Option<u8> {
+        let keys = "awsedftgyhujkolp;";
+        match keys.find(c.to_ascii_lowercase()) {
+            Some(offset) => {return Some(60 + offset as u8);},
+            None => {return None;}
+        }
+    }
+
+    // How much wider than a pitched note the noise band should be. The 2A03's long LFSR mode
+    // is close to white noise and covers a wide range, while the short mode is buzzy and
+    // much closer to a tone.
+    fn noise_band_spread(timbre: Option<Timbre>) -> f32 {
+        match timbre {
+            Some(Timbre::LsfrMode{index: 1, max: _}) => {return 2.0},
+            _ => {return 4.0}
+        }
+    }
+
+    // Blends a color towards its own grey value; a saturation of 0.0 yields pure grey
+    fn desaturate_color(original_color: Color, saturation: f32) -> Color {
+        let grey = 0.299 * original_color.r() as f32 + 0.587 * original_color.g() as f32 + 0.114 * original_color.b() as f32;
+        let r = grey + (original_color.r() as f32 - grey) * saturation;
+        let g = grey + (original_color.g() as f32 - grey) * saturation;
+        let b = grey + (original_color.b() as f32 - grey) * saturation;
+        return Color::rgba(r as u8, g as u8, b as u8, original_color.alpha());
+    }
+
+    // Takes on the slice's own transparency, so faded notes fade their outlines too
+    fn outline_color_for(slice: &ChannelSlice, outline: Color) -> Color {
+        let mut color = outline;
+        color.set_alpha(((outline.alpha() as u32 * slice.color.alpha() as u32) / 255) as u8);
+        return color;
     }
 
-    fn draw_slice_horiz(canvas: &mut SimpleBuffer, slice: &ChannelSlice, x: u32, base_y: u32, key_height: u32) {
+    fn draw_slice_horiz(canvas: &mut SimpleBuffer, slice: &ChannelSlice, x: u32, base_y: u32, key_height: f32, outline: Option<Color>, quality: AaQuality) {
         if !slice.visible {return;}
-        let effective_y = (base_y as f32) - (slice.y * (key_height as f32)) + 0.5;
+        let effective_y = (base_y as f32) - (slice.y * key_height) + 0.5;
 
         let top_edge = effective_y - (slice.thickness / 2.0);
         let bottom_edge = effective_y + (slice.thickness / 2.0);
@@ -936,37 +1920,66 @@ impl PianoRollWindow {
             return;
         }
 
+        if quality == AaQuality::High {
+            for (y, color) in smooth_span(slice, top_edge, bottom_edge, outline, canvas.height) {
+                if color.alpha() == 255 {
+                    canvas.put_pixel(x, y, color);
+                } else {
+                    canvas.blend_pixel(x, y, color);
+                }
+            }
+            return;
+        }
+
         let mut blended_color = slice.color;
+        let slice_alpha = slice.color.alpha() as f32;
         if top_floor == bottom_floor {
             // Special case: alpha here will be related to their distance. Draw one
             // blended point and exit
             let alpha = bottom_edge - top_edge;
-            blended_color.set_alpha((alpha * 255.0) as u8);
+            blended_color.set_alpha((alpha * slice_alpha) as u8);
             canvas.blend_pixel(x, top_floor as u32, blended_color);
             return;
         }
+        // The outline replaces the edges and the outermost solid pixels, so it never makes the
+        // note any wider. Notes too thin to have an inside are left alone.
+        let (edge_color, outline_rows) = match outline {
+            Some(outline) if bottom_floor - top_floor >= 4.0 => (PianoRollWindow::outline_color_for(slice, outline), 1),
+            _ => (slice.color, 0)
+        };
+
         // Alpha blend the edges
+        let mut blended_color = edge_color;
+        let edge_alpha = edge_color.alpha() as f32;
         let top_alpha = 1.0 - (top_edge - top_floor);
-        blended_color.set_alpha((top_alpha * 255.0) as u8);
+        blended_color.set_alpha((top_alpha * edge_alpha) as u8);
         canvas.blend_pixel(x, top_floor as u32, blended_color);
 
         let bottom_alpha = bottom_edge - bottom_floor;
-        blended_color.set_alpha((bottom_alpha * 255.0) as u8);
+        blended_color.set_alpha((bottom_alpha * edge_alpha) as u8);
         canvas.blend_pixel(x, bottom_floor as u32, blended_color);
 
         // If there is any distance at all between the edges, draw a solid color
         // line between them
-        for y in (top_floor as u32) + 1 .. bottom_floor as u32 {
-            canvas.put_pixel(x, y, slice.color);
+        let first_solid = (top_floor as u32) + 1;
+        let last_solid = bottom_floor as u32;
+        for y in first_solid .. last_solid {
+            let on_outline = y < first_solid + outline_rows || y + outline_rows >= last_solid;
+            let color = if on_outline {edge_color} else {slice.color};
+            if color.alpha() == 255 {
+                canvas.put_pixel(x, y, color);
+            } else {
+                canvas.blend_pixel(x, y, color);
+            }
         }
     }
 
-    fn draw_slice_vert(canvas: &mut SimpleBuffer, slice: &ChannelSlice, base_x: u32, y: u32, key_width: u32) {
+    fn draw_slice_vert(canvas: &mut SimpleBuffer, slice: &ChannelSlice, base_x: u32, y: u32, key_width: f32, outline: Option<Color>, quality: AaQuality) {
         if !slice.visible {return;}
-        let effective_x = (base_x as f32) + (slice.y * (key_width as f32)) + 0.5;
+        let effective_x = (base_x as f32) + (slice.y * key_width) + 0.5;
 
-        let left_edge = effective_x - (slice.thickness * (key_width as f32) / 4.0);
-        let right_edge = effective_x + (slice.thickness * (key_width as f32) / 4.0);
+        let left_edge = effective_x - (slice.thickness * key_width / 4.0);
+        let right_edge = effective_x + (slice.thickness * key_width / 4.0);
         let left_floor = left_edge.floor();
         let right_floor = right_edge.floor();
 
@@ -975,37 +1988,65 @@ impl PianoRollWindow {
             return;
         }
 
-        let mut blended_color = slice.color;
+        if quality == AaQuality::High {
+            for (x, color) in smooth_span(slice, left_edge, right_edge, outline, canvas.width) {
+                if color.alpha() == 255 {
+                    canvas.put_pixel(x, y, color);
+                } else {
+                    canvas.blend_pixel(x, y, color);
+                }
+            }
+            return;
+        }
+
+        let mut blended_color = slice.color;
+        let slice_alpha = slice.color.alpha() as f32;
         if left_floor == right_floor {
             // Special case: alpha here will be related to their distance. Draw one
             // blended point and exit
             let alpha = right_edge - left_edge;
-            blended_color.set_alpha((alpha * 255.0) as u8);
+            blended_color.set_alpha((alpha * slice_alpha) as u8);
             canvas.blend_pixel(left_floor as u32, y, blended_color);
             return;
         }
+        // Same as draw_slice_horiz: the outline stays inside the note
+        let (edge_color, outline_columns) = match outline {
+            Some(outline) if right_floor - left_floor >= 4.0 => (PianoRollWindow::outline_color_for(slice, outline), 1),
+            _ => (slice.color, 0)
+        };
+
         // Alpha blend the edges
+        let mut blended_color = edge_color;
+        let edge_alpha = edge_color.alpha() as f32;
         let left_alpha = 1.0 - (left_edge - left_floor);
-        blended_color.set_alpha((left_alpha * 255.0) as u8);
+        blended_color.set_alpha((left_alpha * edge_alpha) as u8);
         canvas.blend_pixel(left_floor as u32, y, blended_color);
 
         let right_alpha = right_edge - right_floor;
-        blended_color.set_alpha((right_alpha * 255.0) as u8);
+        blended_color.set_alpha((right_alpha * edge_alpha) as u8);
         canvas.blend_pixel(right_floor as u32, y, blended_color);
 
         // If there is any distance at all between the edges, draw a solid color
         // line between them
-        for x in (left_floor as u32) + 1 .. right_floor as u32 {
-            canvas.put_pixel(x, y, slice.color);
+        let first_solid = (left_floor as u32) + 1;
+        let last_solid = right_floor as u32;
+        for x in first_solid .. last_solid {
+            let on_outline = x < first_solid + outline_columns || x + outline_columns >= last_solid;
+            let color = if on_outline {edge_color} else {slice.color};
+            if color.alpha() == 255 {
+                canvas.put_pixel(x, y, color);
+            } else {
+                canvas.blend_pixel(x, y, color);
+            }
         }
     }
 
-    fn draw_outline_vert(canvas: &mut SimpleBuffer, slice: &ChannelSlice, base_x: u32, y: u32, key_width: u32, color: Color, thickness: u32) {
+    fn draw_outline_vert(canvas: &mut SimpleBuffer, slice: &ChannelSlice, base_x: u32, y: u32, key_width: f32, color: Color, thickness: u32) {
         if !slice.visible {return;}
-        let effective_x = (base_x as f32) + (slice.y * (key_width as f32)) + 0.5;
+        let effective_x = (base_x as f32) + (slice.y * key_width) + 0.5;
 
-        let left_edge = effective_x - (slice.thickness * (key_width as f32) / 4.0) - (thickness as f32);
-        let right_edge = effective_x + (slice.thickness * (key_width as f32) / 4.0) + (thickness as f32);
+        let left_edge = effective_x - (slice.thickness * key_width / 4.0) - (thickness as f32);
+        let right_edge = effective_x + (slice.thickness * key_width / 4.0) + (thickness as f32);
         let left_floor = left_edge.max(0.0).floor();
         let right_floor = right_edge.min((canvas.width - 1) as f32).floor();
 
@@ -1047,10 +2088,21 @@ impl PianoRollWindow {
     }
 
     fn draw_slices_horiz(&mut self, starting_x: u32, base_y: u32, step_direction: i32) {
+        let offset = self.scroll_offset();
+        let (key_thickness, outline, quality) = (self.key_thickness_f, self.note_outline, self.aa_quality);
         let mut x = starting_x;
-        for channel_slice in self.time_slices.iter() {
-            for note in channel_slice.iter() {
-                PianoRollWindow::draw_slice_horiz(&mut self.canvas, &note, x, base_y, self.key_thickness);
+        for age in 0 .. self.time_slices.len() {
+            let notes = self.prepared_notes(age, true);
+            let draw_note = |canvas: &mut SimpleBuffer, note: &ChannelSlice| {
+                PianoRollWindow::draw_slice_horiz(canvas, note, x, base_y, key_thickness, outline, quality);
+            };
+            if offset > 0.0 && age > 0 {
+                let newer_notes = self.prepared_notes(age - 1, true);
+                PianoRollWindow::draw_blended_line(&mut self.canvas, x, true, offset, &notes, &newer_notes, draw_note);
+            } else {
+                for note in notes.iter() {
+                    draw_note(&mut self.canvas, note);
+                }
             }
             // bail if we hit either screen edge:
             if x == 0 || x == (self.canvas.width - 1) {
@@ -1065,9 +2117,9 @@ impl PianoRollWindow {
         for channel_slice in self.time_slices.iter() {
             for note in channel_slice.iter() {
                 if note.note_type == NoteType::Waveform {
-                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, waveform_pos, y, self.key_thickness, self.outline_color, self.outline_thickness);
+                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, waveform_pos, y, self.key_thickness_f, self.outline_color, self.outline_thickness);
                 } else {
-                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, base_x, y, self.key_thickness, self.outline_color, self.outline_thickness);
+                    PianoRollWindow::draw_outline_vert(&mut self.canvas, &note, base_x, y, self.key_thickness_f, self.outline_color, self.outline_thickness);
                 }
             }
             // bail if we hit either screen edge:
@@ -1079,13 +2131,21 @@ impl PianoRollWindow {
     }
 
     fn draw_slices_vert(&mut self, base_x: u32, starting_y: u32, step_direction: i32, waveform_pos: u32) {
+        let offset = self.scroll_offset();
+        let (key_thickness, outline, quality) = (self.key_thickness_f, self.note_outline, self.aa_quality);
         let mut y = starting_y;
-        for channel_slice in self.time_slices.iter() {
-            for note in channel_slice.iter() {
-                if note.note_type == NoteType::Waveform {
-                    PianoRollWindow::draw_slice_vert(&mut self.canvas, &note, waveform_pos, y, self.key_thickness);
-                } else {
-                    PianoRollWindow::draw_slice_vert(&mut self.canvas, &note, base_x, y, self.key_thickness);
+        for age in 0 .. self.time_slices.len() {
+            let notes = self.prepared_notes(age, false);
+            let draw_note = |canvas: &mut SimpleBuffer, note: &ChannelSlice| {
+                let x = if note.note_type == NoteType::Waveform {waveform_pos} else {base_x};
+                PianoRollWindow::draw_slice_vert(canvas, note, x, y, key_thickness, outline, quality);
+            };
+            if offset > 0.0 && age > 0 {
+                let newer_notes = self.prepared_notes(age - 1, false);
+                PianoRollWindow::draw_blended_line(&mut self.canvas, y, false, offset, &notes, &newer_notes, draw_note);
+            } else {
+                for note in notes.iter() {
+                    draw_note(&mut self.canvas, note);
                 }
             }
             // bail if we hit either screen edge:
@@ -1093,14 +2153,168 @@ impl PianoRollWindow {
                 return; //bail! don't draw offscreen
             }
             y = (y as i32 + step_direction) as u32;
+        }
+    }
+
+    // One time slice's notes, with the end fade (and horizontally, pitch smoothing) applied
+    fn prepared_notes(&self, age: usize, smooth_pitch: bool) -> Vec<ChannelSlice> {
+        let mut notes = Vec::new();
+        for (channel_index, note) in self.time_slices[age].iter().enumerate() {
+            let note = self.apply_note_end_fade(age, channel_index, note);
+            if smooth_pitch {
+                notes.push(self.apply_pitch_smoothing(age, channel_index, &note));
+            } else {
+                notes.push(note);
+            }
+        }
+        return notes;
+    }
+
+    // With smooth scrolling, every slice has moved scroll_remainder of a pixel away from the
+    // keys since it was polled. The line touching the keys is "now", which is still the newest
+    // slice, so it's drawn at full strength and stays lined up with the key spots. Every other
+    // line is blended by coverage between its own slice and the newer one sliding into it.
+    fn scroll_offset(&self) -> f32 {
+        if self.smooth_scroll {
+            return self.scroll_remainder;
+        }
+        return 0.0;
+    }
 
+    // Draws one line of slices (a column when scrolling sideways, a row otherwise) as if the
+    // slices sat `offset` of a pixel further along. The line's own slice covers 1 - offset of it
+    // and the newer slice covers the rest. Each is drawn over the background on its own, and the
+    // two results are mixed by coverage, so a note held across both stays at full strength.
+    fn draw_blended_line<F>(canvas: &mut SimpleBuffer, position: u32, column: bool, offset: f32, notes: &[ChannelSlice], newer_notes: &[ChannelSlice], draw_note: F)
+    where F: Fn(&mut SimpleBuffer, &ChannelSlice) {
+        let length = if column {canvas.height} else {canvas.width};
+        let pixel_at = |i: u32| if column {(position, i)} else {(i, position)};
+        let background: Vec<Color> = (0 .. length).map(|i| {let (x, y) = pixel_at(i); canvas.get_pixel(x, y)}).collect();
+
+        for note in notes.iter() {
+            draw_note(canvas, note);
         }
+        let own_line: Vec<Color> = (0 .. length).map(|i| {let (x, y) = pixel_at(i); canvas.get_pixel(x, y)}).collect();
+        for i in 0 .. length {
+            let (x, y) = pixel_at(i);
+            canvas.put_pixel(x, y, background[i as usize]);
+        }
+
+        for note in newer_notes.iter() {
+            draw_note(canvas, note);
+        }
+        for i in 0 .. length {
+            let (x, y) = pixel_at(i);
+            let newer_color = canvas.get_pixel(x, y);
+            canvas.put_pixel(x, y, lerp_colors(own_line[i as usize], newer_color, offset));
+        }
+    }
+
+    fn same_note(a: &ChannelSlice, b: &ChannelSlice) -> bool {
+        return a.visible && b.visible && (a.y - b.y).abs() < 0.5;
+    }
+
+    // How far into its note this slice is, as a 0.0 - 1.0 fade weight. Only the first and last
+    // note_end_fade slices of a note are affected. A note that runs off either end of the
+    // roll (still playing, or started before the oldest slice) isn't faded on that side.
+    pub fn note_end_fade_weight(&self, age: usize, channel_index: usize) -> f32 {
+        if self.note_end_fade == 0 {
+            return 1.0;
+        }
+        let note = match self.time_slices.get(age).and_then(|slices| slices.get(channel_index)) {
+            Some(note) => note,
+            None => {return 1.0;}
+        };
+        let none = ChannelSlice::none();
+        let fade = self.note_end_fade as usize;
+        let mut distance_to_end = fade + 1;
+        // Newer slices first, towards the note's release
+        for distance in 1 ..= fade {
+            if distance > age {
+                break;
+            }
+            let newer = self.time_slices[age - distance].get(channel_index).unwrap_or(&none);
+            if !PianoRollWindow::same_note(note, newer) {
+                distance_to_end = distance_to_end.min(distance);
+                break;
+            }
+        }
+        // Then older ones, towards its start
+        for distance in 1 ..= fade {
+            if age + distance >= self.time_slices.len() {
+                break;
+            }
+            let older = self.time_slices[age + distance].get(channel_index).unwrap_or(&none);
+            if !PianoRollWindow::same_note(note, older) {
+                distance_to_end = distance_to_end.min(distance);
+                break;
+            }
+        }
+        return distance_to_end as f32 / (fade + 1) as f32;
+    }
+
+    fn apply_note_end_fade(&self, age: usize, channel_index: usize, note: &ChannelSlice) -> ChannelSlice {
+        let weight = self.note_end_fade_weight(age, channel_index);
+        if weight >= 1.0 {
+            return note.clone();
+        }
+        return PianoRollWindow::faded_slice(note, weight);
+    }
+
+    // Grows the slice so it reaches halfway to the same channel's slice in each neighboring
+    // column. Only used horizontally, where y is the axis that stair-steps.
+    pub fn apply_pitch_smoothing(&self, age: usize, channel_index: usize, note: &ChannelSlice) -> ChannelSlice {
+        if !self.smooth_pitch || !note.visible {
+            return note.clone();
+        }
+        let mut low = note.y;
+        let mut high = note.y;
+        let neighbors = [age.checked_sub(1), Some(age + 1)];
+        for neighbor_age in neighbors.iter() {
+            let neighbor = match neighbor_age.and_then(|a| self.time_slices.get(a)).and_then(|slices| slices.get(channel_index)) {
+                Some(neighbor) => neighbor,
+                None => {continue;}
+            };
+            let delta = neighbor.y - note.y;
+            if !neighbor.visible || neighbor.note_type != note.note_type || delta.abs() >= self.pitch_smoothing_threshold {
+                continue;
+            }
+            let midpoint = note.y + delta / 2.0;
+            low = low.min(midpoint);
+            high = high.max(midpoint);
+        }
+        let mut smoothed = note.clone();
+        smoothed.y = (low + high) / 2.0;
+        smoothed.thickness += (high - low) * self.key_thickness_f;
+        return smoothed;
+    }
+
+    fn faded_slice(slice: &ChannelSlice, weight: f32) -> ChannelSlice {
+        let mut faded = slice.clone();
+        let alpha = faded.color.alpha() as f32 * weight;
+        faded.color.set_alpha(alpha as u8);
+        return faded;
     }
 
     fn draw_key_spots_horiz(&mut self, x: u32, base_y: u32) {
         for note in self.time_slices.front().unwrap_or(&Vec::new()) {
             PianoRollWindow::draw_key_spot_horiz(&mut self.canvas, &note, self.key_thickness, x, base_y);
+            if self.show_mute_icons && note.visible && note.muted {
+                let icon_y = (base_y as f32) - note.y.round() * self.key_thickness as f32;
+                PianoRollWindow::draw_mute_icon(&mut self.canvas, &self.font, x + 8, icon_y as u32);
+            }
+        }
+    }
+
+    // Roughly centered on (x, y), and skipped entirely if that would hang off the canvas
+    fn draw_mute_icon(canvas: &mut SimpleBuffer, font: &Font, x: u32, y: u32) {
+        if x < 4 || y < 4 || x + 4 > canvas.width || y + 4 > canvas.height {
+            return;
         }
+        let dot_color = Color::rgb(176, 176, 176);
+        drawing::rect(canvas, x - 3, y - 4, 6, 8, dot_color);
+        drawing::rect(canvas, x - 4, y - 3, 8, 6, dot_color);
+        drawing::text(canvas, font, x - 4, y - 4, "x", Color::rgb(192, 32, 32));
     }
 
     fn draw_key_spots_vert(&mut self, base_x: u32, y: u32, waveform_pos: u32) {
@@ -1108,13 +2322,19 @@ impl PianoRollWindow {
             if note.note_type == NoteType::Waveform {
                 if note.visible {
                     let mut base_color = note.color;
-                    let volume_percent = note.thickness / 6.0;
-                    base_color.set_alpha((volume_percent * 255.0) as u8);
+                    base_color.set_alpha((note.alpha.min(1.0) * 255.0) as u8);
                     draw_speaker_key_vert(&mut self.canvas, base_color, waveform_pos, y - 1, self.key_thickness, self.key_length); 
                 }
             } else {
                PianoRollWindow::draw_key_spot_vert(&mut self.canvas, &note, self.key_thickness, self.key_length, base_x, y);
             }
+            if self.show_mute_icons && note.visible && note.muted {
+                let icon_x = match note.note_type {
+                    NoteType::Waveform => waveform_pos,
+                    _ => ((base_x as f32) + note.y.round() * self.key_thickness as f32) as u32
+                };
+                PianoRollWindow::draw_mute_icon(&mut self.canvas, &self.font, icon_x, y + self.key_length / 2);
+            }
         }
     }
 
@@ -1123,44 +2343,489 @@ impl PianoRollWindow {
             if note.note_type == NoteType::Waveform {
                 if note.visible {
                     let mut base_color = note.color;
-                    let volume_percent = note.thickness / 6.0;
-                    base_color.set_alpha((volume_percent * 255.0) as u8);
+                    base_color.set_alpha((note.alpha.min(1.0) * 255.0) as u8);
                     draw_speaker_key_vert(&mut self.canvas, base_color, waveform_pos, y - 1, self.key_thickness, self.key_length); 
                 }
             } else {
                PianoRollWindow::draw_key_spot_vert(&mut self.canvas, &note, self.key_thickness, self.key_length, base_x, y);
             }
+            if self.show_mute_icons && note.visible && note.muted {
+                let icon_x = match note.note_type {
+                    NoteType::Waveform => waveform_pos,
+                    _ => ((base_x as f32) + note.y.round() * self.key_thickness as f32) as u32
+                };
+                PianoRollWindow::draw_mute_icon(&mut self.canvas, &self.font, icon_x, y + self.key_length / 2);
+            }
+        }
+    }
+
+    // The current canvas, scaled down by export_scale for exporting at a lower resolution
+    pub fn export_canvas(&self) -> SimpleBuffer {
+        if self.export_scale <= 1 {
+            return self.canvas.clone();
+        }
+        return self.canvas.downsample(self.export_scale, self.export_dither);
+    }
+
+    pub fn export_stats(&self, path: &str) -> Result<(), String> {
+        return self.stats.export(path);
+    }
+
+    // How often update() runs, in polls per second
+    pub fn poll_rate_hz(&self) -> f64 {
+        let ntsc_frame_rate = 1789773.0 / 29780.5;
+        match self.polling_type {
+            PollingType::PpuFrame => {return ntsc_frame_rate;},
+            PollingType::PpuScanline => {return ntsc_frame_rate * 262.0;},
+            PollingType::ApuQuarterFrame => {return self.apu_quarter_frame_rate;},
+            PollingType::ApuHalfFrame => {return self.apu_quarter_frame_rate / 2.0;},
+            PollingType::AudioSamples(samples_per_poll) => {return self.audio_sample_rate / samples_per_poll.max(1) as f64;},
+        }
+    }
+
+    // The pixels per poll that scrolls the roll at this many pixels per second
+    pub fn speed_for_pixels_per_second(&self, pixels_per_second: f32) -> f32 {
+        return (pixels_per_second as f64 / self.poll_rate_hz()) as f32;
+    }
+
+    // 0 or less hands control back to speed_multiplier
+    pub fn set_scroll_speed_pps(&mut self, pixels_per_second: f32) {
+        if pixels_per_second > 0.0 {
+            self.scroll_speed_pps = Some(pixels_per_second);
+            self.apply_scroll_speed_pps();
+        } else {
+            self.scroll_speed_pps = None;
+        }
+    }
+
+    // Re-derives the scroll speed from scroll_speed_pps, whenever it or the poll rate changes.
+    // Without smooth scrolling this rounds to whole pixels, so it's only approximate.
+    fn apply_scroll_speed_pps(&mut self) {
+        match self.scroll_speed_pps {
+            Some(pixels_per_second) => {
+                self.scroll_speed = self.speed_for_pixels_per_second(pixels_per_second);
+                self.speed_multiplier = (self.scroll_speed.round() as u32).max(1);
+            },
+            None => {}
+        }
+    }
+
+    fn pixels_per_poll(&self) -> f64 {
+        if self.smooth_scroll {
+            return self.scroll_speed as f64;
+        }
+        return self.speed_multiplier as f64;
+    }
+
+    // Slices (pixels of roll) per second under the current polling and speed settings.
+    // Anything that puts real time on the roll's axis should go through this. Only NTSC
+    // timing is emulated, so there's no region to account for.
+    pub fn effective_poll_rate_hz(&self) -> f64 {
+        return self.poll_rate_hz() * self.pixels_per_poll();
+    }
+
+    // Distance between grid lines in slices (pixels), or None when the grid is off. This is
+    // rarely a whole number, so individual lines are rounded to the nearest slice.
+    pub fn grid_step(&self) -> Option<f64> {
+        if self.tempo_bpm <= 0.0 || self.grid_subdivision == 0 {
+            return None;
+        }
+        let pixels_per_beat = self.effective_poll_rate_hz() * 60.0 / self.tempo_bpm as f64;
+        let step = pixels_per_beat / self.grid_subdivision as f64;
+        // Any tighter than this and the grid is just a solid wash
+        if step < 2.0 {
+            return None;
+        }
+        return Some(step);
+    }
+
+    // The color of the grid line on this slice, if it has one. Bars are every 4 beats.
+    pub fn grid_line_color(&self, slice_index: u64) -> Option<Color> {
+        let step = match self.grid_step() {
+            Some(step) => step,
+            None => {return None;}
+        };
+        let line = (slice_index as f64 / step).round();
+        if (line * step).round() as u64 != slice_index {
+            return None;
+        }
+        let line = line as u64;
+        let subdivision = self.grid_subdivision as u64;
+        if line % (subdivision * 4) == 0 {
+            return Some(Color::rgba(255, 255, 255, 48));
+        }
+        if line % subdivision == 0 {
+            return Some(Color::rgba(255, 255, 255, 24));
+        }
+        return Some(Color::rgba(255, 255, 255, 10));
+    }
+
+    fn draw_tempo_grid_vert(&mut self, x: u32, width: u32, starting_y: u32, step_direction: i32) {
+        if self.grid_step().is_none() {
+            return;
+        }
+        let mut y = starting_y as i32;
+        for age in 0 .. (self.time_slices.len() as u64).min(self.total_slices) {
+            if y < 0 || y >= self.canvas.height as i32 {
+                return;
+            }
+            match self.grid_line_color(self.total_slices - 1 - age) {
+                Some(color) => {drawing::blend_rect(&mut self.canvas, x, y as u32, width, 1, color);},
+                None => {}
+            }
+            y += step_direction;
+        }
+    }
+
+    fn draw_tempo_grid_horiz(&mut self, starting_x: u32, y: u32, height: u32, step_direction: i32) {
+        if self.grid_step().is_none() {
+            return;
+        }
+        let mut x = starting_x as i32;
+        for age in 0 .. (self.time_slices.len() as u64).min(self.total_slices) {
+            if x < 0 || x >= self.canvas.width as i32 {
+                return;
+            }
+            match self.grid_line_color(self.total_slices - 1 - age) {
+                Some(color) => {drawing::blend_rect(&mut self.canvas, x as u32, y, 1, height, color);},
+                None => {}
+            }
+            x += step_direction;
+        }
+    }
+
+    // Where the reference line sits, in the same key units as ChannelSlice::y. None if it's
+    // turned off or outside the current key range.
+    pub fn reference_coordinate(&self) -> Option<f32> {
+        let frequency = match self.reference_frequency {
+            Some(frequency) if frequency > 0.0 => frequency,
+            _ => {return None;}
+        };
+        let coordinate = self.frequency_to_coordinate(frequency);
+        if coordinate < 0.0 || coordinate > self.visible_keys() as f32 {
+            return None;
+        }
+        return Some(coordinate);
+    }
+
+    fn reference_label(&self) -> String {
+        let frequency = self.reference_frequency.unwrap_or(0.0);
+        return format!("{} {:.1}Hz", note_name(frequency), frequency);
+    }
+
+    // Matches the center of a note at the same pitch, see draw_slice_horiz
+    pub fn reference_line_y(&self, base_y: u32) -> Option<u32> {
+        return self.reference_coordinate().map(|coordinate| ((base_y as f32) - (coordinate * self.key_thickness_f) + 0.5) as u32);
+    }
+
+    // Matches the center of a note at the same pitch, see draw_slice_vert
+    pub fn reference_line_x(&self, base_x: u32) -> Option<u32> {
+        return self.reference_coordinate().map(|coordinate| ((base_x as f32) + (coordinate * self.key_thickness_f) + 0.5) as u32);
+    }
+
+    // The C keys in the current range, as (keys above the lowest, label). These sit on the
+    // same strings as draw_piano_strings_*, so they follow the range and zoom along with it.
+    pub fn hz_axis_labels(&self) -> Vec<(u32, String)> {
+        return (0 .. self.visible_keys()).step_by(12).map(|key_offset| {
+            let frequency = midi_frequency(self.lowest_index + key_offset);
+            let label = if frequency < 100.0 {format!("{:.1}Hz", frequency)} else {format!("{:.0}Hz", frequency)};
+            (key_offset, label)
+        }).collect();
+    }
+
+    // Labels sit just above their string, starting from x, or ending at x if right_aligned
+    fn draw_hz_axis_horiz(&mut self, x: u32, base_y: u32, right_aligned: bool) {
+        if !self.show_hz_axis {
+            return;
+        }
+        let color = Color::rgba(160, 160, 160, 192);
+        for (key_offset, label) in self.hz_axis_labels() {
+            let string_y = (base_y as f32) - (key_offset as f32 * self.key_thickness_f) + 0.5;
+            let label_width = label.len() as u32 * self.font.glyph_width;
+            if string_y < 10.0 || (right_aligned && x < label_width) {
+                continue;
+            }
+            let label_x = if right_aligned {x - label_width} else {x};
+            drawing::text(&mut self.canvas, &self.font, label_x, string_y as u32 - 10, &label, color);
+        }
+    }
+
+    // Labels sit just right of their string, at y
+    fn draw_hz_axis_vert(&mut self, base_x: u32, y: u32) {
+        if !self.show_hz_axis || y + 8 > self.canvas.height {
+            return;
+        }
+        let color = Color::rgba(160, 160, 160, 192);
+        for (key_offset, label) in self.hz_axis_labels() {
+            let string_x = (base_x as f32) + (key_offset as f32 * self.key_thickness_f) + 0.5;
+            let label_x = string_x as u32 + 2;
+            if label_x + label.len() as u32 * self.font.glyph_width > self.canvas.width {
+                continue;
+            }
+            drawing::text(&mut self.canvas, &self.font, label_x, y, &label, color);
+        }
+    }
+
+    fn draw_reference_line_horiz(&mut self, x: u32, width: u32, base_y: u32) {
+        let color = Color::rgba(255, 224, 96, 160);
+        let line_y = match self.reference_line_y(base_y) {
+            Some(line_y) if line_y < self.canvas.height => line_y,
+            _ => {return;}
+        };
+        drawing::blend_rect(&mut self.canvas, x, line_y, width, 1, color);
+
+        // Label sits just above the line, or below it if that would run off the top
+        let label = self.reference_label();
+        let label_width = label.len() as u32 * 8;
+        let label_y = if line_y >= 10 {line_y - 10} else {line_y + 3};
+        if label_width + 4 <= width && label_y + 8 <= self.canvas.height {
+            drawing::text(&mut self.canvas, &self.font, x + 4, label_y, &label, color);
+        }
+    }
+
+    fn draw_reference_line_vert(&mut self, base_x: u32, y: u32, height: u32) {
+        let color = Color::rgba(255, 224, 96, 160);
+        let line_x = match self.reference_line_x(base_x) {
+            Some(line_x) if line_x < self.canvas.width => line_x,
+            _ => {return;}
+        };
+        drawing::blend_rect(&mut self.canvas, line_x, y, 1, height, color);
+
+        // Label sits to the right of the line, or the left if that would run off the edge
+        let label = self.reference_label();
+        let label_width = label.len() as u32 * 8;
+        let label_x = if line_x + 3 + label_width <= self.canvas.width {
+            line_x + 3
+        } else if line_x >= label_width + 2 {
+            line_x - 2 - label_width
+        } else {
+            return;
+        };
+        if height >= 12 {
+            drawing::text(&mut self.canvas, &self.font, label_x, y + 2, &label, color);
+        }
+    }
+
+    fn is_note_start(previous: &ChannelSlice, current: &ChannelSlice) -> bool {
+        if !current.visible {
+            return false;
+        }
+        return !previous.visible || (previous.y - current.y).abs() >= 0.5;
+    }
+
+    // Moves note starts onto the nearest grid line. If the last line was closer, the note is
+    // extended back to meet it; if the next one is closer, whatever was playing before carries
+    // on until we get there.
+    fn quantize_slices(&mut self, notes: &mut Vec<ChannelSlice>) {
+        let step = match self.grid_step() {
+            Some(step) => step,
+            None => {return;}
+        };
+        let slice_index = self.total_slices;
+        let previous_notes = self.time_slices.front().cloned().unwrap_or(Vec::new());
+        self.grid_holds.resize(notes.len(), None);
+        for i in 0 .. notes.len() {
+            let previous = previous_notes.get(i).cloned().unwrap_or(ChannelSlice::none());
+            match self.grid_holds[i] {
+                Some(release_at) if slice_index < release_at => {
+                    notes[i] = previous;
+                    continue;
+                },
+                Some(_) => {
+                    // Made it to the grid line, let the note through
+                    self.grid_holds[i] = None;
+                    continue;
+                },
+                None => {}
+            }
+            if !PianoRollWindow::is_note_start(&previous, &notes[i]) || self.grid_line_color(slice_index).is_some() {
+                continue;
+            }
+            let line = (slice_index as f64 / step).floor();
+            let previous_line = (line * step).round() as u64;
+            let next_line = ((line + 1.0) * step).round() as u64;
+            if slice_index - previous_line <= next_line - slice_index {
+                for age in 0 .. (slice_index - previous_line) as usize {
+                    match self.time_slices.get_mut(age) {
+                        Some(older_notes) if i < older_notes.len() => {older_notes[i] = notes[i].clone();},
+                        _ => {}
+                    }
+                }
+            } else {
+                self.grid_holds[i] = Some(next_line);
+                notes[i] = previous;
+            }
+        }
+    }
+
+    fn push_slice(&mut self, frame_notes: Vec<ChannelSlice>, records: Arc<Vec<NoteRecord>>) {
+        let mut frame_notes = frame_notes;
+        if self.quantize_to_grid {
+            self.quantize_slices(&mut frame_notes);
+        }
+        self.time_slices.push_front(frame_notes);
+        self.note_history.push_front(records);
+        self.total_slices += 1;
+
+        while self.time_slices.len() > self.roll_width() as usize {
+            self.time_slices.pop_back();
+        }
+        self.note_history.truncate(self.time_slices.len());
+    }
+
+    // One row per visible note per slice, oldest first. Times are in seconds, counted from the
+    // first slice ever pushed. Noise has no meaningful frequency, so its LFSR index is
+    // recorded in that column instead.
+    pub fn notes_to_csv(&self) -> String {
+        let mut csv = String::from("time,channel,chip,frequency,midi_note,amplitude,timbre\n");
+        let slices_per_second = self.effective_poll_rate_hz();
+        for age in (0 .. self.time_slices.len()).rev() {
+            let records = match self.note_history.get(age) {
+                Some(records) => records,
+                None => {continue;}
+            };
+            let time = (self.total_slices - 1 - age as u64) as f64 / slices_per_second;
+            for (channel_index, slice) in self.time_slices[age].iter().enumerate() {
+                let record = match records.get(channel_index) {
+                    Some(record) if slice.visible => record,
+                    _ => {continue;}
+                };
+                let (frequency, midi_note) = match record.rate {
+                    PlaybackRate::FundamentalFrequency{frequency} => {
+                        (format!("{:.2}", frequency), format!("{}", frequency_to_midi(frequency).round() as i32))
+                    },
+                    PlaybackRate::LfsrRate{index, max: _} => (format!("lfsr {}", index), String::new()),
+                    PlaybackRate::SampleRate{frequency} => (format!("{:.2}", frequency), String::new()),
+                };
+                csv.push_str(&format!("{:.6},{},{},{},{},{:.3},{}\n",
+                    time, record.name, record.chip, frequency, midi_note, record.amplitude, timbre_name(record.timbre.clone())));
+            }
+        }
+        return csv;
+    }
+
+    pub fn export_csv(&self, path: &str) -> Result<(), String> {
+        match fs::write(path, self.notes_to_csv()) {
+            Ok(_) => {return Ok(());},
+            Err(why) => {return Err(format!("Couldn't write note history to {}: {}", path, why));}
+        }
+    }
+
+    // Polls once for every samples_per_poll samples the APU has produced since the last poll
+    fn poll_audio_samples(&mut self, runtime: &RuntimeState) {
+        let samples_per_poll = match self.polling_type {
+            PollingType::AudioSamples(samples_per_poll) if samples_per_poll > 0 => samples_per_poll as u64,
+            _ => {return;}
+        };
+        if self.audio_sample_rate != runtime.nes.apu.sample_rate as f64 {
+            self.audio_sample_rate = runtime.nes.apu.sample_rate as f64;
+            self.apply_scroll_speed_pps();
+        }
+        let total_samples = runtime.nes.apu.generated_samples;
+        // Start counting from here, and again if the count goes backwards (new cartridge)
+        let mut last_polled_sample = match self.last_polled_sample {
+            Some(last_polled_sample) if last_polled_sample <= total_samples => last_polled_sample,
+            _ => total_samples
+        };
+        while total_samples - last_polled_sample >= samples_per_poll {
+            self.poll(runtime);
+            last_polled_sample += samples_per_poll;
+        }
+        self.last_polled_sample = Some(last_polled_sample);
+    }
+
+    // One poll's worth of slices, from both consoles if an A/B comparison is running
+    fn poll(&mut self, runtime: &RuntimeState) {
+        match runtime.comparison {
+            Some(ref comparison) => {self.update_comparison(&runtime.nes.apu, &*runtime.nes.mapper, &comparison.apu, &*comparison.mapper)},
+            None => {self.update(&runtime.nes.apu, &*runtime.nes.mapper)}
         }
     }
 
     fn update(&mut self, apu: &ApuState, mapper: &dyn Mapper) {
+        self.track_frame_sequencer_mode(apu);
         let channels = self.collect_channels(&apu, &*mapper);
+        self.update_from_channels(&channels, apu.sample_rate as f32);
+    }
+
+    // A/B comparison: polls two emulators at once, plotting the second one's channels over the
+    // first in complementary colors. Used in place of update while runtime.comparison is set.
+    pub fn update_comparison(&mut self, apu: &ApuState, mapper: &dyn Mapper, comparison_apu: &ApuState, comparison_mapper: &dyn Mapper) {
+        self.track_frame_sequencer_mode(apu);
+        let comparison_channels = self.collect_channels(comparison_apu, comparison_mapper);
+        let wrapped_channels: Vec<ComparisonChannel> = comparison_channels.into_iter().map(|channel| ComparisonChannel{channel: channel}).collect();
+        let mut channels = self.collect_channels(&apu, &*mapper);
+        for channel in wrapped_channels.iter() {
+            channels.push(channel);
+        }
+        self.update_from_channels(&channels, apu.sample_rate as f32);
+    }
+
+    // Games can switch the frame counter to 5-step mode at any time, which slows down the
+    // quarter and half frame polls, and so everything derived from the poll rate
+    fn track_frame_sequencer_mode(&mut self, apu: &ApuState) {
+        let quarter_frame_rate = apu.quarter_frame_rate_hz();
+        if quarter_frame_rate != self.apu_quarter_frame_rate {
+            self.apu_quarter_frame_rate = quarter_frame_rate;
+            self.apply_scroll_speed_pps();
+        }
+    }
 
-        for _i in 0 .. self.speed_multiplier {
+    fn update_from_channels(&mut self, channels: &Vec<&dyn AudioChannelState>, sample_rate: f32) {
+        let mut slice_count = self.speed_multiplier;
+        if self.smooth_scroll {
+            self.scroll_remainder += self.scroll_speed;
+            slice_count = self.scroll_remainder.floor() as u32;
+            self.scroll_remainder -= slice_count as f32;
+        }
+
+        if self.synthetic_input {
+            self.expire_test_notes();
+            let records = Arc::new(Vec::new());
+            for _i in 0 .. slice_count {
+                let frame_notes = self.synthetic_slices(channels);
+                self.push_slice(frame_notes, records.clone());
+            }
+            return;
+        }
+
+        self.update_detected_pitches(channels, sample_rate);
+        self.stats.record(channels);
+
+        let records = Arc::new(channels.iter().map(|channel| NoteRecord::from_channel(*channel)).collect::<Vec<NoteRecord>>());
+        for _i in 0 .. slice_count {
             let mut frame_notes: Vec<ChannelSlice> = Vec::new();
-            for channel in &channels {
+            for channel in channels {
                 frame_notes.push(self.slice_from_channel(*channel));
             }
-            self.time_slices.push_front(frame_notes);
+            self.push_slice(frame_notes, records.clone());
         }
+    }
 
-        while self.time_slices.len() > self.roll_width() as usize {
-            self.time_slices.pop_back();
+    // Pulse and noise channels really are square, so smoothing them would just be wrong
+    fn scope_interpolation_for(&self, channel: &dyn AudioChannelState) -> ScopeInterpolation {
+        match channel.timbre() {
+            Some(Timbre::DutyIndex{..}) | Some(Timbre::LsfrMode{..}) => {return ScopeInterpolation::Step;},
+            _ => {return self.scope_interpolation;}
         }
     }
 
-    pub fn find_edge(edge_buffer: &RingBuffer, window_size: usize) -> usize {
-        let start_index = (edge_buffer.index() - window_size) % edge_buffer.buffer().len();
-        let mut current_index = start_index;
-        for _i in 0 .. (window_size * 4) {
-            if edge_buffer.buffer()[current_index] != 0 {
-                // center the window on this sample
-                return (current_index - (window_size / 2)) % edge_buffer.buffer().len();
+    pub fn trigger_mode(&self, channel: &dyn AudioChannelState) -> TriggerMode {
+        return *self.surfboard_trigger.get(&(channel.chip(), channel.name())).unwrap_or(&self.surfboard_trigger_default);
+    }
+
+    // Where in the channel's sample buffer its surfboard cell starts, per its trigger mode
+    pub fn surfboard_window_start(&self, channel: &dyn AudioChannelState, window_size: usize) -> usize {
+        match self.trigger_mode(channel) {
+            TriggerMode::RisingEdge => {
+                let threshold = default_edge_threshold(channel);
+                return find_edge(channel.sample_buffer(), window_size, threshold);
+            },
+            TriggerMode::None => {
+                let buffer = channel.sample_buffer();
+                return buffer.index_before(buffer.index(), window_size);
             }
-            current_index = (current_index - 1) % edge_buffer.buffer().len();
         }
-        // couldn't find an edge, so return the most recent slice
-        return start_index;
     }
 
     fn draw_vertical_antialiased_line(&mut self, x: u32, top_edge: f32, bottom_edge: f32, color: Color) {
@@ -1236,17 +2901,20 @@ impl PianoRollWindow {
         self.draw_channel_labels(channel, x, y, width, height);
 
         let speed = 4;
-        let first_sample_index = PianoRollWindow::find_edge(channel.edge_buffer(), (width * speed) as usize);
+        let first_sample_index = self.surfboard_window_start(channel, (width * speed) as usize);
         let sample_min = channel.min_sample();
         let sample_max = channel.max_sample() + 1; // ???
-        let range = (sample_max as u32) - (sample_min as u32);
-        let sample_buffer = channel.sample_buffer().buffer();
-        let mut last_y = ((sample_buffer[first_sample_index] - sample_min) as f32 * height as f32) / range as f32;
-        for i in 0 .. width {
+        let range = (sample_max as i32 - sample_min as i32) as u32;
+        let samples: Vec<i16> = channel.sample_buffer().iter_from(first_sample_index).step_by(speed as usize).take(width as usize).cloned().collect();
+        if samples.len() == 0 {
+            return;
+        }
+        let samples = interpolate_scope(&samples, self.scope_interpolation_for(channel));
+        let mut last_y = ((samples[0] - sample_min as f32) * height as f32) / range as f32;
+        for i in 0 .. samples.len() as u32 {
             let dx = x + i;
-            let sample_index = (first_sample_index + (i * speed) as usize) % sample_buffer.len();
-            let sample = sample_buffer[sample_index];
-            let current_y = ((sample - sample_min) as f32 * height as f32) / range as f32;
+            let sample = samples[i as usize];
+            let current_y = ((sample - sample_min as f32) * height as f32) / range as f32;
             let mut top_edge = current_y;
             let mut bottom_edge = last_y;
             if last_y < current_y {
@@ -1261,9 +2929,18 @@ impl PianoRollWindow {
     }
 
     fn draw_channel_labels(&mut self, channel: &dyn AudioChannelState, x: u32, y: u32, width: u32, height: u32) {
+        if self.surfboard_labels {
+            self.draw_compact_channel_label(channel, x, y, width, height);
+            return;
+        }
         if !self.draw_text_labels {
             return;
         }
+        // Cells too small for both labels (tiny canvas, lots of channels) just go without
+        let widest_label_px = (channel.chip().len().max(channel.name().len()) * 8) as u32;
+        if width < widest_label_px + 17 || height < 24 {
+            return;
+        }
         let channel_color = self.channel_color(channel);
 
         let transparent_color = Color::rgba(0, 0, 0, 0x80);
@@ -1288,23 +2965,85 @@ impl PianoRollWindow {
         drawing::text(&mut self.canvas, &self.font, channel_x, channel_y, &channel_label, channel_color);
     }
 
+    // A small label tucked into the top-left corner of the cell, on a dark backdrop so it stays
+    // readable when the trace passes underneath. Unlike the large labels, this fits in the
+    // short cells you get with several surfboard rows.
+    fn draw_compact_channel_label(&mut self, channel: &dyn AudioChannelState, x: u32, y: u32, width: u32, height: u32) {
+        let glyph_width = self.font.glyph_width;
+        let glyph_height = 8;
+        let margin = self.divider_width + 2;
+        if width <= margin * 2 || height < glyph_height + 4 {
+            return;
+        }
+        let max_chars = ((width - margin * 2) / glyph_width) as usize;
+
+        // Prefer "chip name", then just the name, then however much of the name will fit
+        let full_label = format!("{} {}", channel.chip(), channel.name());
+        let label: String = if full_label.len() <= max_chars {
+            full_label
+        } else {
+            channel.name().chars().take(max_chars).collect()
+        };
+        if label.len() == 0 {
+            return;
+        }
+
+        let colors = self.configured_channel_colors(channel);
+        let mut label_color = colors.first().cloned().unwrap_or(Color::rgb(224, 224, 224));
+        if channel.muted() {
+            label_color = PianoRollWindow::scale_color(label_color, 0.35);
+        }
+
+        let label_x = x + margin;
+        let label_y = y + 2;
+        let label_width = label.len() as u32 * glyph_width;
+        drawing::blend_rect(&mut self.canvas, label_x - 1, label_y - 1, label_width + 2, glyph_height + 2, Color::rgba(0, 0, 0, 0xA0));
+        drawing::text(&mut self.canvas, &self.font, label_x, label_y, &label, label_color);
+    }
+
     fn draw_audio_surfboard_horiz(&mut self, runtime: &RuntimeState, x: u32, y: u32, width: u32, height: u32) {
-        let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
-        let channel_width = width / (channels.len() as u32);
-        let mut leftover_pixels = width - (channel_width * (channels.len() as u32));
-        let mut cx = 0;
+        let channels = self.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper);
         for i in 0 .. channels.len() {
-            let mut effective_width = channel_width;
-            if leftover_pixels > 0 {
-                effective_width += 1;
-                leftover_pixels -= 1;
-            }
             let channel = channels[i];
-            let dx = x + cx;
-            self.draw_channel_surfboard(channel, dx, y, effective_width, height);
-            self.draw_channel_dividers(dx, y, effective_width, height);
-            cx = cx + effective_width;
+            let (cx, cy, cell_width, cell_height) = surfboard_cell(i, channels.len(), self.surfboard_rows, x, y, width, height);
+            self.draw_channel_surfboard(channel, cx, cy, cell_width, cell_height);
+            self.draw_channel_dividers(cx, cy, cell_width, cell_height);
+            // Top right corner, clear of the compact label
+            if self.show_mute_icons && channel.muted() && cell_width > 24 {
+                let margin = self.divider_width + 2;
+                PianoRollWindow::draw_mute_icon(&mut self.canvas, &self.font, cx + cell_width - margin - 5, cy + margin + 4);
+            }
+        }
+        self.apply_surfboard_persistence(x, y, width, height);
+    }
+
+    // Blends the previous surfboard, dimmed, under the one we just drew. Taking the brighter of
+    // the two per channel leaves the static background alone, while a trace that moved away
+    // leaves a fading copy of itself behind.
+    pub fn apply_surfboard_persistence(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        if self.surfboard_persistence <= 0.0 {
+            self.surfboard_trails = None;
+            return;
+        }
+        let decay = self.surfboard_persistence.min(1.0);
+        let mut trails = match self.surfboard_trails.take() {
+            Some((tx, ty, trails)) if tx == x && ty == y && trails.width == width && trails.height == height => trails,
+            // First frame, or the layout changed: nothing to fade in yet
+            _ => SimpleBuffer::new(width, height)
+        };
+        for dy in 0 .. height {
+            for dx in 0 .. width {
+                let current = self.canvas.get_pixel(x + dx, y + dy);
+                let previous = trails.get_pixel(dx, dy);
+                let r = current.r().max((previous.r() as f32 * decay) as u8);
+                let g = current.g().max((previous.g() as f32 * decay) as u8);
+                let b = current.b().max((previous.b() as f32 * decay) as u8);
+                let blended = Color::rgba(r, g, b, current.alpha());
+                self.canvas.put_pixel(x + dx, y + dy, blended);
+                trails.put_pixel(dx, dy, blended);
+            }
         }
+        self.surfboard_trails = Some((x, y, trails));
     }
 
     pub fn mouse_mutes_channel_horiz(&mut self, runtime: &RuntimeState, sx: u32, sy: u32, width: u32, height: u32, mouse_x: i32, mouse_y: i32) -> Vec<Event> {
@@ -1314,18 +3053,17 @@ impl PianoRollWindow {
         }
         let mx = mouse_x as u32;
         let my = mouse_y as u32;
-        let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
-        let channel_width = width / (channels.len() as u32);
-        for i in 0 .. channels.len() {
-            let channel = channels[i];
-            let cx = sx + (i as u32) * channel_width;
-            if mx >= cx && mx < cx + channel_width && my >= sy && my < sy + height {
-               if channel.muted() {
+        let channels = self.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        match surfboard_cell_at(mx, my, channels.len(), self.surfboard_rows, sx, sy, width, height) {
+            Some(i) => {
+                let channel = channels[i];
+                if channel.muted() {
                     events.push(Event::UnmuteChannel(channel.chip(), channel.name()))
                 } else {
                     events.push(Event::MuteChannel(channel.chip(), channel.name()))
-                } 
-            }
+                }
+            },
+            None => {}
         }
         return events;
     }
@@ -1341,6 +3079,9 @@ impl PianoRollWindow {
             self.draw_piano_strings_horiz(0, bottom_key, string_width);
             self.draw_waveform_string_horiz(0, waveform_string_pos, string_width);
         }
+        self.draw_tempo_grid_horiz(string_width, 0, bottom_key, -1);
+        self.draw_reference_line_horiz(0, string_width, bottom_key);
+        self.draw_hz_axis_horiz(2, bottom_key, false);
         self.draw_piano_keys_horiz(string_width, bottom_key);
         //draw_speaker_key(&mut self.canvas, black_key);
         self.draw_slices_horiz(string_width, bottom_key, -1);
@@ -1358,26 +3099,33 @@ impl PianoRollWindow {
             self.draw_piano_strings_horiz(key_width, bottom_key, string_width);
             self.draw_waveform_string_horiz(key_width, waveform_string_pos, string_width);
         }
+        self.draw_tempo_grid_horiz(key_width, 0, bottom_key, 1);
+        self.draw_reference_line_horiz(key_width, string_width, bottom_key);
+        self.draw_hz_axis_horiz(self.canvas.width - 2, bottom_key, true);
         self.draw_piano_keys_horiz(0, bottom_key);
         self.draw_slices_horiz(key_width, bottom_key, 1);
         self.draw_key_spots_horiz(0, bottom_key);
     }
 
     fn draw_top_to_bottom(&mut self, runtime: &RuntimeState) {
-        let keyboard_width = self.keys * self.key_thickness;
+        let keyboard_width = self.visible_keys() * self.key_thickness;
         let waveform_area_width = ((self.canvas.width - keyboard_width) / 2).max(20);
 
         let waveform_string_pos = waveform_area_width / 2;
         let waveform_margin = self.key_thickness / 2;
         let key_height = self.key_length;
         let leftmost_key = waveform_area_width + waveform_margin;
-        let surfboard_height = self.surfboard_height;
-        let string_height = self.canvas.height - key_height - surfboard_height;
+        // On a very short canvas, give up surfboard space before pushing the keys off the bottom
+        let surfboard_height = self.surfboard_height.min(self.canvas.height.saturating_sub(key_height + 1));
+        let string_height = self.canvas.height.saturating_sub(key_height + surfboard_height);
 
         if self.draw_piano_strings {
             self.draw_piano_strings_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, string_height);
             self.draw_waveform_string_vert(waveform_string_pos, surfboard_height + key_height, string_height);
         }
+        self.draw_tempo_grid_vert(waveform_area_width, keyboard_width, surfboard_height + key_height, 1);
+        self.draw_reference_line_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, string_height);
+        self.draw_hz_axis_vert(waveform_area_width + waveform_margin, self.canvas.height.saturating_sub(10));
 
         self.draw_outlines_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, 1, waveform_string_pos);
         self.draw_piano_keys_vert(leftmost_key, surfboard_height);
@@ -1394,12 +3142,15 @@ impl PianoRollWindow {
         let key_height = self.key_length;
         let leftmost_key = waveform_area_width + waveform_margin;
         let surfboard_height = self.surfboard_height;
-        let string_height = self.canvas.height - key_height - surfboard_height;
+        let string_height = self.canvas.height.saturating_sub(key_height + surfboard_height);
 
         if self.draw_piano_strings {
             self.draw_piano_strings_vert(waveform_area_width + waveform_margin, 0, string_height);
             self.draw_waveform_string_vert(waveform_string_pos, 0, string_height);
         }
+        self.draw_tempo_grid_vert(waveform_area_width, self.visible_keys() * self.key_thickness, self.canvas.height - key_height, -1);
+        self.draw_reference_line_vert(waveform_area_width + waveform_margin, 0, self.canvas.height - key_height);
+        self.draw_hz_axis_vert(waveform_area_width + waveform_margin, surfboard_height + 2);
         self.draw_outlines_vert(waveform_area_width + waveform_margin, surfboard_height + key_height, 1, waveform_string_pos);
         self.draw_piano_keys_vert(leftmost_key, self.canvas.height - key_height);
         self.draw_slices_vert(waveform_area_width + waveform_margin, self.canvas.height - key_height, -1, waveform_string_pos);
@@ -1419,21 +3170,66 @@ impl PianoRollWindow {
         self.draw_waveform_string_vert(waveform_string_pos, 0, string_height);
         self.draw_piano_keys_vert(leftmost_key, self.canvas.height - key_height);
 
+        self.draw_tempo_grid_vert(waveform_area_width, self.visible_keys() * self.key_thickness, 1, 1);
+        self.draw_reference_line_vert(waveform_area_width, 0, string_height);
+        self.draw_hz_axis_vert(waveform_area_width, 2);
         self.draw_slices_vert(waveform_area_width, 1, 1, waveform_string_pos);
         self.draw_key_spots_vert_inverted(leftmost_key, self.canvas.height - key_height, waveform_string_pos);
     }
 
+    fn draw_mirrored_vertical(&mut self) {
+        let waveform_area_width = self.key_thickness * 4;
+        let waveform_string_pos = self.key_thickness * 2;
+        let waveform_margin = self.key_thickness / 2;
+        let key_height = self.key_length;
+        let leftmost_key = waveform_area_width + waveform_margin;
+        let keys_top = (self.canvas.height.saturating_sub(key_height) / 2).max(1);
+        let keys_bottom = (keys_top + key_height).min(self.canvas.height - 1);
+        let lower_height = self.canvas.height - keys_bottom;
+
+        // Everything but the keys is drawn twice: once going up from the keyboard, and once going down
+        if self.draw_piano_strings {
+            self.draw_piano_strings_vert(waveform_area_width + waveform_margin, 0, keys_top);
+            self.draw_piano_strings_vert(waveform_area_width + waveform_margin, keys_bottom, lower_height);
+            self.draw_waveform_string_vert(waveform_string_pos, 0, keys_top);
+            self.draw_waveform_string_vert(waveform_string_pos, keys_bottom, lower_height);
+        }
+        self.draw_tempo_grid_vert(waveform_area_width, self.visible_keys() * self.key_thickness, keys_top, -1);
+        self.draw_tempo_grid_vert(waveform_area_width, self.visible_keys() * self.key_thickness, keys_bottom, 1);
+        self.draw_reference_line_vert(waveform_area_width + waveform_margin, 0, keys_top);
+        self.draw_reference_line_vert(waveform_area_width + waveform_margin, keys_bottom, lower_height);
+        self.draw_hz_axis_vert(waveform_area_width + waveform_margin, 2);
+
+        self.draw_outlines_vert(waveform_area_width + waveform_margin, keys_top, -1, waveform_string_pos);
+        self.draw_outlines_vert(waveform_area_width + waveform_margin, keys_bottom, 1, waveform_string_pos);
+        self.draw_piano_keys_vert(leftmost_key, keys_top);
+        self.draw_slices_vert(waveform_area_width + waveform_margin, keys_top, -1, waveform_string_pos);
+        self.draw_slices_vert(waveform_area_width + waveform_margin, keys_bottom, 1, waveform_string_pos);
+        self.draw_key_spots_vert(leftmost_key, keys_top, waveform_string_pos);
+    }
+
     fn draw(&mut self, runtime: &RuntimeState) {
         let width = self.canvas.width;
         let height = self.canvas.height;
-        drawing::rect(&mut self.canvas, 0, 0, width, height, self.background_color);
+        let layout = (width, height, self.scroll_direction, self.time_slices.len());
+        let needs_clear = match self.last_drawn_layout {
+            Some((last_width, last_height, last_direction, last_len)) =>
+                last_width != width || last_height != height || last_direction != self.scroll_direction || layout.3 < last_len,
+            None => true
+        };
+        if self.clear_each_frame || needs_clear {
+            drawing::rect(&mut self.canvas, 0, 0, width, height, self.background_color);
+        }
+        self.last_drawn_layout = Some(layout);
         match self.scroll_direction {
             ScrollDirection::RightToLeft => {self.draw_right_to_left()},
             ScrollDirection::LeftToRight => {self.draw_left_to_right()},
             ScrollDirection::TopToBottom => {self.draw_top_to_bottom(runtime)},
             ScrollDirection::BottomToTop => {self.draw_bottom_to_top(runtime)},
-            ScrollDirection::PlayerPiano => {self.draw_player_piano()}
+            ScrollDirection::PlayerPiano => {self.draw_player_piano()},
+            ScrollDirection::MirroredVertical => {self.draw_mirrored_vertical()}
         }
+        self.draw_mute_feedback();
     }
 
     fn mouse_click(&mut self, runtime: &RuntimeState, mx: i32, my: i32) -> Vec<Event> {
@@ -1450,10 +3246,97 @@ impl PianoRollWindow {
 
     fn set_canvas_height(&mut self, height: u32, width: u32) {
         self.canvas = SimpleBuffer::new(height, width);
+        self.derive_key_geometry();
     }
 
-    fn set_starting_octave(&mut self, octave_number: u32) {
-        let note_name = format!("C{}", octave_number);
+    fn derive_key_geometry(&mut self) {
+        let (key_thickness, key_length) = match self.key_size {
+            Some(key_size) => key_size.dimensions(),
+            None => default_key_geometry(self.canvas.width, self.canvas.height)
+        };
+        let key_thickness = match self.key_thickness_f_override {
+            Some(key_thickness_f) => key_thickness_f.round() as u32,
+            None => self.key_thickness_override.unwrap_or(key_thickness)
+        };
+        self.key_length = self.key_length_override.unwrap_or(key_length);
+
+        // Shrink the keys if the whole keyboard won't fit on the canvas. The key shapes need
+        // at least 2px to draw at all.
+        let mut fitted_thickness = key_thickness.max(2);
+        while fitted_thickness > 2 && !self.keyboard_fits(self.keys, fitted_thickness) {
+            fitted_thickness -= 1;
+        }
+        if fitted_thickness != key_thickness {
+            logging::warn(&format!("{} keys won't fit on a {}x{} piano roll, reducing key_thickness from {} to {}.",
+                self.keys, self.canvas.width, self.canvas.height, key_thickness, fitted_thickness));
+        }
+        self.key_thickness = fitted_thickness;
+        self.key_thickness_f = match self.key_thickness_f_override {
+            Some(key_thickness_f) if fitted_thickness == key_thickness => key_thickness_f,
+            _ => fitted_thickness as f32
+        };
+        if self.visible_keys() < self.keys {
+            logging::warn(&format!("Piano roll is too small for {} keys, only the lowest {} will be drawn.", self.keys, self.visible_keys()));
+        }
+    }
+
+    // Whether the keyboard, plus the waveform area beside it, fits across the canvas
+    fn keyboard_fits(&self, keys: u32, key_thickness: u32) -> bool {
+        let keyboard_size = keys * key_thickness;
+        match self.scroll_direction {
+            ScrollDirection::RightToLeft | ScrollDirection::LeftToRight => {
+                return keyboard_size + 32 <= self.canvas.height;
+            },
+            ScrollDirection::TopToBottom => {
+                return keyboard_size + key_thickness / 2 + 40 <= self.canvas.width;
+            },
+            ScrollDirection::BottomToTop | ScrollDirection::MirroredVertical => {
+                return keyboard_size + key_thickness * 4 + key_thickness / 2 <= self.canvas.width;
+            },
+            ScrollDirection::PlayerPiano => {
+                return keyboard_size + 32 <= self.canvas.width;
+            }
+        }
+    }
+
+    // Normally all of them. If even the thinnest keys don't fit, the highest keys are left off.
+    pub fn visible_keys(&self) -> u32 {
+        let mut keys = self.keys;
+        while keys > 1 && !self.keyboard_fits(keys, self.key_thickness) {
+            keys -= 1;
+        }
+        return keys;
+    }
+
+    // 0 or below turns the reference line off
+    pub fn set_reference_frequency(&mut self, frequency: f32) {
+        self.reference_frequency = if frequency > 0.0 {Some(frequency)} else {None};
+    }
+
+    pub fn set_key_size(&mut self, key_size: Option<KeySize>) {
+        self.key_size = key_size;
+        self.derive_key_geometry();
+    }
+
+    // 0 means "pick something based on the canvas size or key_size"
+    fn set_key_thickness(&mut self, key_thickness: u32) {
+        self.key_thickness_override = if key_thickness != 0 {Some(key_thickness)} else {None};
+        self.derive_key_geometry();
+    }
+
+    // Same as set_key_thickness, but fractional. Takes priority over it while set.
+    fn set_key_thickness_f(&mut self, key_thickness_f: f32) {
+        self.key_thickness_f_override = if key_thickness_f > 0.0 {Some(key_thickness_f.max(2.0))} else {None};
+        self.derive_key_geometry();
+    }
+
+    fn set_key_length(&mut self, key_length: u32) {
+        self.key_length_override = if key_length != 0 {Some(key_length)} else {None};
+        self.derive_key_geometry();
+    }
+
+    fn set_starting_octave(&mut self, octave_number: u32) {
+        let note_name = format!("C{}", octave_number);
 
         let key_index = midi_index(&note_name).unwrap();
         let key_freq = midi_frequency(key_index);
@@ -1475,6 +3358,7 @@ impl PianoRollWindow {
         self.keys = key_count;
         self.highest_index = highest_index;
         self.highest_frequency = highest_freq;
+        self.derive_key_geometry();
     }
 
     fn apply_channel_boolean_setting(&mut self, chip_name: &str, channel_name: &str, setting_name: &str, new_value: bool) {
@@ -1487,17 +3371,17 @@ impl PianoRollWindow {
                                 channel_settings.hidden = new_value;
                             },
                             _ => {
-                                println!("Warning: Failed to apply unrecognized setting {} to channel {}", setting_name, channel_name);
+                                logging::warn(&format!("Failed to apply unrecognized setting {} to channel {}", setting_name, channel_name));
                             }
                         }
                     },
                     None => {
-                        println!("Warning: Failed to apply setting {} to unknown channel {}", setting_name, channel_name);
+                        logging::warn(&format!("Failed to apply setting {} to unknown channel {}", setting_name, channel_name));
                     }
                 }
             },
             None => {
-                println!("Warning: Failed to apply setting {} to unknown audio chip {}", setting_name, chip_name);
+                logging::warn(&format!("Failed to apply setting {} to unknown audio chip {}", setting_name, chip_name));
             }
         }
     }
@@ -1551,77 +3435,86 @@ impl PianoRollWindow {
                                         channel_settings.colors[*setting_index] = color;
                                     },
                                     Err(_) => {
-                                        println!("Warning: Invalid color string {}, ignoring.", color_string);
+                                        logging::warn(&format!("Invalid color string {}, ignoring.", color_string));
                                     }
                                 }
                             },
                             None => {
-                                println!("Warning: setting {} does not correspond to any color slot for channel {} on chip {}", setting_name, channel_name, chip_name);
+                                logging::warn(&format!("setting {} does not correspond to any color slot for channel {} on chip {}", setting_name, channel_name, chip_name));
                             }
                         }
                     },
                     None => {
-                        println!("Warning: Failed to apply color string {} to unknown channel {}", color_string, channel_name);
+                        logging::warn(&format!("Failed to apply color string {} to unknown channel {}", color_string, channel_name));
                     }
                 }
             },
             None => {
-                println!("Warning: Failed to apply color string {} to unknown audio chip {}", color_string, chip_name);
+                logging::warn(&format!("Failed to apply color string {} to unknown audio chip {}", color_string, chip_name));
             }
         }
     }
-}
-
-impl Panel for PianoRollWindow {
-    fn title(&self) -> &str {
-        return "Piano Roll";
-    }
 
-    fn shown(&self) -> bool {
-        return self.shown;
-    }
-
-    fn scale_factor(&self) -> u32 {
-        return self.scale;
+    // Settings arrive as events, but unlike everything else in handle_event they don't need the
+    // emulator, so presets can apply them directly. Everything applied here is remembered for
+    // export_config.
+    // Returns false for settings the piano roll doesn't know about, which are left alone
+    fn apply_setting(&mut self, event: Event) -> bool {
+        let recorded = match &event {
+            Event::ApplyBooleanSetting(path, value) => (path.clone(), Value::Boolean(*value)),
+            Event::ApplyIntegerSetting(path, value) => (path.clone(), Value::Integer(*value)),
+            Event::ApplyFloatSetting(path, value) => (path.clone(), Value::Float(*value)),
+            Event::ApplyStringSetting(path, value) => (path.clone(), Value::String(value.clone())),
+            _ => {return false;}
+        };
+        if !self.apply_setting_value(event) {
+            return false;
+        }
+        self.applied_settings.insert(recorded.0, recorded.1);
+        return true;
     }
 
-    fn handle_event(&mut self, runtime: &RuntimeState, event: Event) -> Vec<Event> {
-        let mut events: Vec<Event> = Vec::new();
+    fn apply_setting_value(&mut self, event: Event) -> bool {
         match event {
-            Event::NesNewFrame => {
-                if self.polling_type == PollingType::PpuFrame {
-                    self.update(&runtime.nes.apu, &*runtime.nes.mapper);
-                }
-            },
-            Event::NesNewScanline => {
-                if self.polling_type == PollingType::PpuScanline {
-                    self.update(&runtime.nes.apu, &*runtime.nes.mapper);
-                }
-            },
-            Event::NesNewApuQuarterFrame => {
-                if self.polling_type == PollingType::ApuQuarterFrame {
-                    self.update(&runtime.nes.apu, &*runtime.nes.mapper);
-                }
-            },
-            Event::NesNewApuHalfFrame => {
-                if self.polling_type == PollingType::ApuHalfFrame {
-                    self.update(&runtime.nes.apu, &*runtime.nes.mapper);
-                }
-            },
-            Event::MouseClick(x, y) => {events.extend(self.mouse_click(runtime, x, y));},
-            Event::RequestFrame => {self.draw(runtime)},
-            Event::ShowPianoRollWindow => {self.shown = true},
-            Event::CloseWindow => {self.shown = false},
-
             Event::ApplyBooleanSetting(path, value) => {
                 let components = path.split(".").collect::<Vec<&str>>();
-                if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" {
+                if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" && components[4] == "surfboard" {
+                    let channel_key = (components[2].to_string(), components[3].to_string());
+                    if value {
+                        self.surfboard_excluded.remove(&channel_key);
+                    } else {
+                        self.surfboard_excluded.insert(channel_key);
+                    }
+                } else if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" {
                     self.apply_channel_boolean_setting(components[2], components[3], components[4], value);
                 } else {
                     match path.as_str() {
                         "piano_roll.draw_piano_strings" => {self.draw_piano_strings = value},
+                        "piano_roll.clear_each_frame" => {self.clear_each_frame = value},
                         "piano_roll.draw_text_labels" => {self.draw_text_labels = value},
-                        _ => {}
+                        "piano_roll.surfboard_labels" => {self.surfboard_labels = value},
+                        "piano_roll.digit_key_muting" => {self.digit_key_muting = value},
+                        "piano_roll.show_mute_icons" => {self.show_mute_icons = value},
+                        "piano_roll.show_hz_axis" => {self.show_hz_axis = value},
+                        "piano_roll.dmc_as_pitch" => {self.dmc_as_pitch = value},
+                        "piano_roll.use_detected_pitch" => {self.use_detected_pitch = value},
+                        "piano_roll.smooth_pitch" => {self.smooth_pitch = value},
+                        "piano_roll.export_dither" => {self.export_dither = value},
+                        "piano_roll.antialias_keyboard" => {self.antialias_keyboard = value},
+                        "piano_roll.desaturate_muted" => {self.desaturate_muted = value},
+                        "piano_roll.synthetic_input" => {
+                            self.synthetic_input = value;
+                            self.test_notes.clear();
+                        },
+                        "piano_roll.quantize_to_grid" => {
+                            self.quantize_to_grid = value;
+                            self.grid_holds.clear();
+                        },
+                        "piano_roll.smooth_scroll" => {
+                            self.smooth_scroll = value;
+                            self.scroll_remainder = 0.0;
+                        },
+                        _ => {return false;}
                     }
                 }
             },
@@ -1630,18 +3523,42 @@ impl Panel for PianoRollWindow {
                 match path.as_str() {
                     "piano_roll.canvas_width" => {self.set_canvas_height(value as u32, self.canvas.height)},
                     "piano_roll.canvas_height" => {self.set_canvas_height(self.canvas.width, value as u32)},
-                    "piano_roll.key_thickness" => {self.key_thickness = value as u32},
-                    "piano_roll.key_length" => {self.key_length = value as u32},
+                    "piano_roll.key_thickness" => {self.set_key_thickness(value as u32)},
+                    "piano_roll.key_length" => {self.set_key_length(value as u32)},
                     "piano_roll.octave_count" => {self.set_octave_count(value as u32)},
+                    "piano_roll.tempo_bpm" => {self.tempo_bpm = value as f32},
+                    "piano_roll.grid_subdivision" => {self.grid_subdivision = value as u32},
+                    "piano_roll.note_end_fade" => {self.note_end_fade = value as u32},
+                    "piano_roll.scale_root" => {
+                        self.scale_root = value as u32;
+                        self.highlight_scale = self.highlight_scale.map(|(_, kind)| (value as u32, kind));
+                    },
+                    "piano_roll.audio_samples_per_poll" => {
+                        self.audio_samples_per_poll = value as u32;
+                        match self.polling_type {
+                            PollingType::AudioSamples(_) => {self.polling_type = PollingType::AudioSamples(value as u32)},
+                            _ => {}
+                        }
+                        self.apply_scroll_speed_pps();
+                    },
+                    "piano_roll.reference_frequency" => {self.set_reference_frequency(value as f32)},
+                    "piano_roll.scroll_speed_pps" => {self.set_scroll_speed_pps(value as f32)},
                     "piano_roll.scale_factor" => {self.scale = value as u32},
-                    "piano_roll.speed_multiplier" => {self.speed_multiplier = value as u32},
+                    // scroll_speed_pps, if set, takes priority
+                    "piano_roll.speed_multiplier" => {
+                        self.speed_multiplier = value as u32;
+                        self.scroll_speed = value as f32;
+                        self.apply_scroll_speed_pps();
+                    },
                     "piano_roll.starting_octave" => {self.set_starting_octave(value as u32)},
                     "piano_roll.waveform_height" => {self.surfboard_height = value as u32},
+                    "piano_roll.waveform_rows" => {self.surfboard_rows = (value as u32).max(1)},
                     "piano_roll.oscilloscope_glow_thickness" => {self.surfboard_glow_thickness = value as f32},
                     "piano_roll.oscilloscope_line_thickness" => {self.surfboard_line_thickness = value as f32},
                     "piano_roll.outline_thickness" => {self.outline_thickness = value as u32},
                     "piano_roll.divider_width" => {self.divider_width = value as u32},
-                    _ => {}
+                    "piano_roll.export_scale" => {self.export_scale = (value as u32).max(1)},
+                    _ => {return false;}
                 }
             },
 
@@ -1649,21 +3566,49 @@ impl Panel for PianoRollWindow {
                 match path.as_str() {
                     "piano_roll.oscilloscope_glow_thickness" => {self.surfboard_glow_thickness = value as f32},
                     "piano_roll.oscilloscope_line_thickness" => {self.surfboard_line_thickness = value as f32},
-                    _ => {}
+                    // Fractional speeds only make sense with smooth scrolling; otherwise round
+                    "piano_roll.speed_multiplier" => {
+                        self.speed_multiplier = (value.round() as u32).max(1);
+                        self.scroll_speed = value as f32;
+                        self.apply_scroll_speed_pps();
+                    },
+                    "piano_roll.scroll_speed_pps" => {self.set_scroll_speed_pps(value as f32)},
+                    "piano_roll.tempo_bpm" => {self.tempo_bpm = value as f32},
+                    "piano_roll.pitch_smoothing_threshold" => {self.pitch_smoothing_threshold = value as f32},
+                    "piano_roll.surfboard_persistence" => {self.surfboard_persistence = value as f32},
+                    "piano_roll.key_thickness_f" => {self.set_key_thickness_f(value as f32)},
+                    "piano_roll.reference_frequency" => {self.set_reference_frequency(value as f32)},
+                    _ => {return false;}
                 }
             },
 
             Event::ApplyStringSetting(path, value) => {
                 let components = path.split(".").collect::<Vec<&str>>();
-                if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" {
+                if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" && components[4] == "trigger" {
+                    match TriggerMode::from_setting(&value) {
+                        Some(mode) => {self.surfboard_trigger.insert((components[2].to_string(), components[3].to_string()), mode);},
+                        None => {logging::warn(&format!("Invalid trigger mode {} for channel {}, ignoring.", value, components[3]));}
+                    }
+                } else if components.len() == 5 && components[0] == "piano_roll" && components[1] == "settings" {
                     self.apply_color_string(components[2], components[3], components[4], value);
                 } else {
                     match path.as_str() {
+                        "piano_roll.key_size" => {
+                            match value.as_str() {
+                                "auto" => {self.set_key_size(None)},
+                                "small" => {self.set_key_size(Some(KeySize::Small))},
+                                "medium" => {self.set_key_size(Some(KeySize::Medium))},
+                                "large" => {self.set_key_size(Some(KeySize::Large))},
+                                _ => {
+                                    logging::warn(&format!("Invalid key size {}, ignoring.", value));
+                                }
+                            }
+                        },
                         "piano_roll.background_color" => {
                             match Color::from_string(&value) {
                                 Ok(color) => {self.background_color = color},
                                 Err(_) => {
-                                    println!("Warning: Invalid color string {}, ignoring.", value);
+                                    logging::warn(&format!("Invalid color string {}, ignoring.", value));
                                 }
                             }
                         },
@@ -1671,7 +3616,138 @@ impl Panel for PianoRollWindow {
                             match Color::from_string(&value) {
                                 Ok(color) => {self.divider_color = color},
                                 Err(_) => {
-                                    println!("Warning: Invalid color string {}, ignoring.", value);
+                                    logging::warn(&format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        // An empty string turns the outline off
+                        "piano_roll.note_outline" => {
+                            if value.len() == 0 {
+                                self.note_outline = None;
+                            } else {
+                                match Color::from_string(&value) {
+                                    Ok(color) => {self.note_outline = Some(color)},
+                                    Err(_) => {
+                                        logging::warn(&format!("Invalid color string {}, ignoring.", value));
+                                    }
+                                }
+                            }
+                        },
+                        // An empty string goes back to the default muted look
+                        "piano_roll.muted_color" => {
+                            if value.len() == 0 {
+                                self.muted_color = None;
+                            } else {
+                                match Color::from_string(&value) {
+                                    Ok(color) => {self.muted_color = Some(color)},
+                                    Err(_) => {
+                                        logging::warn(&format!("Invalid color string {}, ignoring.", value));
+                                    }
+                                }
+                            }
+                        },
+                        "piano_roll.velocity_style" => {
+                            match value.as_str() {
+                                "alpha" => {self.velocity_style = VelocityStyle::Alpha},
+                                "thickness" => {self.velocity_style = VelocityStyle::Thickness},
+                                "saturation" => {self.velocity_style = VelocityStyle::Saturation},
+                                "brightness" => {self.velocity_style = VelocityStyle::Brightness},
+                                _ => {
+                                    logging::warn(&format!("Invalid velocity style {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.polling_type" => {
+                            match value.as_str() {
+                                "frame" => {self.polling_type = PollingType::PpuFrame},
+                                "scanline" => {self.polling_type = PollingType::PpuScanline},
+                                "quarter_frame" => {self.polling_type = PollingType::ApuQuarterFrame},
+                                "half_frame" => {self.polling_type = PollingType::ApuHalfFrame},
+                                "audio_samples" => {self.polling_type = PollingType::AudioSamples(self.audio_samples_per_poll)},
+                                _ => {
+                                    logging::warn(&format!("Invalid polling type {}, ignoring.", value));
+                                }
+                            }
+                            self.last_polled_sample = None;
+                            self.apply_scroll_speed_pps();
+                        },
+                        "piano_roll.scale_kind" => {
+                            match value.as_str() {
+                                "none" => {self.highlight_scale = None},
+                                "major" => {self.highlight_scale = Some((self.scale_root, ScaleKind::Major))},
+                                "minor" => {self.highlight_scale = Some((self.scale_root, ScaleKind::Minor))},
+                                "pentatonic" => {self.highlight_scale = Some((self.scale_root, ScaleKind::Pentatonic))},
+                                "chromatic" => {self.highlight_scale = Some((self.scale_root, ScaleKind::Chromatic))},
+                                _ => {
+                                    logging::warn(&format!("Invalid scale kind {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.aa_quality" => {
+                            match value.as_str() {
+                                "fast" => {self.aa_quality = AaQuality::Fast},
+                                "high" => {self.aa_quality = AaQuality::High},
+                                _ => {
+                                    logging::warn(&format!("Invalid antialiasing quality {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.scope_interpolation" => {
+                            match value.as_str() {
+                                "step" => {self.scope_interpolation = ScopeInterpolation::Step},
+                                "linear" => {self.scope_interpolation = ScopeInterpolation::Linear},
+                                "cubic" => {self.scope_interpolation = ScopeInterpolation::Cubic},
+                                _ => {
+                                    logging::warn(&format!("Invalid scope interpolation {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.surfboard_trigger" => {
+                            match TriggerMode::from_setting(&value) {
+                                Some(mode) => {self.surfboard_trigger_default = mode},
+                                None => {
+                                    logging::warn(&format!("Invalid trigger mode {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.noise_style" => {
+                            match value.as_str() {
+                                "strings" => {self.noise_render_style = NoiseStyle::Strings},
+                                "band" => {self.noise_render_style = NoiseStyle::Band},
+                                _ => {
+                                    logging::warn(&format!("Invalid noise style {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.keyboard.white_key" => {
+                            match Color::from_string(&value) {
+                                Ok(color) => {self.white_key_color = color},
+                                Err(_) => {
+                                    logging::warn(&format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.keyboard.black_key" => {
+                            match Color::from_string(&value) {
+                                Ok(color) => {self.black_key_color = color},
+                                Err(_) => {
+                                    logging::warn(&format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.keyboard.white_key_border" => {
+                            match Color::from_string(&value) {
+                                Ok(color) => {self.white_key_border_color = color},
+                                Err(_) => {
+                                    logging::warn(&format!("Invalid color string {}, ignoring.", value));
+                                }
+                            }
+                        },
+                        "piano_roll.keyboard.top_edge" => {
+                            match Color::from_string(&value) {
+                                Ok(color) => {self.top_edge_color = color},
+                                Err(_) => {
+                                    logging::warn(&format!("Invalid color string {}, ignoring.", value));
                                 }
                             }
                         },
@@ -1679,14 +3755,163 @@ impl Panel for PianoRollWindow {
                             match Color::from_string(&value) {
                                 Ok(color) => {self.outline_color = color},
                                 Err(_) => {
-                                    println!("Warning: Invalid color string {}, ignoring.", value);
+                                    logging::warn(&format!("Invalid color string {}, ignoring.", value));
                                 }
                             }
                         },
-                        _ => {}
+                        _ => {return false;}
                     }    
                 }
             }
+            _ => {return false;}
+        }
+        return true;
+    }
+
+    // Every piano roll setting applied so far, as a TOML document in the same layout as the
+    // settings file, so a preset can also be pasted straight into it
+    pub fn export_config(&self) -> Result<String, String> {
+        let mut preset = SettingsState{root: Value::Table(Map::new())};
+        for (path, value) in self.applied_settings.iter() {
+            preset.ensure_path_exists(path.clone(), value.clone());
+        }
+        return toml::to_string(&preset.root).map_err(|why| format!("Couldn't serialize piano roll preset: {}", why));
+    }
+
+    // Applies a preset from export_config on top of the current configuration. Anything that
+    // isn't a piano roll setting is skipped with a warning, so presets from newer versions
+    // still load. Returns the matching Store*Setting events, so the loaded preset is also
+    // written back to the settings file.
+    pub fn import_config(&mut self, config_str: &str) -> Result<Vec<Event>, String> {
+        let root = match config_str.parse::<Value>() {
+            Ok(root) => root,
+            Err(why) => {return Err(format!("Invalid piano roll preset: {}", why));}
+        };
+        let mut store_events: Vec<Event> = Vec::new();
+        for event in SettingsState::settings_events(root) {
+            let (path, store_event) = match &event {
+                Event::ApplyBooleanSetting(path, value) => (path.clone(), Event::StoreBooleanSetting(path.clone(), *value)),
+                Event::ApplyIntegerSetting(path, value) => (path.clone(), Event::StoreIntegerSetting(path.clone(), *value)),
+                Event::ApplyFloatSetting(path, value) => (path.clone(), Event::StoreFloatSetting(path.clone(), *value)),
+                Event::ApplyStringSetting(path, value) => (path.clone(), Event::StoreStringSetting(path.clone(), value.clone())),
+                _ => {continue;}
+            };
+            if !path.starts_with("piano_roll.") || !self.apply_setting(event) {
+                logging::warn(&format!("Unknown piano roll setting {} in preset, ignoring.", path));
+                continue;
+            }
+            store_events.push(store_event);
+        }
+        return Ok(store_events);
+    }
+}
+
+impl Panel for PianoRollWindow {
+    fn title(&self) -> &str {
+        return "Piano Roll";
+    }
+
+    fn shown(&self) -> bool {
+        return self.shown;
+    }
+
+    fn scale_factor(&self) -> u32 {
+        return self.scale;
+    }
+
+    fn handle_event(&mut self, runtime: &RuntimeState, event: Event) -> Vec<Event> {
+        let mut events: Vec<Event> = Vec::new();
+        match event {
+            Event::NesNewFrame => {
+                if self.polling_type == PollingType::PpuFrame {
+                    self.poll(runtime);
+                }
+            },
+            // Scanlines are frequent enough to keep the sample clock accurate to within a few
+            // samples, without an event for every batch the APU produces
+            Event::NesNewScanline => {
+                match self.polling_type {
+                    PollingType::PpuScanline => {self.poll(runtime)},
+                    PollingType::AudioSamples(_) => {self.poll_audio_samples(runtime)},
+                    _ => {}
+                }
+            },
+            Event::NesNewApuQuarterFrame => {
+                if self.polling_type == PollingType::ApuQuarterFrame {
+                    self.poll(runtime);
+                }
+            },
+            Event::NesNewApuHalfFrame => {
+                if self.polling_type == PollingType::ApuHalfFrame {
+                    self.poll(runtime);
+                }
+            },
+            Event::MouseClick(x, y) => {events.extend(self.mouse_click(runtime, x, y));},
+            Event::RequestFrame => {self.draw(runtime)},
+            Event::ShowPianoRollWindow => {self.shown = true},
+            Event::CartridgeLoaded(_) => {self.reset_all_channel_visualization()},
+            Event::CenterOnActiveNotes => {
+                let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+                self.center_on_active_notes(&channels);
+            },
+            Event::ResetChannelVisualization(chip_name, channel_name) => {
+                let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+                self.reset_channel_visualization(&channels, &chip_name, &channel_name);
+            },
+            Event::PlayTestNote(channel_index, midi_index, velocity) => {
+                let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+                self.play_test_note(&channels, channel_index, midi_index, velocity, None);
+            },
+            Event::TextInput(text) => {
+                let channels = self.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+                for c in text.chars() {
+                    match c.to_digit(10) {
+                        Some(digit) if self.digit_key_muting && digit > 0 => {
+                            events.extend(self.toggle_channel_mute(&channels, digit as usize - 1));
+                            continue;
+                        },
+                        _ => {}
+                    }
+                    if self.synthetic_input {
+                        match PianoRollWindow::typed_test_note(c) {
+                            Some(midi_index) => {self.play_test_note(&channels, 0, midi_index, 1.0, Some(30));},
+                            None => {}
+                        }
+                    }
+                }
+            },
+            Event::PianoRollExportCsv(path) => {
+                match self.export_csv(&path) {
+                    Ok(_) => {logging::info(&format!("Wrote note history to {}", path));},
+                    Err(why) => {logging::warn(&format!("{}", why));}
+                }
+            },
+            Event::PianoRollExportStats(path) => {
+                match self.export_stats(&path) {
+                    Ok(_) => {logging::info(&format!("Wrote channel stats to {}", path));},
+                    Err(why) => {logging::warn(&format!("{}", why));}
+                }
+            },
+            Event::SavePianoRollPreset(path) => {
+                match self.export_config().and_then(|preset| fs::write(&path, preset).map_err(|why| why.to_string())) {
+                    Ok(_) => {logging::info(&format!("Wrote piano roll preset to {}", path));},
+                    Err(why) => {logging::warn(&format!("Couldn't write piano roll preset to {}: {}", path, why));}
+                }
+            },
+            Event::LoadPianoRollPreset(path) => {
+                match fs::read_to_string(&path).map_err(|why| why.to_string()).and_then(|preset| self.import_config(&preset)) {
+                    Ok(store_events) => {
+                        logging::info(&format!("Loaded piano roll preset from {}", path));
+                        events.extend(store_events);
+                    },
+                    Err(why) => {logging::warn(&format!("Couldn't load piano roll preset from {}: {}", path, why));}
+                }
+            },
+            Event::CloseWindow => {self.shown = false},
+
+            Event::ApplyBooleanSetting(_, _) | Event::ApplyIntegerSetting(_, _) | Event::ApplyFloatSetting(_, _) | Event::ApplyStringSetting(_, _) => {
+                let _ = self.apply_setting(event);
+            },
             _ => {}
         }
         return events;
@@ -1695,4 +3920,1694 @@ impl Panel for PianoRollWindow {
     fn active_canvas(&self) -> &SimpleBuffer {
         return &self.canvas;
     }
-}
\ No newline at end of file
+
+    // The test note keys cover most of the letters, so they'd trip pause, save, etc. otherwise
+    fn has_keyboard_focus(&self) -> bool {
+        return self.synthetic_input;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use application::RuntimeState;
+    use std::env;
+    use rustico_core::mmc::vrc6::Vrc6SawtoothChannel;
+    use rustico_core::mmc::mapper::Mirroring;
+    use std::sync::Mutex;
+
+    fn count_pixels(canvas: &SimpleBuffer, r: u8, g: u8, b: u8) -> usize {
+        let mut count = 0;
+        for x in 0 .. canvas.width {
+            for y in 0 .. canvas.height {
+                let pixel = canvas.get_pixel(x, y);
+                if pixel.r() == r && pixel.g() == g && pixel.b() == b {
+                    count += 1;
+                }
+            }
+        }
+        return count;
+    }
+
+    // Stands in for a real channel, reporting whatever a test needs it to
+    struct FakeChannel {
+        chip: String,
+        name: String,
+        rate: PlaybackRate,
+        estimated_frequency: Option<f32>,
+        buffer: RingBuffer,
+        muted: bool,
+        amplitude: f32,
+    }
+
+    impl FakeChannel {
+        fn new(chip: &str, name: &str, rate: PlaybackRate) -> FakeChannel {
+            return FakeChannel {
+                chip: chip.to_string(),
+                name: name.to_string(),
+                rate: rate,
+                estimated_frequency: None,
+                buffer: RingBuffer::new(1024),
+                muted: false,
+                amplitude: 1.0,
+            };
+        }
+    }
+
+    impl AudioChannelState for FakeChannel {
+        fn name(&self) -> String {return self.name.clone();}
+        fn chip(&self) -> String {return self.chip.clone();}
+        fn sample_buffer(&self) -> &RingBuffer {return &self.buffer;}
+        fn edge_buffer(&self) -> &RingBuffer {return &self.buffer;}
+        fn record_current_output(&mut self) {}
+        fn muted(&self) -> bool {return self.muted;}
+        fn mute(&mut self) {self.muted = true;}
+        fn unmute(&mut self) {self.muted = false;}
+        fn playing(&self) -> bool {return true;}
+        fn rate(&self) -> PlaybackRate {return self.rate.clone();}
+        fn estimated_frequency(&self) -> Option<f32> {return self.estimated_frequency;}
+        fn amplitude(&self) -> f32 {return self.amplitude;}
+    }
+
+    // Draws one frame of the piano roll against the built in no-cart ROM
+    fn render(piano_roll: &mut PianoRollWindow, runtime: &RuntimeState) {
+        piano_roll.handle_event(runtime, Event::RequestFrame);
+    }
+
+    // Spread between the strongest and weakest component, a cheap stand-in for saturation
+    fn saturation(color: Color) -> u8 {
+        let components = [color.r(), color.g(), color.b()];
+        return components.iter().max().unwrap() - components.iter().min().unwrap();
+    }
+
+    #[test]
+    fn surfboard_grid_with_two_rows() {
+        // 8 channels over 2 rows is 4 columns; 400x100 gives 100x50 cells
+        assert_eq!(surfboard_cell(0, 8, 2, 0, 0, 400, 100), (0, 0, 100, 50));
+        assert_eq!(surfboard_cell(3, 8, 2, 0, 0, 400, 100), (300, 0, 100, 50));
+        assert_eq!(surfboard_cell(4, 8, 2, 0, 0, 400, 100), (0, 50, 100, 50));
+        assert_eq!(surfboard_cell(7, 8, 2, 10, 20, 400, 100), (310, 70, 100, 50));
+    }
+
+    #[test]
+    fn surfboard_leftover_pixels_go_to_the_first_cells() {
+        assert_eq!(surfboard_cell(0, 3, 1, 0, 0, 100, 10), (0, 0, 34, 10));
+        assert_eq!(surfboard_cell(1, 3, 1, 0, 0, 100, 10), (34, 0, 33, 10));
+        assert_eq!(surfboard_cell(2, 3, 1, 0, 0, 100, 10), (67, 0, 33, 10));
+    }
+
+    #[test]
+    fn surfboard_click_in_second_row_hits_the_right_channel() {
+        assert_eq!(surfboard_cell_at(150, 75, 8, 2, 0, 0, 400, 100), Some(5));
+        assert_eq!(surfboard_cell_at(150, 25, 8, 2, 0, 0, 400, 100), Some(1));
+        assert_eq!(surfboard_cell_at(399, 99, 8, 2, 0, 0, 400, 100), Some(7));
+        assert_eq!(surfboard_cell_at(150, 100, 8, 2, 0, 0, 400, 100), None);
+    }
+
+    #[test]
+    fn saturation_style_fades_quiet_notes_to_grey() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.velocity_style = VelocityStyle::Saturation;
+        let channel_color = Color::rgb(224, 32, 32);
+        let (quiet, _, _) = piano_roll.apply_velocity_style(channel_color, 0.2);
+        let (loud, _, _) = piano_roll.apply_velocity_style(channel_color, 1.0);
+        assert!(saturation(quiet) < saturation(loud));
+        assert_eq!(saturation(loud), saturation(channel_color));
+    }
+
+    #[test]
+    fn white_key_color_setting_changes_the_keyboard() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        render(&mut piano_roll, &runtime);
+        assert_eq!(count_pixels(&piano_roll.canvas, 255, 0, 255), 0);
+
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.keyboard.white_key".to_string(), "#ff00ff".to_string()));
+        render(&mut piano_roll, &runtime);
+        assert!(count_pixels(&piano_roll.canvas, 255, 0, 255) > 0);
+    }
+
+    #[test]
+    fn dmc_as_pitch_places_looping_samples_by_frequency() {
+        let mut dmc = FakeChannel::new("2A03", "DMC", PlaybackRate::SampleRate{frequency: 33143.9});
+        dmc.estimated_frequency = Some(243.7);
+        let mut piano_roll = PianoRollWindow::new();
+
+        let slice = piano_roll.slice_from_channel(&dmc);
+        assert!(slice.note_type == NoteType::Waveform);
+
+        piano_roll.dmc_as_pitch = true;
+        let slice = piano_roll.slice_from_channel(&dmc);
+        assert!(slice.note_type == NoteType::Frequency);
+        assert!((slice.y - piano_roll.frequency_to_coordinate(243.7)).abs() < 0.01);
+
+        // Unreliable estimates fall back to the waveform string
+        dmc.estimated_frequency = None;
+        let slice = piano_roll.slice_from_channel(&dmc);
+        assert!(slice.note_type == NoteType::Waveform);
+    }
+
+    #[test]
+    fn zero_key_thickness_falls_back_to_a_usable_size() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.key_thickness".to_string(), 0));
+        assert!(piano_roll.key_thickness > 0);
+        render(&mut piano_roll, &runtime);
+    }
+
+    // Pulls the literal "piano_roll.*" paths out of one of apply_setting's match arms
+    fn apply_setting_event(path: &str, value: &Value) -> Event {
+        return match value {
+            &Value::Boolean(value) => Event::ApplyBooleanSetting(path.to_string(), value),
+            &Value::Integer(value) => Event::ApplyIntegerSetting(path.to_string(), value),
+            &Value::Float(value) => Event::ApplyFloatSetting(path.to_string(), value),
+            &Value::String(ref value) => Event::ApplyStringSetting(path.to_string(), value.clone()),
+            _ => panic!("{} has a default of an unsupported type", path)
+        };
+    }
+
+    #[test]
+    fn every_registered_setting_is_applied() {
+        let mut piano_roll = PianoRollWindow::new();
+        let mut checked = 0;
+        for description in known_settings().into_iter().filter(|description| description.path.starts_with("piano_roll.")) {
+            let event = apply_setting_event(&description.path, &description.default_value);
+            assert!(piano_roll.apply_setting(event), "{} is registered as {:?}, which apply_setting doesn't handle", description.path, description.setting_type);
+            assert_eq!(piano_roll.applied_settings.get(&description.path), Some(&description.default_value));
+            checked += 1;
+        }
+        assert!(checked > 20);
+    }
+
+    #[test]
+    fn unknown_settings_are_not_applied() {
+        let mut piano_roll = PianoRollWindow::new();
+        assert!(!piano_roll.apply_setting(Event::ApplyBooleanSetting("piano_roll.not_a_real_setting".to_string(), true)));
+        assert!(!piano_roll.apply_setting(Event::ApplyIntegerSetting("piano_roll.draw_text_labels".to_string(), 1)));
+        assert!(piano_roll.applied_settings.is_empty());
+    }
+
+    #[test]
+    fn registry_reports_types_defaults_and_ranges() {
+        let key_thickness = describe_setting("piano_roll.key_thickness").unwrap();
+        assert_eq!(key_thickness.setting_type, SettingType::Integer);
+        assert_eq!(key_thickness.range, Some((0.0, 64.0)));
+        let draw_text_labels = describe_setting("piano_roll.draw_text_labels").unwrap();
+        assert_eq!(draw_text_labels.setting_type, SettingType::Boolean);
+        assert_eq!(draw_text_labels.default_value, Value::Boolean(true));
+        assert!(describe_setting("piano_roll.not_a_real_setting").is_none());
+    }
+
+    // A single wide red note, polled `age` slices ago, on an otherwise empty roll
+    fn roll_with_one_note(age: usize) -> PianoRollWindow {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.scroll_direction = ScrollDirection::LeftToRight;
+        piano_roll.time_slices.clear();
+        for i in 0 .. 10 {
+            let mut note = ChannelSlice::none();
+            if i == age {
+                note.visible = true;
+                note.y = 2.0;
+                note.thickness = 6.0;
+                note.color = Color::rgb(250, 0, 0);
+            }
+            piano_roll.time_slices.push_back(vec!(note));
+        }
+        return piano_roll;
+    }
+
+    #[test]
+    fn half_pixel_offset_splits_a_slice_across_two_columns() {
+        let runtime = RuntimeState::new();
+        let key_width = 16;
+
+        let mut piano_roll = roll_with_one_note(4);
+        render(&mut piano_roll, &runtime);
+        let note_y = (piano_roll.canvas.height - 32) - (2.0 * piano_roll.key_thickness_f) as u32;
+        let background = piano_roll.canvas.get_pixel(key_width + 3, note_y);
+        let own_column = piano_roll.canvas.get_pixel(key_width + 4, note_y);
+        assert_eq!((own_column.r(), own_column.g(), own_column.b()), (250, 0, 0));
+
+        let mut piano_roll = roll_with_one_note(4);
+        piano_roll.smooth_scroll = true;
+        piano_roll.scroll_remainder = 0.5;
+        render(&mut piano_roll, &runtime);
+        let expected = lerp_colors(background, Color::rgb(250, 0, 0), 0.5);
+        for &x in [key_width + 4, key_width + 5].iter() {
+            let pixel = piano_roll.canvas.get_pixel(x, note_y);
+            assert!((pixel.r() as i32 - expected.r() as i32).abs() <= 1, "column {} is {}", x, pixel.r());
+            assert!((pixel.g() as i32 - expected.g() as i32).abs() <= 1);
+        }
+        // Nothing leaks the other way, towards the keys
+        let newer_column = piano_roll.canvas.get_pixel(key_width + 3, note_y);
+        assert_eq!((newer_column.r(), newer_column.g(), newer_column.b()), (background.r(), background.g(), background.b()));
+    }
+
+    #[test]
+    fn smooth_scroll_keeps_the_newest_slice_solid_against_the_keys() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = roll_with_one_note(0);
+        piano_roll.smooth_scroll = true;
+        piano_roll.scroll_remainder = 0.5;
+        render(&mut piano_roll, &runtime);
+        let note_y = (piano_roll.canvas.height - 32) - (2.0 * piano_roll.key_thickness_f) as u32;
+        let now_column = piano_roll.canvas.get_pixel(16, note_y);
+        assert_eq!((now_column.r(), now_column.g(), now_column.b()), (250, 0, 0));
+    }
+
+    #[test]
+    fn held_notes_stay_solid_under_a_fractional_offset() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = roll_with_one_note(4);
+        let held = piano_roll.time_slices[4].clone();
+        piano_roll.time_slices[3] = held;
+        piano_roll.smooth_scroll = true;
+        piano_roll.scroll_remainder = 0.25;
+        render(&mut piano_roll, &runtime);
+        let note_y = (piano_roll.canvas.height - 32) - (2.0 * piano_roll.key_thickness_f) as u32;
+        let pixel = piano_roll.canvas.get_pixel(16 + 4, note_y);
+        assert_eq!((pixel.r(), pixel.g(), pixel.b()), (250, 0, 0));
+    }
+
+    fn count_pixels_in(canvas: &SimpleBuffer, color: Color, x: u32, y: u32, width: u32, height: u32) -> usize {
+        let mut count = 0;
+        for px in x .. x + width {
+            for py in y .. y + height {
+                let pixel = canvas.get_pixel(px, py);
+                if pixel.r() == color.r() && pixel.g() == color.g() && pixel.b() == color.b() {
+                    count += 1;
+                }
+            }
+        }
+        return count;
+    }
+
+    #[test]
+    fn surfboard_label_lands_in_its_own_cell() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.canvas = SimpleBuffer::new(400, 100);
+        let channel = FakeChannel::new("VRC6", "Sawtooth", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        let label_color = piano_roll.configured_channel_colors(&channel)[0];
+
+        // Second cell of a four across grid
+        let (cx, cy, cell_width, cell_height) = surfboard_cell(1, 4, 1, 0, 0, 400, 100);
+        piano_roll.draw_compact_channel_label(&channel, cx, cy, cell_width, cell_height);
+        assert!(count_pixels_in(&piano_roll.canvas, label_color, cx, cy, cell_width, 12) > 0);
+        assert_eq!(count_pixels_in(&piano_roll.canvas, label_color, 0, 0, cx, 100), 0);
+        assert_eq!(count_pixels_in(&piano_roll.canvas, label_color, cx + cell_width, 0, 400 - cx - cell_width, 100), 0);
+        // The label sits in the top corner, leaving the middle of the trace alone
+        assert_eq!(count_pixels_in(&piano_roll.canvas, label_color, cx, cy + 12, cell_width, cell_height - 12), 0);
+    }
+
+    #[test]
+    fn muted_surfboard_labels_are_dimmed() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.canvas = SimpleBuffer::new(400, 100);
+        let mut channel = FakeChannel::new("VRC6", "Sawtooth", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        channel.muted = true;
+        let label_color = piano_roll.configured_channel_colors(&channel)[0];
+        piano_roll.draw_compact_channel_label(&channel, 0, 0, 100, 100);
+        assert_eq!(count_pixels_in(&piano_roll.canvas, label_color, 0, 0, 100, 100), 0);
+        let dimmed = PianoRollWindow::scale_color(label_color, 0.35);
+        assert!(count_pixels_in(&piano_roll.canvas, dimmed, 0, 0, 100, 100) > 0);
+    }
+
+    #[test]
+    fn empty_gradient_falls_back_to_a_default_color() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        for chip_settings in piano_roll.channel_settings.values_mut() {
+            for channel_settings in chip_settings.values_mut() {
+                channel_settings.colors.clear();
+            }
+        }
+        let channel = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        let colors = piano_roll.channel_colors(&channel);
+        assert_eq!(colors.len(), 1);
+        let fallback = unknown_channel_color("2A03", "Pulse 1");
+        assert_eq!((colors[0].r(), colors[0].g(), colors[0].b()), (fallback.r(), fallback.g(), fallback.b()));
+
+        // Polling and drawing every real channel with nothing configured shouldn't panic
+        piano_roll.handle_event(&runtime, Event::Update);
+        render(&mut piano_roll, &runtime);
+    }
+
+    fn count_lit_pixels(canvas: &SimpleBuffer) -> usize {
+        return canvas.buffer.chunks(4).filter(|pixel| pixel[3] != 0).count();
+    }
+
+    #[test]
+    fn band_mode_spreads_noise_over_more_pixels() {
+        let channel = FakeChannel::new("2A03", "Noise", PlaybackRate::LfsrRate{index: 8, max: 0xF});
+        let mut piano_roll = PianoRollWindow::new();
+
+        let strings_slice = piano_roll.slice_from_channel(&channel);
+        piano_roll.noise_render_style = NoiseStyle::Band;
+        let band_slice = piano_roll.slice_from_channel(&channel);
+        assert!(band_slice.thickness > strings_slice.thickness);
+        assert!(band_slice.color.alpha() < strings_slice.color.alpha());
+
+        let mut strings_canvas = SimpleBuffer::new(4, 400);
+        let mut band_canvas = SimpleBuffer::new(4, 400);
+        let key_height = piano_roll.key_thickness_f;
+        PianoRollWindow::draw_slice_horiz(&mut strings_canvas, &strings_slice, 1, 399, key_height, None, AaQuality::Fast);
+        PianoRollWindow::draw_slice_horiz(&mut band_canvas, &band_slice, 1, 399, key_height, None, AaQuality::Fast);
+        assert!(count_lit_pixels(&strings_canvas) > 0);
+        assert!(count_lit_pixels(&band_canvas) > count_lit_pixels(&strings_canvas));
+    }
+
+    fn channel_playing_a_tone(frequency: f32, sample_rate: f32) -> FakeChannel {
+        let mut channel = FakeChannel::new("N163", "NAMCO 1", PlaybackRate::FundamentalFrequency{frequency: frequency});
+        for i in 0 .. 1024 {
+            let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate;
+            channel.buffer.push((phase.sin() * 8000.0) as i16);
+        }
+        return channel;
+    }
+
+    #[test]
+    fn detected_pitch_of_a_pure_tone_lands_on_the_reported_rate() {
+        let mut piano_roll = PianoRollWindow::new();
+        let channel = channel_playing_a_tone(880.0, 44100.0);
+        let reported_y = piano_roll.slice_from_channel(&channel).y;
+
+        piano_roll.use_detected_pitch = true;
+        piano_roll.update_detected_pitches(&vec!(&channel as &dyn AudioChannelState), 44100.0);
+        let detected = piano_roll.detected_pitch(&channel).unwrap();
+        assert!((detected - 880.0).abs() < 880.0 * 0.01, "detected {} Hz", detected);
+        let detected_y = piano_roll.slice_from_channel(&channel).y;
+        let semitone = (piano_roll.frequency_to_coordinate(880.0 * 2.0f32.powf(1.0 / 12.0)) - reported_y).abs();
+        assert!((detected_y - reported_y).abs() < semitone * 0.25,
+            "detected placement {} vs reported {}", detected_y, reported_y);
+    }
+
+    #[test]
+    fn unclear_pitch_falls_back_to_the_reported_rate() {
+        let mut piano_roll = PianoRollWindow::new();
+        // Silence has no peak to speak of
+        let channel = FakeChannel::new("N163", "NAMCO 1", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        let reported_y = piano_roll.slice_from_channel(&channel).y;
+
+        piano_roll.use_detected_pitch = true;
+        piano_roll.update_detected_pitches(&vec!(&channel as &dyn AudioChannelState), 44100.0);
+        assert_eq!(piano_roll.detected_pitch(&channel), None);
+        assert_eq!(piano_roll.slice_from_channel(&channel).y, reported_y);
+    }
+
+    #[test]
+    fn louder_samples_pick_a_brighter_gradient_color() {
+        let piano_roll = PianoRollWindow::new();
+        let mut dmc = FakeChannel::new("2A03", "DMC", PlaybackRate::SampleRate{frequency: 33143.9});
+
+        dmc.amplitude = 0.1;
+        let quiet = piano_roll.slice_from_channel(&dmc);
+        dmc.amplitude = 0.9;
+        let loud = piano_roll.slice_from_channel(&dmc);
+
+        assert!(quiet.note_type == NoteType::Waveform);
+        assert!(loud.note_type == NoteType::Waveform);
+        // The default DMC ramp runs from a dark purple up to a pale one
+        assert!(loud.color.r() > quiet.color.r());
+        assert!(loud.color.g() > quiet.color.g());
+        assert!(loud.color.b() > quiet.color.b());
+    }
+
+    fn resized_roll(runtime: &RuntimeState, width: i64, height: i64) -> PianoRollWindow {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(runtime, Event::ApplyIntegerSetting("piano_roll.canvas_width".to_string(), width));
+        piano_roll.handle_event(runtime, Event::ApplyIntegerSetting("piano_roll.canvas_height".to_string(), height));
+        return piano_roll;
+    }
+
+    #[test]
+    fn small_canvases_get_proportionally_smaller_keys() {
+        let runtime = RuntimeState::new();
+        let small = resized_roll(&runtime, 480, 270);
+        let large = resized_roll(&runtime, 1920, 1080);
+        assert_eq!((large.canvas.width, large.canvas.height), (1920, 1080));
+        assert_eq!((small.canvas.width, small.canvas.height), (480, 270));
+
+        // A quarter of the size on each axis, give or take rounding
+        assert!(small.key_thickness < large.key_thickness);
+        assert!(small.key_length < large.key_length);
+        assert!((small.key_length as f32 - large.key_length as f32 / 4.0).abs() <= 1.0);
+        assert!((small.key_thickness as f32 - large.key_thickness as f32 / 4.0).abs() <= 1.0);
+    }
+
+    #[test]
+    fn explicit_key_geometry_survives_a_resize() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.key_length".to_string(), 50));
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.canvas_width".to_string(), 480));
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.canvas_height".to_string(), 270));
+        assert_eq!(piano_roll.key_length, 50);
+
+        // Going back to 0 hands it back to the canvas size
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.key_length".to_string(), 0));
+        assert_eq!(piano_roll.key_length, default_key_geometry(480, 270).1);
+    }
+
+    #[test]
+    fn beat_lines_fall_at_the_computed_pixel_interval() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.polling_type = PollingType::PpuFrame;
+        piano_roll.speed_multiplier = 1;
+        piano_roll.smooth_scroll = false;
+        piano_roll.tempo_bpm = 120.0;
+        piano_roll.grid_subdivision = 1;
+
+        // ~60.1 polls a second at one pixel each, half a second per beat: not a whole number
+        let frame_rate = 1789773.0 / 29780.5;
+        let pixels_per_beat = frame_rate * 60.0 / 120.0;
+        assert!((piano_roll.grid_step().unwrap() - pixels_per_beat).abs() < 1e-9);
+
+        let lines: Vec<u64> = (0 .. 400).filter(|&slice| piano_roll.grid_line_color(slice).is_some()).collect();
+        let expected: Vec<u64> = (0 .. 14).map(|beat| (beat as f64 * pixels_per_beat).round() as u64).filter(|&slice| slice < 400).collect();
+        assert_eq!(lines, expected);
+
+        // Every fourth beat starts a bar, and is drawn brighter
+        let bar = piano_roll.grid_line_color(expected[4]).unwrap();
+        let beat = piano_roll.grid_line_color(expected[1]).unwrap();
+        assert!(bar.alpha() > beat.alpha());
+    }
+
+    #[test]
+    fn zero_tempo_turns_the_grid_off() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.tempo_bpm = 0.0;
+        assert!(piano_roll.grid_step().is_none());
+        assert!(piano_roll.grid_line_color(0).is_none());
+    }
+
+    #[test]
+    fn note_ends_fade_in_and_out_at_the_run_boundaries() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.time_slices.clear();
+        // One note held from age 9 (its start) to age 2 (its release), silence around it
+        for age in 0 .. 12 {
+            let mut note = ChannelSlice::none();
+            if age >= 2 && age <= 9 {
+                note.visible = true;
+                note.y = 10.0;
+                note.thickness = 6.0;
+                note.color = Color::rgb(250, 0, 0);
+            }
+            piano_roll.time_slices.push_back(vec!(note));
+        }
+        piano_roll.note_end_fade = 2;
+
+        let weights: Vec<f32> = (2 ..= 9).map(|age| piano_roll.note_end_fade_weight(age, 0)).collect();
+        let third = 1.0 / 3.0;
+        assert_eq!(weights, vec!(third, 2.0 * third, 1.0, 1.0, 1.0, 1.0, 2.0 * third, third));
+
+        // The slices actually drawn pick that up as transparency
+        let alpha = |age: usize| piano_roll.prepared_notes(age, false)[0].color.alpha();
+        assert_eq!(alpha(5), 255);
+        assert!(alpha(3) < 255);
+        assert!(alpha(2) < alpha(3));
+        assert!(alpha(9) < alpha(8));
+
+        piano_roll.note_end_fade = 0;
+        assert_eq!(piano_roll.note_end_fade_weight(2, 0), 1.0);
+    }
+
+    #[test]
+    fn test_note_c4_lands_on_middle_c() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.time_slices.clear();
+        piano_roll.handle_event(&runtime, Event::PlayTestNote(0, 60, 1.0));
+
+        let newest = &piano_roll.time_slices[0];
+        assert!(newest[0].visible);
+        let middle_c = piano_roll.frequency_to_coordinate(261.6256);
+        assert!((newest[0].y - middle_c).abs() < 0.01, "C4 drawn at {}, expected {}", newest[0].y, middle_c);
+
+        // Velocity 0 lets it go
+        piano_roll.handle_event(&runtime, Event::PlayTestNote(0, 60, 0.0));
+        assert!(!piano_roll.time_slices[0][0].visible);
+    }
+
+    #[test]
+    fn typed_test_notes_keep_the_keyboard() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        assert!(!piano_roll.has_keyboard_focus());
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.synthetic_input".to_string(), true));
+        assert!(piano_roll.has_keyboard_focus());
+
+        // "s" is a global shortcut (save SRAM), but here it's D4
+        piano_roll.time_slices.clear();
+        piano_roll.handle_event(&runtime, Event::TextInput("s".to_string()));
+        let d4 = piano_roll.frequency_to_coordinate(midi_frequency(62));
+        assert!((piano_roll.time_slices[0][0].y - d4).abs() < 0.01);
+    }
+
+    #[test]
+    fn surfboard_shows_the_roll_channels_plus_the_mix() {
+        let runtime = RuntimeState::new();
+        let piano_roll = PianoRollWindow::new();
+        let roll = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        let surfboard = piano_roll.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+
+        let names = |channels: &Vec<&dyn AudioChannelState>| channels.iter().map(|channel| format!("{}/{}", channel.chip(), channel.name())).collect::<Vec<String>>();
+        let roll_names = names(&roll);
+        let surfboard_names = names(&surfboard);
+
+        // Same voices in the same order, so cell i and roll channel i always agree
+        assert_eq!(surfboard_names.len(), roll_names.len() + 1);
+        assert_eq!(&surfboard_names[.. roll_names.len()], &roll_names[..]);
+        let mix = surfboard.last().unwrap();
+        assert_eq!((mix.chip(), mix.name()), (runtime.nes.apu.chip(), runtime.nes.apu.name()));
+        assert!(!roll_names.contains(&format!("{}/{}", mix.chip(), mix.name())));
+    }
+
+    // Bounding box of every pixel that isn't the empty canvas, as (left, top, right, bottom)
+    fn drawn_bounds(canvas: &SimpleBuffer) -> Option<(u32, u32, u32, u32)> {
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for x in 0 .. canvas.width {
+            for y in 0 .. canvas.height {
+                if canvas.get_pixel(x, y).r() != 0 {
+                    bounds = Some(match bounds {
+                        Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+                        None => (x, y, x, y)
+                    });
+                }
+            }
+        }
+        return bounds;
+    }
+
+    #[test]
+    fn speaker_glyph_stays_inside_its_key_at_every_size() {
+        for key_size in [KeySize::Small, KeySize::Medium].iter() {
+            let (key_thickness, _) = key_size.dimensions();
+            let mut canvas = SimpleBuffer::new(64, 64);
+            // The key is centered on y = 32 and spans the 16 pixel deep keyboard from x = 10
+            draw_speaker_key_horiz(&mut canvas, Color::rgb(255, 0, 0), 10, 32, key_thickness);
+
+            let (left, top, right, bottom) = drawn_bounds(&canvas).unwrap();
+            assert!(left >= 10 && right < 10 + 16, "{}px glyph spans x {} - {}", key_thickness, left, right);
+            assert!(top >= 32 - key_thickness / 2 && bottom < 32 + key_thickness / 2,
+                "{}px glyph spans y {} - {}", key_thickness, top, bottom);
+            // And it's actually been drawn at that size, not just shrunk to a dot
+            assert!(bottom - top + 1 >= key_thickness * 3 / 4);
+        }
+    }
+
+    #[test]
+    fn large_key_size_applies_its_preset() {
+        let runtime = RuntimeState::new();
+        // Big enough that the whole keyboard fits at the large size, so nothing gets shrunk
+        let mut piano_roll = resized_roll(&runtime, 3840, 4320);
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.key_size".to_string(), "large".to_string()));
+        assert!(piano_roll.key_size == Some(KeySize::Large));
+        assert_eq!((piano_roll.key_thickness, piano_roll.key_length), KeySize::Large.dimensions());
+        assert_eq!((piano_roll.key_thickness, piano_roll.key_length), (24, 96));
+
+        // A number still wins over the preset
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.key_length".to_string(), 50));
+        assert_eq!((piano_roll.key_thickness, piano_roll.key_length), (24, 50));
+
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.key_size".to_string(), "small".to_string()));
+        assert_eq!((piano_roll.key_thickness, piano_roll.key_length), (8, 50));
+    }
+
+    #[test]
+    fn gentle_bends_are_smoothed_and_jumps_are_not() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.time_slices.clear();
+        // A slow bend of a tenth of a key per column, then a jump of 5 keys at age 6
+        for age in 0 .. 10 {
+            let mut note = ChannelSlice::none();
+            note.visible = true;
+            note.y = if age < 6 {10.0 + 0.1 * age as f32} else {15.0 + 0.1 * age as f32};
+            note.thickness = 2.0;
+            piano_roll.time_slices.push_back(vec!(note));
+        }
+        piano_roll.smooth_pitch = true;
+        let key_thickness = piano_roll.key_thickness as f32;
+
+        // In the middle of the bend, each column stretches halfway to both neighbors, so the
+        // spans of adjacent columns meet with no step between them
+        let span = |age: usize| {
+            let smoothed = piano_roll.apply_pitch_smoothing(age, 0, &piano_roll.time_slices[age][0]);
+            let half = (smoothed.thickness - 2.0) / key_thickness / 2.0;
+            (smoothed.y - half, smoothed.y + half)
+        };
+        let (low_3, high_3) = span(3);
+        let (low_4, _) = span(4);
+        assert!((low_3 - 10.25).abs() < 1e-4 && (high_3 - 10.35).abs() < 1e-4);
+        assert!((low_4 - high_3).abs() < 1e-4);
+
+        // Right before the jump, only the side towards the bend is stretched
+        let (low_5, high_5) = span(5);
+        assert!((low_5 - 10.45).abs() < 1e-4);
+        assert!((high_5 - 10.5).abs() < 1e-4);
+
+        piano_roll.smooth_pitch = false;
+        assert_eq!(piano_roll.apply_pitch_smoothing(3, 0, &piano_roll.time_slices[3][0]).thickness, 2.0);
+    }
+
+    #[test]
+    fn notes_map_onto_the_keys_that_fit_on_a_tiny_canvas() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 160, 90);
+        let visible_keys = piano_roll.visible_keys();
+        assert!(visible_keys < piano_roll.keys);
+        assert!(piano_roll.keyboard_fits(visible_keys, piano_roll.key_thickness));
+
+        // The top visible key lands at the top of the visible keyboard, not partway up it
+        let top_visible_key = midi_frequency(piano_roll.lowest_index + visible_keys);
+        assert!((piano_roll.frequency_to_coordinate(top_visible_key) - visible_keys as f32).abs() < 0.01);
+        assert!(piano_roll.frequency_to_coordinate(piano_roll.lowest_frequency).abs() < 0.01);
+
+        // And the whole thing still draws without running off the buffer
+        piano_roll.handle_event(&runtime, Event::RequestFrame);
+    }
+
+    #[test]
+    fn reference_line_sits_at_the_reference_pitch_and_follows_the_octave() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyFloatSetting("piano_roll.reference_frequency".to_string(), 440.0));
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.starting_octave".to_string(), 2));
+
+        // Octave numbers here start from MIDI note 0, so C2 is 45 semitones below A440
+        let coordinate = piano_roll.reference_coordinate().unwrap();
+        assert!((coordinate - piano_roll.frequency_to_coordinate(440.0)).abs() < 0.001);
+        assert!((coordinate - 45.0).abs() < 0.01);
+        let expected_y = (500.0 - coordinate * piano_roll.key_thickness_f + 0.5) as u32;
+        assert_eq!(piano_roll.reference_line_y(500), Some(expected_y));
+        let expected_x = (20.0 + coordinate * piano_roll.key_thickness_f + 0.5) as u32;
+        assert_eq!(piano_roll.reference_line_x(20), Some(expected_x));
+
+        // Moving the key range up an octave moves the line down by 12 keys
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.starting_octave".to_string(), 3));
+        assert!((piano_roll.reference_coordinate().unwrap() - 33.0).abs() < 0.01);
+
+        // Out of range, or turned off, there's no line at all
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.starting_octave".to_string(), 7));
+        assert_eq!(piano_roll.reference_coordinate(), None);
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.starting_octave".to_string(), 2));
+        piano_roll.handle_event(&runtime, Event::ApplyFloatSetting("piano_roll.reference_frequency".to_string(), 0.0));
+        assert_eq!(piano_roll.reference_line_y(500), None);
+    }
+
+    #[test]
+    fn export_canvas_follows_the_export_scale_setting() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 640, 360);
+        piano_roll.handle_event(&runtime, Event::RequestFrame);
+        let full_size = piano_roll.export_canvas();
+        assert_eq!((full_size.width, full_size.height), (640, 360));
+        assert_eq!(full_size.buffer, piano_roll.canvas.buffer);
+
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.export_scale".to_string(), 2));
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.export_dither".to_string(), true));
+        let half_size = piano_roll.export_canvas();
+        assert_eq!((half_size.width, half_size.height), (320, 180));
+        assert_eq!(half_size.buffer, piano_roll.canvas.downsample(2, true).buffer);
+    }
+
+    #[test]
+    fn free_running_channels_skip_edge_detection() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.settings.2A03.Noise.trigger".to_string(), "none".to_string()));
+
+        // The same square wave on both, ending partway through a period
+        let mut pulse = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        let mut noise = FakeChannel::new("2A03", "Noise", PlaybackRate::LfsrRate{index: 4, max: 15});
+        for i in 0 .. 1000 {
+            let sample = if (i / 16) % 2 == 0 {0} else {15};
+            pulse.buffer.push(sample);
+            noise.buffer.push(sample);
+        }
+        assert!(piano_roll.trigger_mode(&noise) == TriggerMode::None);
+        assert!(piano_roll.trigger_mode(&pulse) == TriggerMode::RisingEdge);
+
+        let window_size = 64;
+        let latest = noise.buffer.index_before(noise.buffer.index(), window_size);
+        assert_eq!(piano_roll.surfboard_window_start(&noise, window_size), latest);
+
+        // The pulse's window is centered on a rising edge instead of simply ending at the newest sample
+        let pulse_start = piano_roll.surfboard_window_start(&pulse, window_size);
+        assert!(pulse_start != latest);
+        let edge = (pulse_start + window_size / 2) % 1024;
+        assert_eq!(pulse.buffer.buffer()[edge], 15);
+        assert_eq!(pulse.buffer.buffer()[pulse.buffer.index_before(edge, 1)], 0);
+    }
+
+    #[test]
+    fn linear_scope_fills_in_between_samples_and_step_holds() {
+        // Two held levels, like a slow DMC sample stepping up
+        let samples = [0i16, 0, 0, 0, 8, 8, 8, 8];
+        let step = interpolate_scope(&samples, ScopeInterpolation::Step);
+        assert_eq!(step, vec!(0.0, 0.0, 0.0, 0.0, 8.0, 8.0, 8.0, 8.0));
+
+        // The two runs center on 1.5 and 5.5, and the line climbs evenly between them
+        let linear = interpolate_scope(&samples, ScopeInterpolation::Linear);
+        assert_eq!(linear, vec!(0.0, 0.0, 1.0, 3.0, 5.0, 7.0, 8.0, 8.0));
+
+        // Cubic passes through the same points, and also stays strictly between them
+        let cubic = interpolate_scope(&samples, ScopeInterpolation::Cubic);
+        for i in 2 .. 6 {
+            assert!(cubic[i] > 0.0 && cubic[i] < 8.0);
+            assert!(cubic[i] >= cubic[i - 1]);
+        }
+        assert_eq!((cubic[0], cubic[7]), (0.0, 8.0));
+    }
+
+    #[test]
+    fn scope_interpolation_setting_reaches_sampled_channels() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.scope_interpolation".to_string(), "linear".to_string()));
+        assert!(piano_roll.scope_interpolation == ScopeInterpolation::Linear);
+
+        let dmc = FakeChannel::new("2A03", "DMC", PlaybackRate::SampleRate{frequency: 33143.9});
+        assert!(piano_roll.scope_interpolation_for(&dmc) == ScopeInterpolation::Linear);
+        // Nonsense leaves the last good value alone
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.scope_interpolation".to_string(), "sinc".to_string()));
+        assert!(piano_roll.scope_interpolation == ScopeInterpolation::Linear);
+    }
+
+    #[test]
+    fn note_csv_lists_each_visible_note_in_order() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.speed_multiplier = 1;
+        piano_roll.smooth_scroll = false;
+        let mut pulse = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        let noise = FakeChannel::new("2A03", "Noise", PlaybackRate::LfsrRate{index: 4, max: 15});
+        piano_roll.update_from_channels(&vec!(&pulse as &dyn AudioChannelState, &noise as &dyn AudioChannelState), 44100.0);
+        pulse.rate = PlaybackRate::FundamentalFrequency{frequency: 523.25};
+        pulse.amplitude = 0.5;
+        piano_roll.update_from_channels(&vec!(&pulse as &dyn AudioChannelState, &noise as &dyn AudioChannelState), 44100.0);
+
+        let csv = piano_roll.notes_to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "time,channel,chip,frequency,midi_note,amplitude,timbre");
+        let one_poll = 1.0 / piano_roll.effective_poll_rate_hz();
+        assert_eq!(lines[1 ..].to_vec(), vec!(
+            "0.000000,Pulse 1,2A03,440.00,69,1.000,none".to_string(),
+            "0.000000,Noise,2A03,lfsr 4,,1.000,none".to_string(),
+            format!("{:.6},Pulse 1,2A03,523.25,72,0.500,none", one_poll),
+            format!("{:.6},Noise,2A03,lfsr 4,,1.000,none", one_poll),
+        ));
+
+        let path = env::temp_dir().join("rustico_note_csv_test.csv");
+        piano_roll.export_csv(path.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), csv);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn keyboard_antialiasing_adds_blended_edge_pixels() {
+        let runtime = RuntimeState::new();
+        let mut plain = resized_roll(&runtime, 1280, 720);
+        let mut smoothed = resized_roll(&runtime, 1280, 720);
+        smoothed.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.antialias_keyboard".to_string(), true));
+        render(&mut plain, &runtime);
+        render(&mut smoothed, &runtime);
+
+        // Without AA, the keyboard is drawn in its flat colors only. With it, the pixels that
+        // change are in-between shades of the black and white keys.
+        let black_key = plain.black_key_color;
+        let white_key = plain.white_key_color;
+        let between = |color: Color| -> bool {
+            return [(color.r(), black_key.r(), white_key.r()), (color.g(), black_key.g(), white_key.g()), (color.b(), black_key.b(), white_key.b())].iter()
+                .all(|&(value, dark, light)| value > dark.min(light) && value < dark.max(light));
+        };
+        let mut changed = 0;
+        for y in 0 .. plain.canvas.height {
+            for x in 0 .. plain.canvas.width {
+                let before = plain.canvas.get_pixel(x, y);
+                let after = smoothed.canvas.get_pixel(x, y);
+                if before.data != after.data {
+                    changed += 1;
+                    assert!(between(after));
+                    assert!(!between(before));
+                }
+            }
+        }
+        assert!(changed > 0);
+
+        // And turning it back off restores the hard edges exactly
+        smoothed.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.antialias_keyboard".to_string(), false));
+        render(&mut smoothed, &runtime);
+        assert!(smoothed.canvas.buffer == plain.canvas.buffer);
+    }
+
+    #[test]
+    fn audio_sample_clock_polls_once_per_n_samples() {
+        let mut runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.polling_type = PollingType::AudioSamples(100);
+        piano_roll.speed_multiplier = 1;
+        piano_roll.smooth_scroll = false;
+
+        // The first count only sets where we start counting from
+        runtime.nes.apu.generated_samples = 5000;
+        piano_roll.handle_event(&runtime, Event::NesNewScanline);
+        assert_eq!(piano_roll.total_slices, 0);
+        // 250 more samples: two whole polls, with 50 carried over
+        runtime.nes.apu.generated_samples = 5250;
+        piano_roll.handle_event(&runtime, Event::NesNewScanline);
+        assert_eq!(piano_roll.total_slices, 2);
+        // Which the next 750 complete, for 8 more
+        runtime.nes.apu.generated_samples = 6000;
+        piano_roll.handle_event(&runtime, Event::NesNewScanline);
+        assert_eq!(piano_roll.total_slices, 10);
+
+        // Speed multiplier still applies on top, as slices per poll
+        piano_roll.speed_multiplier = 3;
+        runtime.nes.apu.generated_samples = 6200;
+        piano_roll.handle_event(&runtime, Event::NesNewScanline);
+        assert_eq!(piano_roll.total_slices, 16);
+
+        // Other polling types ignore the sample clock entirely
+        piano_roll.polling_type = PollingType::PpuFrame;
+        runtime.nes.apu.generated_samples = 9000;
+        piano_roll.handle_event(&runtime, Event::NesNewScanline);
+        assert_eq!(piano_roll.total_slices, 16);
+    }
+
+    // One frame of a "trace": a black surfboard with a single bright pixel at trace_x
+    fn draw_trace_frame(piano_roll: &mut PianoRollWindow, trace_x: u32) {
+        drawing::rect(&mut piano_roll.canvas, 0, 0, 64, 32, Color::rgb(0, 0, 0));
+        piano_roll.canvas.put_pixel(trace_x, 16, Color::rgb(200, 200, 200));
+        piano_roll.apply_surfboard_persistence(0, 0, 64, 32);
+    }
+
+    #[test]
+    fn persistence_leaves_a_dimmed_trail_where_the_trace_was() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.surfboard_persistence = 0.5;
+        draw_trace_frame(&mut piano_roll, 10);
+        draw_trace_frame(&mut piano_roll, 20);
+        assert_eq!(piano_roll.canvas.get_pixel(20, 16).r(), 200);
+        assert_eq!(piano_roll.canvas.get_pixel(10, 16).r(), 100);
+        // And it keeps fading, a frame at a time
+        draw_trace_frame(&mut piano_roll, 30);
+        assert_eq!(piano_roll.canvas.get_pixel(10, 16).r(), 50);
+        assert_eq!(piano_roll.canvas.get_pixel(20, 16).r(), 100);
+        // The background stays untouched
+        assert_eq!(piano_roll.canvas.get_pixel(40, 16).r(), 0);
+
+        // Without persistence, the old position is simply cleared
+        piano_roll.surfboard_persistence = 0.0;
+        draw_trace_frame(&mut piano_roll, 40);
+        assert_eq!(piano_roll.canvas.get_pixel(30, 16).r(), 0);
+        assert_eq!(piano_roll.canvas.get_pixel(40, 16).r(), 200);
+    }
+
+    #[test]
+    fn nonsense_frequencies_give_invisible_slices() {
+        let piano_roll = PianoRollWindow::new();
+        for &frequency in [0.0f32, -440.0, f32::NAN, f32::INFINITY].iter() {
+            let channel = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: frequency});
+            let slice = piano_roll.slice_from_channel(&channel);
+            assert!(!slice.visible, "{} Hz should not be drawn", frequency);
+            assert!(slice.y.is_finite());
+        }
+        // A sensible one still shows up, for comparison
+        let channel = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        assert!(piano_roll.slice_from_channel(&channel).visible);
+    }
+
+    #[test]
+    fn distorting_sawtooth_picks_the_distortion_color() {
+        let piano_roll = PianoRollWindow::new();
+        let colors = piano_roll.channel_colors(&Vrc6SawtoothChannel::new());
+        // Gradient blending can round a component down by one
+        let close = |a: Color, b: Color| -> bool {
+            return [(a.r(), b.r()), (a.g(), b.g()), (a.b(), b.b())].iter().all(|&(x, y)| (x as i32 - y as i32).abs() <= 1);
+        };
+        let mut sawtooth = Vrc6SawtoothChannel::new();
+
+        sawtooth.accumulator_rate = 30;
+        assert!(close(piano_roll.channel_color(&sawtooth), colors[0]));
+        sawtooth.accumulator_rate = 63;
+        assert!(close(piano_roll.channel_color(&sawtooth), colors[1]));
+        assert!(!close(colors[0], colors[1]));
+    }
+
+    #[test]
+    fn note_names_include_the_cents_offset() {
+        let piano_roll = PianoRollWindow::new();
+        let pitched = |frequency: f32| -> Option<String> {
+            let channel = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: frequency});
+            return piano_roll.channel_note_name(&channel);
+        };
+        assert_eq!(pitched(440.0), Some("A4 +0¢".to_string()));
+        assert_eq!(pitched(440.0 * 2.0f32.powf(12.0 / 1200.0)), Some("A4 +12¢".to_string()));
+        // Closer to A#4 than to A4, so it's named for A#4 and flat of it
+        assert_eq!(pitched(466.16 * 2.0f32.powf(-30.0 / 1200.0)), Some("A#4 -30¢".to_string()));
+
+        let noise = FakeChannel::new("2A03", "Noise", PlaybackRate::LfsrRate{index: 4, max: 15});
+        assert_eq!(piano_roll.channel_note_name(&noise), None);
+        let dmc = FakeChannel::new("2A03", "DMC", PlaybackRate::SampleRate{frequency: 33143.9});
+        assert_eq!(piano_roll.channel_note_name(&dmc), None);
+    }
+
+    #[test]
+    fn muted_channels_use_the_configured_muted_look() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        let mut pulse = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        let own_colors = piano_roll.channel_colors(&pulse);
+        pulse.muted = true;
+
+        // By default, plain dark grey
+        let colors = piano_roll.channel_colors(&pulse);
+        assert_eq!(colors.len(), 1);
+        assert_eq!((colors[0].r(), colors[0].g(), colors[0].b()), (32, 32, 32));
+
+        // Desaturated: the channel's own colors, each drained to a grey
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.desaturate_muted".to_string(), true));
+        let colors = piano_roll.channel_colors(&pulse);
+        assert_eq!(colors.len(), own_colors.len());
+        for (muted, original) in colors.iter().zip(own_colors.iter()) {
+            assert_eq!(saturation(*muted), 0);
+            assert!(saturation(*original) > 0);
+        }
+
+        // An explicit color wins over both
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.muted_color".to_string(), "rgb(200, 0, 100)".to_string()));
+        let colors = piano_roll.channel_colors(&pulse);
+        assert_eq!(colors.len(), 1);
+        assert_eq!((colors[0].r(), colors[0].g(), colors[0].b()), (200, 0, 100));
+
+        // And an empty string clears it again
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.muted_color".to_string(), "".to_string()));
+        assert_eq!(piano_roll.channel_colors(&pulse).len(), own_colors.len());
+    }
+
+    // Which of the first octave's strings differ between two draws, as pitch classes from C
+    fn highlighted_strings(piano_roll: &mut PianoRollWindow) -> Vec<u32> {
+        let mut plain_colors = Vec::new();
+        let highlight_scale = piano_roll.highlight_scale;
+        piano_roll.highlight_scale = None;
+        piano_roll.draw_piano_strings_vert(0, 0, 4);
+        for key in 0 .. 12 {
+            let x = (0.5 + key as f32 * piano_roll.key_thickness_f) as u32;
+            plain_colors.push(piano_roll.canvas.get_pixel(x, 0).data);
+        }
+        piano_roll.highlight_scale = highlight_scale;
+        piano_roll.draw_piano_strings_vert(0, 0, 4);
+        let mut highlighted = Vec::new();
+        for key in 0 .. 12 {
+            let x = (0.5 + key as f32 * piano_roll.key_thickness_f) as u32;
+            if piano_roll.canvas.get_pixel(x, 0).data != plain_colors[key as usize] {
+                highlighted.push(key);
+            }
+        }
+        return highlighted;
+    }
+
+    #[test]
+    fn c_major_highlights_the_seven_white_key_strings() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 1920, 1080);
+        // The roll starts on a C, so key 0 is the root
+        assert_eq!(piano_roll.lowest_index % 12, 0);
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.scale_root".to_string(), 0));
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.scale_kind".to_string(), "major".to_string()));
+        assert_eq!(highlighted_strings(&mut piano_roll), vec!(0, 2, 4, 5, 7, 9, 11));
+
+        // Moving the root moves the pattern: A minor shares C major's notes, G major swaps F for F#
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.scale_root".to_string(), 9));
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.scale_kind".to_string(), "minor".to_string()));
+        assert_eq!(highlighted_strings(&mut piano_roll), vec!(0, 2, 4, 5, 7, 9, 11));
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.scale_root".to_string(), 7));
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.scale_kind".to_string(), "major".to_string()));
+        assert_eq!(highlighted_strings(&mut piano_roll), vec!(0, 2, 4, 6, 7, 9, 11));
+
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.scale_kind".to_string(), "none".to_string()));
+        assert!(highlighted_strings(&mut piano_roll).is_empty());
+    }
+
+    #[test]
+    fn scroll_speed_pps_derives_the_speed_multiplier_from_the_poll_rate() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+
+        // 44100 Hz audio, polled every 735 samples, is exactly 60 polls a second
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.audio_samples_per_poll".to_string(), 735));
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.polling_type".to_string(), "audio_samples".to_string()));
+        piano_roll.handle_event(&runtime, Event::ApplyFloatSetting("piano_roll.scroll_speed_pps".to_string(), 300.0));
+        assert!((piano_roll.scroll_speed - 5.0).abs() < 0.001);
+        assert_eq!(piano_roll.speed_multiplier, 5);
+
+        // Polling twice as often halves the pixels per poll for the same target
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.audio_samples_per_poll".to_string(), 367));
+        assert!((piano_roll.scroll_speed - 300.0 / (44100.0 / 367.0)).abs() < 0.001);
+        assert_eq!(piano_roll.speed_multiplier, 2);
+
+        // Once per NTSC frame, about 60.1 Hz
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.polling_type".to_string(), "frame".to_string()));
+        piano_roll.handle_event(&runtime, Event::ApplyFloatSetting("piano_roll.scroll_speed_pps".to_string(), 480.0));
+        assert!((piano_roll.scroll_speed - 480.0 / (1789773.0 / 29780.5)).abs() < 0.001);
+        assert_eq!(piano_roll.speed_multiplier, 8);
+
+        // Clearing the target hands control back to the raw setting
+        piano_roll.handle_event(&runtime, Event::ApplyFloatSetting("piano_roll.scroll_speed_pps".to_string(), 0.0));
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.speed_multiplier".to_string(), 3));
+        assert_eq!(piano_roll.speed_multiplier, 3);
+        assert_eq!(piano_roll.scroll_speed, 3.0);
+    }
+
+    #[test]
+    fn excluded_channels_leave_the_surfboard_but_stay_on_the_roll() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 1920, 1080);
+        let all_channels = piano_roll.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper).len();
+        let (first_chip, first_name) = {
+            let roll = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+            (roll[0].chip(), roll[0].name())
+        };
+        let second_name = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper)[1].name();
+        let width = piano_roll.canvas.width;
+        let height = piano_roll.surfboard_height;
+        let (_, _, full_cell_width, _) = surfboard_cell(0, all_channels, piano_roll.surfboard_rows, 0, 0, width, height);
+
+        let path = format!("piano_roll.settings.{}.{}.surfboard", first_chip, first_name);
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting(path.clone(), false));
+        let surfboard = piano_roll.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        assert_eq!(surfboard.len(), all_channels - 1);
+        assert!(surfboard.iter().all(|channel| channel.name() != first_name || channel.chip() != first_chip));
+        assert_eq!(piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper)[0].name(), first_name);
+
+        // The remaining cells widen to share the space
+        let (cx, cy, cell_width, cell_height) = surfboard_cell(0, surfboard.len(), piano_roll.surfboard_rows, 0, 0, width, height);
+        assert!(cell_width > full_cell_width);
+
+        // And clicking the first cell now mutes the second channel, not the excluded one
+        let events = piano_roll.handle_event(&runtime, Event::MouseClick((cx + cell_width / 2) as i32, (cy + cell_height / 2) as i32));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            Event::MuteChannel(chip, name) => {assert_eq!((chip.as_str(), name.as_str()), (first_chip.as_str(), second_name.as_str()));},
+            _ => {panic!("clicking a surfboard cell should mute its channel");}
+        }
+
+        // Turning it back on restores the original layout
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting(path, true));
+        assert_eq!(piano_roll.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper).len(), all_channels);
+    }
+
+    #[test]
+    fn thick_notes_get_the_outline_on_their_outermost_pixels() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.note_outline".to_string(), "rgb(10, 20, 30)".to_string()));
+        let outline = piano_roll.note_outline.expect("note_outline setting should apply");
+        let fill = Color::rgb(200, 100, 50);
+        let mut slice = ChannelSlice::none();
+        slice.visible = true;
+        slice.thickness = 8.0;
+        slice.color = fill;
+        slice.alpha = 1.0;
+
+        // Centered on 20.5: half covered edge pixels at 16 and 24, solid from 17 to 23
+        let mut canvas = SimpleBuffer::new(4, 40);
+        PianoRollWindow::draw_slice_horiz(&mut canvas, &slice, 1, 20, 1.0, Some(outline), AaQuality::Fast);
+        for y in [17, 23].iter() {
+            assert_eq!(canvas.get_pixel(1, *y).data, outline.data, "row {}", y);
+        }
+        for y in 18 .. 23 {
+            assert_eq!(canvas.get_pixel(1, y).data, fill.data, "row {}", y);
+        }
+        // Drawn inside the note: nothing beyond the original edges
+        assert_eq!(canvas.get_pixel(1, 15).data, Color::rgba(0, 0, 0, 0).data);
+        assert_eq!(canvas.get_pixel(1, 25).data, Color::rgba(0, 0, 0, 0).data);
+
+        // Same thing on its side, where thickness is measured in half keys
+        let mut canvas = SimpleBuffer::new(40, 4);
+        PianoRollWindow::draw_slice_vert(&mut canvas, &slice, 20, 1, 2.0, Some(outline), AaQuality::Fast);
+        for x in [17, 23].iter() {
+            assert_eq!(canvas.get_pixel(*x, 1).data, outline.data, "column {}", x);
+        }
+        for x in 18 .. 23 {
+            assert_eq!(canvas.get_pixel(x, 1).data, fill.data, "column {}", x);
+        }
+    }
+
+    // Red channel down one column of a note drawn in white over opaque black, i.e. its coverage
+    fn note_coverage_column(quality: AaQuality) -> Vec<u8> {
+        let mut canvas = SimpleBuffer::new(4, 40);
+        drawing::rect(&mut canvas, 0, 0, 4, 40, Color::rgb(0, 0, 0));
+        let mut slice = ChannelSlice::none();
+        slice.visible = true;
+        slice.thickness = 8.0;
+        slice.color = Color::rgb(255, 255, 255);
+        slice.alpha = 1.0;
+        // Centered on 20.8, so the edges land at 16.8 and 24.8
+        slice.y = -0.3;
+        PianoRollWindow::draw_slice_horiz(&mut canvas, &slice, 1, 20, 1.0, None, quality);
+        return (0 .. 40).map(|y| canvas.get_pixel(1, y).r()).collect();
+    }
+
+    #[test]
+    fn high_quality_spreads_fractional_edges_over_more_pixels() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.aa_quality".to_string(), "high".to_string()));
+        assert!(piano_roll.aa_quality == AaQuality::High);
+
+        let fast = note_coverage_column(AaQuality::Fast);
+        let high = note_coverage_column(AaQuality::High);
+        let partial = |column: &Vec<u8>| column.iter().enumerate().filter(|(_, value)| **value > 0 && **value < 255).map(|(y, _)| y).collect::<Vec<usize>>();
+
+        // Fast blends just the one pixel each edge lands in
+        assert_eq!(partial(&fast), vec!(16, 24));
+        assert!(fast[16] < fast[24]);
+
+        // High ramps each edge over a couple of pixels, rising steadily into the note
+        let high_partial = partial(&high);
+        let top: Vec<usize> = high_partial.iter().cloned().filter(|y| *y < 20).collect();
+        let bottom: Vec<usize> = high_partial.iter().cloned().filter(|y| *y > 20).collect();
+        assert!(top.len() >= 2 && bottom.len() >= 2, "{:?}", high_partial);
+        for pair in top.windows(2) {
+            assert!(high[pair[0]] < high[pair[1]]);
+        }
+        for pair in bottom.windows(2) {
+            assert!(high[pair[0]] > high[pair[1]]);
+        }
+
+        // The solid middle is the same either way
+        for y in 18 .. 23 {
+            assert_eq!((fast[y], high[y]), (255, 255), "row {}", y);
+        }
+    }
+
+    #[test]
+    fn resetting_one_channel_leaves_the_others_alone() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        let (first, second) = {
+            let channels = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+            piano_roll.stats.record(&channels);
+            piano_roll.stats.record(&channels);
+            piano_roll.play_test_note(&channels, 0, 60, 1.0, None);
+            piano_roll.play_test_note(&channels, 1, 64, 1.0, None);
+            ((channels[0].chip(), channels[0].name()), (channels[1].chip(), channels[1].name()))
+        };
+        let first_key = format!("{}/{}", first.0, first.1);
+        let second_key = format!("{}/{}", second.0, second.1);
+        piano_roll.detected_pitches.insert(first_key.clone(), Some(440.0));
+        piano_roll.detected_pitches.insert(second_key.clone(), Some(220.0));
+        piano_roll.grid_holds = vec!(Some(3), Some(5));
+        let slices_before = piano_roll.time_slices.len();
+
+        piano_roll.handle_event(&runtime, Event::ResetChannelVisualization(first.0.clone(), first.1.clone()));
+
+        assert!(!piano_roll.detected_pitches.contains_key(&first_key));
+        assert_eq!(piano_roll.detected_pitches.get(&second_key), Some(&Some(220.0)));
+        assert_eq!(piano_roll.grid_holds, vec!(None, Some(5)));
+        assert!(!piano_roll.test_notes.contains_key(&0));
+        assert!(piano_roll.test_notes.contains_key(&1));
+        assert_eq!(piano_roll.stats.channels[0].polls, 0);
+        assert_eq!(piano_roll.stats.channels[1].polls, 2);
+        // The history already on the roll stays put
+        assert_eq!(piano_roll.time_slices.len(), slices_before);
+
+        // A new cartridge forgets everything
+        piano_roll.handle_event(&runtime, Event::CartridgeLoaded("game".to_string()));
+        assert!(piano_roll.detected_pitches.is_empty());
+        assert!(piano_roll.test_notes.is_empty());
+        assert!(piano_roll.grid_holds.is_empty());
+        assert!(piano_roll.stats.channels.is_empty());
+    }
+
+    #[test]
+    fn comparison_channels_get_their_own_complementary_slices() {
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.speed_multiplier = 1;
+        let pulse = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: 440.0});
+        let other_pulse = FakeChannel::new("2A03", "Pulse 1", PlaybackRate::FundamentalFrequency{frequency: 660.0});
+        let comparison_pulse = ComparisonChannel{channel: &other_pulse};
+        assert_eq!(comparison_pulse.chip(), "B:2A03");
+
+        piano_roll.update_from_channels(&vec!(&pulse as &dyn AudioChannelState, &comparison_pulse as &dyn AudioChannelState), 44100.0);
+        let slices = &piano_roll.time_slices[0];
+        assert_eq!(slices.len(), 2);
+        assert!(slices[0].visible && slices[1].visible);
+        assert!(slices[0].y < slices[1].y);
+        // Same channel settings, but the B side is drawn in the complement
+        let (a, b) = (slices[0].color, slices[1].color);
+        assert!(a.r() != b.r() || a.g() != b.g() || a.b() != b.b());
+        let base = piano_roll.base_channel_colors(&pulse)[0];
+        let complement = piano_roll.configured_channel_colors(&comparison_pulse)[0];
+        assert_eq!((complement.r(), complement.g(), complement.b()), (255 - base.r(), 255 - base.g(), 255 - base.b()));
+    }
+
+    #[test]
+    fn polling_plots_both_consoles_while_a_comparison_runs() {
+        let mut runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.speed_multiplier = 1;
+        piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.polling_type".to_string(), "frame".to_string()));
+        let channel_count = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper).len();
+
+        piano_roll.handle_event(&runtime, Event::NesNewFrame);
+        assert_eq!(piano_roll.time_slices[0].len(), channel_count);
+
+        runtime.handle_event(Event::LoadComparisonCartridge(Arc::new(include_bytes!("assets/rustico_no_cart.nes").to_vec())));
+        for _ in 0 .. 3 {
+            runtime.handle_event(Event::NesRunFrame);
+        }
+        // Stepped right alongside the main console
+        let comparison_frame = runtime.comparison.as_ref().map(|comparison| comparison.ppu.current_frame);
+        assert_eq!(comparison_frame, Some(runtime.nes.ppu.current_frame));
+
+        piano_roll.handle_event(&runtime, Event::NesNewFrame);
+        assert_eq!(piano_roll.time_slices[0].len(), channel_count * 2);
+
+        runtime.handle_event(Event::StopComparison);
+        piano_roll.handle_event(&runtime, Event::NesNewFrame);
+        assert_eq!(piano_roll.time_slices[0].len(), channel_count);
+    }
+
+    // The first pixel still showing the background, scanning the roll area row by row
+    fn background_pixel(piano_roll: &PianoRollWindow) -> (u32, u32) {
+        for y in piano_roll.surfboard_height + 8 .. piano_roll.canvas.height {
+            for x in 0 .. piano_roll.canvas.width {
+                if piano_roll.canvas.get_pixel(x, y).data == piano_roll.background_color.data {
+                    return (x, y);
+                }
+            }
+        }
+        panic!("no background left on the canvas");
+    }
+
+    #[test]
+    fn without_clearing_each_frame_builds_on_the_last() {
+        let runtime = RuntimeState::new();
+        let marker = Color::rgb(0x12, 0x34, 0x56);
+        for clear_each_frame in [true, false].iter() {
+            let mut piano_roll = resized_roll(&runtime, 480, 270);
+            piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.clear_each_frame".to_string(), *clear_each_frame));
+            for _ in 0 .. 4 {
+                piano_roll.handle_event(&runtime, Event::NesNewApuQuarterFrame);
+            }
+            render(&mut piano_roll, &runtime);
+
+            // Leave something behind where only the background was, then draw the same frame again
+            let (x, y) = background_pixel(&piano_roll);
+            piano_roll.canvas.put_pixel(x, y, marker);
+            render(&mut piano_roll, &runtime);
+            let kept = piano_roll.canvas.get_pixel(x, y).data == marker.data;
+            assert_eq!(kept, !*clear_each_frame);
+
+            // A static signal only adds history, so the accumulated canvas carries on
+            piano_roll.handle_event(&runtime, Event::NesNewApuQuarterFrame);
+            render(&mut piano_roll, &runtime);
+            assert_eq!(piano_roll.canvas.get_pixel(x, y).data == marker.data, !*clear_each_frame);
+
+            // But a new layout always starts from a clean slate
+            piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.canvas_width".to_string(), 640));
+            render(&mut piano_roll, &runtime);
+            assert!(piano_roll.canvas.get_pixel(x, y).data != marker.data);
+        }
+    }
+
+    #[test]
+    fn effective_poll_rate_covers_every_polling_type() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.speed_multiplier".to_string(), 3));
+        piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.audio_samples_per_poll".to_string(), 735));
+        let ntsc_frame_rate = 1789773.0 / 29780.5;
+        let quarter_frame_rate = 1789773.0 * 4.0 / 29830.0;
+        let expected = [
+            ("frame", ntsc_frame_rate * 3.0),
+            ("scanline", ntsc_frame_rate * 262.0 * 3.0),
+            ("quarter_frame", quarter_frame_rate * 3.0),
+            ("half_frame", quarter_frame_rate / 2.0 * 3.0),
+            ("audio_samples", 44100.0 / 735.0 * 3.0),
+        ];
+        for (polling_type, rate) in expected.iter() {
+            piano_roll.handle_event(&runtime, Event::ApplyStringSetting("piano_roll.polling_type".to_string(), polling_type.to_string()));
+            assert!((piano_roll.effective_poll_rate_hz() - rate).abs() < 0.001, "{}: {} vs {}", polling_type, piano_roll.effective_poll_rate_hz(), rate);
+        }
+
+        // Smooth scrolling goes by the fractional speed instead
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.smooth_scroll".to_string(), true));
+        piano_roll.handle_event(&runtime, Event::ApplyFloatSetting("piano_roll.speed_multiplier".to_string(), 1.5));
+        assert!((piano_roll.effective_poll_rate_hz() - 44100.0 / 735.0 * 1.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn invalid_color_strings_log_a_warning() {
+        let mut piano_roll = PianoRollWindow::new();
+        let original = piano_roll.channel_settings["2A03"]["Pulse 1"].colors[0];
+        let captured: Arc<Mutex<Vec<(logging::LogLevel, String)>>> = Arc::new(Mutex::new(Vec::new()));
+        let sink_messages = captured.clone();
+        logging::set_log_sink(Some(Box::new(move |level, message| {
+            sink_messages.lock().unwrap().push((level, message.to_string()));
+        })));
+        piano_roll.apply_color_string("2A03", "Pulse 1", "duty0", "not a color".to_string());
+        logging::set_log_sink(None);
+
+        // Other tests may log while the sink is installed, so look for ours among them
+        let messages = captured.lock().unwrap();
+        assert!(messages.iter().any(|(level, message)| *level == logging::LogLevel::Warning && message == "Invalid color string not a color, ignoring."), "{:?}", *messages);
+        assert_eq!(piano_roll.channel_settings["2A03"]["Pulse 1"].colors[0].data, original.data);
+    }
+
+    // Center of the pixels a slice covers along one row of a vertical roll
+    fn drawn_note_center(slice: &ChannelSlice, key_width: f32) -> f32 {
+        let mut canvas = SimpleBuffer::new(200, 1);
+        PianoRollWindow::draw_slice_vert(&mut canvas, slice, 0, 0, key_width, None, AaQuality::Fast);
+        let mut weighted = 0.0;
+        let mut total = 0.0;
+        for x in 0 .. 200 {
+            let alpha = canvas.get_pixel(x, 0).alpha() as f32;
+            weighted += (x as f32 + 0.5) * alpha;
+            total += alpha;
+        }
+        return weighted / total;
+    }
+
+    #[test]
+    fn fractional_key_thickness_positions_notes_between_whole_pixel_steps() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 1920, 1080);
+        piano_roll.handle_event(&runtime, Event::ApplyFloatSetting("piano_roll.key_thickness_f".to_string(), 4.5));
+        assert_eq!(piano_roll.key_thickness_f, 4.5);
+        // The keys themselves stay on whole pixels
+        assert!(piano_roll.key_thickness == 4 || piano_roll.key_thickness == 5);
+
+        let mut slice = ChannelSlice::none();
+        slice.visible = true;
+        slice.thickness = 1.0;
+        slice.color = Color::rgb(255, 255, 255);
+        slice.y = 20.0;
+        let at_four = drawn_note_center(&slice, 4.0);
+        let at_five = drawn_note_center(&slice, 5.0);
+        let at_four_and_a_half = drawn_note_center(&slice, piano_roll.key_thickness_f);
+        assert!(at_four < at_four_and_a_half && at_four_and_a_half < at_five, "{} {} {}", at_four, at_four_and_a_half, at_five);
+        assert!((at_four_and_a_half - (20.0 * 4.5 + 0.5)).abs() < 0.5);
+    }
+
+    #[test]
+    fn muted_channels_get_a_mute_icon_on_their_surfboard_cell() {
+        let mut runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 1920, 1080);
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.show_mute_icons".to_string(), true));
+        let (chip, name) = {
+            let channels = piano_roll.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+            (channels[0].chip(), channels[0].name())
+        };
+        runtime.handle_event(Event::MuteChannel(chip, name));
+        render(&mut piano_roll, &runtime);
+
+        let icon_color = Color::rgb(192, 32, 32);
+        let channel_count = piano_roll.collect_surfboard_channels(&runtime.nes.apu, &*runtime.nes.mapper).len();
+        let width = piano_roll.canvas.width;
+        let height = piano_roll.surfboard_height;
+        let (cx, cy, cell_width, cell_height) = surfboard_cell(0, channel_count, piano_roll.surfboard_rows, 0, 0, width, height);
+        assert!(count_pixels_in(&piano_roll.canvas, icon_color, cx, cy, cell_width, cell_height) > 0);
+        let (cx, cy, cell_width, cell_height) = surfboard_cell(1, channel_count, piano_roll.surfboard_rows, 0, 0, width, height);
+        assert_eq!(count_pixels_in(&piano_roll.canvas, icon_color, cx, cy, cell_width, cell_height), 0);
+    }
+
+    #[test]
+    fn muted_notes_get_a_mute_icon_on_their_key_spot() {
+        let runtime = RuntimeState::new();
+        let icon_color = Color::rgb(192, 32, 32);
+        for muted in [false, true].iter() {
+            let mut piano_roll = resized_roll(&runtime, 1920, 1080);
+            piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.show_mute_icons".to_string(), true));
+            let mut note = ChannelSlice::none();
+            note.visible = true;
+            note.y = 40.0;
+            note.thickness = 4.0;
+            note.color = Color::rgb(255, 255, 255);
+            note.alpha = 1.0;
+            note.muted = *muted;
+            piano_roll.time_slices.push_front(vec!(note));
+            render(&mut piano_roll, &runtime);
+            let (width, height) = (piano_roll.canvas.width, piano_roll.canvas.height);
+            let icon_pixels = count_pixels_in(&piano_roll.canvas, icon_color, 0, 0, width, height);
+            assert_eq!(icon_pixels > 0, *muted);
+        }
+    }
+
+    #[test]
+    fn unknown_channels_get_distinct_stable_colors() {
+        let piano_roll = PianoRollWindow::new();
+        let rate = PlaybackRate::FundamentalFrequency{frequency: 440.0};
+        let first = FakeChannel::new("Mystery Chip", "Voice 1", rate.clone());
+        let second = FakeChannel::new("Mystery Chip", "Voice 2", rate.clone());
+        // A known chip, but a channel it doesn't have
+        let stray = FakeChannel::new("2A03", "Pulse 9", rate.clone());
+
+        let first_color = piano_roll.base_channel_colors(&first)[0];
+        let second_color = piano_roll.base_channel_colors(&second)[0];
+        let stray_color = piano_roll.base_channel_colors(&stray)[0];
+        assert!(first_color.data != second_color.data);
+        assert!(first_color.data != stray_color.data && second_color.data != stray_color.data);
+
+        // Same names, same color, every time and from any roll
+        for _ in 0 .. 3 {
+            assert_eq!(piano_roll.base_channel_colors(&first)[0].data, first_color.data);
+        }
+        assert_eq!(PianoRollWindow::new().base_channel_colors(&second)[0].data, second_color.data);
+        assert_eq!(unknown_channel_color("Mystery Chip", "Voice 1").data, first_color.data);
+    }
+
+    fn preset_events() -> Vec<Event> {
+        return vec!(
+            Event::ApplyBooleanSetting("piano_roll.draw_text_labels".to_string(), false),
+            Event::ApplyIntegerSetting("piano_roll.outline_thickness".to_string(), 3),
+            Event::ApplyIntegerSetting("piano_roll.tempo_bpm".to_string(), 150),
+            Event::ApplyIntegerSetting("piano_roll.canvas_width".to_string(), 640),
+            Event::ApplyStringSetting("piano_roll.settings.2A03.Pulse 1.duty0".to_string(), "#102030".to_string()),
+        );
+    }
+
+    #[test]
+    fn exported_presets_import_back_to_the_same_configuration() {
+        let runtime = RuntimeState::new();
+        let mut original = PianoRollWindow::new();
+        for event in preset_events() {
+            original.handle_event(&runtime, event);
+        }
+        let preset = original.export_config().unwrap();
+
+        let mut restored = PianoRollWindow::new();
+        restored.import_config(&preset).unwrap();
+        assert_eq!(restored.draw_text_labels, false);
+        assert_eq!(restored.outline_thickness, 3);
+        assert_eq!(restored.tempo_bpm, 150.0);
+        assert_eq!(restored.canvas.width, 640);
+        assert_eq!(restored.channel_settings["2A03"]["Pulse 1"].colors[0].data, original.channel_settings["2A03"]["Pulse 1"].colors[0].data);
+        assert_eq!(restored.export_config().unwrap(), preset);
+    }
+
+    #[test]
+    fn imported_presets_are_stored_in_the_settings_file() {
+        let mut original = PianoRollWindow::new();
+        let runtime = RuntimeState::new();
+        for event in preset_events() {
+            original.handle_event(&runtime, event);
+        }
+        let preset = original.export_config().unwrap();
+
+        let store_events = PianoRollWindow::new().import_config(&preset).unwrap();
+        let mut settings = SettingsState{root: Value::Table(Map::new())};
+        for event in store_events {
+            assert!(matches!(event, Event::StoreBooleanSetting(_, _) | Event::StoreIntegerSetting(_, _) | Event::StoreStringSetting(_, _)), "{:?}", event);
+            settings.handle_event(event);
+        }
+        assert_eq!(settings.get("piano_roll.draw_text_labels".to_string()).unwrap().as_bool(), Some(false));
+        assert_eq!(settings.get("piano_roll.outline_thickness".to_string()).unwrap().as_integer(), Some(3));
+        assert_eq!(settings.get("piano_roll.settings.2A03.Pulse 1.duty0".to_string()).unwrap().as_str(), Some("#102030"));
+    }
+
+    #[test]
+    fn unknown_preset_keys_are_skipped_without_failing() {
+        let mut piano_roll = PianoRollWindow::new();
+        let store_events = piano_roll.import_config("[piano_roll]\noutline_thickness = 4\nno_such_setting = 1\n[video]\nntsc_filter = true\n").unwrap();
+        assert_eq!(piano_roll.outline_thickness, 4);
+        assert_eq!(store_events.len(), 1);
+        assert!(piano_roll.import_config("this is not toml = = =").is_err());
+    }
+
+    fn pitched_slice(y: f32, alpha: f32) -> ChannelSlice {
+        let mut slice = ChannelSlice::none();
+        slice.visible = true;
+        slice.note_type = NoteType::Frequency;
+        slice.y = y;
+        slice.alpha = alpha;
+        return slice;
+    }
+
+    #[test]
+    fn centering_moves_the_loudest_note_to_the_middle_of_the_keyboard() {
+        let mut piano_roll = PianoRollWindow::new();
+        let a6 = midi_index("A6").unwrap();
+        let c2 = midi_index("C2").unwrap();
+        let a6_y = (a6 - piano_roll.lowest_index) as f32;
+        let c2_y = (c2 - piano_roll.lowest_index) as f32;
+        piano_roll.time_slices.clear();
+        piano_roll.time_slices.push_front(vec!(pitched_slice(c2_y, 1.0)));
+        piano_roll.time_slices.push_front(vec!(pitched_slice(c2_y, 0.25), pitched_slice(a6_y, 1.0)));
+
+        // Without channels to ask, the slices' own alpha decides which one is loudest
+        piano_roll.center_on_active_notes(&Vec::new());
+        assert!(piano_roll.lowest_index < a6 && piano_roll.highest_index > a6);
+        let midpoint = piano_roll.visible_keys() as f32 / 2.0;
+        let new_a6_y = piano_roll.time_slices[0][1].y;
+        assert_eq!(new_a6_y, (a6 - piano_roll.lowest_index) as f32);
+        assert!((new_a6_y - midpoint).abs() <= 6.0, "A6 at {}, midpoint {}", new_a6_y, midpoint);
+
+        // Older slices follow the keyboard, so they still line up with their keys
+        assert_eq!(piano_roll.time_slices[1][0].y, c2 as f32 - piano_roll.lowest_index as f32);
+    }
+
+    #[test]
+    fn centering_without_a_pitched_note_does_nothing() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        let lowest_index = piano_roll.lowest_index;
+        let mut noise = pitched_slice(60.0, 1.0);
+        noise.note_type = NoteType::Noise;
+        piano_roll.time_slices.clear();
+        piano_roll.time_slices.push_front(vec!(noise));
+        piano_roll.handle_event(&runtime, Event::CenterOnActiveNotes);
+        assert_eq!(piano_roll.lowest_index, lowest_index);
+        assert_eq!(piano_roll.time_slices[0][0].y, 60.0);
+    }
+
+    #[test]
+    fn mirrored_notes_sit_equally_far_above_and_below_the_keyboard() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 480, 270);
+        piano_roll.scroll_direction = ScrollDirection::MirroredVertical;
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.draw_piano_strings".to_string(), false));
+        let marker = Color::rgb(0x12, 0xF4, 0x56);
+        let mut note = pitched_slice(30.0, 1.0);
+        note.color = marker;
+        note.thickness = 1.0;
+        piano_roll.time_slices.clear();
+        for age in 0 .. 20 {
+            piano_roll.time_slices.push_back(vec!(if age == 7 {note.clone()} else {ChannelSlice::none()}));
+        }
+        render(&mut piano_roll, &runtime);
+
+        let rows: Vec<u32> = (0 .. piano_roll.canvas.height).filter(|y| count_pixels_in(&piano_roll.canvas, marker, 0, *y, piano_roll.canvas.width, 1) > 0).collect();
+        assert_eq!(rows.len(), 2, "{:?}", rows);
+        let keys_top = (piano_roll.canvas.height - piano_roll.key_length) / 2;
+        let keys_bottom = keys_top + piano_roll.key_length;
+        assert_eq!(keys_top - rows[0], 7);
+        assert_eq!(rows[1] - keys_bottom, 7);
+        // Same key on both sides
+        let columns = |y: u32| (0 .. piano_roll.canvas.width).filter(|x| piano_roll.canvas.get_pixel(*x, y).data == marker.data).collect::<Vec<u32>>();
+        assert_eq!(columns(rows[0]), columns(rows[1]));
+    }
+
+    #[test]
+    fn number_keys_toggle_the_matching_channels_mute() {
+        let mut runtime = RuntimeState::new();
+        let mut piano_roll = PianoRollWindow::new();
+        let (chip, name) = {
+            let channels = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+            (channels[2].chip(), channels[2].name())
+        };
+
+        let events = piano_roll.handle_event(&runtime, Event::TextInput("3".to_string()));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            Event::MuteChannel(muted_chip, muted_name) => {assert_eq!((muted_chip, muted_name), (&chip, &name));},
+            other => panic!("Expected a mute, got {:?}", other)
+        }
+        assert_eq!(piano_roll.mute_feedback.as_ref().unwrap().0, format!("Muted 3: {} {}", chip, name));
+        for event in events {
+            runtime.handle_event(event);
+        }
+        let channels = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        assert!(channels[2].muted());
+        assert!(!channels[1].muted() && !channels[3].muted());
+
+        // Pressing it again unmutes
+        let events = piano_roll.handle_event(&runtime, Event::TextInput("3".to_string()));
+        assert!(matches!(&events[0], Event::UnmuteChannel(unmuted_chip, unmuted_name) if *unmuted_chip == chip && *unmuted_name == name));
+        for event in events {
+            runtime.handle_event(event);
+        }
+        let channels = piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper);
+        assert!(!channels[2].muted());
+
+        // 0 isn't a channel, and the whole thing can be turned off
+        assert_eq!(piano_roll.handle_event(&runtime, Event::TextInput("0".to_string())).len(), 0);
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.digit_key_muting".to_string(), false));
+        assert_eq!(piano_roll.handle_event(&runtime, Event::TextInput("3".to_string())).len(), 0);
+    }
+
+    // A broken mapper that claims a ridiculous number of audio channels
+    struct ChannelHogMapper {
+        channels: Vec<FakeChannel>,
+    }
+
+    impl Mapper for ChannelHogMapper {
+        fn write_cpu(&mut self, _address: u16, _data: u8) {}
+        fn write_ppu(&mut self, _address: u16, _data: u8) {}
+        fn debug_read_cpu(&self, _address: u16) -> Option<u8> {return None;}
+        fn debug_read_ppu(&self, _address: u16) -> Option<u8> {return None;}
+        fn mirroring(&self) -> Mirroring {return Mirroring::Horizontal;}
+        fn channels(&self) -> Vec<& dyn AudioChannelState> {
+            return self.channels.iter().map(|channel| channel as &dyn AudioChannelState).collect();
+        }
+    }
+
+    #[test]
+    fn absurd_mapper_channel_counts_are_capped() {
+        let mut runtime = RuntimeState::new();
+        let apu_channel_count = runtime.nes.apu.channels().len();
+        runtime.nes.mapper = Box::new(ChannelHogMapper {
+            channels: (0 .. 10000).map(|index| FakeChannel::new("Hog", &format!("Voice {}", index), PlaybackRate::FundamentalFrequency{frequency: 440.0})).collect()
+        });
+        let mut piano_roll = PianoRollWindow::new();
+        assert_eq!(capped_mapper_channels(&*runtime.nes.mapper).len(), MAX_MAPPER_CHANNELS);
+        assert_eq!(piano_roll.collect_channels(&runtime.nes.apu, &*runtime.nes.mapper).len(), apu_channel_count + MAX_MAPPER_CHANNELS);
+
+        // Each poll only gets a slice for the channels under the cap
+        piano_roll.time_slices.clear();
+        piano_roll.handle_event(&runtime, Event::NesNewApuQuarterFrame);
+        assert!(piano_roll.time_slices.len() > 0);
+        assert!(piano_roll.time_slices.iter().all(|slices| slices.len() == apu_channel_count + MAX_MAPPER_CHANNELS));
+    }
+
+    #[test]
+    fn hz_axis_labels_follow_the_key_range() {
+        let runtime = RuntimeState::new();
+        let mut piano_roll = resized_roll(&runtime, 1920, 1080);
+        for starting_octave in [0, 3].iter() {
+            piano_roll.handle_event(&runtime, Event::ApplyIntegerSetting("piano_roll.starting_octave".to_string(), *starting_octave));
+            let labels = piano_roll.hz_axis_labels();
+            assert!(labels.len() > 1);
+            for (key_offset, label) in labels.iter() {
+                assert_eq!(key_offset % 12, 0);
+                let frequency = midi_frequency(piano_roll.lowest_index + key_offset);
+                let expected = if frequency < 100.0 {format!("{:.1}Hz", frequency)} else {format!("{:.0}Hz", frequency)};
+                assert_eq!(label, &expected);
+            }
+            // The first label is always the lowest C on the keyboard
+            let lowest_c = midi_index(&format!("C{}", starting_octave)).unwrap();
+            assert_eq!(piano_roll.lowest_index, lowest_c);
+            assert_eq!(labels[0].0, 0);
+        }
+        assert_eq!(piano_roll.hz_axis_labels()[0].1, format!("{:.1}Hz", midi_frequency(midi_index("C3").unwrap())));
+
+        // And they only show up when asked for
+        render(&mut piano_roll, &runtime);
+        let without_axis = piano_roll.canvas.buffer.clone();
+        piano_roll.handle_event(&runtime, Event::ApplyBooleanSetting("piano_roll.show_hz_axis".to_string(), true));
+        render(&mut piano_roll, &runtime);
+        assert!(piano_roll.canvas.buffer != without_axis);
+    }
+}
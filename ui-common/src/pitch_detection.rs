@@ -0,0 +1,132 @@
+// Estimates the dominant pitch of a channel from its recent output, rather than from the
+// period the game programmed. Mostly useful for wavetable channels (N163, FDS) where the
+// programmed period and the audible pitch can disagree depending on the waveform.
+
+// This is a plain radix-2 FFT over the most recent samples with a Hann window. The strongest
+// bin is refined with parabolic interpolation; if it doesn't stand out clearly from the rest
+// of the spectrum, we report no pitch and let the caller fall back to the channel's rate.
+
+use rustico_core::apu::RingBuffer;
+
+use std::f32::consts::PI;
+
+pub struct PitchDetector {
+    size: usize,
+    window: Vec<f32>,
+    real: Vec<f32>,
+    imag: Vec<f32>,
+    // fraction of the spectrum's energy the peak needs to hold before we trust it
+    pub minimum_confidence: f32,
+}
+
+impl PitchDetector {
+    pub fn new(size: usize) -> PitchDetector {
+        let size = size.next_power_of_two();
+        let mut window = vec!(0.0f32; size);
+        for i in 0 .. size {
+            window[i] = 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos();
+        }
+        return PitchDetector {
+            size: size,
+            window: window,
+            real: vec!(0.0f32; size),
+            imag: vec!(0.0f32; size),
+            minimum_confidence: 0.2,
+        };
+    }
+
+    fn fft(&mut self) {
+        let n = self.size;
+
+        // bit reversal permutation
+        let mut j = 0;
+        for i in 1 .. n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                self.real.swap(i, j);
+                self.imag.swap(i, j);
+            }
+        }
+
+        let mut length = 2;
+        while length <= n {
+            let angle = -2.0 * PI / length as f32;
+            for start in (0 .. n).step_by(length) {
+                for k in 0 .. length / 2 {
+                    let (w_imag, w_real) = (angle * k as f32).sin_cos();
+                    let even = start + k;
+                    let odd = start + k + length / 2;
+                    let odd_real = self.real[odd] * w_real - self.imag[odd] * w_imag;
+                    let odd_imag = self.real[odd] * w_imag + self.imag[odd] * w_real;
+                    self.real[odd] = self.real[even] - odd_real;
+                    self.imag[odd] = self.imag[even] - odd_imag;
+                    self.real[even] += odd_real;
+                    self.imag[even] += odd_imag;
+                }
+            }
+            length <<= 1;
+        }
+    }
+
+    pub fn detect(&mut self, buffer: &RingBuffer, sample_rate: f32) -> Option<f32> {
+        let size = self.size.min(buffer.buffer().len());
+        let start = buffer.index_before(buffer.index(), size);
+        let mut sum = 0.0;
+        for (i, sample) in buffer.iter_from(start).take(size).enumerate() {
+            self.real[i] = *sample as f32;
+            sum += *sample as f32;
+        }
+        // Remove DC so a channel sitting at an offset doesn't drown out the tone
+        let mean = sum / size as f32;
+        for i in 0 .. self.size {
+            if i < size {
+                self.real[i] = (self.real[i] - mean) * self.window[i];
+            } else {
+                self.real[i] = 0.0;
+            }
+            self.imag[i] = 0.0;
+        }
+
+        self.fft();
+
+        let bins = self.size / 2;
+        let mut magnitudes = vec!(0.0f32; bins);
+        let mut total_energy = 0.0;
+        let mut peak_bin = 0;
+        // skip bin 0, it's whatever DC survived the window
+        for bin in 1 .. bins {
+            let energy = self.real[bin] * self.real[bin] + self.imag[bin] * self.imag[bin];
+            magnitudes[bin] = energy.sqrt();
+            total_energy += energy;
+            if magnitudes[bin] > magnitudes[peak_bin] {
+                peak_bin = bin;
+            }
+        }
+        if total_energy <= 0.0 || peak_bin == 0 || peak_bin + 1 >= bins {
+            return None;
+        }
+
+        // The window smears a pure tone across its neighbors, so count those towards the peak
+        let peak_energy =
+            magnitudes[peak_bin - 1] * magnitudes[peak_bin - 1] +
+            magnitudes[peak_bin] * magnitudes[peak_bin] +
+            magnitudes[peak_bin + 1] * magnitudes[peak_bin + 1];
+        if peak_energy / total_energy < self.minimum_confidence {
+            return None;
+        }
+
+        // Parabolic interpolation on log magnitudes to get between the bins
+        let left = magnitudes[peak_bin - 1].max(1e-6).ln();
+        let center = magnitudes[peak_bin].max(1e-6).ln();
+        let right = magnitudes[peak_bin + 1].max(1e-6).ln();
+        let denominator = left - 2.0 * center + right;
+        let offset = if denominator != 0.0 {0.5 * (left - right) / denominator} else {0.0};
+
+        return Some((peak_bin as f32 + offset) * sample_rate / self.size as f32);
+    }
+}
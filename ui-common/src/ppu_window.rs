@@ -6,11 +6,14 @@ use drawing::SimpleBuffer;
 use events::Event;
 use panel::Panel;
 
+use rustico_core::logging;
 use rustico_core::mmc::mapper::Mapper;
 use rustico_core::nes::NesState;
 use rustico_core::ppu;
 use rustico_core::palettes::NTSC_PAL;
 
+use std::fs;
+
 fn draw_tile(mapper: &dyn Mapper, pattern_address: u16, tile_index: u16, buffer: &mut SimpleBuffer, dx: u32, dy: u32, palette: &[u8]) {
     for py in 0 .. 8 {
         let tile_address = pattern_address + tile_index * 16 + py;
@@ -57,18 +60,45 @@ fn draw_2x_tile(mapper: &dyn Mapper, pattern_address: u16, tile_index: u16, buff
     }
 }
 
-fn generate_chr_pattern(mapper: &dyn Mapper, pattern_address: u16, buffer: &mut SimpleBuffer, dx: u32, dy: u32) {
-    let debug_palette: [u8; 4*4] = [
-        255, 255, 255, 255,
-        192, 192, 192, 255,
-        128, 128, 128, 255,
-          0,   0,   0, 255];
+fn draw_chr_tile(chr: &[u8], tile_index: usize, buffer: &mut SimpleBuffer, dx: u32, dy: u32, palette: &[u8]) {
+    for py in 0 .. 8 {
+        let mut tile_low  = chr[tile_index * 16 + py];
+        let mut tile_high = chr[tile_index * 16 + py + 8];
+        for px in 0 .. 8 {
+            let palette_index = (tile_low & 0x1) + ((tile_high & 0x1) << 1);
+            tile_low = tile_low >> 1;
+            tile_high = tile_high >> 1;
+            buffer.put_pixel(
+                dx + (7 - px as u32), 
+                dy + (py as u32), 
+                Color::rgb(
+                    palette[(palette_index * 4 + 0) as usize],
+                    palette[(palette_index * 4 + 1) as usize],
+                    palette[(palette_index * 4 + 2) as usize])
+            );
+        }
+    }
+}
 
+// Either whatever is mapped into this pattern table right now, or a specific 4 KB bank of CHR
+// if the user has picked one
+fn read_pattern_table(mapper: &dyn Mapper, pattern_address: u16, bank_override: Option<usize>) -> Vec<u8> {
+    let mut chr = vec!(0u8; 0x1000);
+    for offset in 0 .. 0x1000 {
+        chr[offset] = match bank_override {
+            Some(bank) => mapper.read_chr_bank(bank, offset),
+            None => mapper.debug_read_ppu(pattern_address + offset as u16).unwrap_or(0)
+        };
+    }
+    return chr;
+}
+
+fn generate_chr_pattern(chr: &[u8], buffer: &mut SimpleBuffer, dx: u32, dy: u32, palette: &[u8]) {
     for x in 0 .. 16 {
         for y in 0 .. 16 {
             let tile_index = y * 16 + x;
-            draw_tile(mapper, pattern_address, tile_index as u16, buffer, 
-                      dx + x * 8, dy + y * 8, &debug_palette);
+            draw_chr_tile(chr, tile_index as usize, buffer, 
+                      dx + x * 8, dy + y * 8, palette);
         }
     }
 }
@@ -93,8 +123,33 @@ pub struct PpuWindow {
     pub palette_cache: [[u8; 4*4]; 4*2],
     pub font: Font,
     pub shown: bool,
+
+    // The nametables are decoded into their own buffer, and only the tiles that changed since
+    // the last frame are redrawn. These shadow copies are what we diff against.
+    pub nametable_buffer: SimpleBuffer,
+    pub nametable_valid: bool,
+    pub shadow_tiles: Vec<(u8, u8)>,
+    pub shadow_chr: Vec<u8>,
+    pub shadow_pattern_address: u16,
+    pub shadow_palettes: [[u8; 4*4]; 4*2],
+    pub tiles_redrawn: usize,
+
+    // Manual overrides for the pattern table view; None follows the live PPU state
+    pub chr_bank_override: Option<usize>,
+    pub palette_override: Option<usize>,
+
+    pub attribute_overlay: bool,
+    pub nametable_zoom: u32,
+    pub nametable_pan_x: u32,
+    pub nametable_pan_y: u32,
+    pub drag_origin: Option<(i32, i32)>,
+    pub last_mouse: (i32, i32),
 }
 
+// Where the nametable view sits on the canvas
+const NAMETABLE_X: i32 = 280;
+const NAMETABLE_Y: i32 = 0;
+
 impl PpuWindow {
     pub fn new() -> PpuWindow {
         let font = Font::from_raw(include_bytes!("assets/8x8_font.png"), 8);
@@ -104,11 +159,29 @@ impl PpuWindow {
             palette_cache: [[0u8; 4*4]; 4*2],
             font: font,
             shown: false,
+            nametable_buffer: SimpleBuffer::new(512, 480),
+            nametable_valid: false,
+            shadow_tiles: vec!((0, 0); 64 * 60),
+            shadow_chr: vec!(0u8; 0x1000),
+            shadow_pattern_address: 0x0000,
+            shadow_palettes: [[0u8; 4*4]; 4*2],
+            tiles_redrawn: 0,
+            chr_bank_override: None,
+            palette_override: None,
+            attribute_overlay: false,
+            nametable_zoom: 1,
+            nametable_pan_x: 0,
+            nametable_pan_y: 0,
+            drag_origin: None,
+            last_mouse: (0, 0),
         }
     }
 
     pub fn update_palette_cache(&mut self, nes: &NesState) {
-        // Initialize all palette colors with a straight copy
+        // Initialize all palette colors with a straight copy. This deliberately leaves out the
+        // $2001 emphasis bits, which the game window does apply (they pick one of the 8 emphasis
+        // sets in NTSC_PAL); a tinted nametable view just makes the tiles harder to read.
+        // Greyscale is already folded in by debug_read_byte.
         for p in 0 .. 8 {
             for i in 0 .. 4 {
                 let palette_color = nes.ppu.debug_read_byte(& *nes.mapper, 0x3F00 + p * 4 + i) as usize * 3;
@@ -134,14 +207,8 @@ impl PpuWindow {
             pattern_address = 0x1000;
         }
         
-        for tx in 0 .. 64 {
-            for ty in 0 .. 60 {
-                let tile_index = ppu.get_bg_tile(mapper, tx, ty);
-                let palette_index = ppu.get_bg_palette(mapper, tx, ty);
-                draw_tile(mapper, pattern_address, tile_index as u16, &mut self.canvas, 
-                    dx + tx as u32 * 8, dy + ty as u32 * 8, &self.palette_cache[palette_index as usize]);
-            }
-        }
+        self.update_nametable_buffer(mapper, ppu, pattern_address);
+        self.draw_nametable_view(dx, dy);
     
         // Draw a red border around the present scroll viewport
         let vram_address = ppu.current_vram_address;
@@ -155,18 +222,181 @@ impl PpuWindow {
         for x in scroll_x .. scroll_x + 256 {
             let px = x % 512;
             let mut py = (scroll_y) % 480;
-            self.canvas.put_pixel(dx + px, dy + py, Color::rgb(255, 0, 0));
+            self.put_nametable_pixel(dx, dy, px, py, Color::rgb(255, 0, 0));
             py = (scroll_y + 239) % 480;
-            self.canvas.put_pixel(dx + px, dy + py, Color::rgb(255, 0, 0));
+            self.put_nametable_pixel(dx, dy, px, py, Color::rgb(255, 0, 0));
         }
 
         for y in scroll_y .. scroll_y + 240 {
             let py = y % 480;
             let mut px = scroll_x % 512;
-            self.canvas.put_pixel(dx + px, dy + py, Color::rgb(255, 0, 0));
+            self.put_nametable_pixel(dx, dy, px, py, Color::rgb(255, 0, 0));
             px = (scroll_x + 255) % 512;
-            self.canvas.put_pixel(dx + px, dy + py, Color::rgb(255, 0, 0));
+            self.put_nametable_pixel(dx, dy, px, py, Color::rgb(255, 0, 0));
+        }
+    }
+
+    // Nearest-neighbor copy of the visible part of the nametables, at the current zoom level
+    fn draw_nametable_view(&mut self, dx: u32, dy: u32) {
+        // The overlay goes on a copy, so the decoded tiles stay clean and toggling it
+        // doesn't force a redraw
+        let mut overlay_buffer: SimpleBuffer;
+        let view_buffer = if self.attribute_overlay {
+            overlay_buffer = self.nametable_buffer.clone();
+            self.draw_attribute_overlay(&mut overlay_buffer);
+            &overlay_buffer
+        } else {
+            &self.nametable_buffer
+        };
+
+        if self.nametable_zoom == 1 {
+            drawing::copy(&mut self.canvas, view_buffer, dx, dy);
+            return;
+        }
+        for sy in 0 .. 480 {
+            for sx in 0 .. 512 {
+                let (px, py) = self.screen_to_nametable(sx, sy);
+                let color = view_buffer.get_pixel(px, py);
+                self.canvas.put_pixel(dx + sx, dy + sy, color);
+            }
+        }
+    }
+
+    // Tints each 16x16 attribute block by the palette it selects, and outlines the blocks
+    fn draw_attribute_overlay(&self, buffer: &mut SimpleBuffer) {
+        let palette_tints = [
+            Color::rgba(255,  64,  64, 0x50),
+            Color::rgba( 64, 255,  64, 0x50),
+            Color::rgba( 64,  64, 255, 0x50),
+            Color::rgba(255, 255,  64, 0x50),
+        ];
+        let grid_color = Color::rgba(255, 255, 255, 0x80);
+
+        for block_x in 0 .. 32 {
+            for block_y in 0 .. 30 {
+                // Every tile in the block shares the same palette, so just ask the top-left one
+                let (_, palette_index) = self.shadow_tiles[(block_y * 2) * 64 + (block_x * 2)];
+                let x = block_x as u32 * 16;
+                let y = block_y as u32 * 16;
+                drawing::blend_rect(buffer, x, y, 16, 16, palette_tints[(palette_index & 0x3) as usize]);
+            }
+        }
+        for x in (0 .. 512).step_by(16) {
+            drawing::blend_rect(buffer, x, 0, 1, 480, grid_color);
+        }
+        for y in (0 .. 480).step_by(16) {
+            drawing::blend_rect(buffer, 0, y, 512, 1, grid_color);
+        }
+    }
+
+    // Draws a single nametable pixel as a zoom x zoom block, if it is on screen at all
+    fn put_nametable_pixel(&mut self, dx: u32, dy: u32, px: u32, py: u32, color: Color) {
+        let zoom = self.nametable_zoom;
+        if px < self.nametable_pan_x || py < self.nametable_pan_y {
+            return;
+        }
+        let sx = (px - self.nametable_pan_x) * zoom;
+        let sy = (py - self.nametable_pan_y) * zoom;
+        if sx >= 512 || sy >= 480 {
+            return;
+        }
+        drawing::rect(&mut self.canvas, dx + sx, dy + sy, zoom.min(512 - sx), zoom.min(480 - sy), color);
+    }
+
+    // Converts a position within the nametable view (relative to its top-left corner) into
+    // the nametable pixel displayed there
+    pub fn screen_to_nametable(&self, sx: u32, sy: u32) -> (u32, u32) {
+        let px = self.nametable_pan_x + sx / self.nametable_zoom;
+        let py = self.nametable_pan_y + sy / self.nametable_zoom;
+        return (px.min(511), py.min(479));
+    }
+
+    fn clamp_nametable_pan(&mut self) {
+        let visible_width = 512 / self.nametable_zoom;
+        let visible_height = 480 / self.nametable_zoom;
+        self.nametable_pan_x = self.nametable_pan_x.min(512 - visible_width);
+        self.nametable_pan_y = self.nametable_pan_y.min(480 - visible_height);
+    }
+
+    // Zooms in or out, keeping the nametable pixel under the cursor in place
+    fn zoom_nametables(&mut self, delta: i32) {
+        let (mx, my) = self.last_mouse;
+        if mx < NAMETABLE_X || my < NAMETABLE_Y {
+            return;
+        }
+        let sx = ((mx - NAMETABLE_X) as u32).min(511);
+        let sy = ((my - NAMETABLE_Y) as u32).min(479);
+        let (px, py) = self.screen_to_nametable(sx, sy);
+
+        let new_zoom = (self.nametable_zoom as i32 + delta).max(1).min(8) as u32;
+        self.nametable_zoom = new_zoom;
+        self.nametable_pan_x = px.saturating_sub(sx / new_zoom);
+        self.nametable_pan_y = py.saturating_sub(sy / new_zoom);
+        self.clamp_nametable_pan();
+    }
+
+    fn handle_mouse_move(&mut self, mx: i32, my: i32) {
+        if let Some((drag_x, drag_y)) = self.drag_origin {
+            // Dragging moves the nametables along with the cursor, so the pan goes the other way
+            let zoom = self.nametable_zoom as i32;
+            let pan_x = self.nametable_pan_x as i32 - (mx - drag_x) / zoom;
+            let pan_y = self.nametable_pan_y as i32 - (my - drag_y) / zoom;
+            self.nametable_pan_x = pan_x.max(0) as u32;
+            self.nametable_pan_y = pan_y.max(0) as u32;
+            self.clamp_nametable_pan();
+            // Only consume whole nametable pixels, so slow drags still get there eventually
+            self.drag_origin = Some((drag_x + (mx - drag_x) / zoom * zoom, drag_y + (my - drag_y) / zoom * zoom));
+        }
+        self.last_mouse = (mx, my);
+    }
+
+    fn handle_click(&mut self, mx: i32, my: i32) {
+        if mx >= NAMETABLE_X && mx < NAMETABLE_X + 512 && my >= NAMETABLE_Y && my < NAMETABLE_Y + 480 {
+            self.drag_origin = Some((mx, my));
+        }
+        self.last_mouse = (mx, my);
+    }
+
+    pub fn invalidate_nametables(&mut self) {
+        self.nametable_valid = false;
+    }
+
+    fn update_nametable_buffer(&mut self, mapper: &dyn Mapper, ppu: &ppu::PpuState, pattern_address: u16) {
+        // Changing the pattern table or any palette affects (nearly) every tile, so don't
+        // bother diffing in that case
+        let full_redraw = !self.nametable_valid || 
+            pattern_address != self.shadow_pattern_address || 
+            self.palette_cache != self.shadow_palettes;
+
+        // Work out which CHR tiles changed, either from CHR-RAM writes or bank switching
+        let mut chr_tile_changed = [false; 256];
+        for offset in 0 .. 0x1000 {
+            let chr_byte = mapper.debug_read_ppu(pattern_address + offset as u16).unwrap_or(0);
+            if chr_byte != self.shadow_chr[offset] {
+                chr_tile_changed[offset / 16] = true;
+                self.shadow_chr[offset] = chr_byte;
+            }
+        }
+
+        self.tiles_redrawn = 0;
+        for tx in 0 .. 64 {
+            for ty in 0 .. 60 {
+                let tile_index = ppu.get_bg_tile(mapper, tx, ty);
+                let palette_index = ppu.get_bg_palette(mapper, tx, ty);
+                let shadow_index = ty as usize * 64 + tx as usize;
+                let tile_changed = self.shadow_tiles[shadow_index] != (tile_index, palette_index);
+                if full_redraw || tile_changed || chr_tile_changed[tile_index as usize] {
+                    draw_tile(mapper, pattern_address, tile_index as u16, &mut self.nametable_buffer, 
+                        tx as u32 * 8, ty as u32 * 8, &self.palette_cache[palette_index as usize]);
+                    self.shadow_tiles[shadow_index] = (tile_index, palette_index);
+                    self.tiles_redrawn += 1;
+                }
+            }
         }
+
+        self.shadow_pattern_address = pattern_address;
+        self.shadow_palettes = self.palette_cache;
+        self.nametable_valid = true;
     }
 
     pub fn draw_palettes(&mut self, dx: u32, dy: u32) {
@@ -267,18 +497,102 @@ impl PpuWindow {
         }
     }
 
+    // Reads back both pattern tables exactly as the PPU currently sees them, in the usual
+    // 16 bytes per tile planar layout, so the result can be loaded straight into a CHR editor
+    pub fn chr_raw(mapper: &dyn Mapper) -> Vec<u8> {
+        let mut chr_data: Vec<u8> = Vec::new();
+        for address in 0x0000 .. 0x2000 {
+            chr_data.push(mapper.debug_read_ppu(address).unwrap_or(0));
+        }
+        return chr_data;
+    }
+
+    pub fn export_chr_raw(&self, mapper: &dyn Mapper, path: &str) -> Result<(), String> {
+        let chr_data = PpuWindow::chr_raw(mapper);
+        match fs::write(path, &chr_data) {
+            Ok(_) => {
+                logging::info(&format!("Wrote CHR data to: {}", path));
+                return Ok(());
+            },
+            Err(why) => {
+                return Err(format!("Couldn't write CHR data to {}: {}", path, why));
+            }
+        }
+    }
+
+    // Steps through live -> bank 0 -> bank 1 ... -> live
+    fn cycle_chr_bank(&mut self, mapper: &dyn Mapper, direction: i32) {
+        let bank_count = mapper.chr_bank_count();
+        if bank_count == 0 {
+            self.chr_bank_override = None;
+            return;
+        }
+        let position = match self.chr_bank_override {
+            Some(bank) => bank as i32 + 1,
+            None => 0
+        };
+        let new_position = (position + direction).rem_euclid(bank_count as i32 + 1);
+        self.chr_bank_override = if new_position == 0 {None} else {Some(new_position as usize - 1)};
+    }
+
+    fn cycle_palette(&mut self) {
+        self.palette_override = match self.palette_override {
+            None => Some(0),
+            Some(7) => None,
+            Some(palette_index) => Some(palette_index + 1)
+        };
+    }
+
     fn update(&mut self, nes: &NesState) {
         self.update_palette_cache(nes);
     }
 
+    // When sprite zero hit and sprite overflow happened during the last full frame
+    pub fn draw_sprite_flags(&mut self, nes: &NesState, dx: u32, dy: u32) {
+        let position_text = |position: Option<(u16, u16)>| -> String {
+            match position {
+                Some((scanline, dot)) => format!("{:3},{:3}", scanline, dot),
+                None => "none   ".to_string()
+            }
+        };
+        let flags_text = format!("S0:{} OV:{}",
+            position_text(nes.ppu.last_sprite_zero_hit_position),
+            position_text(nes.ppu.last_sprite_overflow_position));
+        drawing::rect(&mut self.canvas, dx, dy, 280 - dx, 8, Color::rgb(0, 0, 0));
+        drawing::text(&mut self.canvas, &self.font, dx, dy, &flags_text, Color::rgb(255, 255, 255));
+    }
+
     fn draw(&mut self, nes: &NesState) {
         // Left Pane: CHR memory, Palette Colors
-        generate_chr_pattern(& *nes.mapper, 0x0000, &mut self.canvas,   8, 0);
-        generate_chr_pattern(& *nes.mapper, 0x1000, &mut self.canvas, 144, 0);
+        let debug_palette: [u8; 4*4] = [
+            255, 255, 255, 255,
+            192, 192, 192, 255,
+            128, 128, 128, 255,
+              0,   0,   0, 255];
+        let pattern_palette = match self.palette_override {
+            Some(palette_index) => self.palette_cache[palette_index],
+            None => debug_palette
+        };
+        // With an override, show that bank and the one after it, like an 8 KB CHR window. The
+        // last bank has nothing after it, so the right pane is left blank rather than wrapping.
+        let left_bank = self.chr_bank_override;
+        let right_bank = self.chr_bank_override.map(|bank| bank + 1);
+        let left_chr = read_pattern_table(& *nes.mapper, 0x0000, left_bank);
+        generate_chr_pattern(&left_chr, &mut self.canvas,   8, 0, &pattern_palette);
+        match right_bank {
+            Some(bank) if bank >= nes.mapper.chr_bank_count() => {
+                drawing::rect(&mut self.canvas, 144, 0, 128, 128, Color::rgb(0, 0, 0));
+            },
+            _ => {
+                let right_chr = read_pattern_table(& *nes.mapper, 0x1000, right_bank);
+                generate_chr_pattern(&right_chr, &mut self.canvas, 144, 0, &pattern_palette);
+            }
+        }
         self.draw_palettes(14, 130);
         self.draw_sprites(nes, 0, 170);
+        self.draw_sprite_flags(nes, 4, 496);
         // Right Panel: Entire nametable
-        self.generate_nametables(& *nes.mapper, &nes.ppu, 280, 0);
+        self.generate_nametables(& *nes.mapper, &nes.ppu, NAMETABLE_X as u32, NAMETABLE_Y as u32);
     }
 }
 
@@ -295,7 +609,28 @@ impl Panel for PpuWindow {
         match event {
             Event::Update => {self.update(&runtime.nes)},
             Event::RequestFrame => {self.draw(&runtime.nes)},
-            Event::ShowPpuWindow => {self.shown = true},
+            Event::ShowPpuWindow => {
+                self.shown = true;
+                self.invalidate_nametables();
+            },
+            Event::CartridgeLoaded(_) => {
+                self.invalidate_nametables();
+                self.chr_bank_override = None;
+            },
+            Event::PpuViewerNextChrBank => {self.cycle_chr_bank(& *runtime.nes.mapper, 1)},
+            Event::PpuViewerPreviousChrBank => {self.cycle_chr_bank(& *runtime.nes.mapper, -1)},
+            Event::PpuViewerNextPalette => {self.cycle_palette()},
+            Event::PpuViewerToggleAttributeOverlay => {self.attribute_overlay = !self.attribute_overlay},
+            Event::MouseClick(x, y) => {self.handle_click(x, y)},
+            Event::MouseMove(x, y) => {self.handle_mouse_move(x, y)},
+            Event::MouseRelease => {self.drag_origin = None},
+            Event::MouseWheel(delta) => {self.zoom_nametables(delta)},
+            Event::PpuExportChr(path) => {
+                match self.export_chr_raw(& *runtime.nes.mapper, &path) {
+                    Ok(_) => {},
+                    Err(why) => {logging::warn(&why)}
+                }
+            },
             Event::CloseWindow => {self.shown = false},
             _ => {}
         }
@@ -305,4 +640,202 @@ impl Panel for PpuWindow {
     fn active_canvas(&self) -> &SimpleBuffer {
         return &self.canvas;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustico_core::cartridge::mapper_from_file;
+
+    // NROM, one 16k PRG bank and one 8k CHR bank filled with a recognizable pattern
+    fn nrom_with_chr(chr: &[u8]) -> Vec<u8> {
+        let mut rom = vec!(0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+        rom.extend(vec!(0u8; 0x4000));
+        rom.extend_from_slice(chr);
+        return rom;
+    }
+
+    #[test]
+    fn exported_chr_matches_the_cartridge_byte_for_byte() {
+        let chr: Vec<u8> = (0 .. 0x2000).map(|i| ((i * 7) % 251) as u8).collect();
+        let mapper = mapper_from_file(&nrom_with_chr(&chr)).unwrap();
+        assert_eq!(PpuWindow::chr_raw(&*mapper), chr);
+
+        let path = std::env::temp_dir().join(format!("rustico_chr_export_{}.chr", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let ppu_window = PpuWindow::new();
+        ppu_window.export_chr_raw(&*mapper, &path).unwrap();
+        let written = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(written.len(), 0x2000);
+        assert_eq!(written, chr);
+    }
+
+    #[test]
+    fn chr_export_reports_unwritable_paths() {
+        let chr = vec!(0u8; 0x2000);
+        let mapper = mapper_from_file(&nrom_with_chr(&chr)).unwrap();
+        let ppu_window = PpuWindow::new();
+        assert!(ppu_window.export_chr_raw(&*mapper, "/nonexistent/directory/out.chr").is_err());
+    }
+
+    #[test]
+    fn unchanged_frames_redraw_no_tiles() {
+        let chr: Vec<u8> = (0 .. 0x2000).map(|i| ((i * 7) % 251) as u8).collect();
+        let mut nes = NesState::new(mapper_from_file(&nrom_with_chr(&chr)).unwrap());
+        let mut ppu_window = PpuWindow::new();
+
+        ppu_window.update_nametable_buffer(&*nes.mapper, &nes.ppu, 0x0000);
+        assert_eq!(ppu_window.tiles_redrawn, 64 * 60);
+        ppu_window.update_nametable_buffer(&*nes.mapper, &nes.ppu, 0x0000);
+        assert_eq!(ppu_window.tiles_redrawn, 0);
+
+        // One nametable byte, away from any mirroring
+        nes.mapper.write_ppu(0x2000 + 5 * 32 + 3, 0x42);
+        ppu_window.update_nametable_buffer(&*nes.mapper, &nes.ppu, 0x0000);
+        let mirrored_copies = (0 .. 64).flat_map(|tx| (0 .. 60).map(move |ty| (tx, ty)))
+            .filter(|&(tx, ty)| nes.ppu.get_bg_tile(&*nes.mapper, tx, ty) == 0x42).count();
+        assert_eq!(ppu_window.tiles_redrawn, mirrored_copies);
+        assert!(mirrored_copies >= 1);
+        ppu_window.update_nametable_buffer(&*nes.mapper, &nes.ppu, 0x0000);
+        assert_eq!(ppu_window.tiles_redrawn, 0);
+    }
+
+    #[test]
+    fn switching_pattern_tables_redraws_everything() {
+        let chr: Vec<u8> = (0 .. 0x2000).map(|i| ((i * 7) % 251) as u8).collect();
+        let nes = NesState::new(mapper_from_file(&nrom_with_chr(&chr)).unwrap());
+        let mut ppu_window = PpuWindow::new();
+        ppu_window.update_nametable_buffer(&*nes.mapper, &nes.ppu, 0x0000);
+        ppu_window.update_nametable_buffer(&*nes.mapper, &nes.ppu, 0x1000);
+        assert_eq!(ppu_window.tiles_redrawn, 64 * 60);
+        ppu_window.invalidate_nametables();
+        ppu_window.update_nametable_buffer(&*nes.mapper, &nes.ppu, 0x1000);
+        assert_eq!(ppu_window.tiles_redrawn, 64 * 60);
+    }
+
+    fn pane_is_blank(canvas: &SimpleBuffer, x: u32) -> bool {
+        for px in x .. x + 128 {
+            for py in 0 .. 128 {
+                let pixel = canvas.get_pixel(px, py);
+                if pixel.r() != 0 || pixel.g() != 0 || pixel.b() != 0 {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+
+    #[test]
+    fn bank_override_reads_the_requested_bank() {
+        // Bank 0 is all 0x11, bank 1 all 0x22; the mapper has both mapped in order
+        let mut chr = vec!(0x11u8; 0x1000);
+        chr.extend(vec!(0x22u8; 0x1000));
+        let mapper = mapper_from_file(&nrom_with_chr(&chr)).unwrap();
+        assert_eq!(mapper.chr_bank_count(), 2);
+        assert!(read_pattern_table(&*mapper, 0x0000, None).iter().all(|&b| b == 0x11));
+        assert!(read_pattern_table(&*mapper, 0x0000, Some(1)).iter().all(|&b| b == 0x22));
+        assert!(read_pattern_table(&*mapper, 0x1000, Some(0)).iter().all(|&b| b == 0x11));
+    }
+
+    #[test]
+    fn last_bank_leaves_the_right_pane_blank() {
+        let chr: Vec<u8> = (0 .. 0x2000).map(|i| ((i * 7) % 251) as u8).collect();
+        let nes = NesState::new(mapper_from_file(&nrom_with_chr(&chr)).unwrap());
+        let mut ppu_window = PpuWindow::new();
+
+        ppu_window.chr_bank_override = Some(0);
+        ppu_window.draw(&nes);
+        assert!(!pane_is_blank(&ppu_window.canvas, 144));
+
+        // Bank 1 is the last one, so there's no bank 2 to show, and it mustn't wrap to bank 0
+        ppu_window.chr_bank_override = Some(1);
+        ppu_window.draw(&nes);
+        assert!(!pane_is_blank(&ppu_window.canvas, 8));
+        assert!(pane_is_blank(&ppu_window.canvas, 144));
+    }
+
+    #[test]
+    fn clicks_under_2x_zoom_map_to_the_right_nametable_pixel() {
+        let mut ppu_window = PpuWindow::new();
+        ppu_window.nametable_zoom = 2;
+        ppu_window.nametable_pan_x = 100;
+        ppu_window.nametable_pan_y = 40;
+        assert_eq!(ppu_window.screen_to_nametable(0, 0), (100, 40));
+        assert_eq!(ppu_window.screen_to_nametable(1, 1), (100, 40));
+        assert_eq!(ppu_window.screen_to_nametable(21, 10), (110, 45));
+        assert_eq!(ppu_window.screen_to_nametable(511, 479), (355, 279));
+    }
+
+    #[test]
+    fn zooming_keeps_the_pixel_under_the_cursor_and_clamps() {
+        let mut ppu_window = PpuWindow::new();
+        ppu_window.handle_mouse_move(NAMETABLE_X + 200, NAMETABLE_Y + 100);
+        ppu_window.zoom_nametables(1);
+        assert_eq!(ppu_window.nametable_zoom, 2);
+        assert_eq!(ppu_window.screen_to_nametable(200, 100), (200, 100));
+
+        ppu_window.zoom_nametables(100);
+        assert_eq!(ppu_window.nametable_zoom, 8);
+        ppu_window.zoom_nametables(-100);
+        assert_eq!(ppu_window.nametable_zoom, 1);
+        assert_eq!((ppu_window.nametable_pan_x, ppu_window.nametable_pan_y), (0, 0));
+    }
+
+    #[test]
+    fn panning_stops_at_the_buffer_edges() {
+        let mut ppu_window = PpuWindow::new();
+        ppu_window.nametable_zoom = 2;
+        ppu_window.handle_click(NAMETABLE_X + 10, NAMETABLE_Y + 10);
+        // Drag far up and to the left, which pans towards the bottom right corner
+        ppu_window.handle_mouse_move(NAMETABLE_X - 2000, NAMETABLE_Y - 2000);
+        assert_eq!((ppu_window.nametable_pan_x, ppu_window.nametable_pan_y), (256, 240));
+        ppu_window.handle_mouse_move(NAMETABLE_X + 4000, NAMETABLE_Y + 4000);
+        assert_eq!((ppu_window.nametable_pan_x, ppu_window.nametable_pan_y), (0, 0));
+    }
+
+    #[test]
+    fn attribute_overlay_draws_grid_lines_every_16_pixels() {
+        let mut ppu_window = PpuWindow::new();
+        // Second attribute block along the top uses palette 1
+        ppu_window.shadow_tiles[2] = (0, 1);
+        let mut buffer = SimpleBuffer::new(512, 480);
+        drawing::rect(&mut buffer, 0, 0, 512, 480, Color::rgb(0, 0, 0));
+        ppu_window.draw_attribute_overlay(&mut buffer);
+
+        let inside = buffer.get_pixel(8, 8);
+        for x in 0 .. 512 {
+            let pixel = buffer.get_pixel(x, 40);
+            let on_grid = x % 16 == 0;
+            let block_interior = buffer.get_pixel(x - x % 16 + 8, 40);
+            assert_eq!(pixel.r() > block_interior.r(), on_grid, "column {}", x);
+        }
+        for y in 0 .. 480 {
+            let pixel = buffer.get_pixel(40, y);
+            assert_eq!(pixel.b() > inside.b(), y % 16 == 0, "row {}", y);
+        }
+
+        // Palette 0 tints red, palette 1 green
+        let red_block = buffer.get_pixel(8, 8);
+        let green_block = buffer.get_pixel(24, 8);
+        assert!(red_block.r() > red_block.g());
+        assert!(green_block.g() > green_block.r());
+    }
+
+    #[test]
+    fn vram_views_draw_into_plain_buffers_of_the_right_size() {
+        // Nothing here touches a windowing library; any shell can show active_canvas()
+        let chr: Vec<u8> = (0 .. 0x2000).map(|i| ((i * 7) % 251) as u8).collect();
+        let nes = NesState::new(mapper_from_file(&nrom_with_chr(&chr)).unwrap());
+        let mut ppu_window = PpuWindow::new();
+        ppu_window.draw(&nes);
+
+        // Two 128x128 pattern tables, and all four 256x240 nametables
+        let canvas = ppu_window.active_canvas();
+        assert_eq!((canvas.width, canvas.height), (792, 512));
+        assert_eq!(canvas.buffer.len(), 792 * 512 * 4);
+        assert_eq!((ppu_window.nametable_buffer.width, ppu_window.nametable_buffer.height), (512, 480));
+        assert!(!pane_is_blank(&ppu_window.canvas, 8));
+        assert!(!pane_is_blank(&ppu_window.canvas, 144));
+    }
+}
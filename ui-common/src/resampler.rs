@@ -0,0 +1,268 @@
+// Converts audio from the APU's output rate to whatever rate the host device (or export
+// target) actually wants. The APU already band-limits its output, so this only needs to
+// bridge the (usually small) difference between, say, 44.1 kHz and 48 kHz.
+
+// Both resamplers keep a short history of input samples, and track a fractional read
+// position within that history. Incoming samples are appended, outgoing samples are
+// generated until the read position runs out of input, and then fully consumed history
+// is discarded.
+
+use rustico_core::logging;
+
+use settings::SettingsState;
+
+use std::f64::consts::PI;
+
+pub trait Resampler: Send {
+    // Appends the resampled output to an existing buffer, so callers can reuse it
+    fn process_into(&mut self, input: &[f32], output: &mut Vec<f32>);
+
+    fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let mut output: Vec<f32> = Vec::new();
+        self.process_into(input, &mut output);
+        return output;
+    }
+}
+
+pub struct LinearResampler {
+    step: f64,
+    position: f64,
+    history: Vec<f32>,
+}
+
+impl LinearResampler {
+    pub fn new(input_rate: u64, output_rate: u64) -> LinearResampler {
+        return LinearResampler {
+            step: input_rate as f64 / output_rate as f64,
+            position: 0.0,
+            history: Vec::new(),
+        };
+    }
+}
+
+impl Resampler for LinearResampler {
+    fn process_into(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.history.extend_from_slice(input);
+        while (self.position as usize) + 1 < self.history.len() {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let sample = self.history[index] + (self.history[index + 1] - self.history[index]) * fraction;
+            output.push(sample);
+            self.position += self.step;
+        }
+
+        // Keep the last sample we read from, it's needed to interpolate the next batch
+        let consumed = (self.position as usize).min(self.history.len());
+        self.history.drain(0 .. consumed);
+        self.position -= consumed as f64;
+    }
+}
+
+pub struct SincResampler {
+    step: f64,
+    position: f64,
+    history: Vec<f32>,
+    half_width: usize,
+    cutoff: f64,
+}
+
+impl SincResampler {
+    pub fn new(input_rate: u64, output_rate: u64, half_width: usize) -> SincResampler {
+        // The window needs half_width samples of history behind the very first output sample.
+        // We don't have any, so start with silence; this delays the output by half_width input
+        // samples, but avoids a click while the window fills up.
+        return SincResampler {
+            step: input_rate as f64 / output_rate as f64,
+            position: half_width as f64,
+            history: vec!(0.0f32; half_width),
+            half_width: half_width,
+            // When downsampling, lower the cutoff to stay below the new Nyquist frequency
+            cutoff: (output_rate as f64 / input_rate as f64).min(1.0),
+        };
+    }
+
+    fn kernel(&self, t: f64) -> f64 {
+        let width = self.half_width as f64;
+        if t.abs() >= width {
+            return 0.0;
+        }
+        let x = t * self.cutoff;
+        let sinc = if x == 0.0 {1.0} else {(PI * x).sin() / (PI * x)};
+        // Blackman window, centered on t = 0
+        let n = (t + width) / (2.0 * width);
+        let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+        return sinc * window;
+    }
+}
+
+impl Resampler for SincResampler {
+    fn process_into(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.history.extend_from_slice(input);
+        while (self.position as usize) + self.half_width < self.history.len() {
+            let index = self.position as usize;
+            let fraction = self.position - index as f64;
+            let mut total = 0.0;
+            let mut total_weight = 0.0;
+            for tap in (index + 1 - self.half_width) ..= (index + self.half_width) {
+                let weight = self.kernel(tap as f64 - index as f64 - fraction);
+                total += self.history[tap] as f64 * weight;
+                total_weight += weight;
+            }
+            // Normalize, so the filter has unity gain regardless of the phase we landed on
+            output.push((total / total_weight) as f32);
+            self.position += self.step;
+        }
+
+        // Discard history we no longer need, keeping enough behind the read position for the
+        // left half of the next window
+        let keep_from = (self.position as usize + 1).saturating_sub(self.half_width);
+        let consumed = keep_from.min(self.history.len());
+        self.history.drain(0 .. consumed);
+        self.position -= consumed as f64;
+    }
+}
+
+pub fn resampler_from_quality(quality: &str, input_rate: u64, output_rate: u64) -> Box<dyn Resampler> {
+    match quality {
+        "sinc" => {
+            return Box::new(SincResampler::new(input_rate, output_rate, 16));
+        },
+        "linear" => {
+            return Box::new(LinearResampler::new(input_rate, output_rate));
+        },
+        _ => {
+            logging::warn(&format!("Unknown resampler quality {}, using linear.", quality));
+            return Box::new(LinearResampler::new(input_rate, output_rate));
+        }
+    }
+}
+
+// Live playback and export should both go through here, so audio.resampler_quality means
+// the same thing everywhere
+pub fn resampler_from_settings(settings: &SettingsState, input_rate: u64, output_rate: u64) -> Box<dyn Resampler> {
+    let quality = settings.get_string("audio.resampler_quality".to_string()).unwrap_or("linear".to_string());
+    return resampler_from_quality(&quality, input_rate, output_rate);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustico_core::apu::ApuState;
+
+    fn sine(frequency: f64, rate: f64, count: usize) -> Vec<f32> {
+        return (0 .. count).map(|i| (2.0 * PI * frequency * i as f64 / rate).sin() as f32 * 0.5).collect();
+    }
+
+    // Fits a sine at the known frequency (any phase, so resampler delay doesn't matter) and
+    // returns the energy of whatever's left over relative to the fit: THD+N, as a ratio
+    fn thd_plus_noise(samples: &[f32], frequency: f64, rate: f64) -> f64 {
+        let (mut ss, mut sc, mut cc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, &y) in samples.iter().enumerate() {
+            let phase = 2.0 * PI * frequency * i as f64 / rate;
+            let (s, c) = (phase.sin(), phase.cos());
+            ss += s * s; sc += s * c; cc += c * c;
+            ys += y as f64 * s; yc += y as f64 * c;
+        }
+        let determinant = ss * cc - sc * sc;
+        let a = (ys * cc - yc * sc) / determinant;
+        let b = (yc * ss - ys * sc) / determinant;
+        let mut fundamental = 0.0;
+        let mut residual = 0.0;
+        for (i, &y) in samples.iter().enumerate() {
+            let phase = 2.0 * PI * frequency * i as f64 / rate;
+            let fit = a * phase.sin() + b * phase.cos();
+            fundamental += fit * fit;
+            residual += (y as f64 - fit) * (y as f64 - fit);
+        }
+        return (residual / fundamental).sqrt();
+    }
+
+    // Feeds the input in small chunks, the way the audio callback does
+    fn resample(resampler: &mut dyn Resampler, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::new();
+        for chunk in input.chunks(735) {
+            resampler.process_into(chunk, &mut output);
+        }
+        return output;
+    }
+
+    #[test]
+    fn sinc_resampler_is_cleaner_than_linear() {
+        let input = sine(5000.0, 44100.0, 44100);
+        let linear = resample(&mut LinearResampler::new(44100, 48000), &input);
+        let sinc = resample(&mut SincResampler::new(44100, 48000, 16), &input);
+        // Skip the sinc resampler's zero padded start, and the tail end of both
+        let linear_thd = thd_plus_noise(&linear[1000 .. 40000], 5000.0, 48000.0);
+        let sinc_thd = thd_plus_noise(&sinc[1000 .. 40000], 5000.0, 48000.0);
+        assert!(sinc_thd < linear_thd / 10.0, "sinc: {}, linear: {}", sinc_thd, linear_thd);
+    }
+
+    #[test]
+    fn resamplers_produce_the_output_rate() {
+        let input = sine(440.0, 44100.0, 44100);
+        let linear = resample(&mut LinearResampler::new(44100, 48000), &input);
+        let sinc = resample(&mut SincResampler::new(44100, 48000, 16), &input);
+        assert!((linear.len() as i64 - 48000).abs() <= 2);
+        // The sinc window holds back half its width of input until more arrives
+        assert!((sinc.len() as i64 - 48000).abs() <= 20);
+    }
+
+    #[test]
+    fn sinc_resampler_zero_pads_the_start() {
+        let input = vec!(1.0f32; 256);
+        let output = SincResampler::new(44100, 48000, 16).process(&input);
+        // Zero padded history: output starts with the very first input sample, and the window
+        // hanging off the start sees silence instead of reading before the buffer
+        assert_eq!(output[0], 1.0);
+        assert!(output.iter().all(|sample| sample.is_finite() && sample.abs() < 1.2));
+        assert!((output[100] - 1.0).abs() < 0.01);
+    }
+
+    // Chunks of a playing pulse channel, a scanline or so at a time like the worker takes them
+    fn apu_chunks(apu: &mut ApuState, chunk_count: usize) -> Vec<Vec<f32>> {
+        let mut chunks = Vec::new();
+        for _ in 0 .. chunk_count {
+            apu.clock_cycles(114);
+            chunks.push(apu.consume_samples_f32());
+        }
+        return chunks;
+    }
+
+    #[test]
+    fn reused_buffers_match_fresh_allocations() {
+        for &quality in ["linear", "sinc"].iter() {
+            let mut fresh_apu = ApuState::new();
+            let mut reused_apu = ApuState::new();
+            for apu in [&mut fresh_apu, &mut reused_apu].iter_mut() {
+                apu.write_register(0x4015, 0x01);
+                apu.write_register(0x4000, 0xBF);
+                apu.write_register(0x4002, 0x40);
+                apu.write_register(0x4003, 0x01);
+            }
+
+            // The way the worker used to do it: a new Vec at every step
+            let mut fresh_resampler = resampler_from_quality(quality, 44100, 48000);
+            let mut fresh_output: Vec<f32> = Vec::new();
+            for chunk in apu_chunks(&mut fresh_apu, 500) {
+                fresh_output.extend(fresh_resampler.process(&chunk));
+            }
+
+            // And the way it does now, clearing and refilling the same two buffers
+            let mut reused_resampler = resampler_from_quality(quality, 44100, 48000);
+            let mut apu_samples: Vec<f32> = Vec::new();
+            let mut resampled_samples: Vec<f32> = Vec::new();
+            let mut reused_output: Vec<f32> = Vec::new();
+            for _ in 0 .. 500 {
+                reused_apu.clock_cycles(114);
+                apu_samples.clear();
+                resampled_samples.clear();
+                reused_apu.consume_samples_f32_into(&mut apu_samples);
+                reused_resampler.process_into(&apu_samples, &mut resampled_samples);
+                reused_output.extend(resampled_samples.iter());
+            }
+
+            assert!(fresh_output.len() > 1000);
+            assert_eq!(fresh_output, reused_output, "{}", quality);
+        }
+    }
+}
@@ -8,38 +8,122 @@ use toml::map::Map;
 
 // First-party
 use events::Event;
+use rustico_core::logging;
 
 
 const DEFAULT_CONFIG: &str = r###"
+[audio]
+resampler_quality = "linear"
+export_sample_rate = 44100
+underrun_fill = "silence"
+master_mute = false
+expansion_levels = "famicom"
+
+[audio.expansion_gain]
+VRC6 = 1.0
+VRC7 = 1.0
+FDS = 1.0
+MMC5 = 1.0
+N163 = 1.0
+YM2149F = 1.0
+
+[debug]
+event_log = false
+
+[emulation]
+deterministic = false
+
+[render]
+max_frameskip = 0
+
 [video]
 ntsc_filter = false
 simulate_overscan = false
 display_fps = false
+aspect_correction = false
 scale_factor = 2
+brightness = 0.0
+contrast = 1.0
+gamma = 1.0
+
+[video.overscan]
+top = 0
+bottom = 0
+left = 0
+right = 0
 
 [piano_roll]
 canvas_width = 1280
 canvas_height = 720
 draw_piano_strings = true
-key_length = 32
-key_thickness = 11
+clear_each_frame = true
+key_length = 0
+key_thickness = 0
+key_thickness_f = 0.0
+key_size = "auto"
 octave_count = 9
 scale_factor = 1
 speed_multiplier = 4
+smooth_scroll = false
 starting_octave = 0
 waveform_height = 64
+waveform_rows = 1
 draw_text_labels = true
+surfboard_labels = false
+show_mute_icons = false
+show_hz_axis = false
+dmc_as_pitch = false
+use_detected_pitch = false
+velocity_style = "thickness"
+noise_style = "strings"
+surfboard_trigger = "rising_edge"
+scope_interpolation = "step"
+polling_type = "quarter_frame"
+audio_samples_per_poll = 735
+scale_root = 0
+scale_kind = "none"
+scroll_speed_pps = 0.0
+tempo_bpm = 0.0
+grid_subdivision = 4
+quantize_to_grid = false
+note_end_fade = 0
+synthetic_input = false
+digit_key_muting = true
+smooth_pitch = false
+pitch_smoothing_threshold = 0.5
+reference_frequency = 0.0
+export_scale = 1
+export_dither = false
+antialias_keyboard = false
+surfboard_persistence = 0.0
 
 divider_width = 5
 divider_color = "rgb(0, 0, 0)"
 background_color = "rgba(0, 0, 0, 255)"
+muted_color = ""
+desaturate_muted = false
+note_outline = ""
+aa_quality = "fast"
+outline_color = "rgba(0, 0, 0, 255)"
+outline_thickness = 2
+oscilloscope_glow_thickness = 2.5
+oscilloscope_line_thickness = 0.5
+
+[piano_roll.keyboard]
+white_key = "rgb(32, 32, 32)"
+black_key = "rgb(0, 0, 0)"
+white_key_border = "rgb(24, 24, 24)"
+top_edge = "rgb(4, 4, 4)"
 
 [piano_roll.settings.2A03.DMC]
-static = "rgb(96, 32, 192)"
+gradient_low = "rgb(96, 32, 192)"
+gradient_high = "rgb(192, 128, 255)"
+trigger = "none"
 
 [piano_roll.settings.2A03.Noise]
 mode0 = "rgb(192, 192, 192)"
 mode1 = "rgb(128, 240, 255)"
+trigger = "none"
 
 [piano_roll.settings.2A03."Pulse 1"]
 duty0 = "hsv(340, 25%, 100%)"
@@ -240,6 +324,146 @@ hidden = true
 
 "###;
 
+// Valid ranges for numeric settings. Values outside of these are clamped before any window
+// sees them, since most of the drawing code assumes sane, nonzero sizes.
+const INTEGER_RANGES: &[(&str, i64, i64)] = &[
+    ("audio.export_sample_rate", 8000, 192000),
+    ("video.scale_factor", 1, 7),
+    ("video.overscan.top", 0, 64),
+    ("video.overscan.bottom", 0, 64),
+    ("video.overscan.left", 0, 64),
+    ("video.overscan.right", 0, 64),
+    ("render.max_frameskip", 0, 10),
+    ("piano_roll.canvas_width", 64, 7680),
+    ("piano_roll.canvas_height", 64, 4320),
+    ("piano_roll.key_length", 0, 512),
+    ("piano_roll.key_thickness", 0, 64),
+    ("piano_roll.octave_count", 1, 10),
+    ("piano_roll.scale_factor", 1, 8),
+    ("piano_roll.speed_multiplier", 1, 32),
+    ("piano_roll.starting_octave", 0, 9),
+    ("piano_roll.waveform_height", 0, 1024),
+    ("piano_roll.waveform_rows", 1, 16),
+    ("piano_roll.outline_thickness", 0, 16),
+    ("piano_roll.divider_width", 0, 64),
+    ("piano_roll.grid_subdivision", 1, 16),
+    ("piano_roll.note_end_fade", 0, 64),
+    ("piano_roll.audio_samples_per_poll", 1, 65536),
+    ("piano_roll.scale_root", 0, 11),
+];
+
+const FLOAT_RANGES: &[(&str, f64, f64)] = &[
+    ("piano_roll.oscilloscope_glow_thickness", 0.0, 32.0),
+    ("piano_roll.oscilloscope_line_thickness", 0.0, 32.0),
+    ("piano_roll.speed_multiplier", 0.1, 32.0),
+    ("piano_roll.tempo_bpm", 0.0, 999.0),
+    ("piano_roll.pitch_smoothing_threshold", 0.0, 12.0),
+    ("piano_roll.surfboard_persistence", 0.0, 0.99),
+    ("piano_roll.key_thickness_f", 0.0, 64.0),
+    ("piano_roll.scroll_speed_pps", 0.0, 100000.0),
+    ("video.brightness", -1.0, 1.0),
+    ("video.contrast", 0.0, 4.0),
+    ("video.gamma", 0.1, 4.0),
+    ("audio.expansion_gain.VRC6", 0.0, 8.0),
+    ("audio.expansion_gain.VRC7", 0.0, 8.0),
+    ("audio.expansion_gain.FDS", 0.0, 8.0),
+    ("audio.expansion_gain.MMC5", 0.0, 8.0),
+    ("audio.expansion_gain.N163", 0.0, 8.0),
+    ("audio.expansion_gain.YM2149F", 0.0, 8.0),
+];
+
+pub fn validate_integer_setting(path: &str, value: i64) -> i64 {
+    for &(range_path, min, max) in INTEGER_RANGES {
+        if range_path == path && (value < min || value > max) {
+            let clamped_value = value.max(min).min(max);
+            logging::warn(&format!("{} = {} is outside of the range {} - {}, using {} instead.", path, value, min, max, clamped_value));
+            return clamped_value;
+        }
+    }
+    return value;
+}
+
+pub fn validate_float_setting(path: &str, value: f64) -> f64 {
+    for &(range_path, min, max) in FLOAT_RANGES {
+        if range_path == path && !(value >= min && value <= max) {
+            // NaN compares false against everything, so it lands here too
+            let clamped_value = if value.is_nan() {min} else {value.max(min).min(max)};
+            logging::warn(&format!("{} = {} is outside of the range {} - {}, using {} instead.", path, value, min, max, clamped_value));
+            return clamped_value;
+        }
+    }
+    return value;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SettingType {
+    Boolean,
+    Integer,
+    Float,
+    String,
+}
+
+#[derive(Clone, Debug)]
+pub struct SettingDescription {
+    pub path: String,
+    pub setting_type: SettingType,
+    pub default_value: Value,
+    pub range: Option<(f64, f64)>,
+}
+
+fn _describe_settings(value: &Value, prefix: String, descriptions: &mut Vec<SettingDescription>) {
+    let setting_type = match value {
+        Value::Table(table) => {
+            for key in table.keys() {
+                let new_prefix = if prefix == "" {key.to_string()} else {format!("{}.{}", prefix, key)};
+                _describe_settings(&table[key], new_prefix, descriptions);
+            }
+            return;
+        },
+        Value::Boolean(_) => SettingType::Boolean,
+        Value::Integer(_) => SettingType::Integer,
+        Value::Float(_) => SettingType::Float,
+        Value::String(_) => SettingType::String,
+        _ => {
+            /* Unimplemented! */
+            return;
+        }
+    };
+
+    let mut range: Option<(f64, f64)> = None;
+    for &(range_path, min, max) in INTEGER_RANGES {
+        if range_path == prefix {
+            range = Some((min as f64, max as f64));
+        }
+    }
+    for &(range_path, min, max) in FLOAT_RANGES {
+        if range_path == prefix {
+            range = Some((min, max));
+        }
+    }
+
+    descriptions.push(SettingDescription {
+        path: prefix,
+        setting_type: setting_type,
+        default_value: value.clone(),
+        range: range,
+    });
+}
+
+// Every setting the application knows about, along with its type, default value, and valid
+// range (for numeric settings). The default config is the source of truth here, so any new
+// setting only needs to be added there (and to the ranges above, if it's numeric) to show up.
+pub fn known_settings() -> Vec<SettingDescription> {
+    let default_config = DEFAULT_CONFIG.parse::<Value>().unwrap();
+    let mut descriptions: Vec<SettingDescription> = Vec::new();
+    _describe_settings(&default_config, "".to_string(), &mut descriptions);
+    return descriptions;
+}
+
+pub fn describe_setting(path: &str) -> Option<SettingDescription> {
+    return known_settings().into_iter().find(|description| description.path == path);
+}
+
 #[derive(Clone)]
 pub struct SettingsState {
     pub root: Value
@@ -260,7 +484,7 @@ impl SettingsState {
                 self.root = config_from_file;
             },
             Err(_) => {
-                println!("Failed to load settings from: {:?}", filename);
+                logging::warn(&format!("Failed to load settings from: {:?}", filename));
             }
         }
     }
@@ -273,7 +497,7 @@ impl SettingsState {
     pub fn save(&self, filename: &OsString) {
         let config_str = toml::to_string(&self.root).unwrap();
         fs::write(filename, config_str).expect("Unable to write settings!");
-        println!("Wrote settings to {:?}", filename);
+        logging::info(&format!("Wrote settings to {:?}", filename));
     }
 
     fn _emit_events(value: Value, prefix: String) -> Vec<Event> {
@@ -286,8 +510,14 @@ impl SettingsState {
                 }
             },
             Value::Boolean(boolean_value) => {events.push(Event::ApplyBooleanSetting(prefix, boolean_value));},
-            Value::Float(float_value) => {events.push(Event::ApplyFloatSetting(prefix, float_value));},
-            Value::Integer(integer_value) => {events.push(Event::ApplyIntegerSetting(prefix, integer_value));},
+            Value::Float(float_value) => {
+                let validated_value = validate_float_setting(&prefix, float_value);
+                events.push(Event::ApplyFloatSetting(prefix, validated_value));
+            },
+            Value::Integer(integer_value) => {
+                let validated_value = validate_integer_setting(&prefix, integer_value);
+                events.push(Event::ApplyIntegerSetting(prefix, validated_value));
+            },
             Value::String(string_value) => {events.push(Event::ApplyStringSetting(prefix, string_value));},
             _ => {
                 /* Unimplemented! */
@@ -300,6 +530,12 @@ impl SettingsState {
         return SettingsState::_emit_events(self.root.clone(), "".to_string());
     }
 
+    // The validated Apply*Setting events for every setting in a table laid out like the
+    // settings file, without touching the current settings
+    pub fn settings_events(root: Value) -> Vec<Event> {
+        return SettingsState::_emit_events(root, "".to_string());
+    }
+
     fn _ensure_path_exists(path: String, current_table: &mut Map<String, Value>, default_value: Value) {
         let components = path.split(".").collect::<Vec<&str>>();
         if components.len() == 1 {
@@ -435,11 +671,13 @@ impl SettingsState {
                 events.push(Event::ApplyBooleanSetting(path, value));
             },
             Event::StoreFloatSetting(path, value) => {
+                let value = validate_float_setting(&path, value);
                 self.ensure_path_exists(path.clone(), Value::from(false));
                 self.set(path.clone(), Value::from(value));
                 events.push(Event::ApplyFloatSetting(path, value));
             },
             Event::StoreIntegerSetting(path, value) => {
+                let value = validate_integer_setting(&path, value);
                 self.ensure_path_exists(path.clone(), Value::from(false));
                 self.set(path.clone(), Value::from(value));
                 events.push(Event::ApplyIntegerSetting(path, value));
@@ -459,4 +697,72 @@ impl SettingsState {
         }
         return events;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[test]
+    fn huge_scale_is_capped() {
+        assert_eq!(validate_integer_setting("video.scale_factor", 1000), 7);
+        assert_eq!(validate_integer_setting("video.scale_factor", 0), 1);
+        assert_eq!(validate_integer_setting("video.scale_factor", 3), 3);
+    }
+
+    #[test]
+    fn key_thickness_is_clamped_to_its_range() {
+        // 0 asks the piano roll to derive a thickness from the canvas, anything below is nonsense
+        assert_eq!(validate_integer_setting("piano_roll.key_thickness", -4), 0);
+        assert_eq!(validate_integer_setting("piano_roll.key_thickness", 500), 64);
+    }
+
+    #[test]
+    fn unranged_settings_pass_through() {
+        assert_eq!(validate_integer_setting("some.unknown.path", -12345), -12345);
+        assert_eq!(validate_float_setting("some.unknown.path", 1e9), 1e9);
+    }
+
+    #[test]
+    fn nan_floats_are_replaced() {
+        assert_eq!(validate_float_setting("piano_roll.oscilloscope_line_thickness", std::f64::NAN), 0.0);
+    }
+
+    #[test]
+    fn stored_values_are_clamped_before_being_applied_or_saved() {
+        let mut settings = SettingsState::new();
+        let events = settings.handle_event(Event::StoreIntegerSetting("video.scale_factor".to_string(), 99));
+        assert!(events.iter().any(|event| match event {
+            Event::ApplyIntegerSetting(path, 7) => path == "video.scale_factor",
+            _ => false
+        }));
+        assert_eq!(settings.get_integer("video.scale_factor".to_string()), Some(7));
+    }
+
+    #[test]
+    fn loaded_values_are_clamped_before_being_applied() {
+        let mut settings = SettingsState::new();
+        settings.load_str("[video]\nscale_factor = 50\n");
+        assert!(settings.apply_settings().iter().any(|event| match event {
+            Event::ApplyIntegerSetting(path, 7) => path == "video.scale_factor",
+            _ => false
+        }));
+    }
+
+    #[test]
+    fn clamping_is_logged_as_a_warning() {
+        let captured: Arc<Mutex<Vec<(logging::LogLevel, String)>>> = Arc::new(Mutex::new(Vec::new()));
+        let sink_messages = captured.clone();
+        logging::set_log_sink(Some(Box::new(move |level, message| {
+            sink_messages.lock().unwrap().push((level, message.to_string()));
+        })));
+        validate_integer_setting("video.scale_factor", 1000);
+        logging::set_log_sink(None);
+
+        // Other tests may log while the sink is installed, so look for ours among them
+        let messages = captured.lock().unwrap();
+        assert!(messages.iter().any(|(level, message)| *level == logging::LogLevel::Warning && message == "video.scale_factor = 1000 is outside of the range 1 - 7, using 7 instead."), "{:?}", *messages);
+    }
+}
@@ -0,0 +1,124 @@
+// Maps CPU addresses to human readable labels, for the debugger windows. A few simple formats
+// are understood, and can be mixed freely within one file:
+//   C000 = reset          (also $C000 or 0xC000)
+//   $C000#reset#comment   (FCEUX .nl files)
+//   sym id=3,name="reset",addrsize=absolute,scope=0,def=12,ref=40,val=0xC000,type=lab
+//                         (ca65 / ld65 .dbg files, everything but "sym" lines is skipped)
+// Anything else is ignored, so these can be annotated freely.
+
+use std::collections::HashMap;
+use std::fs;
+
+pub struct SymbolTable {
+    pub labels: HashMap<u16, String>,
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let digits = text.trim_start_matches("$").trim_start_matches("0x").trim_start_matches("0X");
+    match u16::from_str_radix(digits, 16) {
+        Ok(address) => {return Some(address);},
+        Err(_) => {return None;}
+    }
+}
+
+fn parse_dbg_line(line: &str) -> Option<(u16, String)> {
+    let mut name: Option<String> = None;
+    let mut address: Option<u16> = None;
+    for field in line["sym".len() ..].trim().split(',') {
+        let mut parts = field.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        match key {
+            "name" => {name = Some(value.trim_matches('"').to_string());},
+            "val" => {address = parse_address(value);},
+            _ => {}
+        }
+    }
+    match (address, name) {
+        (Some(address), Some(name)) => {return Some((address, name));},
+        _ => {return None;}
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        return SymbolTable {
+            labels: HashMap::new(),
+        };
+    }
+
+    pub fn from_string(symbol_text: &str) -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        for line in symbol_text.lines() {
+            let line = line.trim();
+            if line.starts_with("sym ") || line.starts_with("sym\t") {
+                match parse_dbg_line(line) {
+                    Some((address, name)) => {symbols.labels.insert(address, name);},
+                    None => {}
+                }
+            } else if line.starts_with("$") && line.contains('#') {
+                let fields = line.split('#').collect::<Vec<&str>>();
+                match parse_address(fields[0]) {
+                    Some(address) if fields[1].len() > 0 => {symbols.labels.insert(address, fields[1].to_string());},
+                    _ => {}
+                }
+            } else if line.contains('=') {
+                let fields = line.splitn(2, '=').collect::<Vec<&str>>();
+                let name = fields[1].trim();
+                match parse_address(fields[0]) {
+                    Some(address) if name.len() > 0 => {symbols.labels.insert(address, name.to_string());},
+                    _ => {}
+                }
+            }
+        }
+        return symbols;
+    }
+
+    pub fn load(path: &str) -> Result<SymbolTable, String> {
+        match fs::read_to_string(path) {
+            Ok(symbol_text) => {return Ok(SymbolTable::from_string(&symbol_text));},
+            Err(why) => {return Err(format!("Couldn't read symbol file {}: {}", path, why));}
+        }
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        return self.labels.get(&address).map(|label| label.as_str());
+    }
+
+    // The label if there is one, otherwise plain hex
+    pub fn format_address(&self, address: u16) -> String {
+        match self.label(address) {
+            Some(label) => {return label.to_string();},
+            None => {return format!("0x{:04X}", address);}
+        }
+    }
+
+    // Same, but truncated or padded to fit a fixed width column
+    pub fn format_address_column(&self, address: u16, width: usize) -> String {
+        let formatted = self.format_address(address);
+        let truncated = formatted.chars().take(width).collect::<String>();
+        return format!("{:width$}", truncated, width = width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_three_formats_parse_and_junk_is_skipped() {
+        let symbols = SymbolTable::from_string(concat!(
+            "C000 = reset\n",
+            "$C010#nmi#vblank handler\n",
+            "sym id=3,name=\"irq\",addrsize=absolute,scope=0,def=12,ref=40,val=0xC020,type=lab\n",
+            "version major=2,minor=0\n",
+            "this line means nothing\n"));
+        assert_eq!(symbols.labels.len(), 3);
+        assert_eq!(symbols.label(0xC000), Some("reset"));
+        assert_eq!(symbols.label(0xC010), Some("nmi"));
+        assert_eq!(symbols.label(0xC020), Some("irq"));
+        assert_eq!(symbols.format_address(0xC030), "0xC030");
+        assert_eq!(symbols.format_address_column(0xC000, 3), "res");
+    }
+}
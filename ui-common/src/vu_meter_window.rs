@@ -0,0 +1,293 @@
+use application::RuntimeState;
+use drawing;
+use drawing::Color;
+use drawing::Font;
+use drawing::SimpleBuffer;
+use events::Event;
+use panel::Panel;
+
+use rustico_core::apu::ApuState;
+
+// The bars run from this many dB below full scale up to 0 dBFS
+const FLOOR_DB: f32 = -48.0;
+
+pub struct VuMeterWindow {
+    pub canvas: SimpleBuffer,
+    pub font: Font,
+    pub shown: bool,
+    // Length of the RMS window, in seconds of output audio. The staging buffer only holds so
+    // much, so the effective window is capped at its length (see update).
+    pub window_seconds: f32,
+    // How many frames a peak holds still before falling, and how fast it falls (dB per frame)
+    pub peak_hold_frames: u32,
+    pub peak_decay_db: f32,
+    pub level_left: f32,
+    pub level_right: f32,
+    pub peak_left: f32,
+    pub peak_right: f32,
+    pub peak_hold_left: u32,
+    pub peak_hold_right: u32,
+    // False when fed a single stream, which is drawn as one meter instead of a matching L/R pair
+    pub stereo: bool,
+}
+
+// RMS of a block of samples, where 1.0 is a full scale square wave
+pub fn rms(samples: &[i16]) -> f32 {
+    if samples.len() == 0 {
+        return 0.0;
+    }
+    let sum_of_squares: f64 = samples.iter().map(|&sample| {
+        let normalized = sample as f64 / 32767.0;
+        normalized * normalized
+    }).sum();
+    return (sum_of_squares / samples.len() as f64).sqrt() as f32;
+}
+
+pub fn level_to_db(level: f32) -> f32 {
+    if level <= 0.0 {
+        return FLOOR_DB;
+    }
+    return (20.0 * level.log10()).max(FLOOR_DB);
+}
+
+// Louder levels take over the peak immediately; otherwise it sits for a while, then sinks
+fn hold_peak(level: f32, peak: &mut f32, hold: &mut u32, hold_frames: u32, decay_db: f32) {
+    if level >= *peak {
+        *peak = level;
+        *hold = hold_frames;
+        return;
+    }
+    if *hold > 0 {
+        *hold -= 1;
+        return;
+    }
+    let decayed_db = level_to_db(*peak) - decay_db;
+    *peak = if decayed_db <= FLOOR_DB {0.0} else {10.0f32.powf(decayed_db / 20.0)};
+    if *peak < level {
+        *peak = level;
+    }
+}
+
+impl VuMeterWindow {
+    pub fn new() -> VuMeterWindow {
+        let font = Font::from_raw(include_bytes!("assets/8x8_font.png"), 8);
+
+        return VuMeterWindow {
+            canvas: SimpleBuffer::new(256, 36),
+            font: font,
+            shown: false,
+            window_seconds: 0.05,
+            peak_hold_frames: 60,
+            peak_decay_db: 0.5,
+            level_left: 0.0,
+            level_right: 0.0,
+            peak_left: 0.0,
+            peak_right: 0.0,
+            peak_hold_left: 0,
+            peak_hold_right: 0,
+            stereo: false,
+        };
+    }
+
+    // Measures one window's worth of each side
+    pub fn feed(&mut self, left: &[i16], right: &[i16]) {
+        self.stereo = true;
+        self.level_left = rms(left);
+        self.level_right = rms(right);
+        hold_peak(self.level_left, &mut self.peak_left, &mut self.peak_hold_left, self.peak_hold_frames, self.peak_decay_db);
+        hold_peak(self.level_right, &mut self.peak_right, &mut self.peak_hold_right, self.peak_hold_frames, self.peak_decay_db);
+    }
+
+    // For a single stream. Both sides track it, so switching to stereo later starts from sane levels.
+    pub fn feed_mono(&mut self, samples: &[i16]) {
+        self.feed(samples, samples);
+        self.stereo = false;
+    }
+
+    // The number of samples update measures at once: window_seconds of audio, or the whole
+    // staging buffer if that's shorter
+    pub fn window_size(&self, apu: &ApuState) -> usize {
+        let requested = ((apu.sample_rate() as f32 * self.window_seconds) as usize).max(1);
+        return requested.min(apu.staging_buffer.buffer().len());
+    }
+
+    pub fn update(&mut self, apu: &ApuState) {
+        // The staging buffer holds the final mix, after filtering, at the output sample rate.
+        // The core only mixes in mono for now.
+        let samples = apu.staging_buffer.last_n(self.window_size(apu));
+        self.feed_mono(&samples);
+    }
+
+    fn db_to_width(db: f32, bar_width: u32) -> u32 {
+        let fraction = (db - FLOOR_DB) / -FLOOR_DB;
+        return (fraction.max(0.0).min(1.0) * bar_width as f32) as u32;
+    }
+
+    fn segment_color(db: f32) -> Color {
+        if db >= -3.0 {
+            return Color::rgb(224, 48, 32);
+        }
+        if db >= -9.0 {
+            return Color::rgb(224, 192, 32);
+        }
+        return Color::rgb(48, 192, 64);
+    }
+
+    fn draw_bar(&mut self, y: u32, label: &str, level: f32, peak: f32) {
+        let bar_x = 12;
+        let bar_width = self.canvas.width - bar_x - 4;
+        let bar_height = 8;
+
+        drawing::text(&mut self.canvas, &self.font, 2, y, label, Color::rgb(192, 192, 192));
+        drawing::rect(&mut self.canvas, bar_x, y, bar_width, bar_height, Color::rgb(24, 24, 24));
+
+        // Color each column by the level it represents, so the bar changes color as it climbs
+        let level_width = VuMeterWindow::db_to_width(level_to_db(level), bar_width);
+        for dx in 0 .. level_width {
+            let column_db = FLOOR_DB + (dx as f32 / bar_width as f32) * -FLOOR_DB;
+            drawing::rect(&mut self.canvas, bar_x + dx, y, 1, bar_height, VuMeterWindow::segment_color(column_db));
+        }
+
+        if peak > 0.0 {
+            let peak_db = level_to_db(peak);
+            let peak_x = bar_x + VuMeterWindow::db_to_width(peak_db, bar_width).min(bar_width - 2);
+            drawing::rect(&mut self.canvas, peak_x, y, 2, bar_height, Color::rgb(255, 255, 255));
+        }
+    }
+
+    pub fn draw(&mut self) {
+        let width = self.canvas.width;
+        let height = self.canvas.height;
+        drawing::rect(&mut self.canvas, 0, 0, width, height, Color::rgb(12, 12, 12));
+
+        let (level_left, peak_left) = (self.level_left, self.peak_left);
+        let (level_right, peak_right) = (self.level_right, self.peak_right);
+        if self.stereo {
+            self.draw_bar(2, "L", level_left, peak_left);
+            self.draw_bar(12, "R", level_right, peak_right);
+        } else {
+            self.draw_bar(7, "M", level_left, peak_left);
+        }
+
+        // Scale markings every 12 dB, along the bottom
+        let bar_width = width - 16;
+        for db in [-48, -36, -24, -12, 0].iter() {
+            let x = 12 + VuMeterWindow::db_to_width(*db as f32, bar_width).min(bar_width - 1);
+            drawing::rect(&mut self.canvas, x, 22, 1, 3, Color::rgb(96, 96, 96));
+            let label = format!("{}", db);
+            let label_width = label.len() as u32 * 8;
+            let label_x = x.saturating_sub(label_width / 2).min(width - label_width);
+            drawing::text(&mut self.canvas, &self.font, label_x, 26, &label, Color::rgb(96, 96, 96));
+        }
+    }
+}
+
+impl Panel for VuMeterWindow {
+    fn title(&self) -> &str {
+        return "VU Meter";
+    }
+
+    fn shown(&self) -> bool {
+        return self.shown;
+    }
+
+    fn handle_event(&mut self, runtime: &RuntimeState, event: Event) -> Vec<Event> {
+        match event {
+            Event::Update => {self.update(&runtime.nes.apu)},
+            Event::RequestFrame => {self.draw()},
+            Event::ShowVuMeterWindow => {self.shown = true},
+            Event::HideVuMeterWindow => {self.shown = false},
+            Event::CloseWindow => {self.shown = false},
+            _ => {}
+        }
+        return Vec::<Event>::new();
+    }
+
+    fn active_canvas(&self) -> &SimpleBuffer {
+        return &self.canvas;
+    }
+
+    fn scale_factor(&self) -> u32 {
+        return 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A square wave at the given amplitude, which has an RMS of exactly that amplitude
+    fn square_wave(amplitude: i16, length: usize) -> Vec<i16> {
+        return (0 .. length).map(|i| if (i / 10) % 2 == 0 {amplitude} else {-amplitude}).collect();
+    }
+
+    #[test]
+    fn stereo_levels_match_the_rms_of_each_side() {
+        let mut vu_meter = VuMeterWindow::new();
+        let left = square_wave(16384, 400);
+        // A sine at full scale, whose RMS is 1 / sqrt(2)
+        let right: Vec<i16> = (0 .. 400).map(|i| ((i as f32 / 400.0 * 2.0 * std::f32::consts::PI * 8.0).sin() * 32767.0) as i16).collect();
+        vu_meter.feed(&left, &right);
+        assert!(vu_meter.stereo);
+        assert!((vu_meter.level_left - 16384.0 / 32767.0).abs() < 0.0001, "{}", vu_meter.level_left);
+        assert!((vu_meter.level_right - 0.5f32.sqrt()).abs() < 0.001, "{}", vu_meter.level_right);
+        assert!((level_to_db(vu_meter.level_left) - -6.02).abs() < 0.01);
+        // Peaks start out at the level
+        assert_eq!(vu_meter.peak_left, vu_meter.level_left);
+        assert_eq!(vu_meter.peak_right, vu_meter.level_right);
+    }
+
+    #[test]
+    fn update_measures_no_more_than_the_staging_buffer_holds() {
+        let mut apu = ApuState::new();
+        let mut vu_meter = VuMeterWindow::new();
+        let capacity = apu.staging_buffer.buffer().len();
+        // 50ms at the default rate is more than the staging buffer holds
+        assert!((apu.sample_rate() as f32 * vu_meter.window_seconds) as usize > capacity);
+        assert_eq!(vu_meter.window_size(&apu), capacity);
+
+        // Fill it with a known level, so whatever the window covers is that level and nothing older
+        for sample in square_wave(8192, capacity) {
+            apu.staging_buffer.push(sample);
+        }
+        vu_meter.update(&apu);
+        assert!((vu_meter.level_left - 8192.0 / 32767.0).abs() < 0.0001, "{}", vu_meter.level_left);
+
+        // A short window measures just the newest samples
+        vu_meter.window_seconds = 100.0 / apu.sample_rate() as f32;
+        assert_eq!(vu_meter.window_size(&apu), 100);
+        for sample in square_wave(4096, 100) {
+            apu.staging_buffer.push(sample);
+        }
+        vu_meter.update(&apu);
+        assert!((vu_meter.level_left - 4096.0 / 32767.0).abs() < 0.0001, "{}", vu_meter.level_left);
+    }
+
+    #[test]
+    fn mono_output_is_drawn_as_a_single_meter() {
+        let bar_color = Color::rgb(48, 192, 64);
+        let rows_with_bars = |vu_meter: &VuMeterWindow| (0 .. vu_meter.canvas.height).filter(|y| vu_meter.canvas.get_pixel(12, *y).data == bar_color.data).collect::<Vec<u32>>();
+
+        let mut vu_meter = VuMeterWindow::new();
+        let samples = square_wave(8192, 200);
+        vu_meter.feed_mono(&samples);
+        assert!(!vu_meter.stereo);
+        vu_meter.draw();
+        // One 8 pixel bar, centered where the pair would be
+        assert_eq!(rows_with_bars(&vu_meter), (7 .. 15).collect::<Vec<u32>>());
+
+        vu_meter.feed(&samples, &samples);
+        vu_meter.draw();
+        assert_eq!(rows_with_bars(&vu_meter), (2 .. 10).chain(12 .. 20).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn shells_can_hide_the_meter_directly() {
+        let runtime = RuntimeState::new();
+        let mut vu_meter = VuMeterWindow::new();
+        vu_meter.handle_event(&runtime, Event::ShowVuMeterWindow);
+        assert!(vu_meter.shown());
+        vu_meter.handle_event(&runtime, Event::HideVuMeterWindow);
+        assert!(!vu_meter.shown());
+    }
+}